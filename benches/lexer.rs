@@ -0,0 +1,58 @@
+use rslua::lexer::Lexer;
+use std::fs::File;
+use std::io::prelude::*;
+use std::time::Instant;
+
+// generate a lua data file like `return { { id = 1, ... }, ... }` of about `size` bytes
+fn generate_data(size: usize) -> String {
+    let mut output = String::from("-- generated data file\nreturn {\n");
+    let mut i = 0;
+    while output.len() < size {
+        output.push_str(&format!(
+            "  {{ id = {}, name = \"item_{}\", weight = {}.25, ratio = 0x{:x}, \
+             tags = {{ 'common', \"rare\\tdrop\" }}, enabled = {} }}, -- entry {}\n",
+            i,
+            i,
+            i % 100,
+            i,
+            i % 2 == 0,
+            i
+        ));
+        if i % 100 == 0 {
+            output.push_str("  --[[ long comment\n  spans lines ]]\n  [[long\nstring]],\n");
+        }
+        i += 1;
+    }
+    output.push_str("}\n");
+    output
+}
+
+fn bench(name: &str, input: &str, iterations: u32) {
+    let mut lexer = Lexer::new();
+    let mut count = 0;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        count = lexer.run(input).ok().unwrap().len();
+    }
+    let elapsed = start.elapsed();
+    let mb = (input.len() * iterations as usize) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<16} {:>10} bytes {:>8} tokens {:>12.2?}/iter {:>8.2} MB/s",
+        name,
+        input.len(),
+        count,
+        elapsed / iterations,
+        mb / elapsed.as_secs_f64()
+    );
+}
+
+fn main() -> std::io::Result<()> {
+    let data = generate_data(8 * 1024 * 1024);
+    bench("generated data", &data, 5);
+
+    let mut file = File::open("lua/json.lua")?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    bench("json.lua", &content, 200);
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use std::fs::File;
+use std::io::prelude::*;
+use std::time::{Duration, Instant};
+
+// generate a lua source of about `size` bytes mixing statements and data
+fn generate_source(size: usize) -> String {
+    let mut output = String::from("-- generated source\nlocal M = {}\n");
+    let mut i = 0;
+    while output.len() < size {
+        output.push_str(&format!(
+            "function M.func_{}(a, b, ...)\n  \
+               local t = {{ id = {}, name = \"item_{}\", [a] = b, 1, 2.5, 'x' }}\n  \
+               if a > b and not t.enabled then\n    \
+                 t.value = (a + b) * {} // 2 - -b ^ 2\n  \
+               elseif a == nil then\n    \
+                 return M.helper(t, ...):method(\"arg\")\n  \
+               end\n  \
+               for k, v in pairs(t) do print(k, v) end\n  \
+               return t, #t\n\
+             end\n",
+            i, i, i, i
+        ));
+        i += 1;
+    }
+    output.push_str("return M\n");
+    output
+}
+
+fn bench(name: &str, input: &str, iterations: u32) {
+    let mut lexer = Lexer::new();
+    let mut parser = Parser::new();
+    let mut count = 0;
+    let mut elapsed = Duration::default();
+    for _ in 0..iterations {
+        // lexing is benchmarked separately, only parsing is timed here
+        let tokens = lexer.run(input).ok().unwrap();
+        count = tokens.len();
+        let start = Instant::now();
+        parser.run(tokens).ok().unwrap();
+        elapsed += start.elapsed();
+    }
+    let mb = (input.len() * iterations as usize) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<16} {:>10} bytes {:>8} tokens {:>12.2?}/iter {:>8.2} MB/s",
+        name,
+        input.len(),
+        count,
+        elapsed / iterations,
+        mb / elapsed.as_secs_f64()
+    );
+}
+
+fn main() -> std::io::Result<()> {
+    let source = generate_source(8 * 1024 * 1024);
+    bench("generated source", &source, 5);
+
+    let mut file = File::open("lua/json.lua")?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    bench("json.lua", &content, 200);
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use crate::tokens::TokenType;
+use crate::symbol::Symbol;
 use crate::types::Source;
 use crate::types::{FloatType, IntType};
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -86,6 +87,21 @@ impl BinOp {
         }
     }
 
+    // operator of compound assignment like `+=`
+    pub fn from_compound_assign(token: TokenType) -> BinOp {
+        match token {
+            TokenType::AddAssign => BinOp::Add,
+            TokenType::SubAssign => BinOp::Minus,
+            TokenType::MulAssign => BinOp::Mul,
+            TokenType::DivAssign => BinOp::Div,
+            TokenType::IDivAssign => BinOp::IDiv,
+            TokenType::ModAssign => BinOp::Mod,
+            TokenType::PowAssign => BinOp::Pow,
+            TokenType::ConcatAssign => BinOp::Concat,
+            _ => BinOp::None,
+        }
+    }
+
     pub fn priority(self) -> BinOpPriority {
         match self {
             BinOp::Or => BinOpPriority { left: 1, right: 1 },
@@ -131,7 +147,7 @@ pub enum Expr {
     Float(FloatType),
     Int(IntType),
     String(String),
-    Name(String),
+    Name(Symbol),
     ParenExpr(Box<Expr>),
     FuncBody(FuncBody),
     Table(Table),
@@ -152,7 +168,7 @@ impl Expr {
 
 #[derive(PartialEq, Debug)]
 pub enum Assignable {
-    Name(String),
+    Name(Symbol),
     ParenExpr(Box<Expr>),
     SuffixedExpr(SuffixedExpr),
 }
@@ -175,16 +191,17 @@ pub struct SuffixedExpr {
 }
 
 impl SuffixedExpr {
+    // function calls return multiple values
     pub fn has_mult_ret(&self) -> bool {
-        todo!()
+        matches!(self.suffixes.last(), Some(Suffix::FuncArgs(_)))
     }
 }
 
 #[derive(PartialEq, Debug)]
 pub enum Suffix {
-    Attr(String),
+    Attr(Symbol),
     Index(Expr),
-    Method(String),
+    Method(Symbol),
     FuncArgs(FuncArgs),
 }
 
@@ -214,7 +231,7 @@ pub struct RecField {
 
 #[derive(PartialEq, Debug)]
 pub enum FieldKey {
-    Name(String),
+    Name(Symbol),
     Expr(Expr),
 }
 
@@ -262,7 +279,7 @@ pub enum ForStat {
 
 #[derive(PartialEq, Debug)]
 pub struct ForNum {
-    pub var: String,
+    pub var: Symbol,
     pub init: Expr,
     pub limit: Expr,
     pub step: Option<Expr>,
@@ -271,7 +288,7 @@ pub struct ForNum {
 
 #[derive(PartialEq, Debug)]
 pub struct ForList {
-    pub vars: Vec<String>,
+    pub vars: Vec<Symbol>,
     pub exprs: Vec<Expr>,
     pub body: Block,
 }
@@ -297,8 +314,8 @@ pub struct FuncStat {
 
 #[derive(PartialEq, Debug)]
 pub struct FuncName {
-    pub fields: Vec<String>,
-    pub method: Option<String>,
+    pub fields: Vec<Symbol>,
+    pub method: Option<Symbol>,
 }
 
 #[derive(PartialEq, Debug)]
@@ -310,18 +327,25 @@ pub struct FuncBody {
 #[derive(PartialEq, Debug)]
 pub enum Param {
     VarArg,
-    Name(String),
+    Name(Symbol),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Attrib {
+    Const,
+    Close,
 }
 
 #[derive(PartialEq, Debug)]
 pub struct LocalStat {
-    pub names: Vec<String>,
+    pub names: Vec<Symbol>,
+    pub attribs: Vec<Option<Attrib>>,
     pub exprs: Vec<Expr>,
 }
 
 #[derive(PartialEq, Debug)]
 pub struct LabelStat {
-    pub label: String,
+    pub label: Symbol,
 }
 
 #[derive(PartialEq, Debug)]
@@ -334,7 +358,7 @@ pub struct BreakStat {}
 
 #[derive(PartialEq, Debug)]
 pub struct GotoStat {
-    pub label: String,
+    pub label: Symbol,
 }
 
 #[derive(PartialEq, Debug)]
@@ -343,6 +367,14 @@ pub struct AssignStat {
     pub right: Vec<Expr>,
 }
 
+// `a op= b` of the extended dialect, which is `a = a op b`
+#[derive(PartialEq, Debug)]
+pub struct CompoundAssignStat {
+    pub left: Assignable,
+    pub op: BinOp,
+    pub right: Expr,
+}
+
 #[derive(PartialEq, Debug)]
 pub struct CallStat {
     pub call: Assignable,
@@ -368,11 +400,17 @@ pub enum Stat {
     BreakStat(BreakStat),
     GotoStat(GotoStat),
     AssignStat(AssignStat),
+    CompoundAssignStat(CompoundAssignStat),
     CallStat(CallStat),
     CommentStat(CommentStat),
 }
 
 impl Stat {
+    // void stats don't generate code
+    pub fn is_void(&self) -> bool {
+        matches!(self, Stat::LabelStat(_) | Stat::CommentStat(_))
+    }
+
     pub fn to_stat_info(self) -> StatInfo {
         StatInfo {
             stat: self,
@@ -71,6 +71,9 @@ pub trait AstVisitor<E = ()> {
     fn assign_stat(&mut self, _stat: &AssignStat) -> Result<(), E> {
         Ok(())
     }
+    fn compound_assign_stat(&mut self, _stat: &CompoundAssignStat) -> Result<(), E> {
+        Ok(())
+    }
     fn call_stat(&mut self, _stat: &CallStat) -> Result<(), E> {
         Ok(())
     }
@@ -185,6 +188,7 @@ pub mod ast_walker {
             Stat::BreakStat(breakstat) => walk_breakstat(breakstat, visitor),
             Stat::GotoStat(gotostat) => walk_gotostat(gotostat, visitor),
             Stat::AssignStat(assignstat) => walk_assignstat(assignstat, visitor),
+            Stat::CompoundAssignStat(stat) => walk_compound_assignstat(stat, visitor),
             Stat::CallStat(callstat) => walk_callstat(callstat, visitor),
             Stat::CommentStat(comment) => walk_comment(comment, visitor),
         }
@@ -318,6 +322,13 @@ pub mod ast_walker {
         visitor.assign_stat(stat)
     }
 
+    pub fn walk_compound_assignstat<T: AstVisitor<E>, E>(
+        stat: &CompoundAssignStat,
+        visitor: &mut T,
+    ) -> Result<(), E> {
+        visitor.compound_assign_stat(stat)
+    }
+
     pub fn walk_callstat<T: AstVisitor<E>, E>(stat: &CallStat, visitor: &mut T) -> Result<(), E> {
         visitor.call_stat(stat)
     }
@@ -0,0 +1,284 @@
+use crate::gc::GcMode;
+use crate::lib_util::{bad_argument, check_int, type_error, LibFn, LibResult};
+use crate::types::{FloatType, IntType};
+use crate::value::{str_to_number, Value};
+use crate::vm::{NativeFunction, RuntimeError, Vm};
+use std::rc::Rc;
+
+// the basic functions, set as globals
+
+// the arg `n`, which may be nil but not missing
+fn check_any(args: &[Value], n: usize, name: &str) -> Result<Value, RuntimeError> {
+    match args.get(n - 1) {
+        Some(value) => Ok(value.clone()),
+        None => Err(bad_argument(n, name, "value expected")),
+    }
+}
+
+fn check_table(args: &[Value], n: usize, name: &str) -> Result<Value, RuntimeError> {
+    match args.get(n - 1) {
+        Some(value @ Value::Table(_)) => Ok(value.clone()),
+        _ => Err(type_error(args, n, name, "table")),
+    }
+}
+
+// raise the first arg, a string message gets the position of the function `level` calls up
+fn error(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let mut args = args.into_iter();
+    let value = args.next().unwrap_or(Value::Nil);
+    let level = match args.next() {
+        None | Some(Value::Nil) => 1,
+        Some(Value::Int(level)) => level,
+        Some(_) => return Err(bad_argument(2, "error", "number expected")),
+    };
+    let value = match value {
+        Value::Str(msg) if level > 0 => {
+            let position = vm.position(level as usize);
+            Value::str(&format!("{}{}", position, msg.to_str_lossy()))
+        }
+        value => value,
+    };
+    Err(RuntimeError::from_value(value))
+}
+
+// true and the results of the call, or false and the error value
+fn pcall(vm: &mut Vm, mut args: Vec<Value>) -> LibResult {
+    if args.is_empty() {
+        return Err(bad_argument(1, "pcall", "value expected"));
+    }
+    let func = args.remove(0);
+    let result = vm.pcall(&func, &args, None);
+    protected(vm, result)
+}
+
+// pcall with a message handler, whose result is returned instead of the error value
+fn xpcall(vm: &mut Vm, mut args: Vec<Value>) -> LibResult {
+    match args.get(1) {
+        Some(handler) if handler.is_function() => (),
+        _ => return Err(bad_argument(2, "xpcall", "function expected")),
+    }
+    let func = args.remove(0);
+    let handler = args.remove(0);
+    let result = vm.pcall(&func, &args, Some(handler));
+    protected(vm, result)
+}
+
+// uncatchable interruptions are raised again
+fn protected(vm: &mut Vm, result: Result<Vec<Value>, Value>) -> LibResult {
+    match result {
+        Ok(mut values) => {
+            values.insert(0, Value::Bool(true));
+            Ok(values)
+        }
+        Err(value) if vm.is_interrupted() => Err(RuntimeError::from_value(value)),
+        Err(value) => Ok(vec![Value::Bool(false), value]),
+    }
+}
+
+fn opt_int(args: &[Value], n: usize) -> Option<usize> {
+    match args.get(n - 1) {
+        Some(Value::Int(i)) if *i >= 0 => Some(*i as usize),
+        _ => None,
+    }
+}
+
+// "collect" for a full collection, "count" for the memory in use in kilobytes, "step" for a
+// step of an incremental collection and "incremental" to switch to incremental mode, with
+// the pause and step size. the step multiplier of lua is ignored
+fn collectgarbage(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let option = match args.first() {
+        None | Some(Value::Nil) => "collect".to_string(),
+        Some(Value::Str(s)) => s.to_str_lossy(),
+        Some(_) => return Err(bad_argument(1, "collectgarbage", "string expected")),
+    };
+    match option.as_str() {
+        "collect" => {
+            vm.collect_garbage();
+            Ok(vec![Value::Int(0)])
+        }
+        "count" => Ok(vec![Value::Float(vm.memory() as FloatType / 1024.0)]),
+        "step" => {
+            let budget = opt_int(&args, 2).filter(|n| *n > 0).unwrap_or(1);
+            Ok(vec![Value::Bool(vm.step_garbage(budget))])
+        }
+        "incremental" => {
+            vm.tune_gc(opt_int(&args, 2), opt_int(&args, 4));
+            let previous = match vm.set_gc_mode(GcMode::Incremental) {
+                GcMode::Full => "full",
+                GcMode::Incremental => "incremental",
+            };
+            Ok(vec![Value::str(previous)])
+        }
+        option => Err(bad_argument(
+            1,
+            "collectgarbage",
+            &format!("invalid option '{}'", option),
+        )),
+    }
+}
+
+// the `__metatable` field of the metatable if it's set, which protects the metatable
+fn getmetatable(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let meta = match args.first().and_then(|value| vm.get_metatable(value)) {
+        Some(meta) => meta,
+        None => return Ok(vec![Value::Nil]),
+    };
+    let protected = meta.borrow().get_str("__metatable");
+    Ok(vec![match protected {
+        Value::Nil => Value::Table(meta),
+        protected => protected,
+    }])
+}
+
+fn setmetatable(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let table = match args.first() {
+        Some(Value::Table(t)) => t.clone(),
+        _ => return Err(bad_argument(1, "setmetatable", "table expected")),
+    };
+    let meta = match args.get(1) {
+        Some(Value::Table(meta)) => Some(meta.clone()),
+        Some(Value::Nil) => None,
+        _ => return Err(bad_argument(2, "setmetatable", "nil or table expected")),
+    };
+    if let Some(current) = table.borrow().metatable() {
+        if !current.borrow().get_str("__metatable").is_nil() {
+            return Err(RuntimeError::new("cannot change a protected metatable"));
+        }
+    }
+    vm.set_metatable(&table, meta);
+    Ok(vec![Value::Table(table)])
+}
+
+// tonumber(v) is the number of a number or a numeral, and nil for other values.
+// tonumber(s, base) reads an integer in a base between 2 and 36, with letters for the digits
+// above 9
+fn tonumber(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let base = match args.get(1) {
+        None | Some(Value::Nil) => {
+            return match args.first() {
+                None => Err(bad_argument(1, "tonumber", "value expected")),
+                Some(Value::Int(_)) | Some(Value::Float(_)) => Ok(vec![args[0].clone()]),
+                Some(Value::Str(s)) => Ok(vec![str_to_number(s.as_bytes()).unwrap_or(Value::Nil)]),
+                Some(_) => Ok(vec![Value::Nil]),
+            };
+        }
+        Some(Value::Int(base)) if (2..=36).contains(base) => *base,
+        Some(Value::Int(_)) => return Err(bad_argument(2, "tonumber", "base out of range")),
+        Some(_) => return Err(bad_argument(2, "tonumber", "number expected")),
+    };
+    let s = match args.first() {
+        Some(Value::Str(s)) => s.clone(),
+        _ => return Err(type_error(&args, 1, "tonumber", "string")),
+    };
+    let s = s.as_bytes();
+    let is_space = |c: &u8| matches!(c, b' ' | b'\t'..=b'\r');
+    let start = s.iter().position(|c| !is_space(c)).unwrap_or(s.len());
+    let end = s
+        .iter()
+        .rposition(|c| !is_space(c))
+        .map_or(start, |end| end + 1);
+    let (negative, digits) = match &s[start..end] {
+        [b'-', digits @ ..] => (true, digits),
+        digits => (false, digits),
+    };
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+    let mut n: IntType = 0;
+    for c in digits {
+        let digit = match (*c as char).to_digit(36) {
+            Some(digit) if (digit as IntType) < base => digit as IntType,
+            _ => return Ok(vec![Value::Nil]),
+        };
+        // wraps around like lua
+        n = n.wrapping_mul(base).wrapping_add(digit);
+    }
+    Ok(vec![Value::Int(if negative {
+        n.wrapping_neg()
+    } else {
+        n
+    })])
+}
+
+// the string of any value, with `__tostring` if its metatable has it. numbers are formatted
+// like lua, with `%.14g` for floats
+fn tostring(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let value = check_any(&args, 1, "tostring")?;
+    Ok(vec![Value::Str(vm.tostring(&value)?)])
+}
+
+// select('#', ...) is the number of extra args, select(n, ...) the args from the n-th one,
+// counted from the end if n is negative
+fn select(_: &mut Vm, mut args: Vec<Value>) -> LibResult {
+    let count = args.len().saturating_sub(1) as IntType;
+    if let Some(Value::Str(s)) = args.first() {
+        if s.as_bytes() == b"#" {
+            return Ok(vec![Value::Int(count)]);
+        }
+    }
+    let n = check_int(&args, 1, "select")?;
+    let start = if n < 0 {
+        count.checked_add(n).filter(|start| *start >= 0)
+    } else if n > 0 {
+        Some((n - 1).min(count))
+    } else {
+        None
+    };
+    match start {
+        Some(start) => Ok(args.split_off(start as usize + 1)),
+        None => Err(bad_argument(1, "select", "index out of range")),
+    }
+}
+
+// the raw functions access tables without metamethods
+
+fn rawget(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let table = check_table(&args, 1, "rawget")?;
+    let key = check_any(&args, 2, "rawget")?;
+    Ok(vec![vm.raw_get(&table, &key)?])
+}
+
+// rawset(t, k, v) returns t
+fn rawset(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let table = check_table(&args, 1, "rawset")?;
+    let key = check_any(&args, 2, "rawset")?;
+    let value = check_any(&args, 3, "rawset")?;
+    vm.raw_set(&table, key, value)?;
+    Ok(vec![table])
+}
+
+fn rawequal(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let a = check_any(&args, 1, "rawequal")?;
+    let b = check_any(&args, 2, "rawequal")?;
+    Ok(vec![Value::Bool(vm.raw_equal(&a, &b))])
+}
+
+fn rawlen(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    match vm.raw_len(args.first().unwrap_or(&Value::Nil)) {
+        Ok(len) => Ok(vec![Value::Int(len)]),
+        Err(e) => Err(bad_argument(1, "rawlen", e.message())),
+    }
+}
+
+// add the functions to the globals
+pub fn open(vm: &mut Vm) {
+    let functions: [(&str, LibFn); 13] = [
+        ("collectgarbage", collectgarbage),
+        ("error", error),
+        ("getmetatable", getmetatable),
+        ("pcall", pcall),
+        ("rawequal", rawequal),
+        ("rawget", rawget),
+        ("rawlen", rawlen),
+        ("rawset", rawset),
+        ("select", select),
+        ("setmetatable", setmetatable),
+        ("tonumber", tonumber),
+        ("tostring", tostring),
+        ("xpcall", xpcall),
+    ];
+    for (name, func) in functions.iter() {
+        let native = NativeFunction::new(name, *func);
+        vm.set_global(name, Value::Native(Rc::new(native)));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::compiler::{Compiler, CompilerOptions};
+use crate::lexer::{Lexer, LexerConfig};
+use crate::parser::Parser;
+use crate::proto::Proto;
+use crate::types::Dialect;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+// compiled functions cached by a hash of the source and the compiler options,
+// so hosts loading the same scripts again skip lexing, parsing and compiling.
+//
+// chunks are stored as binary chunks with debug info by a `CacheStore`, e.g. files in a directory,
+// after the length and a digest of their source, which are compared on loads in case of a
+// collision of keys. chunks that can't be loaded or don't verify, e.g. written by another
+// version, are compiled and stored again.
+
+#[derive(Debug, PartialEq)]
+pub struct CacheError(pub String);
+
+// where chunks are kept, by the key of their source
+pub trait CacheStore {
+    // none if there is no chunk for the key
+    fn reader(&mut self, key: &str) -> Option<Box<dyn Read + '_>>;
+    // replaces the chunk of the key
+    fn writer(&mut self, key: &str) -> io::Result<Box<dyn Write + '_>>;
+}
+
+// chunks in files named by their keys
+pub struct DirStore {
+    dir: PathBuf,
+}
+
+impl DirStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        DirStore { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.luac", key))
+    }
+}
+
+impl CacheStore for DirStore {
+    fn reader(&mut self, key: &str) -> Option<Box<dyn Read + '_>> {
+        let file = File::open(self.path(key)).ok()?;
+        Some(Box::new(file))
+    }
+
+    fn writer(&mut self, key: &str) -> io::Result<Box<dyn Write + '_>> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(Box::new(File::create(self.path(key))?))
+    }
+}
+
+// chunks in memory, e.g. for tests or hosts with their own persistence
+#[derive(Default)]
+pub struct MemoryStore {
+    chunks: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn reader(&mut self, key: &str) -> Option<Box<dyn Read + '_>> {
+        let chunk = self.chunks.get(key)?;
+        Some(Box::new(chunk.as_slice()))
+    }
+
+    fn writer(&mut self, key: &str) -> io::Result<Box<dyn Write + '_>> {
+        let chunk = self.chunks.entry(key.to_string()).or_default();
+        chunk.clear();
+        Ok(Box::new(chunk))
+    }
+}
+
+pub struct Cache<S: CacheStore> {
+    store: S,
+    options: CompilerOptions,
+    hits: usize,
+    misses: usize,
+}
+
+impl<S: CacheStore> Cache<S> {
+    pub fn new(store: S, options: CompilerOptions) -> Self {
+        Cache {
+            store,
+            options,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // the cached function of the source, compiled and stored on a miss
+    pub fn load(&mut self, source: &str) -> Result<Proto, CacheError> {
+        let key = self.key(source);
+        if let Some(proto) = self.read(&key, source) {
+            self.hits += 1;
+            return Ok(proto);
+        }
+        self.misses += 1;
+        let proto = self.compile(source)?;
+        let write = |store: &mut S| {
+            let mut writer = store.writer(&key)?;
+            writer.write_all(&source_header(source))?;
+            writer.write_all(&proto.dump(false))
+        };
+        write(&mut self.store).map_err(|e| CacheError(format!("can't store chunk: {}", e)))?;
+        Ok(proto)
+    }
+
+    // hex of a hash of the format version, the options and the source, the same for every run
+    // and build of a version
+    pub fn key(&self, source: &str) -> String {
+        let options = &self.options;
+        let dialect = match options.dialect {
+            Dialect::Standard => 0,
+            Dialect::Extended => 1,
+        };
+        // the register warning doesn't change the code
+        let header = [
+            FORMAT_VERSION,
+            options.fold_constants as u8,
+            options.peephole as u8,
+            options.propagate_constants as u8,
+            options.dedupe_constants as u8,
+            options.emit_debug_info as u8,
+            options.string_coercion as u8,
+            options.strict_div_by_zero as u8,
+            dialect,
+        ];
+        let version = env!("CARGO_PKG_VERSION").as_bytes();
+        let hash = fnv1a(header.iter().chain(version).chain(source.as_bytes()));
+        format!("{:016x}", hash)
+    }
+
+    // loads served from the store and compiled sources
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    // none if there's no chunk or it isn't the one of the source
+    fn read(&mut self, key: &str, source: &str) -> Option<Proto> {
+        let mut chunk = Vec::new();
+        self.store.reader(key)?.read_to_end(&mut chunk).ok()?;
+        let header = source_header(source);
+        if !chunk.starts_with(&header) {
+            return None;
+        }
+        let proto = Proto::undump(&chunk[header.len()..]).ok()?;
+        proto.verify().ok()?;
+        Some(proto)
+    }
+
+    fn compile(&self, source: &str) -> Result<Proto, CacheError> {
+        let mut lexer = Lexer::new();
+        lexer.set_config(LexerConfig {
+            dialect: self.options.dialect,
+            ..LexerConfig::default()
+        });
+        let tokens = lexer.run(source).map_err(|e| CacheError(e.to_string()))?;
+        let mut parser = Parser::new();
+        parser.set_dialect(self.options.dialect);
+        let block = parser.run(tokens).map_err(|e| CacheError(e.to_string()))?;
+        Compiler::with_options(self.options)
+            .run(&block)
+            .map_err(|e| CacheError(e.0))
+    }
+}
+
+// changed with the binary chunks or the code the compiler emits, so older chunks aren't loaded
+const FORMAT_VERSION: u8 = 1;
+
+// 64-bit fnv-1a, which is stable unlike the hashers of std
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn fnv1a<'a>(bytes: impl Iterator<Item = &'a u8>) -> u64 {
+    bytes.fold(FNV_OFFSET, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// length and digest of the source, little endian, stored before its chunk
+fn source_header(source: &str) -> [u8; 16] {
+    let mut header = [0; 16];
+    header[..8].copy_from_slice(&(source.len() as u64).to_le_bytes());
+    header[8..].copy_from_slice(&fnv1a(source.as_bytes().iter()).to_le_bytes());
+    header
+}
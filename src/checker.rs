@@ -0,0 +1,427 @@
+use crate::ast::*;
+use crate::ast_walker::{ast_walker, AstVisitor};
+use crate::compiler::{compile_error, CompileError};
+use crate::resolver::{Env, Resolution, Resolutions, Resolver};
+use crate::symbol::Symbol;
+use crate::types::Source;
+use crate::{debuggable, error};
+
+// limits of PUC lua
+pub const MAX_LOCAL_VARS: usize = 200;
+pub const MAX_UP_VALUES: usize = 255;
+
+struct LabelDesc {
+    name: Symbol,
+    line: usize,
+}
+
+struct GotoDesc {
+    name: Symbol,
+    source: Source,
+    // number of active locals at the goto
+    nactvar: usize,
+}
+
+struct BlockScope {
+    // number of active locals when entering the block
+    nactvar: usize,
+    labels: Vec<LabelDesc>,
+    // gotos waiting for a label defined later
+    pending_gotos: Vec<GotoDesc>,
+}
+
+struct FuncScope {
+    // line where the function is defined, 0 for the main function
+    line: usize,
+    vararg: bool,
+    // names of active locals
+    locals: Vec<Symbol>,
+    blocks: Vec<BlockScope>,
+}
+
+impl FuncScope {
+    fn describe(&self) -> String {
+        if self.line == 0 {
+            "main function".to_string()
+        } else {
+            format!("function at line {}", self.line)
+        }
+    }
+}
+
+// semantic checks on the whole ast before codegen. names are resolved by the shared `Resolver`,
+// the checker only limits the number of up values it finds
+pub struct Checker {
+    debug: bool,
+    funcs: Vec<FuncScope>,
+    resolutions: Resolutions,
+    // source of the statement being checked
+    source: Source,
+    // only void statements (labels and comments) follow the current statement in its block
+    at_block_end: bool,
+    // locals declared by the statement which owns the next block, e.g. params and for loop vars
+    block_locals: Vec<Symbol>,
+    // error from a visitor callback which can't return it
+    pending_error: Option<CompileError>,
+    // errors of nested blocks are reported only once, with the innermost line
+    reported: bool,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker {
+            debug: false,
+            funcs: Vec::new(),
+            resolutions: Resolutions::default(),
+            source: Source::new(),
+            at_block_end: false,
+            block_locals: Vec::new(),
+            pending_error: None,
+            reported: false,
+        }
+    }
+
+    // the resolutions of the names of the block if it passes the checks
+    pub fn run(&mut self, block: &Block) -> Result<Resolutions, CompileError> {
+        self.funcs.clear();
+        self.block_locals.clear();
+        self.pending_error = None;
+        self.reported = false;
+        let mut resolver = Resolver::new();
+        resolver.set_debug(self.debug);
+        self.resolutions = resolver.run(block);
+        // the main chunk is always a vararg function
+        let up_values = self.resolutions.main_up_values().len();
+        self.func(0, true, block, up_values)?;
+        Ok(std::mem::take(&mut self.resolutions))
+    }
+
+    fn func(
+        &mut self,
+        line: usize,
+        vararg: bool,
+        block: &Block,
+        up_values: usize,
+    ) -> Result<(), CompileError> {
+        self.funcs.push(FuncScope {
+            line,
+            vararg,
+            locals: Vec::new(),
+            blocks: Vec::new(),
+        });
+        self.block(block, None)?;
+        // variables captured only for nested functions
+        self.check_up_values(up_values)?;
+        self.funcs.pop();
+        Ok(())
+    }
+
+    // check stats of block, `cond` is the until condition of repeat stat which is in the scope of block
+    fn block(&mut self, block: &Block, cond: Option<&Expr>) -> Result<(), CompileError> {
+        let nactvar = self.func_scope().locals.len();
+        self.func_scope().blocks.push(BlockScope {
+            nactvar,
+            labels: Vec::new(),
+            pending_gotos: Vec::new(),
+        });
+        let locals = std::mem::take(&mut self.block_locals);
+        self.add_locals(locals)?;
+        for (i, StatInfo { source, stat }) in block.stats.iter().enumerate() {
+            self.source = *source;
+            // the until condition can see locals of the block, so a repeat block has no void end
+            self.at_block_end =
+                cond.is_none() && block.stats[i + 1..].iter().all(|s| s.stat.is_void());
+            let result = match stat {
+                Stat::RepeatStat(stat) => self.block(&stat.block, Some(&stat.cond)),
+                _ => ast_walker::walk_stat(stat, self),
+            };
+            if let Err(e) = result {
+                return self.error(e, source);
+            }
+        }
+        if let Some(cond) = cond {
+            ast_walker::walk_expr(cond, self)?;
+        }
+        self.leave_block()
+    }
+
+    fn leave_block(&mut self) -> Result<(), CompileError> {
+        let func = self.func_scope();
+        let block = func.blocks.pop().unwrap();
+        func.locals.truncate(block.nactvar);
+        match func.blocks.last_mut() {
+            // pending gotos may jump to labels of enclosing blocks, locals of this block are out of scope then
+            Some(parent) => {
+                for mut goto in block.pending_gotos.into_iter() {
+                    goto.nactvar = block.nactvar;
+                    parent.pending_gotos.push(goto);
+                }
+                Ok(())
+            }
+            None => match block.pending_gotos.first() {
+                Some(goto) => {
+                    let e = CompileError(format!("no visible label '{}' for goto", goto.name));
+                    let source = goto.source;
+                    self.error(e, &source)
+                }
+                None => Ok(()),
+            },
+        }
+    }
+
+    fn add_locals(&mut self, names: Vec<Symbol>) -> Result<(), CompileError> {
+        let func = self.func_scope();
+        func.locals.extend(names);
+        if func.locals.len() > MAX_LOCAL_VARS {
+            return Err(CompileError(format!(
+                "too many local variables (limit is {}) in {}",
+                MAX_LOCAL_VARS,
+                func.describe()
+            )));
+        }
+        Ok(())
+    }
+
+    // a name captured from an enclosing function, or a global through a captured _ENV, is one of
+    // the up values of the function
+    fn check_name(&mut self, name: &Symbol) -> Result<(), CompileError> {
+        match self.resolutions.get(name) {
+            Some(Resolution::UpValue(index)) | Some(Resolution::Global(Env::UpValue(index))) => {
+                self.check_up_values(index as usize + 1)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_up_values(&mut self, count: usize) -> Result<(), CompileError> {
+        if count > MAX_UP_VALUES {
+            return Err(CompileError(format!(
+                "too many upvalues (limit is {}) in {}",
+                MAX_UP_VALUES,
+                self.func_scope().describe()
+            )));
+        }
+        Ok(())
+    }
+
+    fn func_scope(&mut self) -> &mut FuncScope {
+        self.funcs.last_mut().unwrap()
+    }
+
+    fn block_scope(&mut self) -> &mut BlockScope {
+        self.func_scope().blocks.last_mut().unwrap()
+    }
+
+    debuggable!();
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker::new()
+    }
+}
+
+impl AstVisitor<CompileError> for Checker {
+    fn error(&mut self, e: CompileError, source: &Source) -> Result<(), CompileError> {
+        if self.reported {
+            return Err(e);
+        }
+        self.reported = true;
+        compile_error!(self, e, source)
+    }
+
+    fn then(&mut self, block: &Block) -> Result<bool, CompileError> {
+        self.block(block, None)?;
+        Ok(true)
+    }
+
+    fn begin_else(&mut self, block: &Block) -> Result<bool, CompileError> {
+        self.block(block, None)?;
+        Ok(true)
+    }
+
+    fn begin_while_block(&mut self, block: &Block) -> Result<bool, CompileError> {
+        self.block(block, None)?;
+        Ok(true)
+    }
+
+    fn begin_do_block(&mut self, block: &Block) -> Result<bool, CompileError> {
+        self.block(block, None)?;
+        Ok(true)
+    }
+
+    fn begin_for_block(&mut self, block: &Block) -> Result<bool, CompileError> {
+        self.block(block, None)?;
+        Ok(true)
+    }
+
+    // locals of loop body are pending until the block begins, exprs are walked before that
+    fn for_num(&mut self, stat: &ForNum) -> Result<bool, CompileError> {
+        ast_walker::walk_expr(&stat.init, self)?;
+        ast_walker::walk_expr(&stat.limit, self)?;
+        if let Some(step) = &stat.step {
+            ast_walker::walk_expr(step, self)?;
+        }
+        // internal locals of for loop also count in limit
+        self.block_locals = vec![
+            Symbol::from("(for index)"),
+            Symbol::from("(for limit)"),
+            Symbol::from("(for step)"),
+            stat.var.clone(),
+        ];
+        Ok(true)
+    }
+
+    fn for_list(&mut self, stat: &ForList) -> Result<bool, CompileError> {
+        let mut locals = vec![
+            Symbol::from("(for generator)"),
+            Symbol::from("(for state)"),
+            Symbol::from("(for control)"),
+        ];
+        locals.extend(stat.vars.iter().cloned());
+        ast_walker::walk_exprlist(&stat.exprs, self)?;
+        self.block_locals = locals;
+        Ok(true)
+    }
+
+    fn func(&mut self, stat: &FuncStat) {
+        if stat.func_name.method.is_some() {
+            self.block_locals.push(Symbol::from("self"));
+        }
+        let result = match stat.func_name.fields.first() {
+            // local function is in scope of its own body
+            Some(name) if stat.func_type == FuncType::Local => self.add_locals(vec![name.clone()]),
+            Some(name) => self.check_name(name),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            self.pending_error = Some(e);
+        }
+    }
+
+    fn local_stat(&mut self, stat: &LocalStat) -> Result<(), CompileError> {
+        ast_walker::walk_exprlist(&stat.exprs, self)?;
+        self.add_locals(stat.names.clone())
+    }
+
+    fn label_stat(&mut self, stat: &LabelStat) -> Result<(), CompileError> {
+        let line = self.source.line;
+        // locals of the block are out of scope at a label at the end of block
+        let nactvar = if self.at_block_end {
+            self.block_scope().nactvar
+        } else {
+            self.func_scope().locals.len()
+        };
+        let block = self.block_scope();
+        if let Some(label) = block.labels.iter().find(|l| l.name == stat.label) {
+            return Err(CompileError(format!(
+                "label '{}' already defined on line {}",
+                stat.label, label.line
+            )));
+        }
+        block.labels.push(LabelDesc {
+            name: stat.label.clone(),
+            line,
+        });
+
+        // resolve pending gotos to this label
+        let (matched, pending) = std::mem::take(&mut block.pending_gotos)
+            .into_iter()
+            .partition(|g: &GotoDesc| g.name == stat.label);
+        block.pending_gotos = pending;
+        for goto in matched.iter() {
+            if goto.nactvar < nactvar {
+                let local = self.func_scope().locals[goto.nactvar].clone();
+                let e = CompileError(format!(
+                    "<goto {}> jumps into the scope of local '{}'",
+                    goto.name, local
+                ));
+                return self.error(e, &goto.source);
+            }
+        }
+        Ok(())
+    }
+
+    fn goto_stat(&mut self, stat: &GotoStat) -> Result<(), CompileError> {
+        let source = self.source;
+        let func = self.func_scope();
+        let nactvar = func.locals.len();
+        // labels defined before are visible in the same block and nested blocks
+        let visible = func
+            .blocks
+            .iter()
+            .any(|b| b.labels.iter().any(|l| l.name == stat.label));
+        if !visible {
+            func.blocks.last_mut().unwrap().pending_gotos.push(GotoDesc {
+                name: stat.label.clone(),
+                source,
+                nactvar,
+            });
+        }
+        Ok(())
+    }
+
+    fn ret_stat(&mut self, stat: &RetStat) -> Result<(), CompileError> {
+        ast_walker::walk_exprlist(&stat.exprs, self)
+    }
+
+    fn assign_stat(&mut self, stat: &AssignStat) -> Result<(), CompileError> {
+        for assignable in stat.left.iter() {
+            match assignable {
+                Assignable::Name(name) => self.check_name(name)?,
+                _ => ast_walker::walk_assinable(assignable, self)?,
+            }
+        }
+        ast_walker::walk_exprlist(&stat.right, self)
+    }
+
+    fn compound_assign_stat(&mut self, stat: &CompoundAssignStat) -> Result<(), CompileError> {
+        ast_walker::walk_assinable(&stat.left, self)?;
+        ast_walker::walk_expr(&stat.right, self)
+    }
+
+    fn call_stat(&mut self, stat: &CallStat) -> Result<(), CompileError> {
+        ast_walker::walk_assinable(&stat.call, self)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<bool, CompileError> {
+        match expr {
+            Expr::VarArg if !self.func_scope().vararg => Err(CompileError::new(
+                "cannot use '...' outside a vararg function",
+            )),
+            Expr::Name(name) => {
+                self.check_name(name)?;
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn begin_field_key(&mut self, key: &FieldKey) -> Result<bool, CompileError> {
+        // names as field keys are strings
+        if let FieldKey::Expr(expr) = key {
+            ast_walker::walk_expr(expr, self)?;
+        }
+        Ok(true)
+    }
+
+    fn begin_func_body(&mut self, body: &FuncBody) -> Result<bool, CompileError> {
+        if let Some(e) = self.pending_error.take() {
+            return Err(e);
+        }
+        let source = self.source;
+        let at_block_end = self.at_block_end;
+        let mut vararg = false;
+        for param in body.params.iter() {
+            match param {
+                Param::Name(name) => self.block_locals.push(name.clone()),
+                Param::VarArg => vararg = true,
+            }
+        }
+        let up_values = self.resolutions.up_values(body).len();
+        self.func(source.line, vararg, &body.block, up_values)?;
+        self.source = source;
+        self.at_block_end = at_block_end;
+        Ok(true)
+    }
+}
@@ -1,18 +1,93 @@
 use crate::ast::*;
 use crate::ast_walker::{ast_walker, AstVisitor};
+use crate::checker::Checker;
 use crate::consts::Const;
 use crate::opcodes::*;
 use crate::proto::{Proto, ProtoContext};
-use crate::types::Source;
+use crate::resolver::{Env, Resolution, Resolutions};
+use crate::sourcemap::{SourceMap, SourcePos};
+use crate::symbol::Symbol;
+use crate::types::{Dialect, Source};
 use crate::{debuggable, error, success};
 
+#[derive(Debug, Copy, Clone)]
+pub struct CompilerOptions {
+    // evaluate constant expressions at compile time
+    pub fold_constants: bool,
+    // merge adjacent instructions, e.g. consecutive LoadNil
+    pub peephole: bool,
+    // propagate constants through registers in compiled code and fold what the ast folder can't,
+    // off by default as it runs after codegen and changes the code the compiler emits
+    pub propagate_constants: bool,
+    // reuse the same slot for equal constants
+    pub dedupe_constants: bool,
+    // keep local variable names in the output proto
+    pub emit_debug_info: bool,
+    // fold arithmetic on numeric string literals like `"10" + 1`, as classic lua coerces them
+    pub string_coercion: bool,
+    // report constant integer division or modulo by zero as a compile error instead of leaving it to runtime
+    pub strict_div_by_zero: bool,
+    // language extensions, the lexer and parser should be set to the same dialect
+    pub dialect: Dialect,
+    // warn about functions using more registers than this
+    pub register_warning: Option<u32>,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        CompilerOptions {
+            fold_constants: true,
+            peephole: true,
+            propagate_constants: false,
+            dedupe_constants: true,
+            emit_debug_info: true,
+            string_coercion: false,
+            strict_div_by_zero: false,
+            dialect: Dialect::Standard,
+            register_warning: None,
+        }
+    }
+}
+
+impl CompilerOptions {
+    // no optimizations, every expression and constant maps directly to its own code
+    pub fn unoptimized() -> Self {
+        CompilerOptions {
+            fold_constants: false,
+            peephole: false,
+            propagate_constants: false,
+            dedupe_constants: false,
+            ..CompilerOptions::default()
+        }
+    }
+}
+
 pub struct Compiler {
     debug: bool,
+    options: CompilerOptions,
     proto_contexts: Vec<ProtoContext>,
+    resolutions: Resolutions,
+    stats: Vec<FunctionStats>,
+    warnings: Vec<String>,
+    source_map: SourceMap,
+    // line of the statement being compiled
+    line: u32,
+    // name of the function being compiled, children are named after their index in the parent
+    function: String,
 }
 
 pub struct CompileError(pub String);
 
+// size of a compiled function, functions are named like in listings of the disassembler
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionStats {
+    pub name: String,
+    // registers used at most, which is the stack size of the function
+    pub registers: u32,
+    pub constants: usize,
+    pub instructions: usize,
+}
+
 impl CompileError {
     pub fn new(str: &str) -> Self {
         CompileError(str.to_string())
@@ -27,6 +102,7 @@ macro_rules! compile_error {
         error!($self, CompileError, error_msg)
     }};
 }
+pub(crate) use compile_error;
 
 pub struct Reg {
     pub reg: u32,
@@ -66,6 +142,14 @@ impl Reg {
     }
 }
 
+enum AssignTarget {
+    Local(u32),
+    UpValue(u32),
+    Global(Symbol, Env),
+    // register of table and RK of key
+    Index(u32, u32),
+}
+
 pub struct Jump {
     pub reg: Reg,
     pub pc: usize,
@@ -86,6 +170,12 @@ impl Jump {
     }
 
     pub fn free(&self, context: &mut ProtoContext) {
+        self.discharge(context);
+        self.reg.free(context);
+    }
+
+    // load the boolean result into its register, which stays reserved
+    pub fn discharge(&self, context: &mut ProtoContext) {
         let proto = &mut context.proto;
         let target = self.reg.reg;
         if let Some(from) = self.reg_should_move {
@@ -94,7 +184,6 @@ impl Jump {
         let false_pos = proto.code_bool(target, false, 1);
         let true_pos = proto.code_bool(target, true, 0);
         self.fix(true_pos, false_pos, proto);
-        self.reg.free(context);
     }
 
     pub fn free_reg(&self, context: &mut ProtoContext) {
@@ -108,6 +197,12 @@ impl Jump {
         instruction.set_arg_A(1 - instruction.get_arg_A());
     }
 
+    // `not` of the whole condition, the jumps which decide it's true decide it's false instead
+    pub fn negate(&mut self, context: &mut ProtoContext) {
+        self.inverse_cond(context);
+        std::mem::swap(&mut self.true_jumps, &mut self.false_jumps);
+    }
+
     pub fn concat_true_jumps(&mut self, other: &mut Jump) {
         self.true_jumps.append(&mut other.true_jumps);
         self.true_jumps.push(other.pc);
@@ -159,16 +254,41 @@ impl ExprResult {
         ExprResult::Jump(Jump::new(reg, pc))
     }
 
+    // get RK operand of expr result, constant will be loaded into a new temp register
+    // if its index is too large to be encoded as RK, caller should free it after use.
     pub fn get_rk(&self, context: &mut ProtoContext) -> u32 {
+        let k = match self {
+            ExprResult::Const(k) => k.clone(),
+            ExprResult::Nil => Const::Nil,
+            ExprResult::True => Const::Bool(true),
+            ExprResult::False => Const::Bool(false),
+            ExprResult::Reg(i) => return i.reg,
+            ExprResult::Jump(j) => return j.reg.reg,
+        };
+        let index = context.add_const(k);
+        if index <= MAX_INDEX_RK {
+            rk_as_k(index)
+        } else {
+            let reg = context.reserve_regs(1);
+            context.proto.code_const(reg, index);
+            reg
+        }
+    }
+
+    // get register operand of expr result, constant will be loaded into `target` first
+    pub fn get_reg(&self, context: &mut ProtoContext, target: u32) -> u32 {
         match self {
+            ExprResult::Reg(i) => return i.reg,
+            ExprResult::Jump(j) => return j.reg.reg,
             ExprResult::Const(k) => {
-                let index = context.proto.add_const(k.clone());
-                MASK_K | index
+                let index = context.add_const(k.clone());
+                context.proto.code_const(target, index)
             }
-            ExprResult::Reg(i) => i.reg,
-            ExprResult::Jump(j) => j.reg.reg,
-            _ => unreachable!(),
-        }
+            ExprResult::Nil => context.code_nil(target, 1),
+            ExprResult::True => context.proto.code_bool(target, true, 0),
+            ExprResult::False => context.proto.code_bool(target, false, 0),
+        };
+        target
     }
 
     pub fn resolve(&self, context: &mut ProtoContext) {
@@ -178,30 +298,406 @@ impl ExprResult {
             _ => (),
         };
     }
+
+    // same as `resolve`, without freeing the register of the result
+    pub fn discharge(&self, context: &mut ProtoContext) {
+        if let ExprResult::Jump(j) = self {
+            j.discharge(context);
+        }
+    }
+
+    pub fn free_reg(&self, context: &mut ProtoContext) {
+        match self {
+            ExprResult::Reg(r) => r.free(context),
+            ExprResult::Jump(j) => j.free_reg(context),
+            _ => (),
+        };
+    }
 }
 
 impl Compiler {
     pub fn new() -> Self {
+        Compiler::with_options(CompilerOptions::default())
+    }
+
+    pub fn with_options(options: CompilerOptions) -> Self {
         Compiler {
             debug: false,
+            options,
             proto_contexts: Vec::new(),
+            resolutions: Resolutions::default(),
+            stats: Vec::new(),
+            warnings: Vec::new(),
+            source_map: SourceMap::new(),
+            line: 0,
+            function: String::new(),
         }
     }
 
+    // stats of functions compiled by the last run
+    pub fn stats(&self) -> &[FunctionStats] {
+        &self.stats
+    }
+
+    // warnings of the last run, e.g. functions above the register threshold
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // source of instructions compiled by the last run
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
     pub fn run(&mut self, block: &Block) -> CompileResult {
+        self.stats.clear();
+        self.warnings.clear();
+        self.source_map.clear();
+        // contexts left by a failed run
+        self.proto_contexts.clear();
+        let mut checker = Checker::new();
+        checker.set_debug(self.debug);
+        self.resolutions = checker.run(block)?;
         self.main_func(block)
     }
 
     fn main_func(&mut self, block: &Block) -> CompileResult {
+        self.function = "main".to_string();
         self.push_proto();
         self.proto().open();
-        ast_walker::walk_block(block, self)?;
+        // main function is always vararg
+        self.proto().is_vararg = true;
+        // globals are fields of _ENV, which is always the first upvalue of the main function
+        let resolutions = std::mem::take(&mut self.resolutions);
+        for up_val in resolutions.main_up_values() {
+            self.proto().add_up_var(&up_val.name, up_val.in_stack, up_val.index);
+        }
+        self.resolutions = resolutions;
+        self.context().enter_block(false);
+        if let Err(e) = self.stat_list(block, false) {
+            let source = Source {
+                line: self.line as usize,
+                ..Source::new()
+            };
+            self.error(e, &source)?;
+        }
+        let mut proto = self.close_func(block, 1);
+        if !self.options.emit_debug_info {
+            proto.strip_debug_info();
+        }
+        Ok(proto)
+    }
+
+    // compile a function into a child of the current one, its closure is in `input` or a new
+    // register. `self` is the first param of methods
+    fn func_body(
+        &mut self,
+        body: &FuncBody,
+        method: bool,
+        input: Option<u32>,
+    ) -> Result<ExprResult, CompileError> {
+        let up_values = self.resolutions.up_values(body).to_vec();
+        // captured locals are closed when leaving their blocks
+        for up_val in up_values.iter().filter(|up_val| up_val.in_stack) {
+            self.context().mark_upval(up_val.index);
+        }
+        let index = self.proto().protos.len();
+        let name = format!("{}.{}", self.function, index);
+        let parent = std::mem::replace(&mut self.function, name);
+        self.push_proto();
+        let context = self.context();
+        for up_val in up_values.iter() {
+            context
+                .proto
+                .add_up_var(&up_val.name, up_val.in_stack, up_val.index);
+        }
+        context.enter_block(false);
+        if method {
+            context.add_local_var(Symbol::from("self"), false);
+        }
+        for param in body.params.iter() {
+            match param {
+                Param::Name(name) => context.add_local_var(name.clone(), false),
+                Param::VarArg => context.proto.is_vararg = true,
+            }
+        }
+        let nparams = context.nactvar();
+        context.proto.param_count = nparams;
+        context.reserve_regs(nparams);
+        self.stat_list(&body.block, false)?;
+        let proto = self.close_func(&body.block, self.line as usize);
+        self.function = parent;
+        self.proto().protos.push(proto);
+        let alloc_reg = self.alloc_reg(&input);
+        self.proto().code_closure(alloc_reg.reg, index as u32);
+        Ok(ExprResult::Reg(alloc_reg))
+    }
+
+    // end the function being compiled, the return at its end is at the line of its last
+    // statement or at `line`
+    fn close_func(&mut self, block: &Block, line: usize) -> Proto {
+        let pc = self.proto().code.len();
         self.proto().close();
-        Ok(self.pop_proto())
+        self.context().leave_block();
+        let pos = block
+            .stats
+            .last()
+            .map_or(SourcePos { line, col: 1 }, |stat| SourcePos {
+                line: stat.source.line,
+                col: stat.source.col,
+            });
+        self.proto().fix_line_info(pos.line as u32);
+        let end = self.proto().code.len();
+        self.source_map.add(&self.function, pc, end, pos);
+        let mut proto = self.pop_proto();
+        if self.options.propagate_constants {
+            proto.propagate_constants(self.options.string_coercion);
+        }
+        let name = self.function.clone();
+        self.add_stats(&name, &proto);
+        proto
+    }
+
+    // a block in its own scope, like `do ... end`
+    fn block(&mut self, block: &Block) -> Result<(), CompileError> {
+        self.context().enter_block(false);
+        self.stat_list(block, false)?;
+        self.context().leave_block();
+        Ok(())
+    }
+
+    // same as `walk_block`, and records the source of instructions of each statement. the locals
+    // of a block followed by `until` are still in scope at its end
+    fn stat_list(&mut self, block: &Block, until: bool) -> Result<(), CompileError> {
+        // code of the enclosing statement so far is on its line
+        let line = self.line;
+        self.proto().fix_line_info(line);
+        for (i, StatInfo { source, stat }) in block.stats.iter().enumerate() {
+            let pc = self.proto().code.len();
+            // errors are reported at the line of the innermost statement, which is kept then
+            let line = std::mem::replace(&mut self.line, source.line as u32);
+            match stat {
+                Stat::LabelStat(stat) => {
+                    let last = !until && block.stats[i + 1..].iter().all(|s| s.stat.is_void());
+                    self.label(stat, last);
+                }
+                _ => self.stat(stat)?,
+            }
+            self.line = line;
+            // registers of temporary values are free after each statement
+            let context = self.context();
+            context.reg_top = context.nactvar();
+            self.proto().fix_line_info(source.line as u32);
+            let pos = SourcePos {
+                line: source.line,
+                col: source.col,
+            };
+            let end = self.proto().code.len();
+            self.source_map.add(&self.function, pc, end, pos);
+        }
+        Ok(())
+    }
+
+    fn stat(&mut self, stat: &Stat) -> Result<(), CompileError> {
+        match stat {
+            Stat::DoBlock(stat) => self.block(&stat.block),
+            Stat::IfStat(stat) => self.if_stat(stat),
+            Stat::WhileStat(stat) => self.while_stat(stat),
+            Stat::ForStat(stat) => self.for_stat(stat),
+            Stat::RepeatStat(stat) => self.repeat_stat(stat),
+            Stat::FuncStat(stat) => self.func_stat(stat),
+            _ => ast_walker::walk_stat(stat, self),
+        }
+    }
+
+    // each condition is tested after the previous ones are false, and skips its block if it's
+    // false too. blocks jump to the end of the statement
+    fn if_stat(&mut self, stat: &IfStat) -> Result<(), CompileError> {
+        let mut escape = Vec::new();
+        for (i, cond_block) in stat.cond_blocks.iter().enumerate() {
+            let false_jumps = self.cond(&cond_block.cond)?;
+            self.block(&cond_block.block)?;
+            if i < stat.cond_blocks.len() - 1 || stat.else_block.is_some() {
+                escape.push(self.proto().code_jmp(NO_JUMP, 0));
+            }
+            self.fix_jumps_here(&false_jumps);
+        }
+        if let Some(block) = &stat.else_block {
+            self.block(block)?;
+        }
+        self.fix_jumps_here(&escape);
+        Ok(())
+    }
+
+    fn while_stat(&mut self, stat: &WhileStat) -> Result<(), CompileError> {
+        let init = self.proto().get_label();
+        let exit = self.cond(&stat.cond)?;
+        self.context().enter_block(true);
+        self.block(&stat.block)?;
+        let proto = self.proto();
+        let pc = proto.code_jmp(NO_JUMP, 0);
+        proto.fix_jump_pos(init, pc);
+        self.context().leave_block();
+        self.fix_jumps_here(&exit);
+        Ok(())
+    }
+
+    // the condition is in the scope of the locals of the body
+    fn repeat_stat(&mut self, stat: &RepeatStat) -> Result<(), CompileError> {
+        let init = self.proto().get_label();
+        self.context().enter_block(true);
+        self.context().enter_block(false);
+        self.stat_list(&stat.block, true)?;
+        let exit = self.cond(&stat.cond)?;
+        let context = self.context();
+        let block = context.blocks.last().unwrap();
+        // the locals are closed when the loop goes on too
+        if block.upval {
+            let nactvar = block.nactvar;
+            for pc in exit.iter() {
+                context.proto.patch_close(*pc, nactvar);
+            }
+        }
+        context.leave_block();
+        for pc in exit.iter() {
+            context.proto.fix_jump_pos(init, *pc);
+        }
+        context.leave_block();
+        Ok(())
+    }
+
+    // the loop block holds the internal locals, the loop vars are in a block of their own
+    fn for_stat(&mut self, stat: &ForStat) -> Result<(), CompileError> {
+        self.context().enter_block(true);
+        match stat {
+            ForStat::ForNum(stat) => self.for_num(stat)?,
+            ForStat::ForList(stat) => self.for_list(stat)?,
+        }
+        self.context().leave_block();
+        Ok(())
+    }
+
+    fn for_num(&mut self, stat: &ForNum) -> Result<(), CompileError> {
+        let base = self.context().get_reg_top();
+        self.expr_and_save(&stat.init, None)?;
+        self.expr_and_save(&stat.limit, None)?;
+        match &stat.step {
+            Some(step) => self.expr_and_save(step, None)?,
+            None => self.code_and_save(None, |_, _| Ok(ExprResult::new_const(Const::Int(1))))?,
+        };
+        let internals = ["(for index)", "(for limit)", "(for step)"];
+        let vars = std::slice::from_ref(&stat.var);
+        self.for_body(base, &internals, vars, &stat.body, true)
+    }
+
+    fn for_list(&mut self, stat: &ForList) -> Result<(), CompileError> {
+        let base = self.adjust_explist(&stat.exprs, 3)?;
+        // room to call the generator
+        self.context().check_stack(3);
+        let internals = ["(for generator)", "(for state)", "(for control)"];
+        self.for_body(base, &internals, &stat.vars, &stat.body, false)
+    }
+
+    // FORPREP or the JMP of a generic for goes to the end of the loop, which jumps back to the
+    // body while it goes on
+    fn for_body(
+        &mut self,
+        base: u32,
+        internals: &[&str],
+        vars: &[Symbol],
+        body: &Block,
+        numeric: bool,
+    ) -> Result<(), CompileError> {
+        let context = self.context();
+        for name in internals.iter() {
+            context.add_local_var(Symbol::from(*name), false);
+        }
+        let prep = if numeric {
+            context.proto.code_for_prep(base, NO_JUMP)
+        } else {
+            context.proto.code_jmp(NO_JUMP, 0)
+        };
+        context.enter_block(false);
+        for var in vars.iter() {
+            context.add_local_var(var.clone(), false);
+        }
+        context.reserve_regs(vars.len() as u32);
+        self.block(body)?;
+        self.context().leave_block();
+        let proto = self.proto();
+        proto.fix_jump_pos(proto.code.len(), prep);
+        let end = if numeric {
+            proto.code_for_loop(base, NO_JUMP)
+        } else {
+            proto.code_tfor_call(base, vars.len() as u32);
+            proto.code_tfor_loop(base + 2, NO_JUMP)
+        };
+        proto.fix_jump_pos(prep + 1, end);
+        self.check_limits()
+    }
+
+    // jumps taken when `expr` is false, else it goes on at the next instruction
+    fn cond(&mut self, expr: &Expr) -> Result<Vec<usize>, CompileError> {
+        let result = self.expr(expr, None)?;
+        let jumps = match result {
+            ExprResult::Const(_) | ExprResult::True => Vec::new(),
+            ExprResult::Nil | ExprResult::False => vec![self.proto().code_jmp(NO_JUMP, 0)],
+            ExprResult::Reg(reg) => {
+                reg.free(self.context());
+                let proto = self.proto();
+                proto.code_test(reg.reg, 0);
+                vec![proto.code_jmp(NO_JUMP, 0)]
+            }
+            ExprResult::Jump(mut j) => {
+                j.inverse_cond(self.context());
+                j.free_reg(self.context());
+                self.fix_jumps_here(&j.true_jumps);
+                j.false_jumps.push(j.pc);
+                j.false_jumps
+            }
+        };
+        Ok(jumps)
+    }
+
+    // a label at the end of a block is out of the scope of its locals
+    fn label(&mut self, stat: &LabelStat, last: bool) {
+        let context = self.context();
+        let nactvar = if last {
+            context.blocks.last().unwrap().nactvar
+        } else {
+            context.nactvar()
+        };
+        context.add_label(stat.label.clone(), nactvar);
+    }
+
+    fn add_stats(&mut self, name: &str, proto: &Proto) {
+        let stats = FunctionStats {
+            name: name.to_string(),
+            registers: proto.stack_size,
+            constants: proto.consts.len(),
+            instructions: proto.code.len(),
+        };
+        if self.debug {
+            println!("{:?}", stats);
+        }
+        match self.options.register_warning {
+            Some(threshold) if stats.registers > threshold => {
+                let warning = format!(
+                    "[compile warning] function {} uses {} registers, more than {}.",
+                    name, stats.registers, threshold
+                );
+                if self.debug {
+                    println!("{}", warning);
+                }
+                self.warnings.push(warning);
+            }
+            _ => (),
+        }
+        self.stats.push(stats);
     }
 
     fn push_proto(&mut self) {
-        self.proto_contexts.push(ProtoContext::new());
+        self.proto_contexts.push(ProtoContext::new(self.options));
     }
 
     fn pop_proto(&mut self) -> Proto {
@@ -224,50 +720,105 @@ impl Compiler {
         unreachable!()
     }
 
-    fn adjust_assign(&mut self, num_left: usize, right_exprs: &Vec<Expr>) -> i32 {
-        let extra = num_left as i32 - right_exprs.len() as i32;
+    // nil for the values missing on the right, unless the call or vararg at the end gives them
+    fn adjust_assign(&mut self, num_left: usize, right_exprs: &[Expr]) -> i32 {
         if let Some(last_expr) = right_exprs.last() {
             if last_expr.has_mult_ret() {
-                // TODO : process multi return value
-                todo!("process mult ret")
+                return 0;
             }
         }
 
+        let extra = num_left as i32 - right_exprs.len() as i32;
         if extra > 0 {
             let context = self.context();
             let from = context.get_reg_top();
             context.reserve_regs(extra as u32);
-            context.proto.code_nil(from, extra as u32);
+            context.code_nil(from, extra as u32);
         }
 
         extra
     }
 
+    // values of `exprs` adjusted to `n` in consecutive registers from the top, values past `n`
+    // are dropped. returns the first register
+    fn adjust_explist(&mut self, exprs: &[Expr], n: usize) -> Result<u32, CompileError> {
+        let first = self.context().get_reg_top();
+        for (i, expr) in exprs.iter().enumerate() {
+            if i == exprs.len() - 1 && expr.has_mult_ret() {
+                // the call or vararg at the end gives the rest of the values
+                self.code_mult_ret(expr, Some(n.saturating_sub(i) as u32))?;
+            } else {
+                self.expr_and_save(expr, None)?;
+            }
+        }
+        self.adjust_assign(n, exprs);
+        self.context().reg_top = first + n as u32;
+        Ok(first)
+    }
+
+    // values of `exprs` in consecutive registers from the top, the results of a call or vararg
+    // at the end are all left up to the top. returns the number of values, none in that case
+    fn explist(&mut self, exprs: &[Expr]) -> Result<Option<u32>, CompileError> {
+        let (last, init) = match exprs.split_last() {
+            Some(split) => split,
+            None => return Ok(Some(0)),
+        };
+        for expr in init.iter() {
+            self.expr_and_save(expr, None)?;
+        }
+        if last.has_mult_ret() {
+            self.code_mult_ret(last, None)?;
+            Ok(None)
+        } else {
+            self.expr_and_save(last, None)?;
+            Ok(Some(exprs.len() as u32))
+        }
+    }
+
+    // a call or vararg with `n` results in registers from the top, or all of them up to the top
+    // with none. returns the register of the first result
+    fn code_mult_ret(&mut self, expr: &Expr, n: Option<u32>) -> Result<u32, CompileError> {
+        match expr {
+            Expr::SuffixedExpr(expr) => self.code_call(&expr.primary, &expr.suffixes, None, n),
+            Expr::VarArg => {
+                let context = self.context();
+                let reg = context.get_reg_top();
+                context.proto.code_vararg(reg, n);
+                match n {
+                    Some(n) => context.reserve_regs(n),
+                    // the stack has room for the first value at least
+                    None => {
+                        context.check_stack(1);
+                        reg
+                    }
+                };
+                Ok(reg)
+            }
+            _ => unreachable!(),
+        }
+    }
+
     // process expr and return const index or register index
     fn expr(&mut self, expr: &Expr, reg: Option<u32>) -> Result<ExprResult, CompileError> {
-        let proto = self.proto();
         let result = match expr {
             Expr::Int(i) => ExprResult::new_const(Const::Int(*i)),
             Expr::Float(f) => ExprResult::new_const(Const::Float(*f)),
-            Expr::String(s) => {
-                // const string will always be added to consts
-                let k = Const::Str(s.clone());
-                proto.add_const(k.clone());
-                ExprResult::new_const(k)
-            }
+            // added to consts where it's used, like other constants
+            Expr::String(s) => ExprResult::new_const(Const::Str(s.clone())),
             Expr::Nil => ExprResult::Nil,
             Expr::True => ExprResult::True,
             Expr::False => ExprResult::False,
-            Expr::Name(name) => {
-                if let Some(src) = proto.get_local_var(name) {
-                    return Ok(ExprResult::new_const_reg(src));
-                }
-                // TODO : process upval and globals
-                todo!()
-            }
+            Expr::Name(name) => self.code_name(name, reg),
             Expr::BinExpr(_) | Expr::UnExpr(_) => self.folding_or_code(expr, reg)?,
-            Expr::ParenExpr(expr) => self.folding_or_code(&expr, reg)?,
-            _ => todo!(),
+            Expr::SuffixedExpr(expr) => self.code_index(&expr.primary, &expr.suffixes, reg)?,
+            Expr::ParenExpr(expr) => self.expr(&expr, reg)?,
+            Expr::VarArg => {
+                let alloc_reg = self.alloc_reg(&reg);
+                self.proto().code_vararg(alloc_reg.reg, Some(1));
+                ExprResult::Reg(alloc_reg)
+            }
+            Expr::FuncBody(body) => self.func_body(body, false, reg)?,
+            Expr::Table(table) => self.code_table(table, reg)?,
         };
         Ok(result)
     }
@@ -287,6 +838,9 @@ impl Compiler {
 
     // try constant folding expr
     fn try_const_folding(&self, expr: &Expr) -> Result<Option<Const>, CompileError> {
+        if !self.options.fold_constants {
+            return Ok(None);
+        }
         match expr {
             Expr::Int(i) => return success!(Const::Int(*i)),
             Expr::Float(f) => return success!(Const::Float(*f)),
@@ -335,6 +889,11 @@ impl Compiler {
         match expr {
             Expr::BinExpr(bin) => match bin.op {
                 BinOp::And => self.code_and(reg, &bin.left, &bin.right),
+                BinOp::Or => self.code_or(reg, &bin.left, &bin.right),
+                BinOp::Concat => {
+                    let first = self.expr_and_save(&bin.left, None)?;
+                    self.code_concat(reg, first, &bin.right)
+                }
                 _ => self.code_bin_op(bin.op, reg, &bin.left, &bin.right),
             },
             Expr::UnExpr(un) => {
@@ -355,6 +914,17 @@ impl Compiler {
         l: Const,
         r: Const,
     ) -> Result<Option<Const>, CompileError> {
+        let (l, r) = if self.options.string_coercion {
+            (l.coerce_to_number(), r.coerce_to_number())
+        } else {
+            (l, r)
+        };
+        if self.options.strict_div_by_zero
+            && (op == BinOp::IDiv || op == BinOp::Mod)
+            && matches!((&l, &r), (Const::Int(_), Const::Int(0)))
+        {
+            return Err(CompileError::new("divide by zero"));
+        }
         let result = match op {
             BinOp::Add => l.add(r)?,
             BinOp::Minus => l.sub(r)?,
@@ -413,8 +983,25 @@ impl Compiler {
     ) -> Result<ExprResult, CompileError> {
         // get left expr result
         let left = self.expr(left_expr, input)?;
-        // resolve previous expr result
-        left.resolve(self.context());
+        self.code_bin_op_with_left(op, input, left, right_expr)
+    }
+
+    // same as `code_bin_op`, with the left operand already generated
+    fn code_bin_op_with_left(
+        &mut self,
+        op: BinOp,
+        input: Option<u32>,
+        left: ExprResult,
+        right_expr: &Expr,
+    ) -> Result<ExprResult, CompileError> {
+        if op == BinOp::Concat {
+            left.resolve(self.context());
+            let first = self.code_and_save(None, |_, _| Ok(left))?;
+            return self.code_concat(input, first, right_expr);
+        }
+
+        // the register of left is kept until right is evaluated
+        left.discharge(self.context());
 
         // if input reg is not used by left expr, apply it to right expr
         let right_input = self.get_right_input(input, &left);
@@ -422,14 +1009,16 @@ impl Compiler {
         // get right expr result
         let right = self.expr(right_expr, right_input)?;
 
-        // resolve previous expr result
+        // resolve previous expr results
         right.resolve(self.context());
+        left.free_reg(self.context());
 
         let alloc_reg = self.alloc_reg(&input);
         let reg = alloc_reg.reg;
         let mut result = ExprResult::Reg(alloc_reg);
 
         // get rk of left and right expr
+        let top = self.context().get_reg_top();
         let mut get_rk = || {
             let left_rk = left.get_rk(self.context());
             let right_rk = right.get_rk(self.context());
@@ -448,6 +1037,10 @@ impl Compiler {
             }
         };
 
+        // free temp registers of large constants
+        let context = self.context();
+        context.free_reg(context.get_reg_top() - top);
+
         Ok(result)
     }
 
@@ -469,6 +1062,31 @@ impl Compiler {
         }
     }
 
+    // R(A) := R(B) .. ... .. R(C), the left operand is saved in `first` and the others of a
+    // chain like `a .. b .. c` in the registers after it
+    fn code_concat(
+        &mut self,
+        input: Option<u32>,
+        first: u32,
+        right_expr: &Expr,
+    ) -> Result<ExprResult, CompileError> {
+        let mut right_expr = right_expr;
+        let last = loop {
+            match right_expr {
+                Expr::BinExpr(bin) if bin.op == BinOp::Concat => {
+                    self.expr_and_save(&bin.left, None)?;
+                    right_expr = &bin.right;
+                }
+                _ => break self.expr_and_save(right_expr, None)?,
+            }
+        };
+        self.context().free_reg(last - first + 1);
+        let alloc_reg = self.alloc_reg(&input);
+        self.proto().code_concat(alloc_reg.reg, first, last);
+        Ok(ExprResult::Reg(alloc_reg))
+    }
+
+    // `left and right` is left if it's false, else right, which is only evaluated then
     fn code_and(
         &mut self,
         input: Option<u32>,
@@ -476,46 +1094,379 @@ impl Compiler {
         right_expr: &Expr,
     ) -> Result<ExprResult, CompileError> {
         // get left expr result
-        let mut left = self.expr(left_expr, input)?;
-        match &mut left {
+        let left = self.expr(left_expr, input)?;
+        match left {
             // do const folding if left is const value
             ExprResult::True | ExprResult::Const(_) => self.expr(right_expr, input),
-            ExprResult::Jump(j) => {
+            ExprResult::Nil | ExprResult::False => Ok(left),
+            ExprResult::Jump(mut j) => {
                 j.inverse_cond(self.context());
+                // right is evaluated when left is true
+                self.fix_jumps_here(&j.true_jumps);
+                j.true_jumps.clear();
                 let mut right = self.expr(right_expr, Some(j.reg.reg))?;
                 match &mut right {
-                    ExprResult::Jump(rj) => rj.concat_false_jumps(j),
-                    _ => todo!(),
-                };
-                Ok(right)
+                    ExprResult::Jump(rj) => {
+                        rj.concat_false_jumps(&mut j);
+                        Ok(right)
+                    }
+                    _ => Ok(self.code_cond_or_value(j, right, false)),
+                }
+            }
+            ExprResult::Reg(_) => self.code_test(input, left, right_expr, false),
+        }
+    }
+
+    // `left or right` is left if it's true, else right, which is only evaluated then
+    fn code_or(
+        &mut self,
+        input: Option<u32>,
+        left_expr: &Expr,
+        right_expr: &Expr,
+    ) -> Result<ExprResult, CompileError> {
+        let left = self.expr(left_expr, input)?;
+        match left {
+            ExprResult::True | ExprResult::Const(_) => Ok(left),
+            ExprResult::Nil | ExprResult::False => self.expr(right_expr, input),
+            ExprResult::Jump(mut j) => {
+                // right is evaluated when left is false
+                self.fix_jumps_here(&j.false_jumps);
+                j.false_jumps.clear();
+                let mut right = self.expr(right_expr, Some(j.reg.reg))?;
+                match &mut right {
+                    ExprResult::Jump(rj) => {
+                        rj.concat_true_jumps(&mut j);
+                        Ok(right)
+                    }
+                    _ => Ok(self.code_cond_or_value(j, right, true)),
+                }
             }
-            ExprResult::Reg(reg) => self.code_test(input, left, right_expr),
-            _ => todo!(),
+            ExprResult::Reg(_) => self.code_test(input, left, right_expr, true),
+        }
+    }
+
+    // jumps which continue at the next instruction
+    fn fix_jumps_here(&mut self, jumps: &[usize]) {
+        let proto = self.proto();
+        for pc in jumps.iter() {
+            proto.fix_jump_pos(proto.code.len(), *pc);
+        }
+    }
+
+    // a condition `left` and a value `right` of `and`, or of `or` if `value` is true. the jumps
+    // of left which decide the result load `value`, else right is the result
+    fn code_cond_or_value(&mut self, left: Jump, right: ExprResult, value: bool) -> ExprResult {
+        let reg = left.reg.reg;
+        right.resolve(self.context());
+        let src = right.get_reg(self.context(), reg);
+        let proto = self.proto();
+        if src != reg {
+            proto.code_move(reg, src);
         }
+        let skip = proto.code_jmp(NO_JUMP, 0);
+        let pos = proto.code_bool(reg, value, 0);
+        let jumps = if value {
+            &left.true_jumps
+        } else {
+            &left.false_jumps
+        };
+        for pc in jumps.iter().chain(std::iter::once(&left.pc)) {
+            proto.fix_jump_pos(pos, *pc);
+        }
+        proto.fix_jump_pos(pos + 1, skip);
+        Self::value_reg(&left.reg)
     }
 
+    // `left and right`, or `left or right` with `or`, for a value of left in a register. TESTSET
+    // copies left if it's the result, else right is evaluated to the same register
     fn code_test(
         &mut self,
         input: Option<u32>,
         left: ExprResult,
-        right: &Expr,
+        right_expr: &Expr,
+        or: bool,
     ) -> Result<ExprResult, CompileError> {
-        match &left {
-            ExprResult::Reg(r) => {
-                let proto = self.proto();
-                proto.code_test_set(NO_REG, r.reg, 0);
-                let jump = proto.code_jmp(NO_JUMP, 0);
-                let right_input = self.get_right_input(input, &left);
-                let right_result = self.expr(right, right_input)?;
-                let mut jump = Jump::new(self.alloc_reg(&input), jump);
-                match &right_result {
-                    ExprResult::Reg(r) if r.is_const() => jump.set_reg_should_move(r.reg),
-                    _ => (),
-                };
-                Ok(ExprResult::Jump(jump))
+        left.resolve(self.context());
+        let alloc_reg = self.alloc_reg(&input);
+        let reg = alloc_reg.reg;
+        let src = left.get_reg(self.context(), reg);
+        let proto = self.proto();
+        if src == reg {
+            proto.code_test(reg, or as u32);
+        } else {
+            proto.code_test_set(reg, src, or as u32);
+        }
+        let skip = proto.code_jmp(NO_JUMP, 0);
+        let right = self.expr(right_expr, Some(reg))?;
+        right.resolve(self.context());
+        let src = right.get_reg(self.context(), reg);
+        let proto = self.proto();
+        if src != reg {
+            proto.code_move(reg, src);
+        }
+        proto.fix_jump_pos(proto.code.len(), skip);
+        Ok(Self::value_reg(&alloc_reg))
+    }
+
+    // a result in `reg` set by several instructions, which is moved rather than retargeted when
+    // it's saved
+    fn value_reg(reg: &Reg) -> ExprResult {
+        ExprResult::Reg(Reg {
+            reg: reg.reg,
+            temp: reg.temp,
+            mutable: false,
+        })
+    }
+
+    // R(A) := table[key] for each suffix after the last call, if there is one
+    fn code_index(
+        &mut self,
+        primary: &Expr,
+        suffixes: &[Suffix],
+        input: Option<u32>,
+    ) -> Result<ExprResult, CompileError> {
+        let call = suffixes
+            .iter()
+            .rposition(|suffix| matches!(suffix, Suffix::FuncArgs(_)));
+        let (table, suffixes) = match call {
+            Some(i) => {
+                let base = self.code_call(primary, &suffixes[..=i], input, Some(1))?;
+                // the result is in the register of the function, which is moved when it's saved
+                let result = ExprResult::Reg(Reg {
+                    reg: base,
+                    temp: Some(base) != input,
+                    mutable: false,
+                });
+                (result, &suffixes[i + 1..])
             }
+            None => (self.expr(primary, input)?, suffixes),
+        };
+        if suffixes.is_empty() {
+            return Ok(table);
+        }
+        table.resolve(self.context());
+        let alloc_reg = self.alloc_reg(&input);
+        let reg = alloc_reg.reg;
+        let mut table_reg = table.get_reg(self.context(), reg);
+        for suffix in suffixes.iter() {
+            let top = self.context().get_reg_top();
+            let key = self.index_key(suffix)?;
+            self.proto().code_get_table(reg, table_reg, key);
+            let context = self.context();
+            context.free_reg(context.get_reg_top() - top);
+            table_reg = reg;
+        }
+        Ok(ExprResult::Reg(alloc_reg))
+    }
+
+    // RK of table key, constant keys like `t.x`, `t[1]` or `t[2 * 3]` are encoded in the instruction.
+    // caller should free the temp register it may use.
+    fn index_key(&mut self, suffix: &Suffix) -> Result<u32, CompileError> {
+        let key = match suffix {
+            Suffix::Attr(attr) => Const::Str(attr.to_string()),
+            Suffix::Index(expr) => match self.try_const_folding(expr)? {
+                Some(k) => k,
+                None => match expr {
+                    Expr::Int(i) => Const::Int(*i),
+                    Expr::Float(f) => Const::Float(*f),
+                    Expr::String(s) => Const::Str(s.clone()),
+                    Expr::True => Const::Bool(true),
+                    Expr::False => Const::Bool(false),
+                    Expr::Name(name) => match self.resolutions.get(name) {
+                        Some(Resolution::Local(reg)) => return Ok(reg),
+                        _ => return self.expr_and_save(expr, None),
+                    },
+                    _ => return self.expr_and_save(expr, None),
+                },
+            },
+            Suffix::Method(_) | Suffix::FuncArgs(_) => {
+                return Err(CompileError::new("function arguments expected"))
+            }
+        };
+        Ok(ExprResult::new_const(key).get_rk(self.context()))
+    }
+
+    // R(A), ..., R(A + C - 2) := R(A)(R(A + 1), ..., R(A + B - 1)) for `suffixes` ending with
+    // the arguments, which are in registers after the function. `nresults` results are left from
+    // the register of the function, or all of them up to the top with none. the last register
+    // `input` is reused for the function, else it's in a new one. returns the register
+    fn code_call(
+        &mut self,
+        primary: &Expr,
+        suffixes: &[Suffix],
+        input: Option<u32>,
+        nresults: Option<u32>,
+    ) -> Result<u32, CompileError> {
+        let (args, prefix) = match suffixes.split_last() {
+            Some((Suffix::FuncArgs(args), prefix)) => (args, prefix),
             _ => unreachable!(),
+        };
+        let top = self.context().get_reg_top();
+        let base = match input {
+            Some(reg) if reg + 1 == top => reg,
+            _ => self.context().reserve_regs(1),
+        };
+        match prefix.split_last() {
+            // R(A + 1) := R(B), R(A) := R(B)[name]
+            Some((Suffix::Method(name), prefix)) => {
+                let table = self.code_index(primary, prefix, Some(base))?;
+                table.resolve(self.context());
+                let table = table.get_reg(self.context(), base);
+                let context = self.context();
+                context.reserve_regs(1);
+                let top = context.get_reg_top();
+                let key = ExprResult::new_const(Const::Str(name.to_string())).get_rk(context);
+                context.proto.code_self(base, table, key);
+                context.free_reg(context.get_reg_top() - top);
+            }
+            _ => {
+                let func = self.code_index(primary, prefix, Some(base))?;
+                self.move_to_reg(func, base);
+            }
+        }
+        let nargs = match args {
+            FuncArgs::Exprs(exprs) => self.explist(exprs)?,
+            FuncArgs::String(s) => {
+                let arg = ExprResult::new_const(Const::Str(s.clone()));
+                self.code_and_save(None, |_, _| Ok(arg))?;
+                Some(1)
+            }
+            FuncArgs::Table(table) => {
+                self.code_table(table, None)?;
+                Some(1)
+            }
+        };
+        // self of a method is an argument too
+        let top = self.context().get_reg_top();
+        let nargs = nargs.map(|_| top - base - 1);
+        self.proto().code_call(base, nargs, nresults);
+        let context = self.context();
+        context.reg_top = base;
+        context.reserve_regs(nresults.unwrap_or(0));
+        Ok(base)
+    }
+
+    // NEWTABLE, then list items are stored by SETLIST from the registers after the table every
+    // FIELDS_PER_FLUSH items, and record fields are set one by one. the last register `input` is
+    // reused for the table, else it's in a new one
+    fn code_table(
+        &mut self,
+        table: &Table,
+        input: Option<u32>,
+    ) -> Result<ExprResult, CompileError> {
+        let top = self.context().get_reg_top();
+        let reg = match input {
+            Some(reg) if reg + 1 == top => reg,
+            _ => self.context().reserve_regs(1),
+        };
+        let pc = self.proto().code_new_table(reg);
+        let (mut narray, mut nhash, mut pending) = (0, 0, 0);
+        for (i, field) in table.fields.iter().enumerate() {
+            match field {
+                // the call or vararg at the end gives all its results
+                Field::ListField(expr) if i == table.fields.len() - 1 && expr.has_mult_ret() => {
+                    self.code_mult_ret(expr, None)?;
+                    let block = (narray / FIELDS_PER_FLUSH + 1) as u32;
+                    self.proto().code_set_list(reg, None, block);
+                    pending = 0;
+                }
+                Field::ListField(expr) => {
+                    self.expr_and_save(expr, None)?;
+                    narray += 1;
+                    pending += 1;
+                    if pending == FIELDS_PER_FLUSH {
+                        self.flush_list(reg, narray, pending);
+                        pending = 0;
+                    }
+                }
+                Field::RecFileld(field) => {
+                    nhash += 1;
+                    let top = self.context().get_reg_top();
+                    let key = match &field.key {
+                        FieldKey::Name(name) => ExprResult::new_const(Const::Str(name.to_string())),
+                        FieldKey::Expr(expr) => self.expr(expr, None)?,
+                    };
+                    key.discharge(self.context());
+                    let value = self.expr(&field.value, None)?;
+                    value.discharge(self.context());
+                    let context = self.context();
+                    let key = key.get_rk(context);
+                    let value = value.get_rk(context);
+                    context.proto.code_set_table(reg, key, value);
+                    context.reg_top = top;
+                }
+            }
         }
+        if pending > 0 {
+            self.flush_list(reg, narray, pending);
+        }
+        let context = self.context();
+        context.reg_top = reg + 1;
+        context.proto.set_table_size(pc, narray, nhash);
+        Ok(ExprResult::Reg(Reg {
+            reg,
+            temp: Some(reg) != input,
+            mutable: false,
+        }))
+    }
+
+    // store the last `pending` of `narray` list items of the table in `reg`
+    fn flush_list(&mut self, reg: u32, narray: usize, pending: usize) {
+        let block = ((narray - 1) / FIELDS_PER_FLUSH + 1) as u32;
+        self.proto().code_set_list(reg, Some(pending as u32), block);
+        self.context().reg_top = reg + 1;
+    }
+
+    // resolve a result and move it to `reg`
+    fn move_to_reg(&mut self, result: ExprResult, reg: u32) {
+        result.resolve(self.context());
+        let src = result.get_reg(self.context(), reg);
+        if src != reg {
+            self.proto().code_move(reg, src);
+        }
+    }
+
+    // value of a name, locals are used from their registers
+    fn code_name(&mut self, name: &Symbol, input: Option<u32>) -> ExprResult {
+        match self.resolutions.get(name) {
+            Some(Resolution::Local(src)) => ExprResult::new_const_reg(src),
+            Some(Resolution::UpValue(index)) => {
+                let alloc_reg = self.alloc_reg(&input);
+                self.proto().code_get_up_val(alloc_reg.reg, index);
+                ExprResult::Reg(alloc_reg)
+            }
+            Some(Resolution::Global(env)) => self.code_get_global(name, env, input),
+            None => unreachable!(),
+        }
+    }
+
+    // R(A) := _ENV[name]
+    fn code_get_global(&mut self, name: &str, env: Env, input: Option<u32>) -> ExprResult {
+        let alloc_reg = self.alloc_reg(&input);
+        let reg = alloc_reg.reg;
+        let top = self.context().get_reg_top();
+        let context = self.context();
+        let key = ExprResult::new_const(Const::Str(name.to_string())).get_rk(context);
+        match env {
+            Env::Local(env) => context.proto.code_get_table(reg, env, key),
+            Env::UpValue(env) => context.proto.code_get_tab_up(reg, env, key),
+        };
+
+        // free temp register of large constant
+        context.free_reg(context.get_reg_top() - top);
+        ExprResult::Reg(alloc_reg)
+    }
+
+    // _ENV[name] := R(src)
+    fn code_set_global(&mut self, name: &str, env: Env, src: u32) {
+        let top = self.context().get_reg_top();
+        let context = self.context();
+        let key = ExprResult::new_const(Const::Str(name.to_string())).get_rk(context);
+        match env {
+            Env::Local(env) => context.proto.code_set_table(env, key, src),
+            Env::UpValue(env) => context.proto.code_set_tab_up(env, key, src),
+        };
+        context.free_reg(context.get_reg_top() - top);
     }
 
     fn code_un_op(
@@ -524,8 +1475,6 @@ impl Compiler {
         input: Option<u32>,
         expr: ExprResult,
     ) -> Result<ExprResult, CompileError> {
-        let src = expr.get_rk(self.context());
-
         // resolve previous result
         expr.resolve(self.context());
 
@@ -533,6 +1482,9 @@ impl Compiler {
         let reg = alloc_reg.reg;
         let result = ExprResult::Reg(alloc_reg);
 
+        // unary ops only accept register operands
+        let src = expr.get_reg(self.context(), reg);
+
         // gennerate opcode of unop
         let proto = self.proto();
         proto.code_un_op(op, reg, src);
@@ -544,10 +1496,10 @@ impl Compiler {
         if let Some(_) = self.try_const_folding(expr)? {
             Ok(ExprResult::False)
         } else {
-            let result = self.expr(expr, input)?;
-            match &result {
+            let mut result = self.expr(expr, input)?;
+            match &mut result {
                 ExprResult::Jump(j) => {
-                    j.inverse_cond(self.context());
+                    j.negate(self.context());
                     Ok(result)
                 }
                 ExprResult::Nil | ExprResult::False => Ok(ExprResult::True),
@@ -559,6 +1511,15 @@ impl Compiler {
 
     // process expr and save to register
     fn expr_and_save(&mut self, expr: &Expr, save_reg: Option<u32>) -> Result<u32, CompileError> {
+        self.code_and_save(save_reg, |compiler, temp_reg| compiler.expr(expr, Some(temp_reg)))
+    }
+
+    // generate code of an expr by `f` and save the result to register
+    fn code_and_save(
+        &mut self,
+        save_reg: Option<u32>,
+        f: impl FnOnce(&mut Self, u32) -> Result<ExprResult, CompileError>,
+    ) -> Result<u32, CompileError> {
         let reg = save_reg.unwrap_or_else(|| self.context().reserve_regs(1));
 
         // use a register to store temp result
@@ -568,20 +1529,21 @@ impl Compiler {
             self.context().reserve_regs(1)
         };
 
-        let result = self.expr(expr, Some(temp_reg))?;
-        let proto = self.proto();
+        let result = f(self, temp_reg)?;
+        let context = self.context();
         match result {
             ExprResult::Const(k) => {
-                let index = proto.add_const(k);
-                proto.code_const(reg, index)
-            }
-            ExprResult::Reg(src) if src.is_const() => proto.code_move(reg, src.reg),
-            ExprResult::Reg(_) => proto.save(reg),
-            ExprResult::True => proto.code_bool(reg, true, 0),
-            ExprResult::False => proto.code_bool(reg, false, 0),
-            ExprResult::Nil => proto.code_nil(reg, 1),
+                let index = context.add_const(k);
+                context.proto.code_const(reg, index)
+            }
+            ExprResult::Reg(src) if src.reg == reg => 0,
+            ExprResult::Reg(src) if src.is_const() => context.proto.code_move(reg, src.reg),
+            ExprResult::Reg(_) => context.proto.save(reg),
+            ExprResult::True => context.proto.code_bool(reg, true, 0),
+            ExprResult::False => context.proto.code_bool(reg, false, 0),
+            ExprResult::Nil => context.code_nil(reg, 1),
             ExprResult::Jump(j) => {
-                j.free(self.context());
+                j.free(context);
                 0
             }
         };
@@ -593,11 +1555,203 @@ impl Compiler {
         Ok(reg)
     }
 
-    fn get_assinable_reg(&mut self, assignable: &Assignable) -> u32 {
-        match assignable {
-            Assignable::Name(name) => self.proto().get_local_var(name).unwrap(),
-            Assignable::ParenExpr(_) => todo!(),
-            Assignable::SuffixedExpr(_) => todo!(),
+    // same limits as PUC lua, registers and constant indices beyond them can't be encoded
+    fn check_limits(&mut self) -> Result<(), CompileError> {
+        let proto = self.proto();
+        if proto.stack_size >= MAX_REGS {
+            return Err(CompileError::new(
+                "function or expression needs too many registers",
+            ));
+        }
+        if proto.consts.len() > MAXARG_AX as usize {
+            return Err(CompileError(format!(
+                "too many constants (limit is {}) in main function",
+                MAXARG_AX
+            )));
+        }
+        Ok(())
+    }
+
+    // const and to-be-closed locals can't be assigned
+    fn check_readonly(&mut self, left: &[Assignable]) -> Result<(), CompileError> {
+        for assignable in left.iter() {
+            if let Assignable::Name(name) = assignable {
+                self.check_readonly_name(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_readonly_name(&self, name: &Symbol) -> Result<(), CompileError> {
+        let level = self.proto_contexts.len() - 1;
+        let readonly = match self.resolutions.get(name) {
+            Some(Resolution::Local(reg)) => self.is_readonly_var(level, reg),
+            Some(Resolution::UpValue(index)) => self.is_readonly_up_val(level, index),
+            _ => false,
+        };
+        if readonly {
+            return Err(CompileError(format!(
+                "attempt to assign to const variable '{}'",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    fn is_readonly_var(&self, level: usize, reg: u32) -> bool {
+        self.proto_contexts[level].is_readonly_local_var(reg)
+    }
+
+    // an up value is readonly if the local it captures is, through the functions in between
+    fn is_readonly_up_val(&self, level: usize, index: u32) -> bool {
+        let up_val = &self.proto_contexts[level].proto.up_vars[index as usize];
+        match level.checked_sub(1) {
+            // _ENV of the main function
+            None => false,
+            Some(parent) if up_val.in_stack => self.is_readonly_var(parent, up_val.index),
+            Some(parent) => self.is_readonly_up_val(parent, up_val.index),
+        }
+    }
+
+    // `function a.b:m() end` sets field `m` of `a.b`. a local function is in scope of its body,
+    // but its register is only set by the closure
+    fn func_stat(&mut self, stat: &FuncStat) -> Result<(), CompileError> {
+        let FuncName { fields, method } = &stat.func_name;
+        if stat.func_type == FuncType::Local {
+            let reg = self.context().reserve_regs(1);
+            self.func_body(&stat.body, false, Some(reg))?;
+            self.context().add_local_var(fields[0].clone(), false);
+            return self.check_limits();
+        }
+        let (key, path) = match method {
+            Some(method) => (method, &fields[..]),
+            None => (&fields[fields.len() - 1], &fields[..fields.len() - 1]),
+        };
+        let target = match path.split_first() {
+            None => {
+                self.check_readonly_name(key)?;
+                self.name_target(key)
+            }
+            Some((first, path)) => {
+                let (mut table, mut temp) = match self.code_name(first, None) {
+                    ExprResult::Reg(reg) => (reg.reg, reg.temp),
+                    _ => unreachable!(),
+                };
+                for name in path.iter() {
+                    let dst = if temp {
+                        table
+                    } else {
+                        self.context().reserve_regs(1)
+                    };
+                    let context = self.context();
+                    let top = context.get_reg_top();
+                    let key = ExprResult::new_const(Const::Str(name.to_string())).get_rk(context);
+                    context.proto.code_get_table(dst, table, key);
+                    context.free_reg(context.get_reg_top() - top);
+                    table = dst;
+                    temp = true;
+                }
+                let key = ExprResult::new_const(Const::Str(key.to_string()));
+                AssignTarget::Index(table, key.get_rk(self.context()))
+            }
+        };
+        let input = match target {
+            AssignTarget::Local(reg) => Some(reg),
+            _ => None,
+        };
+        let closure = self.func_body(&stat.body, method.is_some(), input)?;
+        if input.is_none() {
+            let reg = closure.get_reg(self.context(), 0);
+            self.code_assign(&target, reg);
+        }
+        self.check_limits()
+    }
+
+    fn name_target(&self, name: &Symbol) -> AssignTarget {
+        match self.resolutions.get(name) {
+            Some(Resolution::Local(reg)) => AssignTarget::Local(reg),
+            Some(Resolution::UpValue(index)) => AssignTarget::UpValue(index),
+            Some(Resolution::Global(env)) => AssignTarget::Global(name.clone(), env),
+            None => unreachable!(),
+        }
+    }
+
+    // registers used by table and key of an index target stay reserved until the end of the statement
+    fn get_assign_target(&mut self, assignable: &Assignable) -> Result<AssignTarget, CompileError> {
+        let target = match assignable {
+            Assignable::Name(name) => self.name_target(name),
+            Assignable::ParenExpr(_) => {
+                return Err(CompileError::new(
+                    "cannot assign to a parenthesized expression",
+                ))
+            }
+            Assignable::SuffixedExpr(expr) => {
+                let (last, prefix) = match expr.suffixes.split_last() {
+                    Some((last @ (Suffix::Attr(_) | Suffix::Index(_)), prefix)) => (last, prefix),
+                    _ => return Err(CompileError::new("cannot assign to a function call")),
+                };
+                let local = match &*expr.primary {
+                    Expr::Name(name) if prefix.is_empty() => match self.resolutions.get(name) {
+                        Some(Resolution::Local(reg)) => Some(reg),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let table = match local {
+                    Some(reg) => reg,
+                    None => self.code_and_save(None, |compiler, temp_reg| {
+                        compiler.code_index(&expr.primary, prefix, Some(temp_reg))
+                    })?,
+                };
+                AssignTarget::Index(table, self.index_key(last)?)
+            }
+        };
+        Ok(target)
+    }
+
+    // a local assigned in a multiple assignment may be used as table or key by a previous index target,
+    // e.g. `t[i], i = i, 2`, the previous target uses a copy of the local instead
+    fn check_conflict(&mut self, targets: &mut [Option<AssignTarget>]) {
+        for j in 0..targets.len() {
+            let local = match targets[j] {
+                Some(AssignTarget::Local(reg)) => reg,
+                _ => continue,
+            };
+            let mut copy = None;
+            for target in targets[..j].iter_mut() {
+                if let Some(AssignTarget::Index(table, key)) = target {
+                    if *table != local && *key != local {
+                        continue;
+                    }
+                    let reg = *copy.get_or_insert_with(|| {
+                        let reg = self.context().reserve_regs(1);
+                        self.proto().code_move(reg, local);
+                        reg
+                    });
+                    if *table == local {
+                        *table = reg;
+                    }
+                    if *key == local {
+                        *key = reg;
+                    }
+                }
+            }
+        }
+    }
+
+    // move value in register `src` to assign target
+    fn code_assign(&mut self, target: &AssignTarget, src: u32) {
+        match target {
+            AssignTarget::Local(reg) => {
+                self.proto().code_move(*reg, src);
+            }
+            AssignTarget::UpValue(index) => {
+                self.proto().code_set_up_val(src, *index);
+            }
+            AssignTarget::Global(name, env) => self.code_set_global(name, *env, src),
+            AssignTarget::Index(table, key) => {
+                self.proto().code_set_table(*table, *key, src);
+            }
         }
     }
 
@@ -612,21 +1766,130 @@ impl AstVisitor<CompileError> for Compiler {
 
     // compile local stat
     fn local_stat(&mut self, stat: &LocalStat) -> Result<(), CompileError> {
-        let proto = self.proto();
-        for name in stat.names.iter() {
-            proto.add_local_var(name);
+        let first = self.adjust_explist(&stat.exprs, stat.names.len())?;
+        // locals come into scope after they are initialized
+        let context = self.context();
+        context.reg_top = first;
+        for (name, attrib) in stat.names.iter().zip(stat.attribs.iter()) {
+            context.add_local_var(name.clone(), attrib.is_some());
+            context.reserve_regs(1);
         }
-        for expr in stat.exprs.iter() {
-            self.expr_and_save(expr, None)?;
+        // closed when leaving the block of the variable
+        for (i, attrib) in stat.attribs.iter().enumerate() {
+            if *attrib == Some(Attrib::Close) {
+                let reg = first + i as u32;
+                self.proto().code_tbc(reg);
+                self.context().mark_upval(reg);
+            }
+        }
+        self.check_limits()
+    }
+
+    fn ret_stat(&mut self, stat: &RetStat) -> Result<(), CompileError> {
+        // a single local is returned from its register
+        if let [Expr::Name(name)] = stat.exprs.as_slice() {
+            if let Some(Resolution::Local(reg)) = self.resolutions.get(name) {
+                self.proto().code_return(reg, 1);
+                return Ok(());
+            }
         }
-        self.adjust_assign(stat.names.len(), &stat.exprs);
+        let first = self.context().get_reg_top();
+        match stat.exprs.as_slice() {
+            // the called function returns to the caller instead
+            [Expr::SuffixedExpr(expr)] if expr.has_mult_ret() => {
+                let base = self.code_call(&expr.primary, &expr.suffixes, None, None)?;
+                let proto = self.proto();
+                proto.set_tail_call(proto.code.len() - 1);
+                proto.code_return_all(base);
+            }
+            exprs => match self.explist(exprs)? {
+                Some(n) => {
+                    self.proto().code_return(first, n);
+                }
+                None => {
+                    self.proto().code_return_all(first);
+                }
+            },
+        }
+        self.check_limits()
+    }
+
+    // a goto to the end of the enclosing loop
+    fn break_stat(&mut self, _stat: &BreakStat) -> Result<(), CompileError> {
+        let context = self.context();
+        if !context.blocks.iter().any(|block| block.is_loop) {
+            return Err(CompileError::new("break outside a loop"));
+        }
+        let pc = context.proto.code_jmp(NO_JUMP, 0);
+        context.add_goto(Symbol::from("break"), pc);
+        Ok(())
+    }
+
+    fn goto_stat(&mut self, stat: &GotoStat) -> Result<(), CompileError> {
+        let context = self.context();
+        let pc = context.proto.code_jmp(NO_JUMP, 0);
+        context.add_goto(stat.label.clone(), pc);
         Ok(())
     }
 
+    fn call_stat(&mut self, stat: &CallStat) -> Result<(), CompileError> {
+        match &stat.call {
+            Assignable::SuffixedExpr(expr) if expr.has_mult_ret() => {
+                self.code_call(&expr.primary, &expr.suffixes, None, Some(0))?;
+                self.check_limits()
+            }
+            _ => Err(CompileError::new("syntax error")),
+        }
+    }
+
+    // compile `a op= b` as `a = a op b`
+    fn compound_assign_stat(&mut self, stat: &CompoundAssignStat) -> Result<(), CompileError> {
+        self.check_readonly(std::slice::from_ref(&stat.left))?;
+        let top = self.context().get_reg_top();
+        let target = self.get_assign_target(&stat.left)?;
+        let save_reg = match target {
+            AssignTarget::Local(reg) => Some(reg),
+            _ => None,
+        };
+        let reg = self.code_and_save(save_reg, |compiler, temp_reg| {
+            let left = match &target {
+                AssignTarget::Local(reg) => ExprResult::new_const_reg(*reg),
+                AssignTarget::UpValue(index) => {
+                    compiler.proto().code_get_up_val(temp_reg, *index);
+                    ExprResult::Reg(Reg::new(temp_reg))
+                }
+                AssignTarget::Global(name, env) => {
+                    compiler.code_get_global(name, *env, Some(temp_reg))
+                }
+                AssignTarget::Index(table, key) => {
+                    compiler.proto().code_get_table(temp_reg, *table, *key);
+                    ExprResult::Reg(Reg::new(temp_reg))
+                }
+            };
+            compiler.code_bin_op_with_left(stat.op, Some(temp_reg), left, &stat.right)
+        })?;
+        if save_reg.is_none() {
+            self.code_assign(&target, reg);
+        }
+        let context = self.context();
+        context.free_reg(context.get_reg_top() - top);
+        self.check_limits()
+    }
+
     // compile assign stat
     fn assign_stat(&mut self, stat: &AssignStat) -> Result<(), CompileError> {
+        self.check_readonly(&stat.left)?;
+
+        let top = self.context().get_reg_top();
         let use_temp_reg = stat.right.len() != stat.left.len();
-        let mut to_move: Vec<(u32, u32)> = Vec::new();
+        let mut to_move: Vec<(AssignTarget, u32)> = Vec::new();
+
+        // tables and keys of index targets are evaluated before the right side
+        let mut targets = Vec::with_capacity(stat.left.len());
+        for assignable in stat.left.iter() {
+            targets.push(Some(self.get_assign_target(assignable)?));
+        }
+        self.check_conflict(&mut targets);
 
         // move rules:
         // if num of left != num of right:
@@ -636,17 +1899,29 @@ impl AstVisitor<CompileError> for Compiler {
         //      MOVE temp[1..(n-1)] right[1..(n-1)]
         //      MOVE left[n] right[n]
         //      MOVE left[1..(n-1)] temp[1..(n-1)]
+        // globals and table fields are always set from registers by SETTABUP and SETTABLE
         for (i, expr) in stat.right.iter().enumerate() {
-            if i != stat.right.len() - 1 || use_temp_reg {
-                let reg = self.expr_and_save(expr, None)?;
-                if i < stat.left.len() {
-                    let target = self.get_assinable_reg(&stat.left[i]);
-                    to_move.push((target, reg));
+            if i == stat.right.len() - 1 && use_temp_reg && expr.has_mult_ret() {
+                // the call or vararg at the end gives the values of the rest of the targets
+                let n = stat.left.len().saturating_sub(i) as u32;
+                let reg = self.code_mult_ret(expr, Some(n))?;
+                for (j, target) in targets.iter_mut().skip(i).enumerate() {
+                    to_move.push((target.take().unwrap(), reg + j as u32));
                 }
-            } else {
-                let reg = self.get_assinable_reg(&stat.left[i]);
-                self.expr_and_save(expr, Some(reg))?;
-            };
+                break;
+            }
+            let target = targets.get_mut(i).and_then(|t| t.take());
+            match target {
+                Some(AssignTarget::Local(reg)) if i == stat.right.len() - 1 && !use_temp_reg => {
+                    self.expr_and_save(expr, Some(reg))?;
+                }
+                _ => {
+                    let reg = self.expr_and_save(expr, None)?;
+                    if let Some(target) = target {
+                        to_move.push((target, reg));
+                    }
+                }
+            }
         }
 
         // nil move
@@ -655,7 +1930,7 @@ impl AstVisitor<CompileError> for Compiler {
         if extra > 0 {
             let left_start = stat.left.len() as i32 - extra;
             for i in 0..extra {
-                let target = self.get_assinable_reg(&stat.left[(left_start + i) as usize]);
+                let target = targets[(left_start + i) as usize].take().unwrap();
                 let src = (reg as i32 + i) as u32;
                 to_move.push((target, src));
             }
@@ -663,15 +1938,13 @@ impl AstVisitor<CompileError> for Compiler {
 
         // apply moves
         for (target, src) in to_move.iter().rev() {
-            self.proto().code_move(*target, *src);
-            self.context().free_reg(1);
+            self.code_assign(target, *src);
         }
 
-        // free extra regs
-        if extra < 0 {
-            self.context().free_reg(-extra as u32);
-        }
+        // free temp and extra regs
+        let context = self.context();
+        context.free_reg(context.get_reg_top() - top);
 
-        Ok(())
+        self.check_limits()
     }
 }
@@ -1,11 +1,15 @@
 use crate::compiler::CompileError;
+use crate::lexer::Lexer;
 use crate::success;
-use crate::types::{FloatType, IntType};
+use crate::types::{FloatType, IntType, Number};
+use crate::value::{float_idiv, float_mod, float_to_int, int_idiv, int_mod, shift_left};
 use num_traits::Float;
 use std::hash::{Hash, Hasher};
 
 #[derive(Clone, PartialEq)]
 pub enum Const {
+    Nil,
+    Bool(bool),
     Int(IntType),
     Float(FloatType),
     Str(String),
@@ -16,6 +20,8 @@ impl Eq for Const {}
 impl Hash for Const {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
+            Const::Nil => (),
+            Const::Bool(b) => b.hash(state),
             Const::Int(i) => i.hash(state),
             Const::Float(f) => {
                 let (m, e, s) = Float::integer_decode(*f);
@@ -28,14 +34,6 @@ impl Hash for Const {
     }
 }
 
-fn float_to_int(f: FloatType) -> Option<IntType> {
-    if f.floor() == f {
-        Some(f as IntType)
-    } else {
-        None
-    }
-}
-
 fn ignore_unhashable_float(
     input: Result<Option<Const>, CompileError>,
 ) -> Result<Option<Const>, CompileError> {
@@ -60,14 +58,15 @@ macro_rules! bin_op {
                 Const::Int(a) => match other {
                     Const::Int(b) => $int_int(a, b),
                     Const::Float(b) => $int_float(a, b),
-                    _ => unreachable!(),
+                    _ => return Ok(None),
                 },
                 Const::Float(a) => match other {
                     Const::Int(b) => $float_int(a, b),
                     Const::Float(b) => $float_float(a, b),
-                    _ => unreachable!(),
+                    _ => return Ok(None),
                 },
-                _ => unreachable!(),
+                // non-numeric operands are left to runtime
+                _ => return Ok(None),
             };
 
             ignore_unhashable_float(result)
@@ -75,11 +74,12 @@ macro_rules! bin_op {
     };
 }
 
+// integers wrap around on overflow like lua
 macro_rules! bin_op_normal {
-    ($name:ident, $op:tt) => {
+    ($name:ident, $op:tt, $int_op:ident) => {
         bin_op! {
             $name,
-            |a, b| success!(Const::Int(a $op b)),
+            |a: IntType, b| success!(Const::Int(a.$int_op(b))),
             |a, b| success!(Const::Float(a as FloatType $op b)),
             |a, b| success!(Const::Float(a $op b as FloatType)),
             |a, b| success!(Const::Float(a $op b))
@@ -87,22 +87,23 @@ macro_rules! bin_op_normal {
     };
 }
 
+// floats without an integer value are an error left to runtime
 macro_rules! bin_op_int {
-    ($name:ident, $op:tt) => {
+    ($name:ident, $op:expr) => {
         bin_op! {
             $name,
-            |a, b| success!(Const::Int(a $op b)),
-            |a, b| Ok(float_to_int(b).map(|b| Const::Int(a $op b))),
-            |a, b| Ok(float_to_int(a).map(|a| Const::Int(a $op b))),
-            |a, b| Ok(float_to_int(a).and_then(|a| float_to_int(b).and_then(|b| Some(Const::Int(a $op b)))))
+            |a, b| success!(Const::Int($op(a, b))),
+            |a, b| Ok(float_to_int(b).map(|b| Const::Int($op(a, b)))),
+            |a, b| Ok(float_to_int(a).map(|a| Const::Int($op(a, b)))),
+            |a, b| Ok(float_to_int(a).and_then(|a| float_to_int(b).and_then(|b| Some(Const::Int($op(a, b))))))
         }
     };
 }
 
 impl Const {
-    bin_op_normal! {add, +}
-    bin_op_normal! {sub, -}
-    bin_op_normal! {mul, *}
+    bin_op_normal! {add, +, wrapping_add}
+    bin_op_normal! {sub, -, wrapping_sub}
+    bin_op_normal! {mul, *, wrapping_mul}
 
     bin_op! {
         div,
@@ -112,20 +113,21 @@ impl Const {
         |a, b| success!(Const::Float(a / b))
     }
 
+    // integer division by zero is a runtime error, leave it to runtime
     bin_op! {
         idiv,
-        |a, b| if b == 0 { Err(CompileError::new("divide by zero")) } else { success!(Const::Int(a / b)) },
-        |_, _| Ok(None),
-        |_, _| Ok(None),
-        |_, _| Ok(None)
+        |a: IntType, b| if b == 0 { Ok(None) } else { success!(Const::Int(int_idiv(a, b))) },
+        |a, b| success!(Const::Float(float_idiv(a as FloatType, b))),
+        |a, b| success!(Const::Float(float_idiv(a, b as FloatType))),
+        |a, b| success!(Const::Float(float_idiv(a, b)))
     }
 
     bin_op! {
         mod_,
-        |a, b| success!(Const::Int(a % b)),
-        |a, b| success!(Const::Float(a as FloatType % b)),
-        |a, b| success!(Const::Float(a % b as FloatType)),
-        |a, b| success!(Const::Float(a % b))
+        |a: IntType, b| if b == 0 { Ok(None) } else { success!(Const::Int(int_mod(a, b))) },
+        |a, b| success!(Const::Float(float_mod(a as FloatType, b))),
+        |a, b| success!(Const::Float(float_mod(a, b as FloatType))),
+        |a, b| success!(Const::Float(float_mod(a, b)))
     }
 
     bin_op! {
@@ -136,15 +138,27 @@ impl Const {
         |a:FloatType, b| success!(Const::Float(a.powf(b)))
     }
 
-    bin_op_int! {band, &}
-    bin_op_int! {bor, |}
-    bin_op_int! {bxor, ^}
-    bin_op_int! {shl, <<}
-    bin_op_int! {shr, >>}
+    bin_op_int! {band, |a, b| a & b}
+    bin_op_int! {bor, |a, b| a | b}
+    bin_op_int! {bxor, |a, b| a ^ b}
+    bin_op_int! {shl, shift_left}
+    bin_op_int! {shr, |a, b: IntType| shift_left(a, b.wrapping_neg())}
+
+    // convert numeric string to number like lua's arithmetic coercion, other consts are unchanged
+    pub fn coerce_to_number(self) -> Const {
+        match self {
+            Const::Str(s) => match Lexer::str_to_num(&s) {
+                Number::Int(i) => Const::Int(i),
+                Number::Float(f) => Const::Float(f),
+                Number::None => Const::Str(s),
+            },
+            k => k,
+        }
+    }
 
     pub fn minus(&self) -> Result<Option<Const>, CompileError> {
         let result = match self {
-            Const::Int(i) => success!(Const::Int(-i)),
+            Const::Int(i) => success!(Const::Int(i.wrapping_neg())),
             Const::Float(f) => success!(Const::Float(-f)),
             _ => return Ok(None),
         };
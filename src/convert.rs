@@ -0,0 +1,427 @@
+use crate::table::Table;
+use crate::types::{FloatType, IntType};
+use crate::value::{float_to_int, LuaStr, TableRef, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::Hash;
+use std::rc::Rc;
+
+// conversions between values and rust types, for hosts.
+//
+// numbers convert as lua's arithmetic does: floats with integral values are integers and
+// integers are floats. vectors and tuples are sequences, maps are tables with their fields
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertError(pub String);
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+pub type ConvertResult<T> = Result<T, ConvertError>;
+
+fn expected<T>(name: &str, value: &Value) -> ConvertResult<T> {
+    Err(ConvertError(format!(
+        "{} expected, got {}",
+        name,
+        value.type_name()
+    )))
+}
+
+pub trait ToLua {
+    fn to_lua(self) -> Value;
+}
+
+pub trait FromLua: Sized {
+    fn from_lua(value: Value) -> ConvertResult<Self>;
+}
+
+impl ToLua for Value {
+    fn to_lua(self) -> Value {
+        self
+    }
+}
+
+impl FromLua for Value {
+    fn from_lua(value: Value) -> ConvertResult<Self> {
+        Ok(value)
+    }
+}
+
+impl ToLua for () {
+    fn to_lua(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl ToLua for bool {
+    fn to_lua(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+// the truthiness, as in conditions
+impl FromLua for bool {
+    fn from_lua(value: Value) -> ConvertResult<Self> {
+        Ok(value.is_truthy())
+    }
+}
+
+macro_rules! int_conversions {
+    ($($t:ty),*) => {$(
+        impl ToLua for $t {
+            fn to_lua(self) -> Value {
+                Value::Int(self as IntType)
+            }
+        }
+
+        impl FromLua for $t {
+            fn from_lua(value: Value) -> ConvertResult<Self> {
+                let i = match value {
+                    Value::Int(i) => i,
+                    Value::Float(f) => match float_to_int(f) {
+                        Some(i) => i,
+                        None => {
+                            return Err(ConvertError(
+                                "number has no integer representation".to_string(),
+                            ))
+                        }
+                    },
+                    value => return expected("integer", &value),
+                };
+                <$t>::try_from(i).map_err(|_| {
+                    ConvertError(format!("{} out of range for {}", i, stringify!($t)))
+                })
+            }
+        }
+    )*};
+}
+
+int_conversions!(i8, i16, i32, i64, u8, u16, u32);
+
+macro_rules! float_conversions {
+    ($($t:ty),*) => {$(
+        impl ToLua for $t {
+            fn to_lua(self) -> Value {
+                Value::Float(self as FloatType)
+            }
+        }
+
+        impl FromLua for $t {
+            fn from_lua(value: Value) -> ConvertResult<Self> {
+                match value {
+                    Value::Int(i) => Ok(i as $t),
+                    Value::Float(f) => Ok(f as $t),
+                    value => expected("number", &value),
+                }
+            }
+        }
+    )*};
+}
+
+float_conversions!(f32, f64);
+
+impl ToLua for &str {
+    fn to_lua(self) -> Value {
+        Value::str(self)
+    }
+}
+
+impl ToLua for String {
+    fn to_lua(self) -> Value {
+        Value::str(&self)
+    }
+}
+
+impl ToLua for LuaStr {
+    fn to_lua(self) -> Value {
+        Value::Str(self)
+    }
+}
+
+impl FromLua for LuaStr {
+    fn from_lua(value: Value) -> ConvertResult<Self> {
+        match value {
+            Value::Str(s) => Ok(s),
+            value => expected("string", &value),
+        }
+    }
+}
+
+// invalid utf-8 is an error, `LuaStr` keeps any bytes
+impl FromLua for String {
+    fn from_lua(value: Value) -> ConvertResult<Self> {
+        let s = LuaStr::from_lua(value)?;
+        String::from_utf8(s.as_bytes().to_vec())
+            .map_err(|_| ConvertError("string is not valid utf-8".to_string()))
+    }
+}
+
+impl ToLua for TableRef {
+    fn to_lua(self) -> Value {
+        Value::Table(self)
+    }
+}
+
+impl FromLua for TableRef {
+    fn from_lua(value: Value) -> ConvertResult<Self> {
+        match value {
+            Value::Table(t) => Ok(t),
+            value => expected("table", &value),
+        }
+    }
+}
+
+// none is nil
+impl<T: ToLua> ToLua for Option<T> {
+    fn to_lua(self) -> Value {
+        self.map_or(Value::Nil, T::to_lua)
+    }
+}
+
+impl<T: FromLua> FromLua for Option<T> {
+    fn from_lua(value: Value) -> ConvertResult<Self> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_lua(value).map(Some),
+        }
+    }
+}
+
+fn sequence(values: impl Iterator<Item = Value>) -> Value {
+    let mut table = Table::new();
+    for (i, value) in values.enumerate() {
+        table.set_int(i as IntType + 1, value);
+    }
+    Value::Table(Rc::new(RefCell::new(table)))
+}
+
+impl<T: ToLua> ToLua for Vec<T> {
+    fn to_lua(self) -> Value {
+        sequence(self.into_iter().map(T::to_lua))
+    }
+}
+
+// the fields from 1 to the border of the table
+impl<T: FromLua> FromLua for Vec<T> {
+    fn from_lua(value: Value) -> ConvertResult<Self> {
+        let table = TableRef::from_lua(value)?;
+        let table = table.borrow();
+        (1..=table.len())
+            .map(|i| T::from_lua(table.get_int(i as IntType)))
+            .collect()
+    }
+}
+
+// nil keys, e.g. of `Option`s, can't be fields and are left out
+impl<K: ToLua, V: ToLua> ToLua for HashMap<K, V> {
+    fn to_lua(self) -> Value {
+        let mut table = Table::new();
+        for (key, value) in self {
+            let key = key.to_lua();
+            let nan = matches!(key, Value::Float(f) if f.is_nan());
+            if !key.is_nil() && !nan {
+                table.set(key, value.to_lua());
+            }
+        }
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+}
+
+impl<K: FromLua + Eq + Hash, V: FromLua> FromLua for HashMap<K, V> {
+    fn from_lua(value: Value) -> ConvertResult<Self> {
+        let table = TableRef::from_lua(value)?;
+        let table = table.borrow();
+        table
+            .iter()
+            .map(|(key, value)| Ok((K::from_lua(key)?, V::from_lua(value.clone())?)))
+            .collect()
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($($name:ident $index:tt),*) => {
+        impl<$($name: ToLua),*> ToLua for ($($name,)*) {
+            fn to_lua(self) -> Value {
+                sequence(vec![$(self.$index.to_lua()),*].into_iter())
+            }
+        }
+
+        impl<$($name: FromLua),*> FromLua for ($($name,)*) {
+            fn from_lua(value: Value) -> ConvertResult<Self> {
+                let table = TableRef::from_lua(value)?;
+                let table = table.borrow();
+                Ok(($($name::from_lua(table.get_int($index + 1))?,)*))
+            }
+        }
+    };
+}
+
+tuple_conversions!(A 0);
+tuple_conversions!(A 0, B 1);
+tuple_conversions!(A 0, B 1, C 2);
+tuple_conversions!(A 0, B 1, C 2, D 3);
+
+// values of a call, args or results
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MultiValue(pub Vec<Value>);
+
+// the rest of the values of a call, of the same type
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Variadic<T>(pub Vec<T>);
+
+// a value of a call which can't be converted, with its position from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct BadArgument(pub usize, pub ConvertError);
+
+// conversions to and from the values of calls. single values are one value, tuples are one
+// value per element, and `MultiValue` and `Variadic` are any number of them
+pub trait ToLuaMulti {
+    fn to_lua_multi(self) -> Vec<Value>;
+}
+
+pub trait FromLuaMulti: Sized {
+    fn from_lua_multi(values: Vec<Value>) -> Result<Self, BadArgument>;
+}
+
+// missing values are nil
+fn from_arg<T: FromLua>(n: usize, value: Option<Value>) -> Result<T, BadArgument> {
+    T::from_lua(value.unwrap_or(Value::Nil)).map_err(|e| BadArgument(n, e))
+}
+
+fn from_args<T: FromLua>(
+    skip: usize,
+    values: impl Iterator<Item = Value>,
+) -> Result<Variadic<T>, BadArgument> {
+    values
+        .enumerate()
+        .map(|(i, value)| from_arg(skip + i + 1, Some(value)))
+        .collect::<Result<_, _>>()
+        .map(Variadic)
+}
+
+impl ToLuaMulti for MultiValue {
+    fn to_lua_multi(self) -> Vec<Value> {
+        self.0
+    }
+}
+
+impl FromLuaMulti for MultiValue {
+    fn from_lua_multi(values: Vec<Value>) -> Result<Self, BadArgument> {
+        Ok(MultiValue(values))
+    }
+}
+
+impl<T: ToLua> ToLuaMulti for Variadic<T> {
+    fn to_lua_multi(self) -> Vec<Value> {
+        self.0.into_iter().map(T::to_lua).collect()
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for Variadic<T> {
+    fn from_lua_multi(values: Vec<Value>) -> Result<Self, BadArgument> {
+        from_args(0, values.into_iter())
+    }
+}
+
+macro_rules! single_value {
+    ($($t:ty),*) => {$(
+        impl ToLuaMulti for $t {
+            fn to_lua_multi(self) -> Vec<Value> {
+                vec![self.to_lua()]
+            }
+        }
+
+        impl FromLuaMulti for $t {
+            fn from_lua_multi(values: Vec<Value>) -> Result<Self, BadArgument> {
+                from_arg(1, values.into_iter().next())
+            }
+        }
+    )*};
+}
+
+single_value!(Value, bool, i8, i16, i32, i64, u8, u16, u32, f32, f64, String, LuaStr, TableRef);
+
+impl ToLuaMulti for &str {
+    fn to_lua_multi(self) -> Vec<Value> {
+        vec![self.to_lua()]
+    }
+}
+
+impl<T: ToLua> ToLuaMulti for Option<T> {
+    fn to_lua_multi(self) -> Vec<Value> {
+        vec![self.to_lua()]
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for Option<T> {
+    fn from_lua_multi(values: Vec<Value>) -> Result<Self, BadArgument> {
+        from_arg(1, values.into_iter().next())
+    }
+}
+
+impl<T: ToLua> ToLuaMulti for Vec<T> {
+    fn to_lua_multi(self) -> Vec<Value> {
+        vec![self.to_lua()]
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for Vec<T> {
+    fn from_lua_multi(values: Vec<Value>) -> Result<Self, BadArgument> {
+        from_arg(1, values.into_iter().next())
+    }
+}
+
+macro_rules! multi_tuple {
+    ($($name:ident $index:tt),*) => {
+        impl<$($name: ToLua),*> ToLuaMulti for ($($name,)*) {
+            fn to_lua_multi(self) -> Vec<Value> {
+                vec![$(self.$index.to_lua()),*]
+            }
+        }
+
+        // extra values are ignored
+        impl<$($name: FromLua),*> FromLuaMulti for ($($name,)*) {
+            #[allow(unused_variables, unused_mut)]
+            fn from_lua_multi(values: Vec<Value>) -> Result<Self, BadArgument> {
+                let mut values = values.into_iter();
+                Ok(($(from_arg::<$name>($index + 1, values.next())?,)*))
+            }
+        }
+    };
+}
+
+multi_tuple!();
+multi_tuple!(A 0);
+multi_tuple!(A 0, B 1);
+multi_tuple!(A 0, B 1, C 2);
+multi_tuple!(A 0, B 1, C 2, D 3);
+
+macro_rules! variadic_tuple {
+    ($($name:ident $index:tt),*; $rest:tt) => {
+        impl<$($name: ToLua,)* V: ToLua> ToLuaMulti for ($($name,)* Variadic<V>) {
+            fn to_lua_multi(self) -> Vec<Value> {
+                let mut values = vec![$(self.$index.to_lua()),*];
+                values.extend(self.$rest.to_lua_multi());
+                values
+            }
+        }
+
+        impl<$($name: FromLua,)* V: FromLua> FromLuaMulti for ($($name,)* Variadic<V>) {
+            fn from_lua_multi(values: Vec<Value>) -> Result<Self, BadArgument> {
+                let mut values = values.into_iter();
+                Ok(($(from_arg::<$name>($index + 1, values.next())?,)* from_args($rest, values)?))
+            }
+        }
+    };
+}
+
+variadic_tuple!(A 0; 1);
+variadic_tuple!(A 0, B 1; 2);
+variadic_tuple!(A 0, B 1, C 2; 3);
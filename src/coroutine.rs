@@ -0,0 +1,96 @@
+use crate::lib_util::{bad_argument, LibFn, LibResult};
+use crate::table::Table;
+use crate::value::{ThreadRef, Value};
+use crate::vm::{NativeFunction, RuntimeError, ThreadStatus, Vm};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// the `coroutine` library, on top of `Vm::resume` and `Vm::yield_values`
+
+fn check_thread(args: &[Value], name: &str) -> Result<ThreadRef, RuntimeError> {
+    match args.first() {
+        Some(Value::Thread(thread)) => Ok(thread.clone()),
+        _ => Err(bad_argument(1, name, "coroutine expected")),
+    }
+}
+
+fn check_function(args: &[Value], name: &str) -> Result<Value, RuntimeError> {
+    match args.first() {
+        Some(func) if func.is_function() => Ok(func.clone()),
+        _ => Err(bad_argument(1, name, "function expected")),
+    }
+}
+
+fn create(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let func = check_function(&args, "create")?;
+    Ok(vec![Value::Thread(vm.create_thread(func))])
+}
+
+// true and the values of yield or return, or false and the error
+fn resume(vm: &mut Vm, mut args: Vec<Value>) -> LibResult {
+    let thread = check_thread(&args, "resume")?;
+    args.remove(0);
+    Ok(match vm.resume(&thread, args) {
+        Ok(mut values) => {
+            values.insert(0, Value::Bool(true));
+            values
+        }
+        Err(e) if vm.is_interrupted() => return Err(e),
+        Err(e) => vec![Value::Bool(false), e.value()],
+    })
+}
+
+fn yield_(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    vm.yield_values(args)?;
+    Ok(Vec::new())
+}
+
+fn status(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let thread = check_thread(&args, "status")?;
+    let status = match thread.borrow().status() {
+        ThreadStatus::Suspended => "suspended",
+        ThreadStatus::Running => "running",
+        ThreadStatus::Normal => "normal",
+        ThreadStatus::Dead => "dead",
+    };
+    Ok(vec![Value::str(status)])
+}
+
+// the running coroutine, or nil in the main thread, and whether it's the main thread
+fn running(vm: &mut Vm, _: Vec<Value>) -> LibResult {
+    Ok(match vm.running() {
+        Some(thread) => vec![Value::Thread(thread.clone()), Value::Bool(false)],
+        None => vec![Value::Nil, Value::Bool(true)],
+    })
+}
+
+fn is_yieldable(vm: &mut Vm, _: Vec<Value>) -> LibResult {
+    Ok(vec![Value::Bool(vm.is_yieldable())])
+}
+
+// a function resuming a new coroutine, which raises its errors instead of returning them
+fn wrap(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let func = check_function(&args, "wrap")?;
+    let thread = vm.create_thread(func);
+    let wrapped = NativeFunction::new("wrap", move |vm, args| vm.resume(&thread, args));
+    Ok(vec![Value::Native(Rc::new(wrapped))])
+}
+
+// add the library to the globals
+pub fn open(vm: &mut Vm) {
+    let functions: [(&str, LibFn); 7] = [
+        ("create", create),
+        ("resume", resume),
+        ("yield", yield_),
+        ("status", status),
+        ("running", running),
+        ("isyieldable", is_yieldable),
+        ("wrap", wrap),
+    ];
+    let mut lib = Table::new();
+    for (name, func) in functions.iter() {
+        let native = NativeFunction::new(name, *func);
+        lib.set_str(name, Value::Native(Rc::new(native)));
+    }
+    vm.set_global("coroutine", Value::Table(Rc::new(RefCell::new(lib))));
+}
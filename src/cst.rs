@@ -0,0 +1,247 @@
+use crate::lexer::{LexError, Lexer, LexerConfig};
+use crate::tokens::{Token, TokenType};
+use crate::types::Source;
+use std::ops::Range;
+
+// lossless syntax tree, keeps whitespaces, comments and the exact text of every token,
+// so tools like formatters or refactorings can edit the source without regenerating it.
+//
+// trivia (whitespaces, line breaks and comments) is attached to the token after it,
+// trivia at the end of the source is attached to the `Eos` token,
+// so concatenating all tokens gives the original source back.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    LineBreak,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstToken {
+    pub t: TokenType,
+    pub text: String,
+    pub leading: Vec<Trivia>,
+    pub source: Source,
+}
+
+impl CstToken {
+    pub fn write(&self, output: &mut String) {
+        for trivia in self.leading.iter() {
+            output.push_str(&trivia.text);
+        }
+        output.push_str(&self.text);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    tokens: Vec<CstToken>,
+}
+
+impl SyntaxTree {
+    // lex `input` and keep everything
+    pub fn parse(input: &str) -> Result<SyntaxTree, LexError> {
+        let mut lexer = Lexer::new();
+        lexer.set_config(LexerConfig {
+            use_origin_string: true,
+            reserve_comments: false,
+            ..LexerConfig::default()
+        });
+        let tokens = lexer.run(input)?;
+        Ok(SyntaxTree::new(input, &tokens))
+    }
+
+    // build from tokens lexed from `input`, the tokens can be used by the parser as well
+    pub fn new(input: &str, tokens: &[Token]) -> SyntaxTree {
+        let mut result = Vec::with_capacity(tokens.len());
+        let mut leading = Vec::new();
+        let mut pos = 0;
+        for token in tokens.iter() {
+            let Source { pos: start, length, .. } = token.source;
+            SyntaxTree::read_trivia(&input[pos..start], &mut leading);
+            let text = &input[start..start + length];
+            pos = start + length;
+            if token.is_comment() {
+                leading.push(Trivia {
+                    kind: TriviaKind::Comment,
+                    text: text.to_string(),
+                });
+                continue;
+            }
+            result.push(CstToken {
+                t: token.t,
+                text: text.to_string(),
+                leading: std::mem::take(&mut leading),
+                source: token.source,
+            });
+        }
+        SyntaxTree { tokens: result }
+    }
+
+    // split text between two tokens into trivia
+    fn read_trivia(text: &str, output: &mut Vec<Trivia>) {
+        let bytes = text.as_bytes();
+        let mut start = 0;
+        while start < bytes.len() {
+            let (kind, end) = match bytes[start] {
+                b'\r' | b'\n' => {
+                    let mut end = start + 1;
+                    // \r\n or \n\r is one line break
+                    if end < bytes.len()
+                        && matches!(bytes[end], b'\r' | b'\n')
+                        && bytes[end] != bytes[start]
+                    {
+                        end += 1;
+                    }
+                    (TriviaKind::LineBreak, end)
+                }
+                b'-' => (TriviaKind::Comment, SyntaxTree::comment_end(bytes, start)),
+                _ => {
+                    let n = bytes[start..]
+                        .iter()
+                        .position(|c| matches!(c, b'\r' | b'\n' | b'-'))
+                        .unwrap_or(bytes.len() - start);
+                    (TriviaKind::Whitespace, start + n)
+                }
+            };
+            output.push(Trivia {
+                kind,
+                text: text[start..end].to_string(),
+            });
+            start = end;
+        }
+    }
+
+    // end of the comment starting at `start`, the lexer has already checked it's well formed
+    fn comment_end(bytes: &[u8], start: usize) -> usize {
+        let mut pos = start + 2;
+        if bytes.get(pos) == Some(&b'[') {
+            let sep = bytes[pos + 1..].iter().take_while(|c| **c == b'=').count();
+            if bytes.get(pos + 1 + sep) == Some(&b'[') {
+                let close = format!("]{}]", "=".repeat(sep));
+                pos += sep + 2;
+                return bytes[pos..]
+                    .windows(close.len())
+                    .position(|w| w == close.as_bytes())
+                    .map_or(bytes.len(), |n| pos + n + close.len());
+            }
+        }
+        bytes[pos..]
+            .iter()
+            .position(|c| matches!(c, b'\r' | b'\n'))
+            .map_or(bytes.len(), |n| pos + n)
+    }
+
+    pub fn tokens(&self) -> &[CstToken] {
+        &self.tokens
+    }
+
+    // regenerate the source, it's identical to the input if nothing is edited
+    pub fn to_source(&self) -> String {
+        let mut output = String::new();
+        for token in self.tokens.iter() {
+            token.write(&mut output);
+        }
+        output
+    }
+
+    // index range of tokens covered by `source`, e.g. the source of a statement in the ast
+    pub fn token_range(&self, source: Source) -> Range<usize> {
+        let end = source.pos + source.length;
+        let start = self
+            .tokens
+            .iter()
+            .position(|t| t.source.pos >= source.pos)
+            .unwrap_or(self.tokens.len());
+        let count = self.tokens[start..]
+            .iter()
+            .take_while(|t| t.source.pos < end && t.t != TokenType::Eos)
+            .count();
+        start..start + count
+    }
+
+    // exact text of tokens in `range`, trivia between them is kept, leading trivia of the first one isn't
+    pub fn text(&self, range: Range<usize>) -> String {
+        let mut output = String::new();
+        for (i, token) in self.tokens[range.clone()].iter().enumerate() {
+            if i == 0 {
+                output.push_str(&token.text);
+            } else {
+                token.write(&mut output);
+            }
+        }
+        output
+    }
+
+    // replace tokens in `range` with `text`, trivia before the range and after it is left untouched,
+    // an empty range inserts `text` right after the previous token
+    pub fn replace(&mut self, range: Range<usize>, text: &str) -> Result<(), LexError> {
+        let mut replacement = SyntaxTree::parse(text)?.tokens;
+        // trivia at the end of the replacement goes before the token following the range
+        let trailing = replacement.pop().map(|eos| eos.leading).unwrap_or_default();
+        let mut leading = match self.tokens.get_mut(range.start) {
+            Some(first) if !range.is_empty() => std::mem::take(&mut first.leading),
+            _ => Vec::new(),
+        };
+        match replacement.first_mut() {
+            Some(first) => {
+                leading.append(&mut first.leading);
+                first.leading = leading;
+                self.tokens[range.end].leading.splice(0..0, trailing);
+            }
+            None => {
+                leading.extend(trailing);
+                self.tokens[range.end].leading.splice(0..0, leading);
+            }
+        }
+        self.tokens.splice(range, replacement);
+        self.update_sources();
+        Ok(())
+    }
+
+    // recalculate positions of tokens after editing
+    fn update_sources(&mut self) {
+        let mut source = Source {
+            pos: 0,
+            length: 0,
+            line: 1,
+            col: 1,
+        };
+        for token in self.tokens.iter_mut() {
+            for trivia in token.leading.iter() {
+                advance(&mut source, &trivia.text);
+            }
+            source.length = token.text.len();
+            token.source = source;
+            advance(&mut source, &token.text);
+        }
+    }
+}
+
+// move `source` to the end of `text`
+fn advance(source: &mut Source, text: &str) {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            c @ b'\r' | c @ b'\n' => {
+                // \r\n or \n\r is one line break
+                if matches!(bytes.get(i + 1), Some(b'\r') | Some(b'\n')) && bytes[i + 1] != c {
+                    i += 1;
+                }
+                source.line += 1;
+                source.col = 1;
+            }
+            _ => source.col += 1,
+        }
+        i += 1;
+    }
+    source.pos += bytes.len();
+}
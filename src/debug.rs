@@ -0,0 +1,174 @@
+use crate::lib_util::{bad_argument, LibFn, LibResult};
+use crate::table::Table;
+use crate::traceback::FunctionInfo;
+use crate::value::Value;
+use crate::vm::{HookEvent, HookMask, NativeFunction, Vm};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// the `debug` library, on top of the hooks and introspection of the vm
+
+// the hook function of scripts is kept in the registry, so it stays alive through collections
+const HOOK_KEY: &str = "_HOOKKEY";
+
+// sethook(f, mask [, count]) calls `f` with the name of the event and the line for line events.
+// the mask has "c" for calls, "r" for returns and "l" for lines, and a count > 0 adds count
+// events. sethook() turns the hook off
+fn sethook(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let func = match args.first() {
+        None | Some(Value::Nil) => {
+            vm.registry().borrow_mut().set_str(HOOK_KEY, Value::Nil);
+            vm.remove_hook();
+            return Ok(Vec::new());
+        }
+        Some(func) if func.is_function() => func.clone(),
+        Some(_) => return Err(bad_argument(1, "sethook", "function expected")),
+    };
+    let mask = match args.get(1) {
+        Some(Value::Str(mask)) => mask.as_bytes().to_vec(),
+        _ => return Err(bad_argument(2, "sethook", "string expected")),
+    };
+    let count = match args.get(2) {
+        None | Some(Value::Nil) => 0,
+        Some(Value::Int(count)) => (*count).max(0).min(u32::MAX as i64) as u32,
+        Some(_) => return Err(bad_argument(3, "sethook", "number expected")),
+    };
+    let mask = HookMask {
+        call: mask.contains(&b'c'),
+        ret: mask.contains(&b'r'),
+        line: mask.contains(&b'l'),
+        count,
+    };
+    vm.registry().borrow_mut().set_str(HOOK_KEY, func);
+    vm.set_hook(mask, |vm, event| {
+        let hook = vm.registry().borrow().get_str(HOOK_KEY);
+        let args = match event {
+            HookEvent::Call => vec![Value::str("call")],
+            HookEvent::TailCall => vec![Value::str("tail call")],
+            HookEvent::Return => vec![Value::str("return")],
+            HookEvent::Line(line) => vec![Value::str("line"), Value::Int(line as i64)],
+            HookEvent::Count => vec![Value::str("count")],
+        };
+        vm.call(&hook, &args)?;
+        Ok(())
+    });
+    Ok(Vec::new())
+}
+
+// the hook function, mask and count set by sethook, nothing without a hook
+fn gethook(vm: &mut Vm, _: Vec<Value>) -> LibResult {
+    let hook = vm.registry().borrow().get_str(HOOK_KEY);
+    let mask = match vm.hook_mask() {
+        Some(mask) if !hook.is_nil() => mask,
+        // hooks set by the host aren't visible to scripts
+        _ => return Ok(Vec::new()),
+    };
+    let mut flags = String::new();
+    for (set, flag) in [(mask.call, 'c'), (mask.ret, 'r'), (mask.line, 'l')].iter() {
+        if *set {
+            flags.push(*flag);
+        }
+    }
+    Ok(vec![
+        hook,
+        Value::str(&flags),
+        Value::Int(mask.count as i64),
+    ])
+}
+
+// traceback([msg [, level]]) is the message followed by the traceback from `level`, 1 for the
+// caller. messages which aren't strings or numbers are returned as they are
+fn traceback(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let msg = match args.first() {
+        None | Some(Value::Nil) => None,
+        Some(Value::Str(msg)) => Some(msg.to_str_lossy()),
+        Some(value @ Value::Int(_)) | Some(value @ Value::Float(_)) => {
+            Some(vm.tostring(value)?.to_str_lossy())
+        }
+        Some(value) => return Ok(vec![value.clone()]),
+    };
+    let level = match args.get(1) {
+        None | Some(Value::Nil) => 1,
+        Some(Value::Int(level)) if *level >= 0 => *level as usize,
+        Some(_) => return Err(bad_argument(2, "traceback", "number expected")),
+    };
+    let traceback = vm.traceback(level).to_string();
+    let traceback = match msg {
+        Some(msg) => format!("{}\n{}", msg, traceback),
+        None => traceback,
+    };
+    Ok(vec![Value::str(&traceback)])
+}
+
+// getinfo(f | level [, what]) is a table about a function, or the lua function `level` calls
+// up the stack, 1 for the caller, or nil if there's none. `what` selects the fields like in
+// lua: "S" for the source and lines, "l" for the current line, "u" for upvalues and params,
+// "t" for tail calls and "f" for the function, all of them by default. names of functions
+// aren't known, so "n" adds nothing
+fn getinfo(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let info = match args.first() {
+        Some(Value::Int(level)) if *level > 0 => vm.frame_info(*level as usize),
+        Some(Value::Int(_)) => None,
+        Some(func) if func.is_function() => FunctionInfo::of(func),
+        _ => return Err(bad_argument(1, "getinfo", "function or level expected")),
+    };
+    let what = match args.get(1) {
+        None | Some(Value::Nil) => b"flnStu".to_vec(),
+        Some(Value::Str(what)) => what.as_bytes().to_vec(),
+        Some(_) => return Err(bad_argument(2, "getinfo", "string expected")),
+    };
+    if what.iter().any(|c| !b"SlnutfL".contains(c)) {
+        return Err(bad_argument(2, "getinfo", "invalid option"));
+    }
+    let info = match info {
+        Some(info) => info,
+        None => return Ok(vec![Value::Nil]),
+    };
+    let line = |line: Option<u32>| Value::Int(line.map_or(-1, |line| line as i64));
+    let mut t = Table::new();
+    if what.contains(&b'S') {
+        let (source, what) = if info.is_native {
+            ("=[C]".to_string(), "C")
+        } else if info.function == "main" {
+            (format!("={}", info.function), "main")
+        } else {
+            (format!("={}", info.function), "Lua")
+        };
+        t.set_str("short_src", Value::str(&source[1..]));
+        t.set_str("source", Value::str(&source));
+        t.set_str("what", Value::str(what));
+        t.set_str("linedefined", line(info.line_defined));
+        t.set_str("lastlinedefined", line(info.last_line_defined));
+    }
+    if what.contains(&b'l') {
+        t.set_str("currentline", line(info.current_line));
+    }
+    if what.contains(&b'u') {
+        t.set_str("nups", Value::Int(info.up_values as i64));
+        t.set_str("nparams", Value::Int(info.params as i64));
+        t.set_str("isvararg", Value::Bool(info.is_vararg));
+    }
+    if what.contains(&b't') {
+        t.set_str("istailcall", Value::Bool(info.is_tail_call));
+    }
+    if what.contains(&b'f') {
+        t.set_str("func", info.func);
+    }
+    Ok(vec![Value::Table(Rc::new(RefCell::new(t)))])
+}
+
+// add the library to the globals
+pub fn open(vm: &mut Vm) {
+    let functions: [(&str, LibFn); 4] = [
+        ("sethook", sethook),
+        ("gethook", gethook),
+        ("traceback", traceback),
+        ("getinfo", getinfo),
+    ];
+    let mut lib = Table::new();
+    for (name, func) in functions.iter() {
+        let native = NativeFunction::new(name, *func);
+        lib.set_str(name, Value::Native(Rc::new(native)));
+    }
+    vm.set_global("debug", Value::Table(Rc::new(RefCell::new(lib))));
+}
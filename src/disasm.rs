@@ -0,0 +1,248 @@
+use crate::consts::Const;
+use crate::opcodes::*;
+use crate::proto::Proto;
+use crate::value::float_to_str;
+use std::fmt::Write;
+
+// listings of compiled functions in the format of `luac -l -l` of lua 5.3.
+//
+// there are no addresses of functions, nested functions are named by their index in the parent,
+// e.g. `main.0.1` is the second function defined in the first function defined in main.
+// lines are printed as `[-]` after stripping debug info.
+
+impl Proto {
+    // listing of the function and all nested functions
+    pub fn disasm(&self) -> String {
+        let mut output = String::new();
+        disasm_function(self, "main", &mut output);
+        output
+    }
+}
+
+fn disasm_function(proto: &Proto, name: &str, output: &mut String) {
+    header(proto, name, output);
+    for pc in 0..proto.code.len() {
+        instruction(proto, pc, name, output);
+    }
+    debug(proto, name, output);
+    for (i, child) in proto.protos.iter().enumerate() {
+        output.push('\n');
+        disasm_function(child, &format!("{}.{}", name, i), output);
+    }
+}
+
+fn header(proto: &Proto, name: &str, output: &mut String) {
+    let kind = if name == "main" { "main" } else { "function" };
+    let _ = writeln!(
+        output,
+        "{} <{}> ({} instruction{})",
+        kind,
+        name,
+        proto.code.len(),
+        plural(proto.code.len())
+    );
+    let _ = writeln!(
+        output,
+        "{}{} param{}, {} slot{}, {} upvalue{}, {} local{}, {} constant{}, {} function{}",
+        proto.param_count,
+        if proto.is_vararg { "+" } else { "" },
+        plural(proto.param_count as usize),
+        proto.stack_size,
+        plural(proto.stack_size as usize),
+        proto.up_vars.len(),
+        plural(proto.up_vars.len()),
+        proto.local_vars.len(),
+        plural(proto.local_vars.len()),
+        proto.consts.len(),
+        plural(proto.consts.len()),
+        proto.protos.len(),
+        plural(proto.protos.len())
+    );
+}
+
+fn instruction(proto: &Proto, pc: usize, name: &str, output: &mut String) {
+    let instruction = &proto.code[pc];
+    let op = instruction.get_op();
+    let (b_mode, c_mode) = op.arg_modes();
+    let a = instruction.get_arg_A();
+    let b = instruction.get_arg_B();
+    let c = instruction.get_arg_C();
+    let _ = match proto.line_of(pc) {
+        Some(line) => write!(output, "\t{}\t[{}]\t", pc + 1, line),
+        None => write!(output, "\t{}\t[-]\t", pc + 1),
+    };
+    let _ = write!(output, "{:<9}\t", op.name());
+
+    // constants in RK args are printed as negative numbers starting from -1
+    let rk = |arg: u32| {
+        if is_const(arg) {
+            -1 - (arg & !MASK_K) as i64
+        } else {
+            arg as i64
+        }
+    };
+    let _ = match instruction.mode() {
+        OpMode::IA | OpMode::IAB | OpMode::IABC | OpMode::IAC => {
+            let _ = write!(output, "{}", a);
+            if b_mode != OpArgMode::N {
+                let _ = write!(output, " {}", rk(b));
+            }
+            if c_mode != OpArgMode::N {
+                let _ = write!(output, " {}", rk(c));
+            }
+            Ok(())
+        }
+        OpMode::IABx => {
+            let bx = instruction.get_arg_Bx();
+            let _ = write!(output, "{}", a);
+            match b_mode {
+                OpArgMode::K => write!(output, " {}", -1 - bx as i64),
+                OpArgMode::U => write!(output, " {}", bx),
+                _ => Ok(()),
+            }
+        }
+        OpMode::IAsBx => write!(output, "{} {}", a, instruction.get_arg_sBx()),
+        OpMode::IAx => write!(output, "{}", -1 - instruction.get_arg_Ax() as i64),
+    };
+
+    let up_val = |index: u32| match proto.up_vars.get(index as usize) {
+        Some(up_val) if !up_val.name().is_empty() => up_val.name().to_string(),
+        _ => "-".to_string(),
+    };
+    let rk_const = |arg: u32| {
+        if is_const(arg) {
+            constant(proto, arg & !MASK_K)
+        } else {
+            "-".to_string()
+        }
+    };
+    let comment = match op {
+        OpCode::LoadK => Some(constant(proto, instruction.get_arg_Bx())),
+        OpCode::GetUpVal | OpCode::SetUpVal => Some(up_val(b)),
+        OpCode::GetTabUp if is_const(c) => Some(format!("{} {}", up_val(b), rk_const(c))),
+        OpCode::GetTabUp => Some(up_val(b)),
+        OpCode::SetTabUp => {
+            let mut comment = up_val(a);
+            for arg in [b, c] {
+                if is_const(arg) {
+                    comment.push(' ');
+                    comment.push_str(&rk_const(arg));
+                }
+            }
+            Some(comment)
+        }
+        OpCode::GetTable | OpCode::Self_ if is_const(c) => Some(rk_const(c)),
+        OpCode::SetTable
+        | OpCode::Add
+        | OpCode::Sub
+        | OpCode::Mul
+        | OpCode::Mod
+        | OpCode::Pow
+        | OpCode::Div
+        | OpCode::IDiv
+        | OpCode::BAdd
+        | OpCode::BOr
+        | OpCode::BXor
+        | OpCode::Shl
+        | OpCode::Shr
+        | OpCode::Eq
+        | OpCode::Lt
+        | OpCode::Le
+            if is_const(b) || is_const(c) =>
+        {
+            Some(format!("{} {}", rk_const(b), rk_const(c)))
+        }
+        OpCode::Jmp | OpCode::ForLoop | OpCode::ForPrep | OpCode::TForLoop => {
+            Some(format!("to {}", instruction.get_arg_sBx() + pc as i32 + 2))
+        }
+        OpCode::Closure => Some(format!("{}.{}", name, instruction.get_arg_Bx())),
+        // block number is in the next instruction
+        OpCode::SetList if c == 0 => proto
+            .code
+            .get(pc + 1)
+            .map(|next| next.get_arg_Ax().to_string()),
+        OpCode::SetList => Some(c.to_string()),
+        _ => None,
+    };
+    if let Some(comment) = comment {
+        let _ = write!(output, "\t; {}", comment);
+    }
+    output.push('\n');
+}
+
+fn debug(proto: &Proto, name: &str, output: &mut String) {
+    let _ = writeln!(output, "constants ({}) for {}:", proto.consts.len(), name);
+    for i in 0..proto.consts.len() {
+        let _ = writeln!(output, "\t{}\t{}", i + 1, constant(proto, i as u32));
+    }
+    let _ = writeln!(output, "locals ({}) for {}:", proto.local_vars.len(), name);
+    for (i, local) in proto.local_vars.iter().enumerate() {
+        let _ = writeln!(
+            output,
+            "\t{}\t{}\t{}\t{}",
+            i,
+            local.name(),
+            local.start_pc() + 1,
+            local.end_pc() + 1
+        );
+    }
+    let _ = writeln!(output, "upvalues ({}) for {}:", proto.up_vars.len(), name);
+    for (i, up_val) in proto.up_vars.iter().enumerate() {
+        let _ = writeln!(
+            output,
+            "\t{}\t{}\t{}\t{}",
+            i,
+            if up_val.name().is_empty() {
+                "-"
+            } else {
+                up_val.name()
+            },
+            up_val.in_stack as u8,
+            up_val.index
+        );
+    }
+}
+
+fn constant(proto: &Proto, index: u32) -> String {
+    match proto.consts.get(index as usize) {
+        Some(Const::Nil) => "nil".to_string(),
+        Some(Const::Bool(b)) => b.to_string(),
+        Some(Const::Int(i)) => i.to_string(),
+        Some(Const::Float(f)) => float_to_str(*f),
+        Some(Const::Str(s)) => string(s),
+        // index out of range in a loaded chunk
+        None => "?".to_string(),
+    }
+}
+
+// quoted with escapes of c, like `PrintString` of luac
+fn string(s: &str) -> String {
+    let mut output = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' => output.push_str("\\\""),
+            b'\\' => output.push_str("\\\\"),
+            0x07 => output.push_str("\\a"),
+            0x08 => output.push_str("\\b"),
+            0x0C => output.push_str("\\f"),
+            b'\n' => output.push_str("\\n"),
+            b'\r' => output.push_str("\\r"),
+            b'\t' => output.push_str("\\t"),
+            0x0B => output.push_str("\\v"),
+            b if b.is_ascii_graphic() || b == b' ' => output.push(b as char),
+            b => {
+                let _ = write!(output, "\\{:03}", b);
+            }
+        }
+    }
+    output.push('"');
+    output
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
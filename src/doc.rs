@@ -0,0 +1,366 @@
+use crate::ast::*;
+
+// extract api docs from LDoc/EmmyLua style comments,
+// source should be lexed with `reserve_comments` so comments are kept in the ast.
+//
+// doc comments start with `---`, a run of comments right before a function documents it:
+//
+//   --- add two numbers        LDoc: @param name desc, @tparam type name desc,
+//   -- @tparam number a            @return desc, @treturn type desc
+//   ---@param b number         EmmyLua: @param name type desc, @return type desc
+//   function M.add(a, b) end
+//
+// the first doc comment which isn't attached to a function describes the module.
+
+#[derive(Debug, PartialEq)]
+pub struct ParamDoc {
+    pub name: String,
+    pub ty: Option<String>,
+    pub desc: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ReturnDoc {
+    pub ty: Option<String>,
+    pub desc: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FuncDoc {
+    pub name: String,
+    pub line: usize,
+    pub desc: String,
+    pub params: Vec<ParamDoc>,
+    pub returns: Vec<ReturnDoc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ModuleDoc {
+    pub name: String,
+    pub desc: String,
+    pub funcs: Vec<FuncDoc>,
+}
+
+pub struct DocExtractor {}
+
+impl DocExtractor {
+    // extract docs of functions defined at the top level of a chunk
+    pub fn run(name: &str, block: &Block) -> ModuleDoc {
+        let mut module = ModuleDoc {
+            name: name.to_string(),
+            desc: String::new(),
+            funcs: Vec::new(),
+        };
+        let mut comments: Vec<&str> = Vec::new();
+        let mut end_line = 0;
+        let mut module_desc_found = false;
+        for StatInfo { source, stat } in block.stats.iter() {
+            if let Stat::CommentStat(comment) = stat {
+                if comment.is_single_line && !comments.is_empty() && source.line > end_line + 1 {
+                    // a blank line ends the run
+                    if !module_desc_found && DocExtractor::is_doc(&comments) {
+                        module.desc = DocExtractor::collect(&comments, &[]).desc;
+                        module_desc_found = !module.desc.is_empty();
+                    }
+                    comments.clear();
+                }
+                comments.push(&comment.comment);
+                end_line = source.line + comment.comment.matches('\n').count();
+                continue;
+            }
+            let attached = source.line <= end_line + 1;
+            match DocExtractor::func_def(stat) {
+                Some((name, body)) if attached && DocExtractor::is_doc(&comments) => {
+                    let mut func = DocExtractor::collect(&comments, &body.params);
+                    func.name = name;
+                    func.line = source.line;
+                    module.funcs.push(func);
+                }
+                _ => {
+                    if !module_desc_found && DocExtractor::is_doc(&comments) {
+                        module.desc = DocExtractor::collect(&comments, &[]).desc;
+                        module_desc_found = !module.desc.is_empty();
+                    }
+                }
+            }
+            comments.clear();
+        }
+        module
+    }
+
+    fn is_doc(comments: &[&str]) -> bool {
+        match comments.first() {
+            Some(first) => first.starts_with('-'),
+            None => false,
+        }
+    }
+
+    // name and body of function definitions like
+    // `function a.b:c()`, `local function f()`, `local f = function()` or `a.b = function()`
+    fn func_def(stat: &Stat) -> Option<(String, &FuncBody)> {
+        match stat {
+            Stat::FuncStat(func) => {
+                let fields: Vec<&str> = func.func_name.fields.iter().map(|f| f.as_str()).collect();
+                let mut name = fields.join(".");
+                if let Some(method) = &func.func_name.method {
+                    name.push(':');
+                    name.push_str(method);
+                }
+                Some((name, &func.body))
+            }
+            Stat::LocalStat(LocalStat { names, exprs, .. }) => match exprs.as_slice() {
+                [Expr::FuncBody(body)] if names.len() == 1 => Some((names[0].to_string(), body)),
+                _ => None,
+            },
+            Stat::AssignStat(AssignStat { left, right }) => match (left.as_slice(), right.as_slice()) {
+                ([target], [Expr::FuncBody(body)]) => {
+                    DocExtractor::assignable_name(target).map(|name| (name, body))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn assignable_name(target: &Assignable) -> Option<String> {
+        match target {
+            Assignable::Name(name) => Some(name.to_string()),
+            Assignable::SuffixedExpr(SuffixedExpr { primary, suffixes }) => {
+                let mut name = match &**primary {
+                    Expr::Name(name) => name.to_string(),
+                    _ => return None,
+                };
+                for suffix in suffixes.iter() {
+                    match suffix {
+                        Suffix::Attr(attr) => {
+                            name.push('.');
+                            name.push_str(attr);
+                        }
+                        _ => return None,
+                    }
+                }
+                Some(name)
+            }
+            Assignable::ParenExpr(_) => None,
+        }
+    }
+
+    // parse comment lines into description and tags, params follow the function signature
+    fn collect(comments: &[&str], params: &[Param]) -> FuncDoc {
+        let mut func = FuncDoc {
+            name: String::new(),
+            line: 0,
+            desc: String::new(),
+            params: params
+                .iter()
+                .map(|p| ParamDoc {
+                    name: match p {
+                        Param::Name(name) => name.to_string(),
+                        Param::VarArg => "...".to_string(),
+                    },
+                    ty: None,
+                    desc: String::new(),
+                })
+                .collect(),
+            returns: Vec::new(),
+        };
+        let mut desc: Vec<&str> = Vec::new();
+        for comment in comments.iter() {
+            for line in comment.lines() {
+                // `---@tag` is EmmyLua style, `-- @tag` is LDoc style
+                let emmy = line.starts_with("-@");
+                let line = line.trim_start_matches(['-', '[']).trim();
+                let line = line.trim_end_matches(['-', ']']).trim();
+                if !line.starts_with('@') {
+                    if !line.is_empty() {
+                        desc.push(line);
+                    }
+                    continue;
+                }
+                let mut words = line.splitn(2, char::is_whitespace);
+                let tag = words.next().unwrap_or("");
+                let rest = words.next().unwrap_or("").trim();
+                match tag {
+                    "@param" if emmy => {
+                        let (name, rest) = split_word(rest);
+                        let (ty, desc) = split_word(rest);
+                        add_param(&mut func.params, name, Some(ty), desc);
+                    }
+                    "@param" => {
+                        let (name, desc) = split_word(rest);
+                        add_param(&mut func.params, name, None, desc);
+                    }
+                    "@tparam" => {
+                        let (ty, rest) = split_word(rest);
+                        let (name, desc) = split_word(rest);
+                        add_param(&mut func.params, name, Some(ty), desc);
+                    }
+                    "@return" if emmy => {
+                        let (ty, desc) = split_word(rest);
+                        func.returns.push(ReturnDoc {
+                            ty: non_empty(ty),
+                            desc: desc.to_string(),
+                        });
+                    }
+                    "@return" => func.returns.push(ReturnDoc {
+                        ty: None,
+                        desc: rest.to_string(),
+                    }),
+                    "@treturn" => {
+                        let (ty, desc) = split_word(rest);
+                        func.returns.push(ReturnDoc {
+                            ty: non_empty(ty),
+                            desc: desc.to_string(),
+                        });
+                    }
+                    _ => (),
+                }
+            }
+        }
+        func.desc = desc.join("\n");
+        func
+    }
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    let mut words = s.splitn(2, char::is_whitespace);
+    let first = words.next().unwrap_or("");
+    (first, words.next().unwrap_or("").trim())
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
+
+fn add_param(params: &mut Vec<ParamDoc>, name: &str, ty: Option<&str>, desc: &str) {
+    let ty = ty.and_then(non_empty);
+    match params.iter_mut().find(|p| p.name == name) {
+        Some(param) => {
+            param.ty = ty;
+            param.desc = desc.to_string();
+        }
+        None => params.push(ParamDoc {
+            name: name.to_string(),
+            ty,
+            desc: desc.to_string(),
+        }),
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut output = String::with_capacity(s.len() + 2);
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+fn json_opt(s: &Option<String>) -> String {
+    match s {
+        Some(s) => escape_json(s),
+        None => "null".to_string(),
+    }
+}
+
+impl ModuleDoc {
+    pub fn to_json(&self) -> String {
+        let funcs: Vec<String> = self.funcs.iter().map(|f| f.to_json()).collect();
+        format!(
+            "{{\"name\":{},\"desc\":{},\"funcs\":[{}]}}",
+            escape_json(&self.name),
+            escape_json(&self.desc),
+            funcs.join(",")
+        )
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut output = format!("# {}\n", self.name);
+        if !self.desc.is_empty() {
+            output.push_str(&format!("\n{}\n", self.desc));
+        }
+        for func in self.funcs.iter() {
+            output.push_str(&func.to_markdown());
+        }
+        output
+    }
+}
+
+impl FuncDoc {
+    pub fn to_json(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|p| {
+                format!(
+                    "{{\"name\":{},\"type\":{},\"desc\":{}}}",
+                    escape_json(&p.name),
+                    json_opt(&p.ty),
+                    escape_json(&p.desc)
+                )
+            })
+            .collect();
+        let returns: Vec<String> = self
+            .returns
+            .iter()
+            .map(|r| format!("{{\"type\":{},\"desc\":{}}}", json_opt(&r.ty), escape_json(&r.desc)))
+            .collect();
+        format!(
+            "{{\"name\":{},\"line\":{},\"desc\":{},\"params\":[{}],\"returns\":[{}]}}",
+            escape_json(&self.name),
+            self.line,
+            escape_json(&self.desc),
+            params.join(","),
+            returns.join(",")
+        )
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let names: Vec<&str> = self.params.iter().map(|p| p.name.as_str()).collect();
+        let mut output = format!("\n## `{}({})`\n", self.name, names.join(", "));
+        if !self.desc.is_empty() {
+            output.push_str(&format!("\n{}\n", self.desc));
+        }
+        if !self.params.is_empty() {
+            output.push_str("\n**Parameters**\n\n");
+            for param in self.params.iter() {
+                output.push_str(&format!("- `{}`", param.name));
+                if let Some(ty) = &param.ty {
+                    output.push_str(&format!(" (`{}`)", ty));
+                }
+                if !param.desc.is_empty() {
+                    output.push_str(&format!(": {}", param.desc));
+                }
+                output.push('\n');
+            }
+        }
+        if !self.returns.is_empty() {
+            output.push_str("\n**Returns**\n\n");
+            for ret in self.returns.iter() {
+                output.push_str("- ");
+                if let Some(ty) = &ret.ty {
+                    output.push_str(&format!("`{}`", ty));
+                    if !ret.desc.is_empty() {
+                        output.push_str(": ");
+                    }
+                }
+                output.push_str(&ret.desc);
+                output.push('\n');
+            }
+        }
+        output
+    }
+}
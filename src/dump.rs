@@ -0,0 +1,536 @@
+use crate::consts::Const;
+use crate::opcodes::Instruction;
+use crate::proto::Proto;
+use crate::symbol::Symbol;
+use crate::types::{FloatType, IntType};
+
+// binary chunks in the format of lua 5.3, which has the same instruction set as the compiler,
+// so dumped chunks can be loaded by `lua` or listed by `luac -l` of lua 5.3.
+//
+// numbers are little endian with 4 byte ints and 8 byte size_t, like luac on 64-bit platforms.
+// the compiler doesn't keep the source name, so there is no source.
+
+pub const LUA_SIGNATURE: &[u8] = b"\x1bLua";
+pub const LUAC_VERSION: u8 = 0x53;
+pub const LUAC_FORMAT: u8 = 0;
+pub const LUAC_DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+pub const LUAC_INT: IntType = 0x5678;
+pub const LUAC_NUM: FloatType = 370.5;
+
+// sizes of int, size_t, Instruction, lua_Integer and lua_Number
+pub const SIZE_INT: u8 = 4;
+pub const SIZE_SIZE_T: u8 = 8;
+pub const SIZE_INSTRUCTION: u8 = 4;
+pub const SIZE_INTEGER: u8 = 8;
+pub const SIZE_NUMBER: u8 = 8;
+
+// type tags of constants
+pub const TAG_NIL: u8 = 0;
+pub const TAG_BOOL: u8 = 1;
+pub const TAG_FLOAT: u8 = 3;
+pub const TAG_INT: u8 = 3 | (1 << 4);
+pub const TAG_SHORT_STR: u8 = 4;
+pub const TAG_LONG_STR: u8 = 4 | (1 << 4);
+
+// longer strings are long strings in lua
+pub const MAX_SHORT_STR_LEN: usize = 40;
+
+// signature of debug info saved apart from a stripped chunk
+pub const DEBUG_INFO_SIGNATURE: &[u8] = b"\x1bLuaDbg";
+
+#[derive(Debug, PartialEq)]
+pub struct UndumpError(pub String);
+
+// debug info of a function and its nested functions, which can be kept apart from a stripped chunk
+// and applied to the loaded proto again to map instructions back to lines and names
+#[derive(Debug, PartialEq, Default)]
+pub struct DebugInfo {
+    pub line_info: Vec<u32>,
+    pub locals: Vec<LocalInfo>,
+    pub up_values: Vec<String>,
+    pub protos: Vec<DebugInfo>,
+}
+
+// a local in scope from `start_pc` until before `end_pc`
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct LocalInfo {
+    pub name: String,
+    pub start_pc: usize,
+    pub end_pc: usize,
+}
+
+type UndumpResult<T> = Result<T, UndumpError>;
+
+struct Dumper {
+    output: Vec<u8>,
+    strip: bool,
+}
+
+struct Undumper<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Proto {
+    // dump as a binary chunk, `strip` removes debug info like `luac -s`
+    pub fn dump(&self, strip: bool) -> Vec<u8> {
+        let mut dumper = Dumper {
+            output: Vec::new(),
+            strip,
+        };
+        dumper.header();
+        dumper.byte(self.up_vars.len() as u8);
+        dumper.function(self);
+        dumper.output
+    }
+
+    pub fn debug_info(&self) -> DebugInfo {
+        DebugInfo {
+            line_info: self.line_info.iter().collect(),
+            locals: self
+                .local_vars
+                .iter()
+                .map(|l| LocalInfo {
+                    name: l.name().to_string(),
+                    start_pc: l.start_pc(),
+                    end_pc: l.end_pc(),
+                })
+                .collect(),
+            up_values: self.up_vars.iter().map(|u| u.name().to_string()).collect(),
+            protos: self.protos.iter().map(|p| p.debug_info()).collect(),
+        }
+    }
+
+    // strip debug info and return it
+    pub fn take_debug_info(&mut self) -> DebugInfo {
+        let info = self.debug_info();
+        self.strip_debug_info();
+        info
+    }
+
+    // restore debug info of a stripped proto, it must come from the same function
+    pub fn apply_debug_info(&mut self, info: &DebugInfo) -> Result<(), UndumpError> {
+        if (!info.line_info.is_empty() && info.line_info.len() != self.code.len())
+            || info.up_values.len() != self.up_vars.len()
+            || info.protos.len() != self.protos.len()
+        {
+            return Err(UndumpError("debug info mismatch".to_string()));
+        }
+        for (proto, info) in self.protos.iter_mut().zip(info.protos.iter()) {
+            proto.apply_debug_info(info)?;
+        }
+        self.line_info = info.line_info.iter().copied().collect();
+        self.local_vars.clear();
+        for local in info.locals.iter() {
+            self.add_local_var_in(Symbol::from(local.name.as_str()), local.start_pc, local.end_pc);
+        }
+        let up_vars = std::mem::take(&mut self.up_vars);
+        for (up_val, name) in up_vars.iter().zip(info.up_values.iter()) {
+            self.add_up_var(name, up_val.in_stack, up_val.index);
+        }
+        Ok(())
+    }
+
+    // load a binary chunk written by `dump` or by luac of lua 5.3
+    pub fn undump(input: &[u8]) -> Result<Proto, UndumpError> {
+        let mut undumper = Undumper { input, pos: 0 };
+        undumper.header()?;
+        let up_vals = undumper.byte()? as usize;
+        let proto = undumper.function()?;
+        if proto.up_vars.len() != up_vals {
+            return undumper.error("upvalue count mismatch");
+        }
+        if undumper.pos != input.len() {
+            return undumper.error("extra bytes after chunk");
+        }
+        Ok(proto)
+    }
+}
+
+impl DebugInfo {
+    pub fn dump(&self) -> Vec<u8> {
+        let mut dumper = Dumper {
+            output: Vec::new(),
+            strip: false,
+        };
+        dumper.bytes(DEBUG_INFO_SIGNATURE);
+        dumper.byte(LUAC_VERSION);
+        dumper.debug_info(self);
+        dumper.output
+    }
+
+    pub fn undump(input: &[u8]) -> Result<DebugInfo, UndumpError> {
+        let mut undumper = Undumper { input, pos: 0 };
+        if !input.starts_with(DEBUG_INFO_SIGNATURE) {
+            return undumper.error("not debug info");
+        }
+        undumper.pos = DEBUG_INFO_SIGNATURE.len();
+        if undumper.byte()? != LUAC_VERSION {
+            return undumper.error("version mismatch");
+        }
+        let info = undumper.debug_info()?;
+        if undumper.pos != input.len() {
+            return undumper.error("extra bytes after debug info");
+        }
+        Ok(info)
+    }
+}
+
+impl Dumper {
+    fn header(&mut self) {
+        self.bytes(LUA_SIGNATURE);
+        self.byte(LUAC_VERSION);
+        self.byte(LUAC_FORMAT);
+        self.bytes(LUAC_DATA);
+        self.byte(SIZE_INT);
+        self.byte(SIZE_SIZE_T);
+        self.byte(SIZE_INSTRUCTION);
+        self.byte(SIZE_INTEGER);
+        self.byte(SIZE_NUMBER);
+        self.integer(LUAC_INT);
+        self.number(LUAC_NUM);
+    }
+
+    fn function(&mut self, proto: &Proto) {
+        // source
+        self.string(None);
+        // first and last line of the function
+        self.int(0);
+        self.int(0);
+        self.byte(proto.param_count as u8);
+        self.byte(proto.is_vararg as u8);
+        self.byte(proto.stack_size as u8);
+
+        self.int(proto.code.len());
+        for instruction in proto.code.iter() {
+            self.bytes(&instruction.raw().to_le_bytes());
+        }
+
+        self.int(proto.consts.len());
+        for k in proto.consts.iter() {
+            self.constant(k);
+        }
+
+        self.int(proto.up_vars.len());
+        for up_val in proto.up_vars.iter() {
+            self.byte(up_val.in_stack as u8);
+            self.byte(up_val.index as u8);
+        }
+
+        self.int(proto.protos.len());
+        for proto in proto.protos.iter() {
+            self.function(proto);
+        }
+
+        self.debug(proto);
+    }
+
+    fn constant(&mut self, k: &Const) {
+        match k {
+            Const::Nil => self.byte(TAG_NIL),
+            Const::Bool(b) => {
+                self.byte(TAG_BOOL);
+                self.byte(*b as u8);
+            }
+            Const::Float(f) => {
+                self.byte(TAG_FLOAT);
+                self.number(*f);
+            }
+            Const::Int(i) => {
+                self.byte(TAG_INT);
+                self.integer(*i);
+            }
+            Const::Str(s) => {
+                self.byte(if s.len() <= MAX_SHORT_STR_LEN {
+                    TAG_SHORT_STR
+                } else {
+                    TAG_LONG_STR
+                });
+                self.string(Some(s));
+            }
+        }
+    }
+
+    fn debug(&mut self, proto: &Proto) {
+        if self.strip {
+            self.int(0);
+            self.int(0);
+            self.int(0);
+            return;
+        }
+        self.int(proto.line_info.len());
+        for line in proto.line_info.iter() {
+            self.bytes(&line.to_le_bytes());
+        }
+        self.int(proto.local_vars.len());
+        for local in proto.local_vars.iter() {
+            self.string(Some(local.name()));
+            self.int(local.start_pc());
+            self.int(local.end_pc());
+        }
+        self.int(proto.up_vars.len());
+        for up_val in proto.up_vars.iter() {
+            // names are empty after stripping debug info in the compiler
+            let name = up_val.name();
+            self.string(if name.is_empty() { None } else { Some(name) });
+        }
+    }
+
+    // line info, names of locals and upvalues of each function, like the debug section of a chunk
+    fn debug_info(&mut self, info: &DebugInfo) {
+        self.int(info.line_info.len());
+        for line in info.line_info.iter() {
+            self.bytes(&line.to_le_bytes());
+        }
+        self.int(info.locals.len());
+        for local in info.locals.iter() {
+            self.string(Some(&local.name));
+            self.int(local.start_pc);
+            self.int(local.end_pc);
+        }
+        self.int(info.up_values.len());
+        for name in info.up_values.iter() {
+            self.string(Some(name));
+        }
+        self.int(info.protos.len());
+        for proto in info.protos.iter() {
+            self.debug_info(proto);
+        }
+    }
+
+    // size + 1 in one byte, or 0xFF followed by a size_t, none is size 0
+    fn string(&mut self, s: Option<&str>) {
+        let s = match s {
+            Some(s) => s,
+            None => return self.byte(0),
+        };
+        let size = s.len() + 1;
+        if size < 0xFF {
+            self.byte(size as u8);
+        } else {
+            self.byte(0xFF);
+            self.bytes(&(size as u64).to_le_bytes());
+        }
+        self.bytes(s.as_bytes());
+    }
+
+    fn int(&mut self, i: usize) {
+        self.bytes(&(i as u32).to_le_bytes());
+    }
+
+    fn integer(&mut self, i: IntType) {
+        self.bytes(&i.to_le_bytes());
+    }
+
+    fn number(&mut self, f: FloatType) {
+        self.bytes(&f.to_le_bytes());
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.output.push(b);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.output.extend_from_slice(bytes);
+    }
+}
+
+impl<'a> Undumper<'a> {
+    fn error<T>(&self, msg: &str) -> UndumpResult<T> {
+        Err(UndumpError(format!("bad binary format ({})", msg)))
+    }
+
+    fn header(&mut self) -> UndumpResult<()> {
+        if !self.input.starts_with(LUA_SIGNATURE) {
+            return self.error("not a binary chunk");
+        }
+        self.pos = LUA_SIGNATURE.len();
+        if self.byte()? != LUAC_VERSION {
+            return self.error("version mismatch");
+        }
+        if self.byte()? != LUAC_FORMAT {
+            return self.error("format mismatch");
+        }
+        if self.bytes(LUAC_DATA.len())? != LUAC_DATA {
+            return self.error("corrupted");
+        }
+        for (size, name) in [
+            (SIZE_INT, "int"),
+            (SIZE_SIZE_T, "size_t"),
+            (SIZE_INSTRUCTION, "Instruction"),
+            (SIZE_INTEGER, "lua_Integer"),
+            (SIZE_NUMBER, "lua_Number"),
+        ] {
+            if self.byte()? != size {
+                return self.error(&format!("{} size mismatch", name));
+            }
+        }
+        if self.integer()? != LUAC_INT {
+            return self.error("endianness mismatch");
+        }
+        if self.number()? != LUAC_NUM {
+            return self.error("float format mismatch");
+        }
+        Ok(())
+    }
+
+    fn function(&mut self) -> UndumpResult<Proto> {
+        let mut proto = Proto::new();
+        // source, first and last line of the function
+        self.string()?;
+        self.int()?;
+        self.int()?;
+        proto.param_count = self.byte()? as u32;
+        proto.is_vararg = self.byte()? != 0;
+        proto.stack_size = self.byte()? as u32;
+
+        let n = self.int()?;
+        for pc in 0..n {
+            let raw = u32::from_le_bytes(self.array()?);
+            match Instruction::try_from_raw(raw) {
+                Some(instruction) => proto.code.push(instruction),
+                None => return self.error(&format!("bad opcode {} at pc {}", raw & 0x3F, pc)),
+            }
+        }
+
+        let n = self.int()?;
+        for _ in 0..n {
+            let k = self.constant()?;
+            proto.const_map.entry(k.clone()).or_insert(proto.consts.len() as u32);
+            proto.consts.push(k);
+        }
+
+        // names of upvalues are in the debug section
+        let n = self.int()?;
+        // counts aren't trusted to reserve memory, the input ends before a bad count is read
+        let mut up_vals = Vec::new();
+        for _ in 0..n {
+            let in_stack = self.byte()? != 0;
+            up_vals.push((in_stack, self.byte()? as u32));
+        }
+
+        let n = self.int()?;
+        for _ in 0..n {
+            proto.protos.push(self.function()?);
+        }
+
+        let n = self.int()?;
+        for _ in 0..n {
+            proto.line_info.push(u32::from_le_bytes(self.array()?));
+        }
+        let n = self.int()?;
+        for _ in 0..n {
+            let name = self.string()?.unwrap_or_default();
+            let start_pc = self.int()?;
+            let end_pc = self.int()?;
+            proto.add_local_var_in(Symbol::from(name), start_pc, end_pc);
+        }
+        let n = self.int()?;
+        if n != 0 && n != up_vals.len() {
+            return self.error("upvalue names mismatch");
+        }
+        let mut names = Vec::new();
+        for _ in 0..n {
+            names.push(self.string()?.unwrap_or_default());
+        }
+        for (i, (in_stack, index)) in up_vals.into_iter().enumerate() {
+            let name = names.get(i).map_or("", |name| name.as_str());
+            proto.add_up_var(name, in_stack, index);
+        }
+        Ok(proto)
+    }
+
+    fn debug_info(&mut self) -> UndumpResult<DebugInfo> {
+        let mut info = DebugInfo::default();
+        let n = self.int()?;
+        for _ in 0..n {
+            info.line_info.push(u32::from_le_bytes(self.array()?));
+        }
+        let n = self.int()?;
+        for _ in 0..n {
+            let name = self.string()?.unwrap_or_default();
+            let start_pc = self.int()?;
+            let end_pc = self.int()?;
+            info.locals.push(LocalInfo {
+                name,
+                start_pc,
+                end_pc,
+            });
+        }
+        let n = self.int()?;
+        for _ in 0..n {
+            info.up_values.push(self.string()?.unwrap_or_default());
+        }
+        let n = self.int()?;
+        for _ in 0..n {
+            info.protos.push(self.debug_info()?);
+        }
+        Ok(info)
+    }
+
+    fn constant(&mut self) -> UndumpResult<Const> {
+        let k = match self.byte()? {
+            TAG_NIL => Const::Nil,
+            TAG_BOOL => Const::Bool(self.byte()? != 0),
+            TAG_FLOAT => Const::Float(self.number()?),
+            TAG_INT => Const::Int(self.integer()?),
+            TAG_SHORT_STR | TAG_LONG_STR => match self.string()? {
+                Some(s) => Const::Str(s),
+                None => return self.error("missing string constant"),
+            },
+            tag => return self.error(&format!("bad constant type {}", tag)),
+        };
+        Ok(k)
+    }
+
+    fn string(&mut self) -> UndumpResult<Option<String>> {
+        let size = match self.byte()? {
+            0 => return Ok(None),
+            0xFF => u64::from_le_bytes(self.array()?) as usize,
+            size => size as usize,
+        };
+        let bytes = match size.checked_sub(1) {
+            Some(n) => self.bytes(n)?,
+            None => return self.error("bad string size"),
+        };
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(s) => Ok(Some(s)),
+            Err(_) => self.error("string is not valid utf-8"),
+        }
+    }
+
+    fn int(&mut self) -> UndumpResult<usize> {
+        let i = i32::from_le_bytes(self.array()?);
+        if i < 0 {
+            return self.error("negative count");
+        }
+        Ok(i as usize)
+    }
+
+    fn integer(&mut self) -> UndumpResult<IntType> {
+        Ok(IntType::from_le_bytes(self.array()?))
+    }
+
+    fn number(&mut self) -> UndumpResult<FloatType> {
+        Ok(FloatType::from_le_bytes(self.array()?))
+    }
+
+    fn byte(&mut self) -> UndumpResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> UndumpResult<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn bytes(&mut self, n: usize) -> UndumpResult<&'a [u8]> {
+        if self.input.len() - self.pos < n {
+            return self.error("truncated chunk");
+        }
+        let bytes = &self.input[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+}
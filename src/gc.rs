@@ -0,0 +1,688 @@
+use crate::table::Table;
+use crate::value::{TableRef, ThreadRef, UserData, Value};
+use crate::vm::{Closure, NativeFunction, Thread, UpValue, UpValueRef};
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+// mark and sweep collection of the objects created by the vm.
+//
+// objects are reference counted, so strings, userdata and anything else without cycles are
+// freed as soon as they are unused. the collector frees cycles of tables, closures, upvalues
+// and threads: it clears the contents of unreachable objects, which drops the references they
+// hold on each other.
+//
+// roots are the values of the vm, e.g. its stack, globals and registry, and all objects the
+// host refers to. those are found by trial deletion, references among objects are subtracted
+// from their counts, and objects with references left are referred to from outside.
+//
+// a collection counts the references among objects, marks and then sweeps. in incremental
+// mode each phase runs in steps between instructions of scripts. objects are white until
+// marked, gray while their references are pending and black after that. writes to tables
+// change their versions, which is the write barrier: at the end of marking, the atomic step
+// recounts the tables written since they were counted and turns them gray again, together
+// with threads and upvalues, which are written without barriers. objects created during a
+// collection aren't part of it.
+
+// collect when the number of objects grows to `pause` percent of the objects alive after
+// the last collection
+const PAUSE: usize = 200;
+const MIN_THRESHOLD: usize = 256;
+// objects counted, marked or swept by a step
+const STEP_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcMode {
+    // collections run to the end once started
+    Full,
+    // collections run in steps, interleaved with scripts
+    Incremental,
+}
+
+#[derive(Clone)]
+enum Object {
+    Table(TableRef),
+    Closure(Rc<Closure>),
+    UpValue(UpValueRef),
+    Thread(ThreadRef),
+    // opaque to the collector but for its metatable, they may be keys or values of weak tables
+    UserData(Rc<UserData>),
+    Native(Rc<NativeFunction>),
+}
+
+impl Object {
+    fn from_value(value: &Value) -> Option<Object> {
+        match value {
+            Value::Table(t) => Some(Object::Table(t.clone())),
+            Value::Function(f) => Some(Object::Closure(f.clone())),
+            Value::Thread(t) => Some(Object::Thread(t.clone())),
+            Value::UserData(u) => Some(Object::UserData(u.clone())),
+            Value::Native(f) => Some(Object::Native(f.clone())),
+            _ => None,
+        }
+    }
+
+    fn ptr(&self) -> *const () {
+        match self {
+            Object::Table(t) => Rc::as_ptr(t) as *const (),
+            Object::Closure(f) => Rc::as_ptr(f) as *const (),
+            Object::UpValue(v) => Rc::as_ptr(v) as *const (),
+            Object::Thread(t) => Rc::as_ptr(t) as *const (),
+            Object::UserData(u) => Rc::as_ptr(u) as *const (),
+            Object::Native(f) => Rc::as_ptr(f) as *const (),
+        }
+    }
+
+    // upvalues aren't values
+    fn to_value(&self) -> Option<Value> {
+        match self {
+            Object::Table(t) => Some(Value::Table(t.clone())),
+            Object::Closure(f) => Some(Value::Function(f.clone())),
+            Object::Thread(t) => Some(Value::Thread(t.clone())),
+            Object::UserData(u) => Some(Value::UserData(u.clone())),
+            Object::Native(f) => Some(Value::Native(f.clone())),
+            Object::UpValue(_) => None,
+        }
+    }
+
+    fn version(&self) -> u32 {
+        match self {
+            Object::Table(t) => t.borrow().version(),
+            _ => 0,
+        }
+    }
+
+    // tables written since they were counted or marked
+    fn written(&self, version: u32) -> bool {
+        match self {
+            Object::Table(t) => t.borrow().version() != version,
+            Object::Closure(_) | Object::Native(_) => false,
+            Object::UpValue(_) | Object::Thread(_) | Object::UserData(_) => true,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Table(t) => Rc::strong_count(t),
+            Object::Closure(f) => Rc::strong_count(f),
+            Object::UpValue(v) => Rc::strong_count(v),
+            Object::Thread(t) => Rc::strong_count(t),
+            Object::UserData(u) => Rc::strong_count(u),
+            Object::Native(f) => Rc::strong_count(f),
+        }
+    }
+
+    // objects this one refers to, once per reference
+    fn children(&self) -> Vec<Object> {
+        let values = match self {
+            Object::Table(t) => {
+                let t = t.borrow();
+                let mut values = Vec::new();
+                for (key, value) in t.iter() {
+                    values.push(key);
+                    values.push(value.clone());
+                }
+                values.extend(t.metatable().cloned().map(Value::Table));
+                values
+            }
+            Object::Closure(f) => {
+                return f.up_values.iter().cloned().map(Object::UpValue).collect();
+            }
+            // the values of open upvalues are in the stack
+            Object::UpValue(v) => match &*v.borrow() {
+                UpValue::Open(_) => Vec::new(),
+                UpValue::Closed(value) => vec![value.clone()],
+            },
+            Object::Thread(t) => t.borrow().references(),
+            Object::UserData(u) => u.metatable().map(Value::Table).into_iter().collect(),
+            Object::Native(_) => Vec::new(),
+        };
+        values.iter().filter_map(Object::from_value).collect()
+    }
+
+    // drop the references of an unreachable object
+    fn clear(&self) {
+        match self {
+            Object::Table(t) => t.borrow_mut().clear(),
+            // upvalues of a closure are cleared by themselves, they may be shared
+            Object::UserData(u) => u.set_metatable(None),
+            Object::Closure(_) | Object::Native(_) => (),
+            Object::UpValue(v) => {
+                if let UpValue::Closed(value) = &mut *v.borrow_mut() {
+                    *value = Value::Nil;
+                }
+            }
+            Object::Thread(t) => t.borrow_mut().clear(),
+        }
+    }
+}
+
+type Ptr = *const ();
+
+struct Node {
+    object: Object,
+    // references from other objects of the collection
+    internal: usize,
+    // objects of the collection this one refers to, when it was counted
+    children: Vec<Ptr>,
+    // of a table when it was counted
+    version: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Count,
+    Mark,
+    Sweep,
+}
+
+// state of a running collection
+struct Cycle {
+    phase: Phase,
+    nodes: HashMap<Ptr, Node>,
+    // to count in the count phase, gray objects in the mark phase and white ones to clear in
+    // the sweep phase
+    pending: Vec<Ptr>,
+    marked: HashSet<Ptr>,
+    // marked tables with weak keys or values, which are cleared by the atomic step
+    weak: HashSet<Ptr>,
+    // objects with finalizers when the collection started, in the order they got them
+    finalizable: Vec<Ptr>,
+    // the unreachable ones, found by the atomic step
+    finalize: Vec<Ptr>,
+}
+
+// whether the keys and the values of a table are weak, from `__mode` of its metatable
+fn weakness(t: &TableRef) -> (bool, bool) {
+    let mode = match t.borrow().metatable() {
+        Some(meta) => meta.borrow().get_str("__mode"),
+        None => return (false, false),
+    };
+    match mode {
+        Value::Str(mode) => (
+            mode.as_bytes().contains(&b'k'),
+            mode.as_bytes().contains(&b'v'),
+        ),
+        _ => (false, false),
+    }
+}
+
+impl Cycle {
+    // add an object found while counting
+    fn add(&mut self, object: Object) -> Ptr {
+        let ptr = object.ptr();
+        if let Entry::Vacant(entry) = self.nodes.entry(ptr) {
+            entry.insert(Node {
+                object,
+                internal: 0,
+                children: Vec::new(),
+                version: 0,
+            });
+            self.pending.push(ptr);
+        }
+        ptr
+    }
+
+    // count the references of an object, replacing those it had when counted before
+    fn count(&mut self, ptr: Ptr, discover: bool) {
+        let (object, old) = match self.nodes.get_mut(&ptr) {
+            Some(node) => (node.object.clone(), std::mem::take(&mut node.children)),
+            None => return,
+        };
+        for child in old {
+            if let Some(node) = self.nodes.get_mut(&child) {
+                node.internal -= 1;
+            }
+        }
+        let mut children = Vec::new();
+        for child in object.children() {
+            let child = if discover {
+                self.add(child)
+            } else if self.nodes.contains_key(&child.ptr()) {
+                child.ptr()
+            } else {
+                continue;
+            };
+            self.nodes.get_mut(&child).unwrap().internal += 1;
+            children.push(child);
+        }
+        let version = object.version();
+        drop(object);
+        let node = self.nodes.get_mut(&ptr).unwrap();
+        node.children = children;
+        node.version = version;
+    }
+
+    // objects with references from outside, the nodes hold one reference of each object
+    fn external(&self) -> Vec<Ptr> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.object.strong_count() > node.internal + 1)
+            .map(|(ptr, _)| *ptr)
+            .collect()
+    }
+
+    fn shade(&mut self, ptr: Ptr) {
+        if self.nodes.contains_key(&ptr) && self.marked.insert(ptr) {
+            self.pending.push(ptr);
+        }
+    }
+
+    // mark the references of a gray object. weak references aren't followed, and values with
+    // weak keys are marked by the atomic step once their keys are
+    fn blacken(&mut self, ptr: Ptr) {
+        let object = match self.nodes.get(&ptr) {
+            Some(node) => node.object.clone(),
+            None => return,
+        };
+        let weakness = match &object {
+            Object::Table(t) => weakness(t),
+            _ => (false, false),
+        };
+        let children: Vec<Ptr> = match (&object, weakness) {
+            (Object::Table(t), (weak_keys, weak_values)) if weak_keys || weak_values => {
+                self.weak.insert(ptr);
+                let t = t.borrow();
+                let mut children = Vec::new();
+                for (key, value) in t.iter() {
+                    if !weak_keys {
+                        children.push(key);
+                        if !weak_values {
+                            children.push(value.clone());
+                        }
+                    }
+                }
+                children.extend(t.metatable().cloned().map(Value::Table));
+                children
+                    .iter()
+                    .filter_map(Object::from_value)
+                    .map(|child| child.ptr())
+                    .collect()
+            }
+            _ => object.children().iter().map(Object::ptr).collect(),
+        };
+        drop(object);
+        for child in children {
+            self.shade(child);
+        }
+    }
+
+    // an object which will be cleared, values which aren't objects of the collection aren't
+    fn is_white(&self, value: &Value) -> bool {
+        match Object::from_value(value) {
+            Some(object) => {
+                let ptr = object.ptr();
+                self.nodes.contains_key(&ptr) && !self.marked.contains(&ptr)
+            }
+            None => false,
+        }
+    }
+
+    fn propagate(&mut self) {
+        while let Some(ptr) = self.pending.pop() {
+            self.blacken(ptr);
+        }
+    }
+
+    // mark the values of tables with weak keys whose keys are marked, until none are left
+    fn converge_ephemerons(&mut self) {
+        loop {
+            let mut values = Vec::new();
+            for ptr in self.weak.iter() {
+                if let Object::Table(t) = &self.nodes[ptr].object {
+                    if weakness(t) != (true, false) {
+                        continue;
+                    }
+                    for (key, value) in t.borrow().iter() {
+                        if !self.is_white(&key) && self.is_white(value) {
+                            values.push(value.clone());
+                        }
+                    }
+                }
+            }
+            if values.is_empty() {
+                return;
+            }
+            for value in values.iter().filter_map(Object::from_value) {
+                self.shade(value.ptr());
+            }
+            drop(values);
+            self.propagate();
+        }
+    }
+
+    // remove the fields of weak tables whose weak keys or values will be cleared, only those
+    // of weak values if `keys` is false
+    fn clear_weak(&mut self, keys: bool) {
+        for ptr in self.weak.iter() {
+            if let Object::Table(t) = &self.nodes[ptr].object {
+                let (weak_keys, weak_values) = weakness(t);
+                let weak_keys = weak_keys && keys;
+                let dead: Vec<Value> = t
+                    .borrow()
+                    .iter()
+                    .filter(|(key, value)| {
+                        (weak_keys && self.is_white(key)) || (weak_values && self.is_white(value))
+                    })
+                    .map(|(key, _)| key)
+                    .collect();
+                let mut t = t.borrow_mut();
+                for key in dead {
+                    t.set(key, Value::Nil);
+                }
+            }
+        }
+    }
+
+    // finish marking: rescan what was written since, find all roots and mark from them
+    fn atomic(&mut self, roots: Vec<Value>) {
+        let written: Vec<Ptr> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.object.written(node.version))
+            .map(|(ptr, _)| *ptr)
+            .collect();
+        for ptr in written.iter() {
+            self.count(*ptr, false);
+        }
+        // black objects which were written are gray again
+        for ptr in written {
+            if self.marked.contains(&ptr) {
+                self.pending.push(ptr);
+            }
+        }
+        for root in roots.iter().filter_map(Object::from_value) {
+            self.shade(root.ptr());
+        }
+        drop(roots);
+        for ptr in self.external() {
+            self.shade(ptr);
+        }
+        self.propagate();
+        self.converge_ephemerons();
+        // objects to finalize and what they refer to are resurrected, they are removed from
+        // weak values before and from weak keys after their finalizers run
+        self.clear_weak(false);
+        for ptr in self.finalizable.clone() {
+            if !self.marked.contains(&ptr) {
+                self.finalize.push(ptr);
+                self.shade(ptr);
+            }
+        }
+        self.propagate();
+        self.converge_ephemerons();
+        self.clear_weak(true);
+        self.pending = self
+            .nodes
+            .keys()
+            .filter(|ptr| !self.marked.contains(ptr))
+            .cloned()
+            .collect();
+        self.phase = Phase::Sweep;
+    }
+}
+
+// objects created by the vm, which may be part of cycles
+pub(crate) struct Heap {
+    tables: Vec<Weak<RefCell<Table>>>,
+    closures: Vec<Weak<Closure>>,
+    threads: Vec<Weak<RefCell<Thread>>>,
+    user_data: Vec<Weak<UserData>>,
+    threshold: usize,
+    // tables and userdata whose metatables had `__gc` when they were set
+    finalizable: Vec<Value>,
+    // unreachable ones, whose finalizers haven't run yet
+    finalizing: Vec<Value>,
+    mode: GcMode,
+    pause: usize,
+    step_size: usize,
+    cycle: Option<Cycle>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            tables: Vec::new(),
+            closures: Vec::new(),
+            threads: Vec::new(),
+            user_data: Vec::new(),
+            threshold: MIN_THRESHOLD,
+            finalizable: Vec::new(),
+            finalizing: Vec::new(),
+            mode: GcMode::Full,
+            pause: PAUSE,
+            step_size: STEP_SIZE,
+            cycle: None,
+        }
+    }
+}
+
+impl Heap {
+    pub(crate) fn track_table(&mut self, table: &TableRef) {
+        self.tables.push(Rc::downgrade(table));
+    }
+
+    // call the finalizer of a table or userdata when it's unreachable, once
+    pub(crate) fn track_finalizer(&mut self, value: Value) {
+        if !self.finalizable.iter().any(|v| v == &value) {
+            self.finalizable.push(value);
+        }
+    }
+
+    // objects whose finalizers are due, in the order to call them
+    pub(crate) fn take_finalizing(&mut self) -> Vec<Value> {
+        std::mem::take(&mut self.finalizing)
+    }
+
+    // all objects with finalizers, which are due when the vm is dropped
+    pub(crate) fn take_finalizable(&mut self) -> Vec<Value> {
+        let mut values = self.take_finalizing();
+        values.extend(std::mem::take(&mut self.finalizable).into_iter().rev());
+        values
+    }
+
+    pub(crate) fn track_closure(&mut self, closure: &Rc<Closure>) {
+        self.closures.push(Rc::downgrade(closure));
+    }
+
+    pub(crate) fn track_thread(&mut self, thread: &ThreadRef) {
+        self.threads.push(Rc::downgrade(thread));
+    }
+
+    pub(crate) fn track_user_data(&mut self, data: &Rc<UserData>) {
+        self.user_data.push(Rc::downgrade(data));
+    }
+
+    // number of tracked objects, some of them may have been freed already
+    pub(crate) fn objects(&self) -> usize {
+        self.tables.len() + self.closures.len() + self.threads.len() + self.user_data.len()
+    }
+
+    pub(crate) fn mode(&self) -> GcMode {
+        self.mode
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: GcMode) {
+        self.mode = mode;
+    }
+
+    pub(crate) fn tune(&mut self, pause: Option<usize>, step_size: Option<usize>) {
+        if let Some(pause) = pause {
+            self.pause = pause;
+        }
+        if let Some(step_size) = step_size {
+            self.step_size = step_size.max(1);
+        }
+    }
+
+    pub(crate) fn is_collecting(&self) -> bool {
+        self.cycle.is_some()
+    }
+
+    pub(crate) fn should_collect(&self) -> bool {
+        self.cycle.is_some() || self.objects() >= self.threshold
+    }
+
+    // approximate number of bytes used by the live objects
+    pub(crate) fn memory(&self) -> usize {
+        let tables: usize = self
+            .tables
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|t| t.borrow().memory())
+            .sum();
+        let closures: usize = self
+            .closures
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|f| {
+                std::mem::size_of::<Closure>()
+                    + f.up_values.len() * std::mem::size_of::<RefCell<UpValue>>()
+            })
+            .sum();
+        let threads: usize = self
+            .threads
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|t| t.borrow().memory())
+            .sum();
+        let user_data: usize = self
+            .user_data
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|u| u.memory())
+            .sum();
+        tables + closures + threads + user_data
+    }
+
+    // free the unreachable cycles, `roots` are the values of the vm
+    pub(crate) fn collect(&mut self, roots: impl Fn() -> Vec<Value>) {
+        // a collection which is running has missed the objects created since it started
+        while self.cycle.is_some() {
+            self.step(usize::MAX, &roots);
+        }
+        self.start();
+        while self.cycle.is_some() {
+            self.step(usize::MAX, &roots);
+        }
+    }
+
+    // a step of the step size, in incremental mode, or a full collection
+    pub(crate) fn run(&mut self, roots: impl Fn() -> Vec<Value>) {
+        match self.mode {
+            GcMode::Full => self.collect(roots),
+            GcMode::Incremental => {
+                if self.cycle.is_none() {
+                    self.start();
+                }
+                self.step(self.step_size, &roots);
+            }
+        }
+    }
+
+    pub(crate) fn start(&mut self) {
+        let mut cycle = Cycle {
+            phase: Phase::Count,
+            nodes: HashMap::new(),
+            pending: Vec::new(),
+            marked: HashSet::new(),
+            weak: HashSet::new(),
+            finalizable: Vec::new(),
+            finalize: Vec::new(),
+        };
+        let tracked = self
+            .tables
+            .iter()
+            .filter_map(|t| t.upgrade().map(Object::Table))
+            .chain(
+                self.closures
+                    .iter()
+                    .filter_map(|f| f.upgrade().map(Object::Closure)),
+            )
+            .chain(
+                self.threads
+                    .iter()
+                    .filter_map(|t| t.upgrade().map(Object::Thread)),
+            )
+            .chain(
+                self.user_data
+                    .iter()
+                    .filter_map(|u| u.upgrade().map(Object::UserData)),
+            );
+        for object in tracked {
+            cycle.add(object);
+        }
+        // the references of the heap to objects with finalizers are internal
+        for value in self.finalizable.iter() {
+            if let Some(object) = Object::from_value(value) {
+                let ptr = cycle.add(object);
+                cycle.nodes.get_mut(&ptr).unwrap().internal += 1;
+                cycle.finalizable.push(ptr);
+            }
+        }
+        self.cycle = Some(cycle);
+    }
+
+    // count, mark or sweep up to `budget` objects, true when the collection is finished
+    pub(crate) fn step(&mut self, budget: usize, roots: impl Fn() -> Vec<Value>) -> bool {
+        let cycle = match self.cycle.as_mut() {
+            Some(cycle) => cycle,
+            None => return true,
+        };
+        let mut work = 0;
+        while work < budget {
+            match (cycle.phase, cycle.pending.pop()) {
+                (Phase::Count, Some(ptr)) => cycle.count(ptr, true),
+                (Phase::Count, None) => {
+                    // start from the roots, more are found by the atomic step
+                    cycle.phase = Phase::Mark;
+                    for root in roots().iter().filter_map(Object::from_value) {
+                        cycle.shade(root.ptr());
+                    }
+                    for ptr in cycle.external() {
+                        cycle.shade(ptr);
+                    }
+                }
+                (Phase::Mark, Some(ptr)) => cycle.blacken(ptr),
+                (Phase::Mark, None) => {
+                    cycle.atomic(roots());
+                    // finalizers run in the reverse order of their objects getting them
+                    for ptr in cycle.finalize.iter().rev() {
+                        let object = &cycle.nodes[ptr].object;
+                        self.finalizing.extend(object.to_value());
+                    }
+                    let finalize: HashSet<Ptr> = cycle.finalize.iter().cloned().collect();
+                    self.finalizable
+                        .retain(|value| match Object::from_value(value) {
+                            Some(object) => !finalize.contains(&object.ptr()),
+                            None => false,
+                        });
+                }
+                (Phase::Sweep, Some(ptr)) => {
+                    if let Some(node) = cycle.nodes.remove(&ptr) {
+                        node.object.clear();
+                    }
+                }
+                (Phase::Sweep, None) => {
+                    self.finish();
+                    return true;
+                }
+            }
+            work += 1;
+        }
+        false
+    }
+
+    fn finish(&mut self) {
+        self.cycle = None;
+        // tables may be tracked again when their metatables are set
+        let mut tables = HashSet::new();
+        self.tables
+            .retain(|t| t.strong_count() > 0 && tables.insert(t.as_ptr()));
+        self.closures.retain(|f| f.strong_count() > 0);
+        self.threads.retain(|t| t.strong_count() > 0);
+        self.user_data.retain(|u| u.strong_count() > 0);
+        self.threshold = (self.objects() * self.pause / 100).max(MIN_THRESHOLD);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::ast::*;
+use crate::lexer::{LexError, Lexer};
+use crate::parser::{Parser, SyntaxError};
+use crate::tokens::TokenType;
+use crate::types::Source;
+use crate::debuggable;
+use std::ops::Range;
+
+// reparse only the top level statements touched by an edit, for editors and language servers.
+//
+// the reparsed region starts one statement before the edit and ends one statement after it,
+// statements outside the region are kept, their sources are shifted to the new text.
+// if the region doesn't lex or parse into the same statement boundaries,
+// e.g. the edit opens a long comment, the whole source is parsed again.
+
+// replace bytes in `start..end` of the old source with `text`
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Lex(LexError),
+    Syntax(SyntaxError),
+    // range of an edit which is reversed, beyond the source or not on char boundaries
+    InvalidEdit(Range<usize>),
+}
+
+pub struct IncrementalParser {
+    debug: bool,
+    source: String,
+    // none if the last parse failed
+    block: Option<Block>,
+    // byte range of the new source reparsed by the last edit, none for a full parse
+    reparsed: Option<Range<usize>>,
+}
+
+impl Default for IncrementalParser {
+    fn default() -> Self {
+        IncrementalParser::new()
+    }
+}
+
+impl IncrementalParser {
+    pub fn new() -> Self {
+        IncrementalParser {
+            debug: false,
+            source: String::new(),
+            block: None,
+            reparsed: None,
+        }
+    }
+
+    // parse the whole source
+    pub fn run(&mut self, source: &str) -> Result<&Block, ParseError> {
+        self.source = source.to_string();
+        self.reparsed = None;
+        self.block = None;
+        let block = self.parse(source)?;
+        Ok(self.block.get_or_insert(block))
+    }
+
+    // an invalid edit is rejected and leaves the parser as it was
+    pub fn edit(&mut self, edit: &TextEdit) -> Result<&Block, ParseError> {
+        if edit.start > edit.end
+            || !self.source.is_char_boundary(edit.start)
+            || !self.source.is_char_boundary(edit.end)
+        {
+            return Err(ParseError::InvalidEdit(edit.start..edit.end));
+        }
+        let mut source = String::with_capacity(self.source.len() + edit.text.len());
+        source.push_str(&self.source[..edit.start]);
+        source.push_str(&edit.text);
+        source.push_str(&self.source[edit.end..]);
+        let block = match self.block.take() {
+            Some(block) => block,
+            None => return self.run(&source),
+        };
+        match self.reparse(block, &source, edit) {
+            Some((block, reparsed)) => {
+                self.source = source;
+                self.reparsed = Some(reparsed);
+                Ok(self.block.get_or_insert(block))
+            }
+            None => self.run(&source),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn block(&self) -> Option<&Block> {
+        self.block.as_ref()
+    }
+
+    pub fn reparsed(&self) -> Option<Range<usize>> {
+        self.reparsed.clone()
+    }
+
+    fn parse(&self, source: &str) -> Result<Block, ParseError> {
+        let mut lexer = Lexer::new();
+        lexer.set_debug(self.debug);
+        let tokens = lexer.run(source).map_err(ParseError::Lex)?;
+        let mut parser = Parser::new();
+        parser.set_debug(self.debug);
+        parser.run(tokens).map_err(ParseError::Syntax)
+    }
+
+    // reparse the region around the edit, return the new block and the reparsed range
+    fn reparse(
+        &self,
+        mut block: Block,
+        source: &str,
+        edit: &TextEdit,
+    ) -> Option<(Block, Range<usize>)> {
+        let stats = &block.stats;
+        let end_of = |stat: &StatInfo| stat.source.pos + stat.source.length;
+        // one statement before the one containing the edit start
+        let first = stats
+            .iter()
+            .rposition(|stat| stat.source.pos <= edit.start)
+            .unwrap_or(0)
+            .saturating_sub(1);
+        // one statement after the one containing the edit end
+        let last = stats
+            .iter()
+            .position(|stat| end_of(stat) > edit.end)
+            .map(|i| i + 1)
+            .filter(|i| *i < stats.len());
+        let old_start = if first == 0 { 0 } else { stats[first].source.pos };
+        let old_end = last.map_or(self.source.len(), |i| end_of(&stats[i]));
+        let delta = edit.text.len() as isize - (edit.end - edit.start) as isize;
+        let new_end = (old_end as isize + delta) as usize;
+        let region = &source[old_start..new_end];
+
+        // the region must lex and parse completely, and end with the same statement as before
+        let mut lexer = Lexer::new();
+        let tokens = lexer.run(region).ok()?;
+        let tokens_end = tokens
+            .iter()
+            .rev()
+            .find(|t| !t.is_comment() && t.t != TokenType::Eos)
+            .map_or(0, |t| t.source.pos + t.source.length);
+        let mut region_block = Parser::new().run(tokens).ok()?;
+        let parsed_end = region_block.stats.last().map_or(0, end_of);
+        if parsed_end != tokens_end {
+            return None;
+        }
+        if let Some(i) = last {
+            let guard = &stats[i].source;
+            let new_guard = region_block.stats.last()?.source;
+            if new_guard.pos + old_start != (guard.pos as isize + delta) as usize
+                || new_guard.length != guard.length
+            {
+                return None;
+            }
+        }
+
+        // move region statements to their place in the new source
+        let (start_line, start_col) = if first == 0 {
+            (1, 1)
+        } else {
+            (stats[first].source.line, stats[first].source.col)
+        };
+        for stat in region_block.stats.iter_mut() {
+            shift_stat(stat, &|s: &mut Source| {
+                if s.line == 1 {
+                    s.col += start_col - 1;
+                }
+                s.pos += old_start;
+                s.line += start_line - 1;
+            });
+        }
+
+        // statements after the region keep their relative positions
+        let old_lines = count_lines(&self.source[..old_end]);
+        let new_lines = count_lines(&source[..new_end]);
+        let rest = last.map_or(stats.len(), |i| i + 1);
+        let mut tail = block.stats.split_off(rest);
+        for stat in tail.iter_mut() {
+            shift_stat(stat, &|s: &mut Source| {
+                s.pos = (s.pos as isize + delta) as usize;
+                if s.line == old_lines {
+                    // on the same line as the region end, the column changes as well
+                    s.col = s.pos - line_start(source, s.pos) + 1;
+                }
+                s.line = s.line + new_lines - old_lines;
+            });
+        }
+        block.stats.truncate(first);
+        block.stats.append(&mut region_block.stats);
+        block.stats.append(&mut tail);
+        Some((block, old_start..new_end))
+    }
+
+    debuggable!();
+}
+
+// line number at the end of `text`
+fn count_lines(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut lines = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        if matches!(bytes[i], b'\r' | b'\n') {
+            // \r\n or \n\r is one line break
+            if matches!(bytes.get(i + 1), Some(b'\r') | Some(b'\n')) && bytes[i + 1] != bytes[i] {
+                i += 1;
+            }
+            lines += 1;
+        }
+        i += 1;
+    }
+    lines
+}
+
+fn line_start(text: &str, pos: usize) -> usize {
+    text.as_bytes()[..pos]
+        .iter()
+        .rposition(|c| matches!(c, b'\r' | b'\n'))
+        .map_or(0, |i| i + 1)
+}
+
+fn shift_block(block: &mut Block, f: &dyn Fn(&mut Source)) {
+    for stat in block.stats.iter_mut() {
+        shift_stat(stat, f);
+    }
+}
+
+fn shift_stat(stat: &mut StatInfo, f: &dyn Fn(&mut Source)) {
+    f(&mut stat.source);
+    match &mut stat.stat {
+        Stat::IfStat(stat) => {
+            for cond_block in stat.cond_blocks.iter_mut() {
+                shift_expr(&mut cond_block.cond, f);
+                shift_block(&mut cond_block.block, f);
+            }
+            if let Some(block) = &mut stat.else_block {
+                shift_block(block, f);
+            }
+        }
+        Stat::WhileStat(WhileStat { cond, block }) | Stat::RepeatStat(RepeatStat { cond, block }) => {
+            shift_expr(cond, f);
+            shift_block(block, f);
+        }
+        Stat::DoBlock(DoBlock { block }) => shift_block(block, f),
+        Stat::ForStat(ForStat::ForNum(stat)) => {
+            shift_expr(&mut stat.init, f);
+            shift_expr(&mut stat.limit, f);
+            if let Some(step) = &mut stat.step {
+                shift_expr(step, f);
+            }
+            shift_block(&mut stat.body, f);
+        }
+        Stat::ForStat(ForStat::ForList(stat)) => {
+            shift_exprs(&mut stat.exprs, f);
+            shift_block(&mut stat.body, f);
+        }
+        Stat::FuncStat(stat) => shift_block(&mut stat.body.block, f),
+        Stat::LocalStat(LocalStat { exprs, .. }) | Stat::RetStat(RetStat { exprs }) => {
+            shift_exprs(exprs, f)
+        }
+        Stat::AssignStat(stat) => {
+            for assignable in stat.left.iter_mut() {
+                shift_assignable(assignable, f);
+            }
+            shift_exprs(&mut stat.right, f);
+        }
+        Stat::CompoundAssignStat(stat) => {
+            shift_assignable(&mut stat.left, f);
+            shift_expr(&mut stat.right, f);
+        }
+        Stat::CallStat(stat) => shift_assignable(&mut stat.call, f),
+        Stat::LabelStat(_) | Stat::BreakStat(_) | Stat::GotoStat(_) | Stat::CommentStat(_) => (),
+    }
+}
+
+fn shift_exprs(exprs: &mut [Expr], f: &dyn Fn(&mut Source)) {
+    for expr in exprs.iter_mut() {
+        shift_expr(expr, f);
+    }
+}
+
+fn shift_assignable(assignable: &mut Assignable, f: &dyn Fn(&mut Source)) {
+    match assignable {
+        Assignable::Name(_) => (),
+        Assignable::ParenExpr(expr) => shift_expr(expr, f),
+        Assignable::SuffixedExpr(expr) => shift_suffixed_expr(expr, f),
+    }
+}
+
+fn shift_suffixed_expr(expr: &mut SuffixedExpr, f: &dyn Fn(&mut Source)) {
+    shift_expr(&mut expr.primary, f);
+    for suffix in expr.suffixes.iter_mut() {
+        match suffix {
+            Suffix::Index(expr) => shift_expr(expr, f),
+            Suffix::FuncArgs(FuncArgs::Exprs(exprs)) => shift_exprs(exprs, f),
+            Suffix::FuncArgs(FuncArgs::Table(table)) => shift_table(table, f),
+            _ => (),
+        }
+    }
+}
+
+fn shift_table(table: &mut Table, f: &dyn Fn(&mut Source)) {
+    for field in table.fields.iter_mut() {
+        match field {
+            Field::ListField(value) => shift_expr(value, f),
+            Field::RecFileld(RecField { key, value }) => {
+                if let FieldKey::Expr(key) = key {
+                    shift_expr(key, f);
+                }
+                shift_expr(value, f);
+            }
+        }
+    }
+}
+
+// only function bodies in expressions contain statements
+fn shift_expr(expr: &mut Expr, f: &dyn Fn(&mut Source)) {
+    match expr {
+        Expr::ParenExpr(expr) => shift_expr(expr, f),
+        Expr::FuncBody(body) => shift_block(&mut body.block, f),
+        Expr::Table(table) => shift_table(table, f),
+        Expr::BinExpr(expr) => {
+            shift_expr(&mut expr.left, f);
+            shift_expr(&mut expr.right, f);
+        }
+        Expr::UnExpr(expr) => shift_expr(&mut expr.expr, f),
+        Expr::SuffixedExpr(expr) => shift_suffixed_expr(expr, f),
+        _ => (),
+    }
+}
@@ -0,0 +1,38 @@
+use crate::value::Value;
+use crate::vm::RuntimeError;
+
+// hooks of the host around calls of a wrapped function, see `Vm::wrap`.
+//
+// `before` sees the args and may veto the call by returning an error, which is raised in the
+// caller like any runtime error. `after` sees the results.
+
+type BeforeHook = Box<dyn FnMut(&[Value]) -> Result<(), RuntimeError>>;
+type AfterHook = Box<dyn FnMut(&[Value])>;
+
+#[derive(Default)]
+pub struct CallHooks {
+    pub(crate) before: Option<BeforeHook>,
+    pub(crate) after: Option<AfterHook>,
+}
+
+impl CallHooks {
+    pub fn new() -> Self {
+        CallHooks::default()
+    }
+
+    pub fn before<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&[Value]) -> Result<(), RuntimeError> + 'static,
+    {
+        self.before = Some(Box::new(hook));
+        self
+    }
+
+    pub fn after<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&[Value]) + 'static,
+    {
+        self.after = Some(Box::new(hook));
+        self
+    }
+}
@@ -1,6 +1,7 @@
 use crate::tokens::{Token, TokenType, TokenValue};
-use crate::types::{FloatType, IntType, Number, Source};
+use crate::types::{Dialect, FloatType, IntType, Number, Source};
 use crate::{debuggable, error, success};
+use std::fmt;
 use std::mem;
 use std::str;
 
@@ -60,6 +61,26 @@ impl<'a> Context<'a> {
         }
     }
 
+    // skip chars while `f` returns true, return count of skipped chars
+    pub fn skip_while(&mut self, f: impl Fn(u8) -> bool) -> usize {
+        let rest = &self.buffer.as_bytes()[self.current..];
+        let n = rest.iter().position(|c| !f(*c)).unwrap_or(rest.len());
+        self.skip(n);
+        n
+    }
+
+    // eat chars while `f` returns true, and write these chars to output
+    pub fn write_while(&mut self, f: impl Fn(u8) -> bool, output: &mut Vec<u8>) {
+        let start = self.current;
+        self.skip_while(f);
+        output.extend_from_slice(&self.buffer.as_bytes()[start..self.current]);
+    }
+
+    // get chars from `start` to current position
+    pub fn slice_from(&self, start: usize) -> &'a str {
+        &self.buffer[start..self.current]
+    }
+
     pub fn inc_line(&mut self) {
         self.col = 1;
         self.line += 1;
@@ -82,14 +103,17 @@ pub struct LexerConfig {
     // if use origin string, lexer won't escape special chars and keep the quotes or string boundaries.
     pub use_origin_string: bool,
     // reserve comments or not
-    pub reserve_comments: bool
+    pub reserve_comments: bool,
+    // lex `!=` as `~=` in the extended dialect
+    pub dialect: Dialect,
 }
 
 impl LexerConfig {
     pub fn default() -> Self {
         LexerConfig {
             use_origin_string: false,
-            reserve_comments: false
+            reserve_comments: false,
+            dialect: Dialect::Standard,
         }
     }
 }
@@ -103,6 +127,12 @@ pub struct Lexer {
 #[derive(Debug)]
 pub struct LexError(String);
 
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 type LexResult = Result<Option<(TokenType, TokenValue)>, LexError>;
 
 macro_rules! lex_error {
@@ -130,6 +160,8 @@ impl<'a> Lexer {
 
     pub fn run(&mut self, input: &'a str) -> Result<Vec<Token>, LexError> {
         self.reset();
+        // roughly one token every 8 bytes in typical sources
+        self.tokens.reserve(input.len() / 8);
         let mut ctx = Context::new(input);
         loop {
             ctx.save();
@@ -138,12 +170,20 @@ impl<'a> Lexer {
                     _ if Lexer::is_line_break(c) => self.read_line_break(&mut ctx)?,
                     _ if Lexer::is_space(c) => self.read_space(&mut ctx)?,
                     _ if Lexer::is_digit(c) => self.read_number(&mut ctx)?,
+                    _ if self.config.dialect == Dialect::Extended
+                        && Lexer::compound_assign(&ctx).is_some() =>
+                    {
+                        self.read_compound_assign(&mut ctx)?
+                    }
                     b'-' if self.check_next(&ctx, '-') => self.read_comment(&mut ctx)?,
                     b'=' => self.read_eq_assign(&mut ctx)?,
                     b'<' => self.read_le_shl_lt(&mut ctx)?,
                     b'>' => self.read_ge_shr_gt(&mut ctx)?,
                     b'/' if self.check_next(&ctx, '/') => self.read_idiv(&mut ctx)?,
                     b'~' => self.read_ne_xor(&mut ctx)?,
+                    b'!' if self.config.dialect == Dialect::Extended && self.check_next(&ctx, '=') => {
+                        self.read_bang_ne(&mut ctx)?
+                    }
                     b':' => self.read_colon(&mut ctx)?,
                     b'.' => self.read_attr_concat_dots_numbers(&mut ctx)?,
                     b'"' | b'\'' => self.read_short_string(&mut ctx)?,
@@ -174,7 +214,7 @@ impl<'a> Lexer {
     }
 
     fn read_space(&self, ctx: &mut Context) -> LexResult {
-        ctx.next();
+        ctx.skip_while(Lexer::is_space);
         Ok(None)
     }
 
@@ -194,21 +234,13 @@ impl<'a> Lexer {
     }
 
     fn read_short_comment(&mut self, ctx: &mut Context) -> LexResult {
-        let mut bytes: Vec<u8> = Vec::new();
-        while let Some(c) = ctx.get() {
-            if Lexer::is_line_break(c) {
-                break;
-            }
-            ctx.write_into(1, &mut bytes);
-        }
-        if let Ok(comment) = str::from_utf8(&bytes) {
-            if self.config.reserve_comments {
-                success!((TokenType::SComment, TokenValue::Str(comment.to_string())))
-            } else {
-                Ok(None)
-            }
+        let start = ctx.current;
+        ctx.skip_while(|c| !Lexer::is_line_break(c));
+        if self.config.reserve_comments {
+            let comment = ctx.slice_from(start).to_string();
+            success!((TokenType::SComment, TokenValue::Str(comment)))
         } else {
-            lex_error!(self, ctx, "invalid single line comment")
+            Ok(None)
         }
     }
 
@@ -271,6 +303,40 @@ impl<'a> Lexer {
         self.read_token2(ctx, '=', TokenType::Ne, TokenType::BXor)
     }
 
+    fn read_bang_ne(&mut self, ctx: &mut Context) -> LexResult {
+        ctx.skip(2);
+        success!((TokenType::Ne, TokenValue::None))
+    }
+
+    // compound assignment operator at current position and its length
+    fn compound_assign(ctx: &Context) -> Option<(TokenType, usize)> {
+        let c = ctx.get()?;
+        match (c, ctx.get_next()?, ctx.get_ahead(2)) {
+            (b'/', b'/', Some(b'=')) => Some((TokenType::IDivAssign, 3)),
+            (b'.', b'.', Some(b'=')) => Some((TokenType::ConcatAssign, 3)),
+            (_, b'=', _) => match c {
+                b'+' => Some((TokenType::AddAssign, 2)),
+                b'-' => Some((TokenType::SubAssign, 2)),
+                b'*' => Some((TokenType::MulAssign, 2)),
+                b'/' => Some((TokenType::DivAssign, 2)),
+                b'%' => Some((TokenType::ModAssign, 2)),
+                b'^' => Some((TokenType::PowAssign, 2)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn read_compound_assign(&mut self, ctx: &mut Context) -> LexResult {
+        match Lexer::compound_assign(ctx) {
+            Some((t, n)) => {
+                ctx.skip(n);
+                success!((t, TokenValue::None))
+            }
+            None => unreachable!(),
+        }
+    }
+
     fn read_colon(&mut self, ctx: &mut Context) -> LexResult {
         self.read_token2(ctx, ':', TokenType::DbColon, TokenType::Colon)
     }
@@ -297,37 +363,33 @@ impl<'a> Lexer {
 
     fn read_number(&mut self, ctx: &mut Context) -> LexResult {
         let mut expo = ('E', 'e');
-        let mut num_str: Vec<u8> = Vec::new();
+        let start = ctx.current;
         let mut hex = false;
         if self.check_current(ctx, '0') && self.check_next2(ctx, 'x', 'X') {
             expo = ('P', 'p');
-            ctx.write_into(2, &mut num_str);
+            ctx.skip(2);
             hex = true;
         }
         let is_digit = |c| {
             (hex && Lexer::is_hex_digit(c)) || (!hex && Lexer::is_digit(c)) || (c as char) == '.'
         };
         loop {
-            if self.check_current_if(ctx, is_digit) {
-                ctx.write_into(1, &mut num_str)
+            if ctx.skip_while(is_digit) > 0 {
+                continue;
             } else if self.check_current2(ctx, expo.0, expo.1) {
-                ctx.write_into(1, &mut num_str);
+                ctx.next();
                 if self.check_current2(ctx, '-', '+') {
-                    ctx.write_into(1, &mut num_str)
+                    ctx.next();
                 }
             } else {
                 break;
             }
         }
-        if let Ok(string) = str::from_utf8(&num_str) {
-            let num = Lexer::str_to_num(string);
-            match num {
-                Number::Int(n) => success!((TokenType::Int, TokenValue::Int(n))),
-                Number::Float(n) => success!((TokenType::Flt, TokenValue::Float(n))),
-                _ => lex_error!(self, ctx, "malformed number"),
-            }
-        } else {
-            unreachable!();
+        let num = Lexer::str_to_num(ctx.slice_from(start));
+        match num {
+            Number::Int(n) => success!((TokenType::Int, TokenValue::Int(n))),
+            Number::Float(n) => success!((TokenType::Flt, TokenValue::Float(n))),
+            _ => lex_error!(self, ctx, "malformed number"),
         }
     }
 
@@ -461,18 +523,15 @@ impl<'a> Lexer {
         }
         ctx.next();
         let unfinished_error: &'static str = "unfinished string";
+        let is_plain = |c| Some(c) != start && c != b'\\' && !Lexer::is_line_break(c);
         while ctx.get() != start {
             match ctx.get() {
                 Some(b'\\') if self.config.use_origin_string => ctx.write_into(2, &mut bytes),
                 Some(b'\\') => self.try_read_esc(ctx, &mut bytes)?,
-                Some(c) => {
-                    if Lexer::is_line_break(c) {
-                        return lex_error!(self, ctx, unfinished_error);
-                    } else {
-                        bytes.push(c);
-                        ctx.next();
-                    }
+                Some(c) if Lexer::is_line_break(c) => {
+                    return lex_error!(self, ctx, unfinished_error)
                 }
+                Some(_) => ctx.write_while(is_plain, &mut bytes),
                 None => return lex_error!(self, ctx, unfinished_error),
             }
         }
@@ -550,7 +609,9 @@ impl<'a> Lexer {
                 _ if Lexer::is_line_break(c) => {
                     self.read_line_break(ctx)?;
                 }
-                _ => ctx.next(),
+                _ => {
+                    ctx.skip_while(|c| c != b']' && !Lexer::is_line_break(c));
+                }
             }
         }
         lex_error!(
@@ -596,17 +657,14 @@ impl<'a> Lexer {
                 ctx.next();
                 return success!((t, TokenValue::None));
             } else if self.check_current_if(ctx, |c| Lexer::is_valid_name_start(c)) {
-                let mut word: Vec<u8> = Vec::new();
-                ctx.write_into(1, &mut word);
-                while self.check_current_if(ctx, |c| Lexer::is_valid_name(c)) {
-                    ctx.write_into(1, &mut word);
-                }
-                if let Ok(s) = str::from_utf8(&word) {
-                    if let Some(t) = TokenType::from_keyword(s) {
-                        return success!((t, TokenValue::None));
-                    } else {
-                        return success!((TokenType::Name, TokenValue::Str(s.to_string())));
-                    }
+                let start = ctx.current;
+                ctx.next();
+                ctx.skip_while(Lexer::is_valid_name);
+                let word = ctx.slice_from(start);
+                if let Some(t) = TokenType::from_keyword(word) {
+                    return success!((t, TokenValue::None));
+                } else {
+                    return success!((TokenType::Name, TokenValue::Str(word.to_string())));
                 }
             } else {
                 return lex_error!(self, ctx, &format!("unknown token near {}", c as char));
@@ -674,9 +732,14 @@ impl<'a> Lexer {
         }
     }
 
+    // whitespace of c's isspace, which lua skips around numerals in strings
+    fn is_numeral_space(c: u8) -> bool {
+        matches!(c, b' ' | b'\t'..=b'\r')
+    }
+
     fn skip_spaces(bytes: &[u8], i: usize) -> usize {
         let mut index = i;
-        while index < bytes.len() && bytes[index] == b' ' {
+        while index < bytes.len() && Lexer::is_numeral_space(bytes[index]) {
             index += 1;
         }
         index
@@ -702,24 +765,29 @@ impl<'a> Lexer {
         (sign, index)
     }
 
+    // hexadecimal integers wrap around, decimal ones which overflow are left to `str_to_float`
     pub fn str_to_int(s: &str) -> Option<IntType> {
         let bytes = s.as_bytes();
         let len = bytes.len();
-        let mut r: IntType = 0;
-        let mut i = 0;
+        let mut r: u64 = 0;
         let mut empty = true;
-        i = Lexer::skip_spaces(bytes, i);
+        let i = Lexer::skip_spaces(bytes, 0);
         let (sign, mut i) = Lexer::get_sign(bytes, i);
         if Lexer::starts_with_0x(bytes, i) {
             i += 2;
             while i < len && Lexer::is_hex_digit(bytes[i]) {
-                r = (r << 4) + (Lexer::to_hex_digit(bytes[i]) as IntType);
+                r = (r << 4) + (Lexer::to_hex_digit(bytes[i]) as u64);
                 i += 1;
                 empty = false;
             }
         } else {
+            // the magnitude of the minimum integer is one more than the maximum
+            let max = IntType::MAX as u64 + (sign < 0) as u64;
             while i < len && Lexer::is_digit(bytes[i]) {
-                r = r * 10 + (Lexer::to_digit(bytes[i]) as IntType);
+                r = r
+                    .checked_mul(10)
+                    .and_then(|r| r.checked_add(Lexer::to_digit(bytes[i]) as u64))
+                    .filter(|r| *r <= max)?;
                 i += 1;
                 empty = false;
             }
@@ -727,27 +795,39 @@ impl<'a> Lexer {
         i = Lexer::skip_spaces(bytes, i);
         if empty || i != len {
             None
+        } else if sign < 0 {
+            Some(r.wrapping_neg() as IntType)
         } else {
-            Some((r as IntType) * sign)
+            Some(r as IntType)
         }
     }
 
     pub fn str_to_float(s: &str) -> Option<FloatType> {
         let bytes = s.as_bytes();
-        let mut i = 0;
-        i = Lexer::skip_spaces(bytes, i);
+        let start = Lexer::skip_spaces(bytes, 0);
+        let mut end = bytes.len();
+        while end > start && Lexer::is_numeral_space(bytes[end - 1]) {
+            end -= 1;
+        }
+        let bytes = &bytes[start..end];
+        // rust accepts 'inf' and 'nan', lua doesn't
+        if bytes.iter().any(|c| *c == b'n' || *c == b'N') {
+            return None;
+        }
+        let (sign, i) = Lexer::get_sign(bytes, 0);
         if Lexer::starts_with_0x(bytes, i) {
-            Lexer::str_to_hex_float(&bytes[2..])
+            Lexer::str_to_hex_float(&bytes[i + 2..]).map(|f| f * sign as FloatType)
         } else {
-            match s.parse::<FloatType>() {
+            match s[start..end].parse::<FloatType>() {
                 Ok(f) => Some(f),
                 Err(_e) => None,
             }
         }
     }
 
+    // digits after `0x` with an optional fraction and binary exponent, e.g. `1.8p4`
     pub fn str_to_hex_float(bytes: &[u8]) -> Option<FloatType> {
-        let (sign, mut i) = Lexer::get_sign(bytes, 0);
+        let mut i = 0;
         let mut has_dot = false;
         let mut e: IntType = 0;
         let mut r = 0.0;
@@ -777,12 +857,14 @@ impl<'a> Lexer {
         if i < bytes.len() && (bytes[i] == b'p' || bytes[i] == b'P') {
             i += 1;
             let (esign, mut index) = Lexer::get_sign(bytes, i);
-            let mut exp_value = 0;
+            let mut exp_value: IntType = 0;
             let mut exp_empty = true;
             while index < bytes.len() {
                 if Lexer::is_digit(bytes[index]) {
                     exp_empty = false;
-                    exp_value = exp_value * 10 + Lexer::to_digit(bytes[index]) as IntType;
+                    exp_value = exp_value
+                        .saturating_mul(10)
+                        .saturating_add(Lexer::to_digit(bytes[index]) as IntType);
                 } else {
                     break;
                 }
@@ -791,19 +873,19 @@ impl<'a> Lexer {
             if exp_empty {
                 return None;
             }
-            e += exp_value * esign;
+            e = e.saturating_add(exp_value * esign);
             i = index;
         }
         r = r * (2 as FloatType).powf(e as FloatType);
-        Lexer::skip_spaces(bytes, i);
+        i = Lexer::skip_spaces(bytes, i);
         if empty || i != bytes.len() {
             None
         } else {
-            Some(r * (sign as FloatType))
+            Some(r)
         }
     }
 
-    fn str_to_num(s: &str) -> Number {
+    pub fn str_to_num(s: &str) -> Number {
         if let Some(i) = Lexer::str_to_int(s) {
             Number::Int(i)
         } else if let Some(f) = Lexer::str_to_float(s) {
@@ -1,11 +1,38 @@
 pub mod ast;
 pub mod ast_walker;
+pub mod base;
+pub mod cache;
+pub mod checker;
 pub mod compiler;
 pub mod consts;
+pub mod convert;
+pub mod coroutine;
+pub mod cst;
+pub mod debug;
+pub mod disasm;
+pub mod doc;
+pub mod dump;
+pub mod gc;
+pub mod incremental;
+pub mod intercept;
 pub mod lexer;
+mod lib_util;
 pub mod macros;
+pub mod metamethod;
 pub mod opcodes;
 pub mod parser;
+pub mod propagate;
+pub mod resolver;
+pub mod sourcemap;
+pub mod stack;
+pub mod stable;
+pub mod string;
+pub mod symbol;
+pub mod table;
 pub mod tokens;
+pub mod traceback;
 pub mod types;
+pub mod value;
+pub mod verify;
+pub mod vm;
 pub mod proto;
@@ -0,0 +1,34 @@
+use crate::types::IntType;
+use crate::value::{float_to_int, str_to_number, Value};
+use crate::vm::{RuntimeError, Vm};
+
+// helpers of the libraries for checking args of their functions, with the messages of lua
+
+pub(crate) type LibResult = Result<Vec<Value>, RuntimeError>;
+pub(crate) type LibFn = fn(&mut Vm, Vec<Value>) -> LibResult;
+
+pub(crate) fn bad_argument(n: usize, name: &str, msg: &str) -> RuntimeError {
+    RuntimeError::new(format!("bad argument #{} to '{}' ({})", n, name, msg))
+}
+
+// the arg `n` isn't of the type `expected`
+pub(crate) fn type_error(args: &[Value], n: usize, name: &str, expected: &str) -> RuntimeError {
+    let got = args
+        .get(n - 1)
+        .map_or("no value", |value| value.type_name());
+    bad_argument(n, name, &format!("{} expected, got {}", expected, got))
+}
+
+// an integer arg, floats with integral values and numerals are converted
+pub(crate) fn check_int(args: &[Value], n: usize, name: &str) -> Result<IntType, RuntimeError> {
+    let value = match args.get(n - 1) {
+        Some(Value::Str(s)) => str_to_number(s.as_bytes()),
+        value => value.cloned(),
+    };
+    match value {
+        Some(Value::Int(i)) => Ok(i),
+        Some(Value::Float(f)) => float_to_int(f)
+            .ok_or_else(|| bad_argument(n, name, "number has no integer representation")),
+        _ => Err(type_error(args, n, name, "number")),
+    }
+}
@@ -24,7 +24,6 @@ macro_rules! error {
         if $self.is_debug() {
             panic!("{}", &$msg);
         } else {
-            println!("{}", &$msg);
             Err($error_type($msg))
         }
     };
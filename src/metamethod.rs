@@ -0,0 +1,123 @@
+use crate::opcodes::OpCode;
+use crate::value::Value;
+
+// events of metatables, the vm looks up handlers by their names, e.g. `__index`
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaMethod {
+    Index,
+    NewIndex,
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    Pow,
+    Div,
+    IDiv,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+    Unm,
+    BNot,
+    Eq,
+    Lt,
+    Le,
+    Concat,
+    Len,
+    // not an event of the vm, called by `Vm::tostring`
+    ToString,
+    // finalizer, called by the collector
+    Gc,
+    // of to-be-closed variables
+    Close,
+}
+
+impl MetaMethod {
+    pub const ALL: [MetaMethod; 24] = [
+        MetaMethod::Index,
+        MetaMethod::NewIndex,
+        MetaMethod::Add,
+        MetaMethod::Sub,
+        MetaMethod::Mul,
+        MetaMethod::Mod,
+        MetaMethod::Pow,
+        MetaMethod::Div,
+        MetaMethod::IDiv,
+        MetaMethod::BAnd,
+        MetaMethod::BOr,
+        MetaMethod::BXor,
+        MetaMethod::Shl,
+        MetaMethod::Shr,
+        MetaMethod::Unm,
+        MetaMethod::BNot,
+        MetaMethod::Eq,
+        MetaMethod::Lt,
+        MetaMethod::Le,
+        MetaMethod::Concat,
+        MetaMethod::Len,
+        MetaMethod::ToString,
+        MetaMethod::Gc,
+        MetaMethod::Close,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MetaMethod::Index => "__index",
+            MetaMethod::NewIndex => "__newindex",
+            MetaMethod::Add => "__add",
+            MetaMethod::Sub => "__sub",
+            MetaMethod::Mul => "__mul",
+            MetaMethod::Mod => "__mod",
+            MetaMethod::Pow => "__pow",
+            MetaMethod::Div => "__div",
+            MetaMethod::IDiv => "__idiv",
+            MetaMethod::BAnd => "__band",
+            MetaMethod::BOr => "__bor",
+            MetaMethod::BXor => "__bxor",
+            MetaMethod::Shl => "__shl",
+            MetaMethod::Shr => "__shr",
+            MetaMethod::Unm => "__unm",
+            MetaMethod::BNot => "__bnot",
+            MetaMethod::Eq => "__eq",
+            MetaMethod::Lt => "__lt",
+            MetaMethod::Le => "__le",
+            MetaMethod::Concat => "__concat",
+            MetaMethod::Len => "__len",
+            MetaMethod::ToString => "__tostring",
+            MetaMethod::Gc => "__gc",
+            MetaMethod::Close => "__close",
+        }
+    }
+
+    // event of an arithmetic or bitwise operator
+    pub fn from_op(op: OpCode) -> Option<MetaMethod> {
+        let event = match op {
+            OpCode::Add => MetaMethod::Add,
+            OpCode::Sub => MetaMethod::Sub,
+            OpCode::Mul => MetaMethod::Mul,
+            OpCode::Mod => MetaMethod::Mod,
+            OpCode::Pow => MetaMethod::Pow,
+            OpCode::Div => MetaMethod::Div,
+            OpCode::IDiv => MetaMethod::IDiv,
+            OpCode::BAdd => MetaMethod::BAnd,
+            OpCode::BOr => MetaMethod::BOr,
+            OpCode::BXor => MetaMethod::BXor,
+            OpCode::Shl => MetaMethod::Shl,
+            OpCode::Shr => MetaMethod::Shr,
+            OpCode::Unm => MetaMethod::Unm,
+            OpCode::BNot => MetaMethod::BNot,
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+// names as values, created once per vm, indexed by `MetaMethod as usize`
+pub(crate) fn names() -> Vec<Value> {
+    MetaMethod::ALL
+        .iter()
+        .map(|event| Value::str(event.name()))
+        .collect()
+}
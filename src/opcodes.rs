@@ -26,6 +26,18 @@ pub enum OpMode {
     IAC,
 }
 
+// args of an instruction decoded by its op mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OpArgs {
+    A(u32),
+    AB(u32, u32),
+    ABC(u32, u32, u32),
+    ABx(u32, u32),
+    AsBx(u32, i32),
+    Ax(u32),
+    AC(u32, u32),
+}
+
 pub const SIZE_OP: u32 = 6;
 pub const SIZE_A: u32 = 8;
 pub const SIZE_B: u32 = 9;
@@ -48,10 +60,21 @@ pub const MAXARG_BX: u32 = (1 << SIZE_BX) - 1;
 pub const MAXARG_SBX: i32 = (MAXARG_BX as i32) >> 1;
 
 pub const MASK_K: u32 = 1 << (SIZE_B - 1);
+// max index of constants that can be encoded as RK
+pub const MAX_INDEX_RK: u32 = MASK_K - 1;
 
 pub const NO_JUMP: i32 = -1;
 pub const NO_REG: u32 = MAXARG_A;
 
+// max number of registers in a function, must fit in A
+pub const MAX_REGS: u32 = 255;
+
+// number of list items stored by each SETLIST
+pub const FIELDS_PER_FLUSH: usize = 50;
+
+// largest size hint of NEWTABLE, 2^24 slots. the vm presizes no further
+pub const MAX_TABLE_HINT: u32 = 176;
+
 pub fn is_const(index: u32) -> bool {
     index & MASK_K != 0
 }
@@ -60,6 +83,37 @@ pub fn is_var(index: u32) -> bool {
     !is_const(index)
 }
 
+// encode constant index as RK
+pub fn rk_as_k(index: u32) -> u32 {
+    index | MASK_K
+}
+
+// sizes of NEWTABLE are "floating point bytes", eeeeexxx is (1xxx) * 2^(eeeee - 1) from 8 on.
+// a size is rounded up to the next one that can be encoded
+pub fn int2fb(mut x: usize) -> u32 {
+    if x < 8 {
+        return x as u32;
+    }
+    let mut e = 0;
+    while x >= 8 << 4 {
+        x = (x + 0xf) >> 4;
+        e += 4;
+    }
+    while x >= 8 << 1 {
+        x = (x + 1) >> 1;
+        e += 1;
+    }
+    ((e + 1) << 3) | (x as u32 - 8)
+}
+
+pub fn fb2int(x: u32) -> usize {
+    if x < 8 {
+        x as usize
+    } else {
+        (((x & 7) + 8) as usize) << ((x >> 3) - 1)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
     // A B
@@ -227,6 +281,10 @@ pub enum OpCode {
     // Ax
     // extra (larger) argument for previous opcode
     ExtraArg,
+
+    // A
+    // mark R(A) to be closed, from lua 5.4, after the opcodes of 5.3 to keep their numbers
+    Tbc,
 }
 
 impl OpCode {
@@ -279,28 +337,341 @@ impl OpCode {
             _ if OpCode::Closure as u32 == u => OpCode::Closure,
             _ if OpCode::Vararg as u32 == u => OpCode::Vararg,
             _ if OpCode::ExtraArg as u32 == u => OpCode::ExtraArg,
+            _ if OpCode::Tbc as u32 == u => OpCode::Tbc,
             _ => unreachable!("unknown op code : {}!", u),
         }
     }
+
+    // name in listings of luac, e.g. `GETTABUP`
+    pub fn name(&self) -> String {
+        match self {
+            // bitwise and, named BAND in lua
+            OpCode::BAdd => "BAND".to_string(),
+            _ => format!("{:?}", self).trim_end_matches('_').to_uppercase(),
+        }
+    }
+
+    // how the instruction uses its B and C args, from `luaP_opmodes` of lopcodes.c
+    pub fn arg_modes(&self) -> (OpArgMode, OpArgMode) {
+        use OpArgMode::*;
+        match self {
+            OpCode::Move => (R, N),
+            OpCode::LoadK => (K, N),
+            OpCode::LoadKx => (N, N),
+            OpCode::LoadBool => (U, U),
+            OpCode::LoadNil => (U, N),
+            OpCode::GetUpVal => (U, N),
+            OpCode::GetTabUp => (U, K),
+            OpCode::GetTable => (R, K),
+            OpCode::SetTabUp => (K, K),
+            OpCode::SetUpVal => (U, N),
+            OpCode::SetTable => (K, K),
+            OpCode::NewTable => (U, U),
+            OpCode::Self_ => (R, K),
+            OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Mod
+            | OpCode::Pow
+            | OpCode::Div
+            | OpCode::IDiv
+            | OpCode::BAdd
+            | OpCode::BOr
+            | OpCode::BXor
+            | OpCode::Shl
+            | OpCode::Shr => (K, K),
+            OpCode::Unm | OpCode::BNot | OpCode::Not | OpCode::Len => (R, N),
+            OpCode::Concat => (R, R),
+            OpCode::Jmp => (R, N),
+            OpCode::Eq | OpCode::Lt | OpCode::Le => (K, K),
+            OpCode::Test => (N, U),
+            OpCode::TestSet => (R, U),
+            OpCode::Call | OpCode::TailCall => (U, U),
+            OpCode::Return => (U, N),
+            OpCode::ForLoop | OpCode::ForPrep => (R, N),
+            OpCode::TForCall => (N, U),
+            OpCode::TForLoop => (R, N),
+            OpCode::SetList => (U, U),
+            OpCode::Closure => (U, N),
+            OpCode::Vararg => (U, N),
+            OpCode::ExtraArg => (U, U),
+            OpCode::Tbc => (N, N),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OpArgMode {
+    // not used
+    N,
+    // used as a number, e.g. a count or an index of upvalues
+    U,
+    // register or jump offset
+    R,
+    // constant or register encoded as RK
+    K,
+}
+
+// instruction with named operands, registers and upvalues are indexes,
+// operands named `key`, `value`, `left` or `right` are RK.
+// it's encoded to 32 bits in the formats above only for binary chunks and listings.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Move { dst: u32, src: u32 },
+    LoadK { dst: u32, k: u32 },
+    // constant index is in the following ExtraArg
+    LoadKx { dst: u32 },
+    LoadBool { dst: u32, value: u32, skip: u32 },
+    // R(dst), ..., R(dst + n) := nil
+    LoadNil { dst: u32, n: u32 },
+    GetUpVal { dst: u32, up: u32 },
+    GetTabUp { dst: u32, up: u32, key: u32 },
+    GetTable { dst: u32, table: u32, key: u32 },
+    SetTabUp { up: u32, key: u32, value: u32 },
+    SetUpVal { src: u32, up: u32 },
+    SetTable { table: u32, key: u32, value: u32 },
+    // sizes of array and hash part as floating point bytes
+    NewTable { dst: u32, array: u32, hash: u32 },
+    Self_ { dst: u32, table: u32, key: u32 },
+    Add { dst: u32, left: u32, right: u32 },
+    Sub { dst: u32, left: u32, right: u32 },
+    Mul { dst: u32, left: u32, right: u32 },
+    Mod { dst: u32, left: u32, right: u32 },
+    Pow { dst: u32, left: u32, right: u32 },
+    Div { dst: u32, left: u32, right: u32 },
+    IDiv { dst: u32, left: u32, right: u32 },
+    BAdd { dst: u32, left: u32, right: u32 },
+    BOr { dst: u32, left: u32, right: u32 },
+    BXor { dst: u32, left: u32, right: u32 },
+    Shl { dst: u32, left: u32, right: u32 },
+    Shr { dst: u32, left: u32, right: u32 },
+    Unm { dst: u32, src: u32 },
+    BNot { dst: u32, src: u32 },
+    Not { dst: u32, src: u32 },
+    Len { dst: u32, src: u32 },
+    Concat { dst: u32, first: u32, last: u32 },
+    // close upvalues >= R(close - 1) if close is not 0
+    Jmp { close: u32, offset: i32 },
+    // skip the next instruction if the result is not `expect`
+    Eq { expect: u32, left: u32, right: u32 },
+    Lt { expect: u32, left: u32, right: u32 },
+    Le { expect: u32, left: u32, right: u32 },
+    Test { src: u32, expect: u32 },
+    TestSet { dst: u32, src: u32, expect: u32 },
+    // args and results are count + 1, 0 means up to the top
+    Call { func: u32, args: u32, results: u32 },
+    TailCall { func: u32, args: u32, results: u32 },
+    // count + 1, 0 means up to the top
+    Return { first: u32, count: u32 },
+    ForLoop { base: u32, offset: i32 },
+    ForPrep { base: u32, offset: i32 },
+    TForCall { base: u32, results: u32 },
+    TForLoop { base: u32, offset: i32 },
+    // block is in the following ExtraArg if it's 0
+    SetList { table: u32, count: u32, block: u32 },
+    Closure { dst: u32, proto: u32 },
+    Vararg { dst: u32, count: u32 },
+    ExtraArg { arg: u32 },
+    Tbc { src: u32 },
+}
+
+fn abc(op: OpCode, a: u32, b: u32, c: u32) -> u32 {
+    ((op as u32) << POS_OP) | (a << POS_A) | (b << POS_B) | (c << POS_C)
 }
 
-pub struct Instruction(u32);
+fn abx(op: OpCode, a: u32, bx: u32) -> u32 {
+    ((op as u32) << POS_OP) | (a << POS_A) | (bx << POS_BX)
+}
+
+fn asbx(op: OpCode, a: u32, sbx: i32) -> u32 {
+    abx(op, a, sbx.wrapping_add(MAXARG_SBX) as u32)
+}
 
 #[allow(dead_code)]
 #[allow(non_snake_case)]
 impl Instruction {
     pub fn new() -> Self {
-        Instruction(0)
+        Instruction::Move { dst: 0, src: 0 }
     }
 
-    pub fn get_op(&self) -> OpCode {
-        OpCode::from_u32(((self.0) >> POS_OP) & Instruction::mask1(SIZE_OP, 0))
+    // encoded instruction, as stored in binary chunks
+    pub fn raw(&self) -> u32 {
+        use Instruction::*;
+        match *self {
+            Move { dst, src } => abc(OpCode::Move, dst, src, 0),
+            LoadK { dst, k } => abx(OpCode::LoadK, dst, k),
+            LoadKx { dst } => abc(OpCode::LoadKx, dst, 0, 0),
+            LoadBool { dst, value, skip } => abc(OpCode::LoadBool, dst, value, skip),
+            LoadNil { dst, n } => abc(OpCode::LoadNil, dst, n, 0),
+            GetUpVal { dst, up } => abc(OpCode::GetUpVal, dst, up, 0),
+            GetTabUp { dst, up, key } => abc(OpCode::GetTabUp, dst, up, key),
+            GetTable { dst, table, key } => abc(OpCode::GetTable, dst, table, key),
+            SetTabUp { up, key, value } => abc(OpCode::SetTabUp, up, key, value),
+            SetUpVal { src, up } => abc(OpCode::SetUpVal, src, up, 0),
+            SetTable { table, key, value } => abc(OpCode::SetTable, table, key, value),
+            NewTable { dst, array, hash } => abc(OpCode::NewTable, dst, array, hash),
+            Self_ { dst, table, key } => abc(OpCode::Self_, dst, table, key),
+            Add { dst, left, right } => abc(OpCode::Add, dst, left, right),
+            Sub { dst, left, right } => abc(OpCode::Sub, dst, left, right),
+            Mul { dst, left, right } => abc(OpCode::Mul, dst, left, right),
+            Mod { dst, left, right } => abc(OpCode::Mod, dst, left, right),
+            Pow { dst, left, right } => abc(OpCode::Pow, dst, left, right),
+            Div { dst, left, right } => abc(OpCode::Div, dst, left, right),
+            IDiv { dst, left, right } => abc(OpCode::IDiv, dst, left, right),
+            BAdd { dst, left, right } => abc(OpCode::BAdd, dst, left, right),
+            BOr { dst, left, right } => abc(OpCode::BOr, dst, left, right),
+            BXor { dst, left, right } => abc(OpCode::BXor, dst, left, right),
+            Shl { dst, left, right } => abc(OpCode::Shl, dst, left, right),
+            Shr { dst, left, right } => abc(OpCode::Shr, dst, left, right),
+            Unm { dst, src } => abc(OpCode::Unm, dst, src, 0),
+            BNot { dst, src } => abc(OpCode::BNot, dst, src, 0),
+            Not { dst, src } => abc(OpCode::Not, dst, src, 0),
+            Len { dst, src } => abc(OpCode::Len, dst, src, 0),
+            Concat { dst, first, last } => abc(OpCode::Concat, dst, first, last),
+            Jmp { close, offset } => asbx(OpCode::Jmp, close, offset),
+            Eq { expect, left, right } => abc(OpCode::Eq, expect, left, right),
+            Lt { expect, left, right } => abc(OpCode::Lt, expect, left, right),
+            Le { expect, left, right } => abc(OpCode::Le, expect, left, right),
+            Test { src, expect } => abc(OpCode::Test, src, 0, expect),
+            TestSet { dst, src, expect } => abc(OpCode::TestSet, dst, src, expect),
+            Call { func, args, results } => abc(OpCode::Call, func, args, results),
+            TailCall { func, args, results } => abc(OpCode::TailCall, func, args, results),
+            Return { first, count } => abc(OpCode::Return, first, count, 0),
+            ForLoop { base, offset } => asbx(OpCode::ForLoop, base, offset),
+            ForPrep { base, offset } => asbx(OpCode::ForPrep, base, offset),
+            TForCall { base, results } => abc(OpCode::TForCall, base, 0, results),
+            TForLoop { base, offset } => asbx(OpCode::TForLoop, base, offset),
+            SetList { table, count, block } => abc(OpCode::SetList, table, count, block),
+            Closure { dst, proto } => abx(OpCode::Closure, dst, proto),
+            Vararg { dst, count } => abc(OpCode::Vararg, dst, count, 0),
+            ExtraArg { arg } => ((OpCode::ExtraArg as u32) << POS_OP) | (arg << POS_AX),
+            Tbc { src } => abc(OpCode::Tbc, src, 0, 0),
+        }
     }
 
-    pub fn set_op(&mut self, op: OpCode) {
-        self.set_arg(op as u32, POS_OP, SIZE_OP)
+    // None if the op code is invalid, e.g. in a corrupted binary chunk
+    pub fn try_from_raw(raw: u32) -> Option<Self> {
+        if (raw >> POS_OP) & Instruction::mask1(SIZE_OP, 0) > OpCode::Tbc as u32 {
+            return None;
+        }
+        Some(Instruction::from_raw(raw))
     }
 
+    // caller should make sure the op code is valid, args not used by the op are dropped
+    pub fn from_raw(raw: u32) -> Self {
+        use Instruction::*;
+        let arg = |pos, size| (raw >> pos) & Instruction::mask1(size, 0);
+        let (a, b, c) = (arg(POS_A, SIZE_A), arg(POS_B, SIZE_B), arg(POS_C, SIZE_C));
+        let bx = arg(POS_BX, SIZE_BX);
+        let sbx = bx as i32 - MAXARG_SBX;
+        match OpCode::from_u32(arg(POS_OP, SIZE_OP)) {
+            OpCode::Move => Move { dst: a, src: b },
+            OpCode::LoadK => LoadK { dst: a, k: bx },
+            OpCode::LoadKx => LoadKx { dst: a },
+            OpCode::LoadBool => LoadBool { dst: a, value: b, skip: c },
+            OpCode::LoadNil => LoadNil { dst: a, n: b },
+            OpCode::GetUpVal => GetUpVal { dst: a, up: b },
+            OpCode::GetTabUp => GetTabUp { dst: a, up: b, key: c },
+            OpCode::GetTable => GetTable { dst: a, table: b, key: c },
+            OpCode::SetTabUp => SetTabUp { up: a, key: b, value: c },
+            OpCode::SetUpVal => SetUpVal { src: a, up: b },
+            OpCode::SetTable => SetTable { table: a, key: b, value: c },
+            OpCode::NewTable => NewTable { dst: a, array: b, hash: c },
+            OpCode::Self_ => Self_ { dst: a, table: b, key: c },
+            OpCode::Add => Add { dst: a, left: b, right: c },
+            OpCode::Sub => Sub { dst: a, left: b, right: c },
+            OpCode::Mul => Mul { dst: a, left: b, right: c },
+            OpCode::Mod => Mod { dst: a, left: b, right: c },
+            OpCode::Pow => Pow { dst: a, left: b, right: c },
+            OpCode::Div => Div { dst: a, left: b, right: c },
+            OpCode::IDiv => IDiv { dst: a, left: b, right: c },
+            OpCode::BAdd => BAdd { dst: a, left: b, right: c },
+            OpCode::BOr => BOr { dst: a, left: b, right: c },
+            OpCode::BXor => BXor { dst: a, left: b, right: c },
+            OpCode::Shl => Shl { dst: a, left: b, right: c },
+            OpCode::Shr => Shr { dst: a, left: b, right: c },
+            OpCode::Unm => Unm { dst: a, src: b },
+            OpCode::BNot => BNot { dst: a, src: b },
+            OpCode::Not => Not { dst: a, src: b },
+            OpCode::Len => Len { dst: a, src: b },
+            OpCode::Concat => Concat { dst: a, first: b, last: c },
+            OpCode::Jmp => Jmp { close: a, offset: sbx },
+            OpCode::Eq => Eq { expect: a, left: b, right: c },
+            OpCode::Lt => Lt { expect: a, left: b, right: c },
+            OpCode::Le => Le { expect: a, left: b, right: c },
+            OpCode::Test => Test { src: a, expect: c },
+            OpCode::TestSet => TestSet { dst: a, src: b, expect: c },
+            OpCode::Call => Call { func: a, args: b, results: c },
+            OpCode::TailCall => TailCall { func: a, args: b, results: c },
+            OpCode::Return => Return { first: a, count: b },
+            OpCode::ForLoop => ForLoop { base: a, offset: sbx },
+            OpCode::ForPrep => ForPrep { base: a, offset: sbx },
+            OpCode::TForCall => TForCall { base: a, results: c },
+            OpCode::TForLoop => TForLoop { base: a, offset: sbx },
+            OpCode::SetList => SetList { table: a, count: b, block: c },
+            OpCode::Closure => Closure { dst: a, proto: bx },
+            OpCode::Vararg => Vararg { dst: a, count: b },
+            OpCode::ExtraArg => ExtraArg { arg: arg(POS_AX, SIZE_AX) },
+            OpCode::Tbc => Tbc { src: a },
+        }
+    }
+
+    pub fn get_op(&self) -> OpCode {
+        use Instruction::*;
+        match self {
+            Move { .. } => OpCode::Move,
+            LoadK { .. } => OpCode::LoadK,
+            LoadKx { .. } => OpCode::LoadKx,
+            LoadBool { .. } => OpCode::LoadBool,
+            LoadNil { .. } => OpCode::LoadNil,
+            GetUpVal { .. } => OpCode::GetUpVal,
+            GetTabUp { .. } => OpCode::GetTabUp,
+            GetTable { .. } => OpCode::GetTable,
+            SetTabUp { .. } => OpCode::SetTabUp,
+            SetUpVal { .. } => OpCode::SetUpVal,
+            SetTable { .. } => OpCode::SetTable,
+            NewTable { .. } => OpCode::NewTable,
+            Self_ { .. } => OpCode::Self_,
+            Add { .. } => OpCode::Add,
+            Sub { .. } => OpCode::Sub,
+            Mul { .. } => OpCode::Mul,
+            Mod { .. } => OpCode::Mod,
+            Pow { .. } => OpCode::Pow,
+            Div { .. } => OpCode::Div,
+            IDiv { .. } => OpCode::IDiv,
+            BAdd { .. } => OpCode::BAdd,
+            BOr { .. } => OpCode::BOr,
+            BXor { .. } => OpCode::BXor,
+            Shl { .. } => OpCode::Shl,
+            Shr { .. } => OpCode::Shr,
+            Unm { .. } => OpCode::Unm,
+            BNot { .. } => OpCode::BNot,
+            Not { .. } => OpCode::Not,
+            Len { .. } => OpCode::Len,
+            Concat { .. } => OpCode::Concat,
+            Jmp { .. } => OpCode::Jmp,
+            Eq { .. } => OpCode::Eq,
+            Lt { .. } => OpCode::Lt,
+            Le { .. } => OpCode::Le,
+            Test { .. } => OpCode::Test,
+            TestSet { .. } => OpCode::TestSet,
+            Call { .. } => OpCode::Call,
+            TailCall { .. } => OpCode::TailCall,
+            Return { .. } => OpCode::Return,
+            ForLoop { .. } => OpCode::ForLoop,
+            ForPrep { .. } => OpCode::ForPrep,
+            TForCall { .. } => OpCode::TForCall,
+            TForLoop { .. } => OpCode::TForLoop,
+            SetList { .. } => OpCode::SetList,
+            Closure { .. } => OpCode::Closure,
+            Vararg { .. } => OpCode::Vararg,
+            ExtraArg { .. } => OpCode::ExtraArg,
+            Tbc { .. } => OpCode::Tbc,
+        }
+    }
+
+    // args by their position in the encoded instruction, for generic tools like the verifier
+
     pub fn get_arg_A(&self) -> u32 {
         self.get_arg(POS_A, SIZE_A)
     }
@@ -313,72 +684,62 @@ impl Instruction {
         self.get_arg(POS_B, SIZE_B)
     }
 
-    pub fn set_arg_B(&mut self, value: u32) {
-        self.set_arg(value, POS_B, SIZE_B);
-    }
-
     pub fn get_arg_C(&self) -> u32 {
         self.get_arg(POS_C, SIZE_C)
     }
 
-    pub fn set_arg_C(&mut self, value: u32) {
-        self.set_arg(value, POS_C, SIZE_C);
-    }
-
     pub fn get_arg_Ax(&self) -> u32 {
         self.get_arg(POS_AX, SIZE_AX)
     }
 
-    pub fn set_arg_Ax(&mut self, value: u32) {
-        self.set_arg(value, POS_AX, SIZE_AX);
-    }
-
     pub fn get_arg_Bx(&self) -> u32 {
         self.get_arg(POS_BX, SIZE_BX)
     }
 
-    pub fn set_arg_Bx(&mut self, value: u32) {
-        self.set_arg(value, POS_BX, SIZE_BX);
-    }
-
     pub fn get_arg_sBx(&self) -> i32 {
         (self.get_arg(POS_BX, SIZE_BX) as i32) - MAXARG_SBX
     }
 
-    pub fn set_arg_sBx(&mut self, value: i32) {
-        self.set_arg((value + MAXARG_SBX) as u32, POS_BX, SIZE_BX);
+    // set the offset of a jump or loop instruction
+    pub fn set_jump(&mut self, to: i32) {
+        match self {
+            Instruction::Jmp { offset, .. }
+            | Instruction::ForLoop { offset, .. }
+            | Instruction::ForPrep { offset, .. }
+            | Instruction::TForLoop { offset, .. } => *offset = to,
+            _ => unreachable!(),
+        }
     }
 
     pub fn create_ABC(op: OpCode, a: u32, b: u32, c: u32) -> Self {
-        Instruction(((op as u32) << POS_OP) | (a << POS_A) | (b << POS_B) | (c << POS_C))
+        Instruction::from_raw(abc(op, a, b, c))
     }
 
     pub fn create_ABx(op: OpCode, a: u32, bx: u32) -> Self {
-        Instruction(((op as u32) << POS_OP) | (a << POS_A) | (bx << POS_BX))
+        Instruction::from_raw(abx(op, a, bx))
     }
 
     pub fn create_AsBx(op: OpCode, a: u32, sBx: i32) -> Self {
-        Instruction(
-            ((op as u32) << POS_OP) | (a << POS_A) | (((sBx + MAXARG_SBX) as u32) << POS_BX),
-        )
+        Instruction::from_raw(asbx(op, a, sBx))
     }
 
     pub fn create_Ax(op: OpCode, a: u32) -> Self {
-        Instruction(((op as u32) << POS_OP) | (a << POS_AX))
+        Instruction::from_raw(((op as u32) << POS_OP) | (a << POS_AX))
     }
 
+    // save result to register `a`
     pub fn save(&mut self, a: u32) {
-        let mask = !(((1 << SIZE_A) - 1) << POS_A);
-        self.0 = (self.0 & mask) | (a << POS_A);
+        self.set_arg_A(a);
     }
 
     fn get_arg(&self, pos: u32, size: u32) -> u32 {
-        (self.0 >> pos) & Instruction::mask1(size, 0)
+        (self.raw() >> pos) & Instruction::mask1(size, 0)
     }
 
     fn set_arg(&mut self, value: u32, pos: u32, size: u32) {
-        self.0 = (Instruction::mask1(size, pos) & (value << pos))
-            | (self.0 & Instruction::mask0(size, pos))
+        let raw = (Instruction::mask1(size, pos) & (value << pos))
+            | (self.raw() & Instruction::mask0(size, pos));
+        *self = Instruction::from_raw(raw);
     }
 
     fn mask1(n: u32, p: u32) -> u32 {
@@ -389,6 +750,19 @@ impl Instruction {
         !Instruction::mask1(n, p)
     }
 
+    pub fn decode(&self) -> (OpCode, OpArgs) {
+        let args = match self.mode() {
+            OpMode::IA => OpArgs::A(self.get_arg_A()),
+            OpMode::IAB => OpArgs::AB(self.get_arg_A(), self.get_arg_B()),
+            OpMode::IABC => OpArgs::ABC(self.get_arg_A(), self.get_arg_B(), self.get_arg_C()),
+            OpMode::IABx => OpArgs::ABx(self.get_arg_A(), self.get_arg_Bx()),
+            OpMode::IAsBx => OpArgs::AsBx(self.get_arg_A(), self.get_arg_sBx()),
+            OpMode::IAx => OpArgs::Ax(self.get_arg_Ax()),
+            OpMode::IAC => OpArgs::AC(self.get_arg_A(), self.get_arg_C()),
+        };
+        (self.get_op(), args)
+    }
+
     pub fn mode(&self) -> OpMode {
         match self.get_op() {
             OpCode::Move => OpMode::IAB,
@@ -434,10 +808,11 @@ impl Instruction {
             OpCode::ForPrep => OpMode::IAsBx,
             OpCode::TForCall => OpMode::IAC,
             OpCode::TForLoop => OpMode::IAsBx,
-            OpCode::SetList => OpMode::IAsBx,
+            OpCode::SetList => OpMode::IABC,
             OpCode::Closure => OpMode::IABx,
             OpCode::Vararg => OpMode::IAB,
             OpCode::ExtraArg => OpMode::IAx,
+            OpCode::Tbc => OpMode::IABC,
         }
     }
 }
@@ -505,3 +880,68 @@ impl fmt::Debug for Instruction {
         }
     }
 }
+
+#[derive(Debug, PartialEq)]
+pub struct PackError(pub String);
+
+// code packed into 32 bits per instruction, a quarter of the size of `Instruction`,
+// for keeping many functions in memory and for binary chunks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackedCode(Vec<u32>);
+
+impl PackedCode {
+    // fails if an operand doesn't fit in its field, e.g. a jump longer than sBx
+    pub fn pack(code: &[Instruction]) -> Result<Self, PackError> {
+        let mut words = Vec::with_capacity(code.len());
+        for (pc, instruction) in code.iter().enumerate() {
+            let raw = instruction.raw();
+            if Instruction::from_raw(raw) != *instruction {
+                return Err(PackError(format!(
+                    "operand out of range in {} at pc {}",
+                    instruction.get_op().name(),
+                    pc
+                )));
+            }
+            words.push(raw);
+        }
+        Ok(PackedCode(words))
+    }
+
+    // fails if a word has an invalid op code
+    pub fn from_words(words: Vec<u32>) -> Result<Self, PackError> {
+        for (pc, raw) in words.iter().enumerate() {
+            if Instruction::try_from_raw(*raw).is_none() {
+                return Err(PackError(format!(
+                    "bad opcode {} at pc {}",
+                    raw & Instruction::mask1(SIZE_OP, 0),
+                    pc
+                )));
+            }
+        }
+        Ok(PackedCode(words))
+    }
+
+    pub fn unpack(&self) -> Vec<Instruction> {
+        self.iter().collect()
+    }
+
+    pub fn get(&self, pc: usize) -> Option<Instruction> {
+        self.0.get(pc).map(|raw| Instruction::from_raw(*raw))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Instruction> + '_ {
+        self.0.iter().map(|raw| Instruction::from_raw(*raw))
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
@@ -2,17 +2,31 @@ use crate::{debuggable, error};
 
 use crate::ast::*;
 use crate::tokens::{Token, TokenType, TokenValue};
-use crate::types::Source;
+use crate::symbol::Symbol;
+use crate::types::{Dialect, Source};
+use std::fmt;
+
+// label which `continue` jumps to, at the end of the loop body
+pub const CONTINUE_LABEL: &str = "continue";
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
     debug: bool,
+    dialect: Dialect,
+    // for each enclosing loop of the current function, if `continue` is used in its body
+    loops: Vec<bool>,
 }
 
 #[derive(Debug)]
 pub struct SyntaxError(String);
 
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 type ParseResult<T> = Result<T, SyntaxError>;
 
 macro_rules! syntax_error {
@@ -42,9 +56,15 @@ impl Parser {
             tokens: Vec::new(),
             current: 0,
             debug: false,
+            dialect: Dialect::Standard,
+            loops: Vec::new(),
         }
     }
 
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
     pub fn run(&mut self, tokens: Vec<Token>) -> ParseResult<Block> {
         self.reset();
         self.tokens = tokens;
@@ -54,14 +74,15 @@ impl Parser {
     // block -> { stat [';'] }
     fn block(&mut self) -> ParseResult<Block> {
         let mut stats: Vec<StatInfo> = Vec::new();
-        let saved = self.current_source();
         while !self.is_block_end() {
+            let start = self.current;
+            let saved = self.current_source();
             let (stat, should_break) = match self.current_token_type() {
                 TokenType::Return => (self.stat()?, true),
                 _ => (self.stat()?, false),
             };
             if let Some(stat) = stat {
-                let source = self.current_source() - saved;
+                let source = self.last_source(start) - saved;
                 stats.push(StatInfo { source, stat });
             }
             if should_break {
@@ -83,7 +104,7 @@ impl Parser {
             TokenType::SComment | TokenType::MComment => {
                 let stat = Stat::CommentStat(CommentStat {
                     is_single_line: self.current_token_type() == TokenType::SComment,
-                    comment: self.current_token().get_string(),
+                    comment: self.take_string(),
                 });
                 self.next();
                 stat
@@ -128,6 +149,8 @@ impl Parser {
             TokenType::Break => Stat::BreakStat(self.breakstat()?),
             // stat -> gotostat
             TokenType::Goto => Stat::GotoStat(self.gotostat()?),
+            // stat -> continuestat
+            TokenType::Name if self.is_continue() => Stat::GotoStat(self.continuestat()?),
             // stat -> func | assignment
             _ => self.exprstat()?,
         };
@@ -168,7 +191,7 @@ impl Parser {
         self.next_and_skip_comment();
         let cond = self.cond()?;
         self.check_next(TokenType::Do)?;
-        let block = self.block()?;
+        let block = self.loop_block()?;
         self.check_match(TokenType::End, TokenType::While, line)?;
         Ok(WhileStat { cond, block })
     }
@@ -183,8 +206,8 @@ impl Parser {
         self.next_and_skip_comment();
         let var_name = self.check_name()?;
         let forstat = match self.current_token_type() {
-            TokenType::Assign => self.forenum(&var_name),
-            TokenType::Comma | TokenType::In => self.forlist(&var_name),
+            TokenType::Assign => self.forenum(var_name),
+            TokenType::Comma | TokenType::In => self.forlist(var_name),
             _ => syntax_error!(self, "'=' or 'in' expected"),
         };
         match forstat {
@@ -197,7 +220,7 @@ impl Parser {
     }
 
     // fornum -> NAME = exp1,exp1[,exp1] forbody
-    fn forenum(&mut self, var_name: &str) -> ParseResult<ForStat> {
+    fn forenum(&mut self, var_name: Symbol) -> ParseResult<ForStat> {
         self.next_and_skip_comment();
         let init = self.expr()?;
         self.check_next(TokenType::Comma)?;
@@ -208,9 +231,9 @@ impl Parser {
             step = Some(self.expr()?);
         }
         self.check_next(TokenType::Do)?;
-        let body = self.block()?;
+        let body = self.loop_block()?;
         Ok(ForStat::ForNum(ForNum {
-            var: String::from(var_name),
+            var: var_name,
             init,
             limit,
             step,
@@ -219,9 +242,9 @@ impl Parser {
     }
 
     // forlist -> NAME {,NAME} IN explist forbody
-    fn forlist(&mut self, var_name: &str) -> ParseResult<ForStat> {
-        let mut vars: Vec<String> = Vec::new();
-        vars.push(String::from(var_name));
+    fn forlist(&mut self, var_name: Symbol) -> ParseResult<ForStat> {
+        let mut vars: Vec<Symbol> = Vec::new();
+        vars.push(var_name);
         while self.test_next(TokenType::Comma) {
             vars.push(self.check_name()?);
         }
@@ -229,7 +252,7 @@ impl Parser {
         self.skip_comment();
         let exprs = self.exprlist()?;
         self.check_next(TokenType::Do)?;
-        let body = self.block()?;
+        let body = self.loop_block()?;
         Ok(ForStat::ForList(ForList { vars, exprs, body }))
     }
 
@@ -237,7 +260,7 @@ impl Parser {
     fn repeatstat(&mut self) -> ParseResult<RepeatStat> {
         let line = self.current_line();
         self.next();
-        let block = self.block()?;
+        let block = self.loop_block()?;
         self.check_match(TokenType::Until, TokenType::Repeat, line)?;
         let cond = self.cond()?;
         Ok(RepeatStat { block, cond })
@@ -257,7 +280,7 @@ impl Parser {
 
     // funcname -> NAME {'.' NAME} [':' NAME]
     fn funcname(&mut self) -> ParseResult<FuncName> {
-        let mut fields: Vec<String> = Vec::new();
+        let mut fields: Vec<Symbol> = Vec::new();
         fields.push(self.check_name()?);
         while self.test_next(TokenType::Attr) {
             fields.push(self.check_name()?);
@@ -292,7 +315,11 @@ impl Parser {
             }
         }
         self.check_next(TokenType::Rp)?;
-        let block = self.block()?;
+        // `continue` can't jump out of a function
+        let loops = std::mem::take(&mut self.loops);
+        let block = self.block();
+        self.loops = loops;
+        let block = block?;
         self.check_match(TokenType::End, TokenType::Function, line)?;
         Ok(FuncBody { params, block })
     }
@@ -309,11 +336,17 @@ impl Parser {
         })
     }
 
-    // stat -> LOCAL NAME {',' NAME} ['=' explist]
+    // stat -> LOCAL NAME attrib {',' NAME attrib} ['=' explist]
     fn localstat(&mut self) -> ParseResult<LocalStat> {
-        let mut names: Vec<String> = Vec::new();
+        let mut names: Vec<Symbol> = Vec::new();
+        let mut attribs: Vec<Option<Attrib>> = Vec::new();
         loop {
             names.push(self.check_name()?);
+            let attrib = self.attrib()?;
+            if attrib == Some(Attrib::Close) && attribs.contains(&Some(Attrib::Close)) {
+                return syntax_error!(self, "multiple to-be-closed variables in local list");
+            }
+            attribs.push(attrib);
             if !self.test_next(TokenType::Comma) {
                 break;
             }
@@ -322,7 +355,26 @@ impl Parser {
         if self.test_next(TokenType::Assign) {
             exprs = self.exprlist()?;
         }
-        Ok(LocalStat { names, exprs })
+        Ok(LocalStat {
+            names,
+            attribs,
+            exprs,
+        })
+    }
+
+    // attrib -> ['<' NAME '>']
+    fn attrib(&mut self) -> ParseResult<Option<Attrib>> {
+        if self.test_next(TokenType::Lt) {
+            let attrib = match self.check_name()?.as_str() {
+                "const" => Attrib::Const,
+                "close" => Attrib::Close,
+                name => return syntax_error!(self, &format!("unknown attribute '{}'", name)),
+            };
+            self.check_next(TokenType::Gt)?;
+            Ok(Some(attrib))
+        } else {
+            Ok(None)
+        }
     }
 
     // label -> '::' NAME '::'
@@ -348,6 +400,54 @@ impl Parser {
         Ok(BreakStat {})
     }
 
+    // `continue` is only a keyword in the extended dialect, when it can't be the start of an expression statement
+    fn is_continue(&self) -> bool {
+        self.dialect == Dialect::Extended
+            && matches!(&self.current_token().value, TokenValue::Str(s) if s == CONTINUE_LABEL)
+            && !matches!(
+                self.next_token_type(),
+                TokenType::Assign
+                    | TokenType::Comma
+                    | TokenType::Attr
+                    | TokenType::Colon
+                    | TokenType::Lp
+                    | TokenType::Ls
+                    | TokenType::Lb
+                    | TokenType::String
+            )
+    }
+
+    // continuestat -> CONTINUE, desugared to `goto continue` with the label at the end of the loop body
+    fn continuestat(&mut self) -> ParseResult<GotoStat> {
+        match self.loops.last_mut() {
+            Some(used) => *used = true,
+            None => return syntax_error!(self, "no loop to continue"),
+        }
+        self.next_and_skip_comment();
+        Ok(GotoStat {
+            label: Symbol::from(CONTINUE_LABEL),
+        })
+    }
+
+    // body of while, for and repeat
+    fn loop_block(&mut self) -> ParseResult<Block> {
+        self.loops.push(false);
+        let block = self.block();
+        let used = self.loops.pop() == Some(true);
+        let mut block = block?;
+        if used {
+            let mut source = self.current_source();
+            source.length = 0;
+            block.stats.push(StatInfo {
+                source,
+                stat: Stat::LabelStat(LabelStat {
+                    label: Symbol::from(CONTINUE_LABEL),
+                }),
+            });
+        }
+        Ok(block)
+    }
+
     fn gotostat(&mut self) -> ParseResult<GotoStat> {
         self.next_and_skip_comment();
         let label = self.check_name()?;
@@ -357,7 +457,17 @@ impl Parser {
     // stat -> func call | assignment
     fn exprstat(&mut self) -> ParseResult<Stat> {
         let expr = self.suffixedexpr()?;
-        if self.test(TokenType::Assign) || self.test(TokenType::Comma) {
+        let op = BinOp::from_compound_assign(self.current_token_type());
+        if op != BinOp::None {
+            // compound assignment -> suffixedexp op= expr
+            self.next_and_skip_comment();
+            let right = self.expr()?;
+            Ok(Stat::CompoundAssignStat(CompoundAssignStat {
+                left: expr.to_assignable(),
+                op,
+                right,
+            }))
+        } else if self.test(TokenType::Assign) || self.test(TokenType::Comma) {
             Ok(Stat::AssignStat(self.assignment(expr.to_assignable())?))
         } else {
             Ok(Stat::CallStat(CallStat {
@@ -369,7 +479,7 @@ impl Parser {
     // assignment -> ',' suffixedexp assignment
     // assignment -> '=' explist
     fn assignment(&mut self, first: Assignable) -> ParseResult<AssignStat> {
-        let mut left: Vec<Assignable> = Vec::new();
+        let mut left: Vec<Assignable> = Vec::with_capacity(1);
         left.push(first);
         while self.test_next(TokenType::Comma) {
             left.push(self.suffixedexpr()?.to_assignable())
@@ -382,7 +492,8 @@ impl Parser {
 
     // exprlist -> expr { ',' expr }
     fn exprlist(&mut self) -> ParseResult<Vec<Expr>> {
-        let mut exprs: Vec<Expr> = Vec::new();
+        // most expression lists hold a single expression
+        let mut exprs: Vec<Expr> = Vec::with_capacity(1);
         exprs.push(self.expr()?);
         while self.test_next(TokenType::Comma) {
             exprs.push(self.expr()?)
@@ -434,7 +545,7 @@ impl Parser {
         let expr = match token.t {
             TokenType::Flt => Expr::Float(token.get_float()),
             TokenType::Int => Expr::Int(token.get_int()),
-            TokenType::String => Expr::String(token.get_string()),
+            TokenType::String => Expr::String(self.take_string()),
             TokenType::Nil => Expr::Nil,
             TokenType::True => Expr::True,
             TokenType::False => Expr::False,
@@ -590,7 +701,7 @@ impl Parser {
             }
             TokenType::Lb => FuncArgs::Table(self.table()?),
             TokenType::String => {
-                let arg = FuncArgs::String(self.current_token().get_string());
+                let arg = FuncArgs::String(self.take_string());
                 self.next_and_skip_comment();
                 arg
             }
@@ -601,6 +712,7 @@ impl Parser {
 
     fn reset(&mut self) {
         self.current = 0;
+        self.loops.clear();
     }
 
     fn current_token(&self) -> &Token {
@@ -625,6 +737,15 @@ impl Parser {
         token.source
     }
 
+    // source of the last token consumed since `start`, trailing comments are skipped
+    fn last_source(&self, start: usize) -> Source {
+        let mut last = self.current - 1;
+        while last > start && self.tokens[last].is_comment() {
+            last -= 1;
+        }
+        self.tokens[last].source
+    }
+
     fn current_line(&self) -> usize {
         let token = self.current_token();
         token.source.line
@@ -711,17 +832,21 @@ impl Parser {
         Ok(())
     }
 
-    fn check_name(&mut self) -> ParseResult<String> {
+    fn check_name(&mut self) -> ParseResult<Symbol> {
         self.skip_comment();
         self.check(TokenType::Name)?;
-        let token = self.current_token();
-        let name = match &token.value {
-            TokenValue::Str(name) => name.clone(),
-            _ => unreachable!(),
-        };
+        let name = Symbol::from(self.take_string());
         self.next();
         Ok(name)
     }
 
+    // every token is consumed only once, so its string can be moved out instead of cloned
+    fn take_string(&mut self) -> String {
+        match &mut self.tokens[self.current].value {
+            TokenValue::Str(s) => std::mem::take(s),
+            _ => unreachable!(),
+        }
+    }
+
     debuggable!();
 }
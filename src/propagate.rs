@@ -0,0 +1,167 @@
+use crate::consts::Const;
+use crate::opcodes::*;
+use crate::proto::Proto;
+use std::collections::HashMap;
+use std::ops::Range;
+
+// constant propagation on compiled code, which catches constants the ast folder can't see,
+// e.g. `local a = 1; local b = a * 2` loads 2 instead of computing it at runtime.
+//
+// within each basic block, registers loaded by LoadK are tracked through Move, Move of a constant
+// becomes LoadK, and arithmetic on constants is folded into LoadK like the ast folder does.
+// registers hold the same values as before, only how they're computed changes.
+
+impl Proto {
+    // propagate constants in the function and all nested functions,
+    // `string_coercion` folds numeric strings like `CompilerOptions::string_coercion`
+    pub fn propagate_constants(&mut self, string_coercion: bool) {
+        let leaders = self.block_leaders();
+        // registers known to hold a constant, by constant index
+        let mut known: HashMap<u32, u32> = HashMap::new();
+        for (pc, leader) in leaders.into_iter().enumerate() {
+            if leader {
+                known.clear();
+            }
+            let instruction = self.code[pc];
+            match instruction {
+                Instruction::LoadK { dst, k } => {
+                    known.insert(dst, k);
+                }
+                Instruction::Move { dst, src } => match known.get(&src).copied() {
+                    Some(k) => {
+                        self.code[pc] = Instruction::LoadK { dst, k };
+                        known.insert(dst, k);
+                    }
+                    None => {
+                        known.remove(&dst);
+                    }
+                },
+                _ => {
+                    let folded = self.fold(&instruction, &known, string_coercion);
+                    match folded.map(|k| self.add_const(k)) {
+                        Some(k) if k <= MAXARG_BX => {
+                            let dst = instruction.get_arg_A();
+                            self.code[pc] = Instruction::LoadK { dst, k };
+                            known.insert(dst, k);
+                        }
+                        _ => match written(&instruction) {
+                            Some(regs) => known.retain(|reg, _| !regs.contains(reg)),
+                            None => known.clear(),
+                        },
+                    }
+                }
+            }
+        }
+        for proto in self.protos.iter_mut() {
+            proto.propagate_constants(string_coercion);
+        }
+    }
+
+    // pcs that may be reached other than from the previous instruction, or that follow a jump
+    fn block_leaders(&self) -> Vec<bool> {
+        let len = self.code.len();
+        let mut leaders = vec![false; len + 2];
+        for (pc, instruction) in self.code.iter().enumerate() {
+            match *instruction {
+                Instruction::Jmp { offset, .. }
+                | Instruction::ForLoop { offset, .. }
+                | Instruction::ForPrep { offset, .. }
+                | Instruction::TForLoop { offset, .. } => {
+                    let target = pc as i64 + 1 + offset as i64;
+                    if target >= 0 && (target as usize) < len {
+                        leaders[target as usize] = true;
+                    }
+                    leaders[pc + 1] = true;
+                }
+                // skip the next instruction
+                Instruction::Eq { .. }
+                | Instruction::Lt { .. }
+                | Instruction::Le { .. }
+                | Instruction::Test { .. }
+                | Instruction::TestSet { .. } => leaders[pc + 2] = true,
+                Instruction::LoadBool { skip, .. } if skip != 0 => leaders[pc + 2] = true,
+                Instruction::Return { .. }
+                | Instruction::TailCall { .. }
+                | Instruction::TForCall { .. } => leaders[pc + 1] = true,
+                _ => (),
+            }
+        }
+        leaders.truncate(len);
+        leaders
+    }
+
+    // value of an arithmetic instruction whose operands are all constants
+    fn fold(
+        &self,
+        instruction: &Instruction,
+        known: &HashMap<u32, u32>,
+        string_coercion: bool,
+    ) -> Option<Const> {
+        // RK operand, constants are propagated from known registers
+        let operand = |arg: u32| {
+            let k = if is_const(arg) {
+                arg & !MASK_K
+            } else {
+                *known.get(&arg)?
+            };
+            let k = self.consts.get(k as usize)?.clone();
+            Some(if string_coercion {
+                k.coerce_to_number()
+            } else {
+                k
+            })
+        };
+        let result = match *instruction {
+            Instruction::Add { left, right, .. } => operand(left)?.add(operand(right)?),
+            Instruction::Sub { left, right, .. } => operand(left)?.sub(operand(right)?),
+            Instruction::Mul { left, right, .. } => operand(left)?.mul(operand(right)?),
+            Instruction::Mod { left, right, .. } => operand(left)?.mod_(operand(right)?),
+            Instruction::Pow { left, right, .. } => operand(left)?.pow(operand(right)?),
+            Instruction::Div { left, right, .. } => operand(left)?.div(operand(right)?),
+            Instruction::IDiv { left, right, .. } => operand(left)?.idiv(operand(right)?),
+            Instruction::BAdd { left, right, .. } => operand(left)?.band(operand(right)?),
+            Instruction::BOr { left, right, .. } => operand(left)?.bor(operand(right)?),
+            Instruction::BXor { left, right, .. } => operand(left)?.bxor(operand(right)?),
+            Instruction::Shl { left, right, .. } => operand(left)?.shl(operand(right)?),
+            Instruction::Shr { left, right, .. } => operand(left)?.shr(operand(right)?),
+            // string coercion doesn't apply to unary operators of the ast folder
+            Instruction::Unm { src, .. } => self.consts.get(*known.get(&src)? as usize)?.minus(),
+            Instruction::BNot { src, .. } => self.consts.get(*known.get(&src)? as usize)?.bnot(),
+            _ => return None,
+        };
+        // errors like division by zero are left to runtime
+        result.ok().flatten()
+    }
+}
+
+// registers written by an instruction, none if unknown, e.g. results of calls
+fn written(instruction: &Instruction) -> Option<Range<u32>> {
+    match *instruction {
+        Instruction::SetTabUp { .. }
+        | Instruction::SetUpVal { .. }
+        | Instruction::SetTable { .. }
+        | Instruction::Eq { .. }
+        | Instruction::Lt { .. }
+        | Instruction::Le { .. }
+        | Instruction::Test { .. }
+        | Instruction::Jmp { .. }
+        | Instruction::Return { .. }
+        | Instruction::SetList { .. }
+        | Instruction::ExtraArg { .. }
+        | Instruction::Tbc { .. } => Some(0..0),
+        Instruction::LoadNil { dst, n } => Some(dst..dst + n + 1),
+        Instruction::Self_ { dst, .. } => Some(dst..dst + 2),
+        Instruction::Call { .. }
+        | Instruction::TailCall { .. }
+        | Instruction::ForLoop { .. }
+        | Instruction::ForPrep { .. }
+        | Instruction::TForCall { .. }
+        | Instruction::TForLoop { .. }
+        | Instruction::Vararg { .. } => None,
+        // the others write register A only
+        _ => {
+            let dst = instruction.get_arg_A();
+            Some(dst..dst + 1)
+        }
+    }
+}
@@ -1,24 +1,160 @@
 use std::collections::HashMap;
 
 use crate::ast::{BinOp, UnOp};
+use crate::compiler::CompilerOptions;
 use crate::consts::Const;
-use crate::opcodes::{Instruction, OpCode};
+use crate::opcodes::{int2fb, Instruction, OpArgs, OpCode, MAXARG_BX, MAXARG_C, MAX_TABLE_HINT};
+use crate::symbol::Symbol;
 
 pub struct LocalVal {
+    name: Symbol,
+    // const and to-be-closed variables can't be assigned
+    readonly: bool,
+    // the local is in scope from `start_pc` until before `end_pc`
+    start_pc: usize,
+    end_pc: usize,
+}
+
+pub struct UpVal {
     name: String,
+    // captured from a register of the enclosing function, or from its upvalues
+    pub in_stack: bool,
+    pub index: u32,
+}
+
+impl LocalVal {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn start_pc(&self) -> usize {
+        self.start_pc
+    }
+
+    pub fn end_pc(&self) -> usize {
+        self.end_pc
+    }
 }
 
-pub struct UpVal {}
+impl UpVal {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// name of the upvalue holding the global environment
+pub const ENV: &str = "_ENV";
+
+// source lines of instructions stored like `abslineinfo` of lua 5.4, which is a byte per
+// instruction instead of 4: the difference from the line of the previous instruction,
+// or a mark for an absolute line when the difference doesn't fit in a byte.
+// absolute lines are also stored at least every `MAX_WITHOUT_ABS` instructions,
+// so finding a line only sums up a few differences.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineInfo {
+    deltas: Vec<i8>,
+    // (pc, line) of instructions marked with `ABS_LINE`, ordered by pc
+    abs: Vec<(usize, u32)>,
+    last_line: u32,
+    // instructions since the last absolute line
+    without_abs: usize,
+}
+
+const ABS_LINE: i8 = i8::MIN;
+const MAX_WITHOUT_ABS: usize = 128;
+
+impl LineInfo {
+    pub fn new() -> Self {
+        LineInfo::default()
+    }
+
+    // add the line of the next instruction
+    pub fn push(&mut self, line: u32) {
+        let delta = line as i64 - self.last_line as i64;
+        if delta.abs() > i8::MAX as i64 || self.without_abs >= MAX_WITHOUT_ABS {
+            self.abs.push((self.deltas.len(), line));
+            self.deltas.push(ABS_LINE);
+            self.without_abs = 0;
+        } else {
+            self.deltas.push(delta as i8);
+            self.without_abs += 1;
+        }
+        self.last_line = line;
+    }
+
+    // line of the instruction at `pc`
+    pub fn get(&self, pc: usize) -> Option<u32> {
+        if pc >= self.deltas.len() {
+            return None;
+        }
+        // start from the last absolute line before `pc`, or from line 0 at the beginning
+        let (base_pc, mut line) = match self.abs.partition_point(|(abs_pc, _)| *abs_pc <= pc) {
+            0 => (0, 0),
+            i => (self.abs[i - 1].0 + 1, self.abs[i - 1].1),
+        };
+        for delta in self.deltas[base_pc..=pc].iter() {
+            if *delta != ABS_LINE {
+                line = (line as i64 + *delta as i64) as u32;
+            }
+        }
+        Some(line)
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = LineInfo::default();
+    }
+
+    // lines of all instructions in the order of pc
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut abs = self.abs.iter();
+        let mut line = 0u32;
+        self.deltas.iter().map(move |delta| {
+            line = match *delta {
+                ABS_LINE => abs.next().unwrap().1,
+                delta => (line as i64 + delta as i64) as u32,
+            };
+            line
+        })
+    }
+
+    // size of the storage in bytes
+    pub fn size(&self) -> usize {
+        self.deltas.len() + self.abs.len() * std::mem::size_of::<(usize, u32)>()
+    }
+}
+
+impl std::iter::FromIterator<u32> for LineInfo {
+    fn from_iter<I: IntoIterator<Item = u32>>(lines: I) -> Self {
+        let mut line_info = LineInfo::new();
+        for line in lines {
+            line_info.push(line);
+        }
+        line_info
+    }
+}
 
 pub struct Proto {
     pub stack_size: u32,
     pub param_count: u32,
+    pub is_vararg: bool,
     pub code: Vec<Instruction>,
     pub consts: Vec<Const>,
     pub const_map: HashMap<Const, u32>,
     pub local_vars: Vec<LocalVal>,
     pub up_vars: Vec<UpVal>,
     pub protos: Vec<Proto>,
+    // source line of each instruction, empty after stripping debug info
+    pub line_info: LineInfo,
+    // last pc that is a jump target, instructions before it can't be merged
+    pub last_target: usize,
 }
 
 impl Proto {
@@ -26,12 +162,15 @@ impl Proto {
         Proto {
             stack_size: 2,
             param_count: 0,
+            is_vararg: false,
             code: Vec::new(),
             consts: Vec::new(),
             const_map: HashMap::new(),
             local_vars: Vec::new(),
             up_vars: Vec::new(),
             protos: Vec::new(),
+            line_info: LineInfo::new(),
+            last_target: 0,
         }
     }
 
@@ -42,98 +181,214 @@ impl Proto {
     }
 
     pub fn code_return(&mut self, first: u32, nret: u32) -> usize {
-        self.code
-            .push(Instruction::create_ABC(OpCode::Return, first, nret + 1, 0));
+        self.code.push(Instruction::Return {
+            first,
+            count: nret + 1,
+        });
+        self.code.len() - 1
+    }
+
+    // return R(first) up to the top, which is set by a call or vararg before it
+    pub fn code_return_all(&mut self, first: u32) -> usize {
+        self.code.push(Instruction::Return { first, count: 0 });
+        self.code.len() - 1
+    }
+
+    // R(func)(R(func + 1), ...), none of `nargs` or `nresults` means up to the top
+    pub fn code_call(&mut self, func: u32, nargs: Option<u32>, nresults: Option<u32>) -> usize {
+        self.code.push(Instruction::Call {
+            func,
+            args: nargs.map_or(0, |n| n + 1),
+            results: nresults.map_or(0, |n| n + 1),
+        });
+        self.code.len() - 1
+    }
+
+    // the call at `pc` returns the results of the called function instead
+    pub fn set_tail_call(&mut self, pc: usize) {
+        if let Instruction::Call { func, args, .. } = self.code[pc] {
+            self.code[pc] = Instruction::TailCall {
+                func,
+                args,
+                results: 0,
+            };
+        }
+    }
+
+    // R(dst), ... := varargs, none of `n` means all of them up to the top
+    pub fn code_vararg(&mut self, dst: u32, n: Option<u32>) -> usize {
+        self.code.push(Instruction::Vararg {
+            dst,
+            count: n.map_or(0, |n| n + 1),
+        });
+        self.code.len() - 1
+    }
+
+    // R(dst) := R(table)[key], R(dst + 1) := R(table)
+    pub fn code_self(&mut self, dst: u32, table: u32, key: u32) -> usize {
+        self.code.push(Instruction::Self_ { dst, table, key });
         self.code.len() - 1
     }
 
     pub fn code_nil(&mut self, start_reg: u32, n: u32) -> usize {
-        // TODO : optimize for duplicate LoadNil
-        self.code.push(Instruction::create_ABC(
-            OpCode::LoadNil,
-            start_reg,
-            n - 1,
-            0,
-        ));
+        self.code.push(Instruction::LoadNil {
+            dst: start_reg,
+            n: n - 1,
+        });
         self.code.len() - 1
     }
 
+    // try to merge LoadNil of [start_reg, start_reg + n) into the previous LoadNil
+    pub fn merge_nil(&mut self, start_reg: u32, n: u32) -> bool {
+        if self.code.len() <= self.last_target {
+            return false;
+        }
+        if let Some(Instruction::LoadNil { dst, n: prev_n }) = self.code.last_mut() {
+            let prev_from = *dst;
+            let prev_to = prev_from + *prev_n;
+            let to = start_reg + n - 1;
+            // ranges overlap or are adjacent
+            if (prev_from <= start_reg && start_reg <= prev_to + 1)
+                || (start_reg <= prev_from && prev_from <= to + 1)
+            {
+                let from = prev_from.min(start_reg);
+                let to = prev_to.max(to);
+                *dst = from;
+                *prev_n = to - from;
+                return true;
+            }
+        }
+        false
+    }
+
     pub fn code_bool(&mut self, reg: u32, v: bool, pc: u32) -> usize {
-        self.code.push(Instruction::create_ABC(
-            OpCode::LoadBool,
-            reg,
-            if v { 1 } else { 0 },
-            pc,
-        ));
+        self.code.push(Instruction::LoadBool {
+            dst: reg,
+            value: if v { 1 } else { 0 },
+            skip: pc,
+        });
         self.code.len() - 1
     }
 
     pub fn code_const(&mut self, reg_index: u32, const_index: u32) -> usize {
-        self.code.push(Instruction::create_ABx(
-            OpCode::LoadK,
-            reg_index,
-            const_index,
-        ));
+        if const_index <= MAXARG_BX {
+            self.code.push(Instruction::LoadK {
+                dst: reg_index,
+                k: const_index,
+            });
+        } else {
+            // const index doesn't fit in Bx, put it in the following extra arg
+            self.code.push(Instruction::LoadKx { dst: reg_index });
+            self.code.push(Instruction::ExtraArg { arg: const_index });
+        }
         self.code.len() - 1
     }
 
     pub fn code_move(&mut self, reg: u32, src: u32) -> usize {
-        self.code
-            .push(Instruction::create_ABC(OpCode::Move, reg, src, 0));
+        self.code.push(Instruction::Move { dst: reg, src });
         self.code.len() - 1
     }
 
     pub fn code_bin_op(&mut self, op: BinOp, target: u32, left: u32, right: u32) -> usize {
-        let op_code = match op {
-            BinOp::Add => OpCode::Add,
-            BinOp::Minus => OpCode::Sub,
-            BinOp::Mul => OpCode::Mul,
-            BinOp::Mod => OpCode::Mod,
-            BinOp::Pow => OpCode::Pow,
-            BinOp::Div => OpCode::Div,
-            BinOp::IDiv => OpCode::IDiv,
-            BinOp::BAnd => OpCode::BAdd,
-            BinOp::BOr => OpCode::BOr,
-            BinOp::BXor => OpCode::BXor,
-            BinOp::Shl => OpCode::Shl,
-            BinOp::Shr => OpCode::Shr,
-            BinOp::Concat => OpCode::Concat,
+        let dst = target;
+        let instruction = match op {
+            BinOp::Add => Instruction::Add { dst, left, right },
+            BinOp::Minus => Instruction::Sub { dst, left, right },
+            BinOp::Mul => Instruction::Mul { dst, left, right },
+            BinOp::Mod => Instruction::Mod { dst, left, right },
+            BinOp::Pow => Instruction::Pow { dst, left, right },
+            BinOp::Div => Instruction::Div { dst, left, right },
+            BinOp::IDiv => Instruction::IDiv { dst, left, right },
+            BinOp::BAnd => Instruction::BAdd { dst, left, right },
+            BinOp::BOr => Instruction::BOr { dst, left, right },
+            BinOp::BXor => Instruction::BXor { dst, left, right },
+            BinOp::Shl => Instruction::Shl { dst, left, right },
+            BinOp::Shr => Instruction::Shr { dst, left, right },
             _ => unreachable!(),
         };
-        self.code
-            .push(Instruction::create_ABC(op_code, target, left, right));
+        self.code.push(instruction);
+        self.code.len() - 1
+    }
+
+    // R(target) := R(first) .. ... .. R(last)
+    pub fn code_concat(&mut self, target: u32, first: u32, last: u32) -> usize {
+        self.code.push(Instruction::Concat {
+            dst: target,
+            first,
+            last,
+        });
         self.code.len() - 1
     }
 
     pub fn code_comp(&mut self, op: BinOp, left: u32, right: u32) -> usize {
-        let op_code = match op {
-            BinOp::Lt | BinOp::Gt => OpCode::Lt,
-            BinOp::Ne | BinOp::Eq => OpCode::Eq,
-            BinOp::Le | BinOp::Ge => OpCode::Le,
+        let expect = if op == BinOp::Ne { 0 } else { 1 };
+        let instruction = match op {
+            BinOp::Lt | BinOp::Gt => Instruction::Lt {
+                expect,
+                left,
+                right,
+            },
+            BinOp::Ne | BinOp::Eq => Instruction::Eq {
+                expect,
+                left,
+                right,
+            },
+            BinOp::Le | BinOp::Ge => Instruction::Le {
+                expect,
+                left,
+                right,
+            },
             _ => unreachable!(),
         };
-        let cond = if op == BinOp::Ne { 0 } else { 1 };
-        self.code
-            .push(Instruction::create_ABC(op_code, cond, left, right));
+        self.code.push(instruction);
         self.code.len() - 1
     }
 
     pub fn code_un_op(&mut self, op: UnOp, target: u32, src: u32) -> usize {
-        let op_code = match op {
-            UnOp::Minus => OpCode::Unm,
-            UnOp::BNot => OpCode::BNot,
-            UnOp::Not => OpCode::Not,
-            UnOp::Len => OpCode::Len,
+        let dst = target;
+        let instruction = match op {
+            UnOp::Minus => Instruction::Unm { dst, src },
+            UnOp::BNot => Instruction::BNot { dst, src },
+            UnOp::Not => Instruction::Not { dst, src },
+            UnOp::Len => Instruction::Len { dst, src },
             _ => unimplemented!(),
         };
-        self.code
-            .push(Instruction::create_ABC(op_code, target, src, 0));
+        self.code.push(instruction);
         self.code.len() - 1
     }
 
     pub fn code_jmp(&mut self, offset: i32, upvars: u32) -> usize {
-        self.code
-            .push(Instruction::create_AsBx(OpCode::Jmp, upvars, offset));
+        self.code.push(Instruction::Jmp {
+            close: upvars,
+            offset,
+        });
+        self.code.len() - 1
+    }
+
+    pub fn code_for_prep(&mut self, base: u32, offset: i32) -> usize {
+        self.code.push(Instruction::ForPrep { base, offset });
+        self.code.len() - 1
+    }
+
+    pub fn code_for_loop(&mut self, base: u32, offset: i32) -> usize {
+        self.code.push(Instruction::ForLoop { base, offset });
+        self.code.len() - 1
+    }
+
+    // R(base + 3), ... R(base + 2 + results) := R(base)(R(base + 1), R(base + 2))
+    pub fn code_tfor_call(&mut self, base: u32, results: u32) -> usize {
+        self.code.push(Instruction::TForCall { base, results });
+        self.code.len() - 1
+    }
+
+    pub fn code_tfor_loop(&mut self, base: u32, offset: i32) -> usize {
+        self.code.push(Instruction::TForLoop { base, offset });
+        self.code.len() - 1
+    }
+
+    // mark a local as to-be-closed
+    pub fn code_tbc(&mut self, reg: u32) -> usize {
+        self.code.push(Instruction::Tbc { src: reg });
         self.code.len() - 1
     }
 
@@ -144,32 +399,194 @@ impl Proto {
         } else {
             false_pos
         };
-        instruction.set_arg_sBx(pos as i32 - pc as i32 - 1);
+        instruction.set_jump(pos as i32 - pc as i32 - 1);
+        self.last_target = self.last_target.max(true_pos).max(false_pos);
+    }
+
+    // pc of the next instruction, which is a jump target
+    pub fn get_label(&mut self) -> usize {
+        self.last_target = self.code.len();
+        self.last_target
+    }
+
+    // the jump at `pc` closes upvalues and to-be-closed variables from register `level`
+    pub fn patch_close(&mut self, pc: usize, level: u32) {
+        self.get_instruction(pc).set_arg_A(level + 1);
     }
 
     pub fn fix_jump_pos(&mut self, pos: usize, pc: usize) {
         let instruction = self.get_instruction(pc);
-        instruction.set_arg_sBx(pos as i32 - pc as i32 - 1);
+        instruction.set_jump(pos as i32 - pc as i32 - 1);
+        self.last_target = self.last_target.max(pos);
+    }
+
+    pub fn code_get_up_val(&mut self, dst: u32, up: u32) -> usize {
+        self.code.push(Instruction::GetUpVal { dst, up });
+        self.code.len() - 1
+    }
+
+    pub fn code_set_up_val(&mut self, src: u32, up: u32) -> usize {
+        self.code.push(Instruction::SetUpVal { src, up });
+        self.code.len() - 1
+    }
+
+    // R(dst) := closure of the child proto at `index`
+    pub fn code_closure(&mut self, dst: u32, index: u32) -> usize {
+        self.code.push(Instruction::Closure { dst, proto: index });
+        self.code.len() - 1
+    }
+
+    pub fn code_get_tab_up(&mut self, target: u32, up_val: u32, key: u32) -> usize {
+        self.code.push(Instruction::GetTabUp {
+            dst: target,
+            up: up_val,
+            key,
+        });
+        self.code.len() - 1
+    }
+
+    pub fn code_set_tab_up(&mut self, up_val: u32, key: u32, value: u32) -> usize {
+        self.code.push(Instruction::SetTabUp {
+            up: up_val,
+            key,
+            value,
+        });
+        self.code.len() - 1
+    }
+
+    pub fn code_get_table(&mut self, target: u32, table: u32, key: u32) -> usize {
+        self.code.push(Instruction::GetTable {
+            dst: target,
+            table,
+            key,
+        });
+        self.code.len() - 1
+    }
+
+    // sizes are set by `set_table_size` after the fields are known
+    pub fn code_new_table(&mut self, dst: u32) -> usize {
+        self.code.push(Instruction::NewTable {
+            dst,
+            array: 0,
+            hash: 0,
+        });
+        self.code.len() - 1
+    }
+
+    pub fn set_table_size(&mut self, pc: usize, narray: usize, nhash: usize) {
+        if let Instruction::NewTable { array, hash, .. } = self.get_instruction(pc) {
+            *array = int2fb(narray).min(MAX_TABLE_HINT);
+            *hash = int2fb(nhash).min(MAX_TABLE_HINT);
+        }
+    }
+
+    // R(table)[(block - 1) * FIELDS_PER_FLUSH + i] := R(table + i) for `count` items, or up to
+    // the top with none. large blocks are in the following EXTRAARG
+    pub fn code_set_list(&mut self, table: u32, count: Option<u32>, block: u32) -> usize {
+        let count = count.unwrap_or(0);
+        if block <= MAXARG_C {
+            self.code.push(Instruction::SetList {
+                table,
+                count,
+                block,
+            });
+        } else {
+            self.code.push(Instruction::SetList {
+                table,
+                count,
+                block: 0,
+            });
+            self.code.push(Instruction::ExtraArg { arg: block });
+        }
+        self.code.len() - 1
+    }
+
+    pub fn code_set_table(&mut self, table: u32, key: u32, value: u32) -> usize {
+        self.code.push(Instruction::SetTable { table, key, value });
+        self.code.len() - 1
+    }
+
+    pub fn code_test(&mut self, test: u32, to_test: u32) -> usize {
+        self.code.push(Instruction::Test {
+            src: test,
+            expect: to_test,
+        });
+        self.code.len() - 1
+    }
+
+    pub fn code_test_set(&mut self, set: u32, test: u32, to_test: u32) -> usize {
+        self.code.push(Instruction::TestSet {
+            dst: set,
+            src: test,
+            expect: to_test,
+        });
+        self.code.len() - 1
+    }
+
+    // a local in scope from the next instruction until `end_local_var`, returns its index
+    pub fn add_local_var(&mut self, name: Symbol, readonly: bool) -> usize {
+        let pc = self.code.len();
+        self.local_vars.push(LocalVal {
+            name,
+            readonly,
+            start_pc: pc,
+            end_pc: pc,
+        });
+        self.local_vars.len() - 1
     }
 
-    pub fn code_test_set(&mut self, set: u32, test: u32, to_test: u32) {
-        self.code
-            .push(Instruction::create_ABC(OpCode::TestSet, set, test, to_test));
+    // the local goes out of scope at the next instruction
+    pub fn end_local_var(&mut self, index: usize) {
+        self.local_vars[index].end_pc = self.code.len();
     }
 
-    pub fn add_local_var(&mut self, name: &str) {
+    // a local in scope from `start_pc` until before `end_pc`, e.g. of a loaded chunk
+    pub fn add_local_var_in(&mut self, name: Symbol, start_pc: usize, end_pc: usize) {
         self.local_vars.push(LocalVal {
+            name,
+            readonly: false,
+            start_pc,
+            end_pc,
+        });
+    }
+
+    // name of the local in register `reg` at `pc`, like `luaF_getlocalname`. locals are ordered
+    // by their registers among those in scope
+    pub fn local_var_name(&self, reg: u32, pc: usize) -> Option<&str> {
+        self.local_vars
+            .iter()
+            .filter(|var| var.start_pc <= pc && pc < var.end_pc)
+            .nth(reg as usize)
+            .map(|var| var.name())
+    }
+
+    pub fn add_up_var(&mut self, name: &str, in_stack: bool, index: u32) -> u32 {
+        self.up_vars.push(UpVal {
             name: name.to_string(),
+            in_stack,
+            index,
         });
+        (self.up_vars.len() - 1) as u32
     }
 
-    pub fn get_local_var(&self, name: &str) -> Option<u32> {
-        for (i, var) in self.local_vars.iter().enumerate() {
-            if var.name == name {
-                return Some(i as u32);
-            }
-        }
-        None
+    pub fn get_up_var(&self, name: &str) -> Option<u32> {
+        self.up_vars
+            .iter()
+            .position(|up_val| up_val.name == name)
+            .map(|i| i as u32)
+    }
+
+    pub fn get_local_var(&self, name: Symbol) -> Option<u32> {
+        self.local_vars
+            .iter()
+            .position(|var| var.name == name)
+            .map(|i| i as u32)
+    }
+
+    // add const without looking for an equal one
+    pub fn push_const(&mut self, k: Const) -> u32 {
+        self.consts.push(k);
+        (self.consts.len() - 1) as u32
     }
 
     pub fn add_const(&mut self, k: Const) -> u32 {
@@ -184,6 +601,18 @@ impl Proto {
         }
     }
 
+    // remove names of locals and upvalues, which are only used for debugging after compiling
+    pub fn strip_debug_info(&mut self) {
+        self.local_vars.clear();
+        self.line_info.clear();
+        for up_val in self.up_vars.iter_mut() {
+            up_val.name.clear();
+        }
+        for proto in self.protos.iter_mut() {
+            proto.strip_debug_info();
+        }
+    }
+
     // save result to target reg
     pub fn save(&mut self, target: u32) -> usize {
         let last = self.code.last_mut();
@@ -196,6 +625,85 @@ impl Proto {
     pub fn get_instruction(&mut self, index: usize) -> &mut Instruction {
         &mut self.code[index]
     }
+
+    // instructions added since the last call are at `line`
+    pub fn fix_line_info(&mut self, line: u32) {
+        while self.line_info.len() < self.code.len() {
+            self.line_info.push(line);
+        }
+    }
+
+    // read-only views for tools like disassemblers and verifiers
+
+    // decoded instructions in the order of pc
+    pub fn instructions(&self) -> impl Iterator<Item = (OpCode, OpArgs)> + '_ {
+        self.code.iter().map(|instruction| instruction.decode())
+    }
+
+    pub fn constants(&self) -> &[Const] {
+        &self.consts
+    }
+
+    // functions defined in this function, indexed by Bx of Closure
+    pub fn children(&self) -> &[Proto] {
+        &self.protos
+    }
+
+    // source line of the instruction at `pc`, none without debug info
+    pub fn line_of(&self, pc: usize) -> Option<u32> {
+        self.line_info.get(pc)
+    }
+
+    // sizes of the function and all nested functions
+    pub fn stats(&self) -> ProtoStats {
+        let mut stats = ProtoStats::default();
+        self.add_stats(&mut stats);
+        stats
+    }
+
+    fn add_stats(&self, stats: &mut ProtoStats) {
+        stats.instructions += self.code.len();
+        stats.registers = stats.registers.max(self.stack_size);
+        for k in self.consts.iter() {
+            let count = match k {
+                Const::Nil => &mut stats.constants.nil,
+                Const::Bool(_) => &mut stats.constants.bool,
+                Const::Int(_) => &mut stats.constants.int,
+                Const::Float(_) => &mut stats.constants.float,
+                Const::Str(_) => &mut stats.constants.str,
+            };
+            *count += 1;
+        }
+        stats.functions += self.protos.len();
+        for proto in self.protos.iter() {
+            proto.add_stats(stats);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProtoStats {
+    pub instructions: usize,
+    pub constants: ConstStats,
+    // registers used at most by any function, which is the largest stack size
+    pub registers: u32,
+    // nested functions at any depth
+    pub functions: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConstStats {
+    pub nil: usize,
+    pub bool: usize,
+    pub int: usize,
+    pub float: usize,
+    pub str: usize,
+}
+
+impl ConstStats {
+    pub fn total(&self) -> usize {
+        self.nil + self.bool + self.int + self.float + self.str
+    }
 }
 
 use std::fmt;
@@ -212,6 +720,8 @@ impl fmt::Debug for Proto {
                 "| {:<5} | {:<10} |",
                 i,
                 match k {
+                    Const::Nil => "nil".to_string(),
+                    Const::Bool(b) => b.to_string(),
                     Const::Int(i) => i.to_string(),
                     Const::Float(f) => f.to_string(),
                     Const::Str(s) => format!("\"{}\"", s.clone()),
@@ -238,16 +748,274 @@ impl fmt::Debug for Proto {
     }
 }
 
+// build a proto without the parser and compiler, e.g. for tests of the vm and code generators.
+// nothing is checked or added implicitly, not even the final return, call `Proto::verify`
+// on the result to check it.
+pub struct ProtoBuilder {
+    proto: Proto,
+    line: u32,
+}
+
+impl Default for ProtoBuilder {
+    fn default() -> Self {
+        ProtoBuilder::new()
+    }
+}
+
+impl ProtoBuilder {
+    pub fn new() -> Self {
+        ProtoBuilder {
+            proto: Proto::new(),
+            line: 1,
+        }
+    }
+
+    pub fn params(&mut self, count: u32, is_vararg: bool) -> &mut Self {
+        self.proto.param_count = count;
+        self.proto.is_vararg = is_vararg;
+        self
+    }
+
+    pub fn stack_size(&mut self, size: u32) -> &mut Self {
+        self.proto.stack_size = size;
+        self
+    }
+
+    // source line of the instructions emitted after it, 1 by default
+    pub fn line(&mut self, line: u32) -> &mut Self {
+        self.line = line;
+        self
+    }
+
+    // index of the constant, equal constants share the same index
+    pub fn constant(&mut self, k: Const) -> u32 {
+        self.proto.add_const(k)
+    }
+
+    // locals are named in the order of their registers, and in scope in the whole function
+    pub fn local(&mut self, name: &str) -> u32 {
+        self.proto.add_local_var_in(Symbol::from(name), 0, 0);
+        self.proto.local_vars.len() as u32 - 1
+    }
+
+    pub fn up_value(&mut self, name: &str, in_stack: bool, index: u32) -> u32 {
+        self.proto.add_up_var(name, in_stack, index)
+    }
+
+    // index of the nested function for Closure
+    pub fn child(&mut self, proto: Proto) -> u32 {
+        self.proto.protos.push(proto);
+        self.proto.protos.len() as u32 - 1
+    }
+
+    // pc of the instruction
+    pub fn emit(&mut self, instruction: Instruction) -> usize {
+        self.proto.code.push(instruction);
+        self.proto.fix_line_info(self.line);
+        self.proto.code.len() - 1
+    }
+
+    // change an emitted instruction, e.g. to patch a jump
+    pub fn patch(&mut self, pc: usize, instruction: Instruction) {
+        self.proto.code[pc] = instruction;
+    }
+
+    pub fn pc(&self) -> usize {
+        self.proto.code.len()
+    }
+
+    pub fn build(mut self) -> Proto {
+        for i in 0..self.proto.local_vars.len() {
+            self.proto.end_local_var(i);
+        }
+        self.proto
+    }
+}
+
+// a block of the function being compiled, like `BlockCnt` of lparser.c
+pub struct BlockScope {
+    // number of locals in scope when entering the block
+    pub nactvar: u32,
+    pub is_loop: bool,
+    // a local of the block is captured by a closure or to be closed, which leaving the block closes
+    pub upval: bool,
+    // labels and pending gotos of the block start from these indices
+    pub first_label: usize,
+    pub first_goto: usize,
+}
+
+// a label, or a goto waiting for its label, like `Labeldesc` of lparser.c
+pub struct LabelDesc {
+    pub name: Symbol,
+    // pc of the label, or of the jump of the goto
+    pub pc: usize,
+    // number of locals in scope at the label or goto
+    pub nactvar: u32,
+}
+
 pub struct ProtoContext {
     pub reg_top: u32,
     pub proto: Proto,
+    pub options: CompilerOptions,
+    // index in `proto.local_vars` of the local in each register, for the locals in scope
+    pub actvar: Vec<usize>,
+    pub blocks: Vec<BlockScope>,
+    // labels visible in the current block, and gotos which aren't resolved yet
+    pub labels: Vec<LabelDesc>,
+    pub gotos: Vec<LabelDesc>,
 }
 
 impl ProtoContext {
-    pub fn new() -> Self {
+    pub fn new(options: CompilerOptions) -> Self {
         ProtoContext {
             reg_top: 0,
             proto: Proto::new(),
+            options,
+            actvar: Vec::new(),
+            blocks: Vec::new(),
+            labels: Vec::new(),
+            gotos: Vec::new(),
+        }
+    }
+
+    // number of locals in scope, which take the registers below it
+    pub fn nactvar(&self) -> u32 {
+        self.actvar.len() as u32
+    }
+
+    // the local in the next register comes into scope
+    pub fn add_local_var(&mut self, name: Symbol, readonly: bool) {
+        let index = self.proto.add_local_var(name, readonly);
+        self.actvar.push(index);
+    }
+
+    // registers of locals which aren't in scope yet are not readonly
+    pub fn is_readonly_local_var(&self, reg: u32) -> bool {
+        self.actvar
+            .get(reg as usize)
+            .is_some_and(|index| self.proto.local_vars[*index].readonly)
+    }
+
+    pub fn enter_block(&mut self, is_loop: bool) {
+        self.blocks.push(BlockScope {
+            nactvar: self.nactvar(),
+            is_loop,
+            upval: false,
+            first_label: self.labels.len(),
+            first_goto: self.gotos.len(),
+        });
+    }
+
+    // locals of the block go out of scope and their registers are free again, its gotos which
+    // aren't resolved yet jump to labels of the enclosing blocks
+    pub fn leave_block(&mut self) {
+        let block = self.blocks.last().unwrap();
+        let (nactvar, is_loop) = (block.nactvar, block.is_loop);
+        // locals of the function are closed by its return instead
+        if block.upval && self.blocks.len() > 1 {
+            self.proto.code_jmp(0, nactvar + 1);
+        }
+        // breaks continue after the loop
+        if is_loop {
+            self.add_label(Symbol::from("break"), self.nactvar());
+        }
+        let block = self.blocks.pop().unwrap();
+        for index in self.actvar.drain(block.nactvar as usize..) {
+            self.proto.end_local_var(index);
+        }
+        self.reg_top = block.nactvar;
+        self.labels.truncate(block.first_label);
+        // the checker reports gotos without a label, so none are left at the end of the function
+        if !self.blocks.is_empty() {
+            self.move_gotos_out(&block);
+        }
+    }
+
+    // a label at the next instruction, which resolves the pending gotos of the current block
+    pub fn add_label(&mut self, name: Symbol, nactvar: u32) {
+        let pc = self.proto.get_label();
+        self.labels.push(LabelDesc { name, pc, nactvar });
+        let first_goto = self.blocks.last().unwrap().first_goto;
+        let label = self.labels.last().unwrap();
+        let mut i = first_goto;
+        while i < self.gotos.len() {
+            if self.gotos[i].name == label.name {
+                let goto = self.gotos.remove(i);
+                self.proto.fix_jump_pos(pc, goto.pc);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    // the jump at `pc` goes to the label, now if it's visible in the current block
+    pub fn add_goto(&mut self, name: Symbol, pc: usize) {
+        self.gotos.push(LabelDesc {
+            name,
+            pc,
+            nactvar: self.nactvar(),
+        });
+        self.find_label(self.gotos.len() - 1);
+    }
+
+    // resolve the goto at index `g` to a label of the current block, if there is one
+    fn find_label(&mut self, g: usize) -> bool {
+        let first_label = self.blocks.last().unwrap().first_label;
+        let goto = &self.gotos[g];
+        let label = match self.labels[first_label..]
+            .iter()
+            .find(|label| label.name == goto.name)
+        {
+            Some(label) => label,
+            None => return false,
+        };
+        // locals of the goto which the label is out of the scope of are closed
+        if goto.nactvar > label.nactvar {
+            self.proto.patch_close(goto.pc, label.nactvar);
+        }
+        self.proto.fix_jump_pos(label.pc, goto.pc);
+        self.gotos.remove(g);
+        true
+    }
+
+    // pending gotos of a block leave the scope of its locals, and are resolved to the labels
+    // of the enclosing block, like `movegotosout` of lparser.c
+    fn move_gotos_out(&mut self, block: &BlockScope) {
+        let mut i = block.first_goto;
+        while i < self.gotos.len() {
+            let goto = &mut self.gotos[i];
+            if goto.nactvar > block.nactvar {
+                if block.upval {
+                    self.proto.patch_close(goto.pc, block.nactvar);
+                }
+                goto.nactvar = block.nactvar;
+            }
+            if !self.find_label(i) {
+                i += 1;
+            }
+        }
+    }
+
+    // the local in `reg` has to be closed when leaving its block
+    pub fn mark_upval(&mut self, reg: u32) {
+        if let Some(block) = self.blocks.iter_mut().rev().find(|b| b.nactvar <= reg) {
+            block.upval = true;
+        }
+    }
+
+    pub fn add_const(&mut self, k: Const) -> u32 {
+        if self.options.dedupe_constants {
+            self.proto.add_const(k)
+        } else {
+            self.proto.push_const(k)
+        }
+    }
+
+    pub fn code_nil(&mut self, start_reg: u32, n: u32) -> usize {
+        if self.options.peephole && self.proto.merge_nil(start_reg, n) {
+            self.proto.code.len() - 1
+        } else {
+            self.proto.code_nil(start_reg, n)
         }
     }
 
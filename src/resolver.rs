@@ -0,0 +1,341 @@
+use crate::ast::*;
+use crate::ast_walker::{ast_walker, AstVisitor};
+use crate::debuggable;
+use crate::proto::ENV;
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+// resolve every name in the ast as a local, an up value or a global before codegen,
+// so the compiler and tools like linters or language servers share the same scope rules.
+//
+// names are annotated by their address in the ast, so the block must not be moved or changed
+// after resolving, and lookups must use a reference into the block instead of a copy of the symbol.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    // register of a local of the current function
+    Local(u32),
+    // index in up values of the current function
+    UpValue(u32),
+    // field of _ENV, which is a local or an up value itself
+    Global(Env),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Env {
+    Local(u32),
+    UpValue(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpValueDesc {
+    pub name: Symbol,
+    // captured from a register of the enclosing function, or from its up values
+    pub in_stack: bool,
+    pub index: u32,
+}
+
+#[derive(Default)]
+pub struct Resolutions {
+    names: HashMap<usize, Resolution>,
+    up_values: HashMap<usize, Vec<UpValueDesc>>,
+    main_up_values: Vec<UpValueDesc>,
+}
+
+impl Resolutions {
+    // resolution of a name used or declared in the ast, declarations are locals
+    pub fn get(&self, name: &Symbol) -> Option<Resolution> {
+        self.names.get(&key(name)).copied()
+    }
+
+    // up values captured by a function
+    pub fn up_values(&self, body: &FuncBody) -> &[UpValueDesc] {
+        self.up_values.get(&key(body)).map_or(&[], |u| u.as_slice())
+    }
+
+    pub fn main_up_values(&self) -> &[UpValueDesc] {
+        &self.main_up_values
+    }
+}
+
+fn key<T>(node: &T) -> usize {
+    node as *const T as usize
+}
+
+struct FuncScope {
+    // active locals, the index is the register
+    locals: Vec<Symbol>,
+    up_values: Vec<UpValueDesc>,
+    // number of active locals when entering each block
+    blocks: Vec<usize>,
+}
+
+pub struct Resolver {
+    debug: bool,
+    funcs: Vec<FuncScope>,
+    // locals declared by the statement which owns the next block, e.g. params and for loop vars,
+    // with the address of their names, internal locals have no names in the ast
+    block_locals: Vec<(Symbol, Option<usize>)>,
+    resolutions: Resolutions,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            debug: false,
+            funcs: Vec::new(),
+            block_locals: Vec::new(),
+            resolutions: Resolutions::default(),
+        }
+    }
+
+    pub fn run(&mut self, block: &Block) -> Resolutions {
+        self.funcs.clear();
+        self.block_locals.clear();
+        self.resolutions = Resolutions::default();
+        // _ENV is the first up value of the main function
+        self.funcs.push(FuncScope {
+            locals: Vec::new(),
+            up_values: vec![UpValueDesc {
+                name: Symbol::from(ENV),
+                in_stack: true,
+                index: 0,
+            }],
+            blocks: Vec::new(),
+        });
+        self.block(block, None);
+        let func = self.funcs.pop().unwrap();
+        self.resolutions.main_up_values = func.up_values;
+        std::mem::take(&mut self.resolutions)
+    }
+
+    fn func(&mut self, body: &FuncBody) {
+        self.funcs.push(FuncScope {
+            locals: Vec::new(),
+            up_values: Vec::new(),
+            blocks: Vec::new(),
+        });
+        self.block(&body.block, None);
+        let func = self.funcs.pop().unwrap();
+        self.resolutions.up_values.insert(key(body), func.up_values);
+    }
+
+    // `cond` is the until condition of repeat stat which is in the scope of block
+    fn block(&mut self, block: &Block, cond: Option<&Expr>) {
+        let nactvar = self.func_scope().locals.len();
+        self.func_scope().blocks.push(nactvar);
+        for (name, key) in std::mem::take(&mut self.block_locals) {
+            self.add_local(name, key);
+        }
+        for StatInfo { stat, .. } in block.stats.iter() {
+            let _ = match stat {
+                Stat::RepeatStat(stat) => {
+                    self.block(&stat.block, Some(&stat.cond));
+                    Ok(())
+                }
+                _ => ast_walker::walk_stat(stat, self),
+            };
+        }
+        if let Some(cond) = cond {
+            let _ = ast_walker::walk_expr(cond, self);
+        }
+        let func = self.func_scope();
+        let nactvar = func.blocks.pop().unwrap();
+        func.locals.truncate(nactvar);
+    }
+
+    fn add_local(&mut self, name: Symbol, key: Option<usize>) {
+        let func = self.func_scope();
+        let reg = func.locals.len() as u32;
+        func.locals.push(name);
+        if let Some(key) = key {
+            self.resolutions.names.insert(key, Resolution::Local(reg));
+        }
+    }
+
+    // annotate a name used in the ast
+    fn resolve_name(&mut self, name: &Symbol) {
+        let level = self.funcs.len() - 1;
+        let resolution = match self.find(name, level) {
+            Some(Env::Local(reg)) => Resolution::Local(reg),
+            Some(Env::UpValue(index)) => Resolution::UpValue(index),
+            // _ENV is always visible, at least as the up value of the main function
+            None => Resolution::Global(self.find(&Symbol::from(ENV), level).unwrap()),
+        };
+        self.resolutions.names.insert(key(name), resolution);
+    }
+
+    // find a variable visible to function at `level`, captured variables are added as up values
+    // of every function between the use and the definition
+    fn find(&mut self, name: &Symbol, level: usize) -> Option<Env> {
+        let func = &self.funcs[level];
+        if let Some(reg) = func.locals.iter().rposition(|l| l == name) {
+            return Some(Env::Local(reg as u32));
+        }
+        if let Some(index) = func.up_values.iter().position(|u| u.name == *name) {
+            return Some(Env::UpValue(index as u32));
+        }
+        if level == 0 {
+            return None;
+        }
+        let (in_stack, index) = match self.find(name, level - 1)? {
+            Env::Local(reg) => (true, reg),
+            Env::UpValue(index) => (false, index),
+        };
+        let up_values = &mut self.funcs[level].up_values;
+        up_values.push(UpValueDesc {
+            name: name.clone(),
+            in_stack,
+            index,
+        });
+        Some(Env::UpValue(up_values.len() as u32 - 1))
+    }
+
+    fn func_scope(&mut self) -> &mut FuncScope {
+        self.funcs.last_mut().unwrap()
+    }
+
+    debuggable!();
+}
+
+impl AstVisitor for Resolver {
+    fn then(&mut self, block: &Block) -> Result<bool, ()> {
+        self.block(block, None);
+        Ok(true)
+    }
+
+    fn begin_else(&mut self, block: &Block) -> Result<bool, ()> {
+        self.block(block, None);
+        Ok(true)
+    }
+
+    fn begin_while_block(&mut self, block: &Block) -> Result<bool, ()> {
+        self.block(block, None);
+        Ok(true)
+    }
+
+    fn begin_do_block(&mut self, block: &Block) -> Result<bool, ()> {
+        self.block(block, None);
+        Ok(true)
+    }
+
+    fn begin_for_block(&mut self, block: &Block) -> Result<bool, ()> {
+        self.block(block, None);
+        Ok(true)
+    }
+
+    // internal locals of for loop take registers before the loop vars
+    fn for_num(&mut self, stat: &ForNum) -> Result<bool, ()> {
+        ast_walker::walk_expr(&stat.init, self)?;
+        ast_walker::walk_expr(&stat.limit, self)?;
+        if let Some(step) = &stat.step {
+            ast_walker::walk_expr(step, self)?;
+        }
+        self.block_locals = vec![
+            (Symbol::from("(for index)"), None),
+            (Symbol::from("(for limit)"), None),
+            (Symbol::from("(for step)"), None),
+            (stat.var.clone(), Some(key(&stat.var))),
+        ];
+        Ok(true)
+    }
+
+    fn for_list(&mut self, stat: &ForList) -> Result<bool, ()> {
+        ast_walker::walk_exprlist(&stat.exprs, self)?;
+        self.block_locals = vec![
+            (Symbol::from("(for generator)"), None),
+            (Symbol::from("(for state)"), None),
+            (Symbol::from("(for control)"), None),
+        ];
+        for var in stat.vars.iter() {
+            self.block_locals.push((var.clone(), Some(key(var))));
+        }
+        Ok(true)
+    }
+
+    fn func(&mut self, stat: &FuncStat) {
+        if stat.func_name.method.is_some() {
+            self.block_locals.push((Symbol::from("self"), None));
+        }
+        match stat.func_name.fields.first() {
+            // local function is in scope of its own body
+            Some(name) if stat.func_type == FuncType::Local => {
+                self.add_local(name.clone(), Some(key(name)))
+            }
+            Some(name) => self.resolve_name(name),
+            None => (),
+        }
+    }
+
+    fn local_stat(&mut self, stat: &LocalStat) -> Result<(), ()> {
+        ast_walker::walk_exprlist(&stat.exprs, self)?;
+        for name in stat.names.iter() {
+            self.add_local(name.clone(), Some(key(name)));
+        }
+        Ok(())
+    }
+
+    fn ret_stat(&mut self, stat: &RetStat) -> Result<(), ()> {
+        ast_walker::walk_exprlist(&stat.exprs, self)
+    }
+
+    fn assign_stat(&mut self, stat: &AssignStat) -> Result<(), ()> {
+        for assignable in stat.left.iter() {
+            self.assignable(assignable)?;
+        }
+        ast_walker::walk_exprlist(&stat.right, self)
+    }
+
+    fn compound_assign_stat(&mut self, stat: &CompoundAssignStat) -> Result<(), ()> {
+        self.assignable(&stat.left)?;
+        ast_walker::walk_expr(&stat.right, self)
+    }
+
+    fn call_stat(&mut self, stat: &CallStat) -> Result<(), ()> {
+        self.assignable(&stat.call)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<bool, ()> {
+        if let Expr::Name(name) = expr {
+            self.resolve_name(name);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn begin_field_key(&mut self, key: &FieldKey) -> Result<bool, ()> {
+        // names as field keys are strings
+        if let FieldKey::Expr(expr) = key {
+            ast_walker::walk_expr(expr, self)?;
+        }
+        Ok(true)
+    }
+
+    fn begin_func_body(&mut self, body: &FuncBody) -> Result<bool, ()> {
+        for param in body.params.iter() {
+            if let Param::Name(name) = param {
+                self.block_locals.push((name.clone(), Some(key(name))));
+            }
+        }
+        self.func(body);
+        Ok(true)
+    }
+}
+
+impl Resolver {
+    fn assignable(&mut self, assignable: &Assignable) -> Result<(), ()> {
+        match assignable {
+            Assignable::Name(name) => {
+                self.resolve_name(name);
+                Ok(())
+            }
+            _ => ast_walker::walk_assinable(assignable, self),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Write;
+
+// maps instructions of compiled functions back to the source, for debuggers and error reporters.
+//
+// each instruction belongs to the statement it was generated for, so passes that rewrite
+// instructions in place keep the map valid. functions are named like in listings of the
+// disassembler, e.g. `main.0`, lines and columns start from 1.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourcePos {
+    pub line: usize,
+    pub col: usize,
+}
+
+// instructions [start_pc, end_pc) of a function generated from the source at `pos`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub function: String,
+    pub start_pc: usize,
+    pub end_pc: usize,
+    pub pos: SourcePos,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    // map instructions to `pos`, empty ranges are ignored
+    pub fn add(&mut self, function: &str, start_pc: usize, end_pc: usize, pos: SourcePos) {
+        if start_pc < end_pc {
+            self.mappings.push(Mapping {
+                function: function.to_string(),
+                start_pc,
+                end_pc,
+                pos,
+            });
+        }
+    }
+
+    // source of the instruction at `pc` of a function
+    pub fn get(&self, function: &str, pc: usize) -> Option<SourcePos> {
+        self.mappings
+            .iter()
+            .find(|m| m.function == function && m.start_pc <= pc && pc < m.end_pc)
+            .map(|m| m.pos)
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    pub fn clear(&mut self) {
+        self.mappings.clear();
+    }
+
+    // json for external tools, `file` is the name of the source chunk
+    pub fn to_json(&self, file: &str) -> String {
+        let mut output = String::new();
+        let _ = write!(output, "{{\"file\":{},\"mappings\":[", json_string(file));
+        for (i, m) in self.mappings.iter().enumerate() {
+            if i > 0 {
+                output.push(',');
+            }
+            let _ = write!(
+                output,
+                "{{\"function\":{},\"start_pc\":{},\"end_pc\":{},\"line\":{},\"col\":{}}}",
+                json_string(&m.function),
+                m.start_pc,
+                m.end_pc,
+                m.pos.line,
+                m.pos.col
+            );
+        }
+        output.push_str("]}");
+        output
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut output = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
@@ -0,0 +1,164 @@
+use crate::base;
+use crate::compiler::Compiler;
+use crate::coroutine;
+use crate::lexer::Lexer;
+use crate::lib_util::bad_argument;
+use crate::parser::Parser;
+use crate::string;
+use crate::vm::{FuncProto, NativeFunction, RuntimeError, Vm};
+use std::fmt;
+use std::rc::Rc;
+
+// the stable subset of the api for embedders.
+//
+// items here only change in semver compatible ways, while the modules they are built on, e.g.
+// `proto` and `compiler`, may change with any release. chunks are opaque, so embedders never
+// depend on the layout of instructions or compiler state.
+
+pub use crate::convert::{
+    BadArgument, ConvertError, FromLua, FromLuaMulti, MultiValue, ToLua, ToLuaMulti, Variadic,
+};
+pub use crate::table::Table;
+pub use crate::value::{LuaStr, TableRef, UserData, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Syntax(String),
+    Compile(String),
+    Runtime(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax(msg) => write!(f, "syntax error: {}", msg),
+            Error::Compile(msg) => write!(f, "compile error: {}", msg),
+            Error::Runtime(msg) => write!(f, "runtime error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// a compiled chunk, which can run many times
+#[derive(Clone)]
+pub struct Chunk(Rc<FuncProto>);
+
+pub fn compile(source: &str) -> Result<Chunk, Error> {
+    let tokens = Lexer::new()
+        .run(source)
+        .map_err(|e| Error::Syntax(e.to_string()))?;
+    let block = Parser::new()
+        .run(tokens)
+        .map_err(|e| Error::Syntax(e.to_string()))?;
+    Compiler::new()
+        .run(&block)
+        .map_err(|e| Error::Compile(e.0))
+        .and_then(|proto| FuncProto::new(proto).map_err(|e| Error::Compile(e.0)))
+        .map(Chunk)
+}
+
+// a lua state, with its globals and the libraries
+pub struct Lua {
+    vm: Vm,
+}
+
+impl Default for Lua {
+    fn default() -> Self {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        coroutine::open(&mut vm);
+        string::open(&mut vm);
+        Lua { vm }
+    }
+}
+
+impl Lua {
+    pub fn new() -> Self {
+        Lua::default()
+    }
+
+    // run the main function of a chunk, returning its results
+    pub fn run(&mut self, chunk: &Chunk) -> Result<Vec<Value>, Error> {
+        let main = self.vm.load_func(chunk.0.clone());
+        self.call(&main, &[])
+    }
+
+    // compile and run a source
+    pub fn exec(&mut self, source: &str) -> Result<Vec<Value>, Error> {
+        let chunk = compile(source)?;
+        self.run(&chunk)
+    }
+
+    pub fn call(&mut self, func: &Value, args: &[Value]) -> Result<Vec<Value>, Error> {
+        self.vm
+            .call(func, args)
+            .map_err(|e| Error::Runtime(e.message().to_string()))
+    }
+
+    pub fn get_global(&self, name: &str) -> Value {
+        self.vm.get_global(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.vm.set_global(name, value);
+    }
+
+    pub fn globals(&self) -> &TableRef {
+        self.vm.globals()
+    }
+
+    // a function for scripts, calling `func` with the args converted to `A`, e.g. a tuple of
+    // one type per arg, and returning the values of its result. args which can't be converted
+    // fail before it runs, and its errors are raised in lua with their messages
+    pub fn create_function<A, R, E, F>(&self, name: &str, func: F) -> Value
+    where
+        A: FromLuaMulti,
+        R: ToLuaMulti,
+        E: Into<Box<dyn std::error::Error>>,
+        F: Fn(&mut Context, A) -> Result<R, E> + 'static,
+    {
+        let fname = name.to_string();
+        let native = NativeFunction::new(name, move |vm, args| {
+            let args = A::from_lua_multi(args)
+                .map_err(|BadArgument(n, e)| bad_argument(n, &fname, &e.to_string()))?;
+            match func(&mut Context { vm }, args) {
+                Ok(results) => Ok(results.to_lua_multi()),
+                Err(e) => {
+                    let e = e.into();
+                    Err(match e.downcast_ref::<Error>() {
+                        // from calls of the context, which were lua errors already
+                        Some(Error::Runtime(msg)) => RuntimeError::from_value(Value::str(msg)),
+                        _ => RuntimeError::new(e.to_string()),
+                    })
+                }
+            }
+        });
+        Value::Native(Rc::new(native))
+    }
+}
+
+// the lua state as functions of the host see it while they run
+pub struct Context<'a> {
+    vm: &'a mut Vm,
+}
+
+impl<'a> Context<'a> {
+    pub fn call(&mut self, func: &Value, args: &[Value]) -> Result<Vec<Value>, Error> {
+        self.vm
+            .call(func, args)
+            .map_err(|e| Error::Runtime(e.message().to_string()))
+    }
+
+    pub fn get_global(&self, name: &str) -> Value {
+        self.vm.get_global(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.vm.set_global(name, value);
+    }
+
+    pub fn globals(&self) -> &TableRef {
+        self.vm.globals()
+    }
+}
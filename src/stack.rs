@@ -0,0 +1,223 @@
+use crate::table::Table;
+use crate::types::{FloatType, IntType};
+use crate::value::{float_to_int, str_to_number, LuaStr, Value};
+use crate::vm::{to_lua_str, RuntimeError, Vm};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// a stack of values on top of the vm, for code ported from the c api.
+//
+// indices are as in c: 1 is the bottom, -1 the top, and functions take their operands from
+// the top and push their results. invalid indices panic, where c would be undefined
+
+// `call` with all the results
+pub const MULTRET: Option<usize> = None;
+
+pub struct Stack<'a> {
+    vm: &'a mut Vm,
+    values: Vec<Value>,
+}
+
+impl<'a> Stack<'a> {
+    pub fn new(vm: &'a mut Vm) -> Self {
+        Stack::with_values(vm, Vec::new())
+    }
+
+    // e.g. with the args of a native function, which returns `into_values`
+    pub fn with_values(vm: &'a mut Vm, values: Vec<Value>) -> Self {
+        Stack { vm, values }
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+
+    pub fn vm(&mut self) -> &mut Vm {
+        self.vm
+    }
+
+    // the slot of an index, 0 is the bottom
+    fn slot(&self, idx: i32) -> usize {
+        let top = self.values.len() as i32;
+        let slot = if idx > 0 { idx - 1 } else { top + idx };
+        if idx == 0 || slot < 0 || slot >= top {
+            panic!("invalid stack index {}", idx);
+        }
+        slot as usize
+    }
+
+    pub fn get_top(&self) -> usize {
+        self.values.len()
+    }
+
+    // values above are removed, missing ones are nil
+    pub fn set_top(&mut self, top: usize) {
+        self.values.resize(top, Value::Nil);
+    }
+
+    pub fn pop(&mut self, n: usize) {
+        let top = self.values.len();
+        assert!(n <= top, "popping {} of {} values", n, top);
+        self.values.truncate(top - n);
+    }
+
+    pub fn get(&self, idx: i32) -> &Value {
+        &self.values[self.slot(idx)]
+    }
+
+    pub fn push_value(&mut self, value: Value) {
+        self.values.push(value);
+    }
+
+    pub fn push_nil(&mut self) {
+        self.push_value(Value::Nil);
+    }
+
+    pub fn push_boolean(&mut self, b: bool) {
+        self.push_value(Value::Bool(b));
+    }
+
+    pub fn push_integer(&mut self, i: IntType) {
+        self.push_value(Value::Int(i));
+    }
+
+    pub fn push_number(&mut self, f: FloatType) {
+        self.push_value(Value::Float(f));
+    }
+
+    pub fn push_string(&mut self, s: &str) {
+        self.push_value(Value::str(s));
+    }
+
+    // a copy of the value at `idx`, `lua_pushvalue` in c
+    pub fn push_copy(&mut self, idx: i32) {
+        let value = self.get(idx).clone();
+        self.push_value(value);
+    }
+
+    // move the top value to `idx`, shifting up the values above
+    pub fn insert(&mut self, idx: i32) {
+        let slot = self.slot(idx);
+        let value = self.values.pop().unwrap();
+        self.values.insert(slot, value);
+    }
+
+    pub fn remove(&mut self, idx: i32) {
+        let slot = self.slot(idx);
+        self.values.remove(slot);
+    }
+
+    // pop the top value into `idx`
+    pub fn replace(&mut self, idx: i32) {
+        let slot = self.slot(idx);
+        let value = self.values.pop().unwrap();
+        if slot < self.values.len() {
+            self.values[slot] = value;
+        }
+    }
+
+    pub fn type_name(&self, idx: i32) -> &'static str {
+        self.get(idx).type_name()
+    }
+
+    pub fn is_nil(&self, idx: i32) -> bool {
+        self.get(idx).is_nil()
+    }
+
+    pub fn to_boolean(&self, idx: i32) -> bool {
+        self.get(idx).is_truthy()
+    }
+
+    // none if it isn't a number or numeral with an integer value
+    pub fn to_integer(&self, idx: i32) -> Option<IntType> {
+        match self.number(idx)? {
+            Value::Int(i) => Some(i),
+            Value::Float(f) => float_to_int(f),
+            _ => None,
+        }
+    }
+
+    // numbers, and strings holding numerals
+    pub fn to_number(&self, idx: i32) -> Option<FloatType> {
+        match self.number(idx)? {
+            Value::Int(i) => Some(i as FloatType),
+            Value::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    fn number(&self, idx: i32) -> Option<Value> {
+        match self.get(idx) {
+            Value::Str(s) => str_to_number(s.as_bytes()),
+            value => Some(value.clone()),
+        }
+    }
+
+    // strings, and numbers as strings, which doesn't change the value on the stack unlike c
+    pub fn to_string(&self, idx: i32) -> Option<LuaStr> {
+        to_lua_str(self.get(idx))
+    }
+
+    pub fn new_table(&mut self) {
+        self.push_value(Value::Table(Rc::new(RefCell::new(Table::new()))));
+    }
+
+    pub fn get_global(&mut self, name: &str) {
+        let value = self.vm.get_global(name);
+        self.push_value(value);
+    }
+
+    // pop the value into the global
+    pub fn set_global(&mut self, name: &str) {
+        let value = self.values.pop().expect("empty stack");
+        self.vm.set_global(name, value);
+    }
+
+    // push t[k] of the table at `idx`, with `__index`
+    pub fn get_field(&mut self, idx: i32, k: &str) -> Result<(), RuntimeError> {
+        let table = self.get(idx).clone();
+        let value = self.vm.index(&table, &Value::str(k))?;
+        self.push_value(value);
+        Ok(())
+    }
+
+    // pop the value into t[k] of the table at `idx`, with `__newindex`
+    pub fn set_field(&mut self, idx: i32, k: &str) -> Result<(), RuntimeError> {
+        let table = self.get(idx).clone();
+        let value = self.values.pop().expect("empty stack");
+        self.vm.new_index(&table, Value::str(k), value)
+    }
+
+    // replace the key on the top with t[key] of the table at `idx`
+    pub fn get_table(&mut self, idx: i32) -> Result<(), RuntimeError> {
+        let table = self.get(idx).clone();
+        let key = self.values.pop().expect("empty stack");
+        let value = self.vm.index(&table, &key)?;
+        self.push_value(value);
+        Ok(())
+    }
+
+    // pop the value and then the key into t[key] of the table at `idx`
+    pub fn set_table(&mut self, idx: i32) -> Result<(), RuntimeError> {
+        let table = self.get(idx).clone();
+        let value = self.values.pop().expect("empty stack");
+        let key = self.values.pop().expect("empty stack");
+        self.vm.new_index(&table, key, value)
+    }
+
+    // pop the function and its `nargs` args, which were pushed in order, and push `nresults`
+    // of its results, or all of them with `MULTRET`. the function and args are popped on
+    // errors too
+    pub fn call(&mut self, nargs: usize, nresults: Option<usize>) -> Result<(), RuntimeError> {
+        let top = self.values.len();
+        assert!(nargs < top, "calling with {} args on {} values", nargs, top);
+        let args = self.values.split_off(top - nargs);
+        let func = self.values.pop().unwrap();
+        let mut results = self.vm.call(&func, &args)?;
+        if let Some(n) = nresults {
+            results.resize(n, Value::Nil);
+        }
+        self.values.extend(results);
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use crate::lib_util::{bad_argument, check_int, type_error, LibFn, LibResult};
+use crate::table::Table;
+use crate::types::IntType;
+use crate::value::{LuaStr, Value};
+use crate::vm::{to_lua_str, NativeFunction, RuntimeError, Vm};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// the `string` library, which is also the `__index` of strings, so `s:upper()` works.
+// strings are bytes, positions start at 1 and negative ones count from the end
+
+// a string arg, numbers are converted
+fn check_str(args: &[Value], n: usize, name: &str) -> Result<LuaStr, RuntimeError> {
+    args.get(n - 1)
+        .and_then(to_lua_str)
+        .ok_or_else(|| type_error(args, n, name, "string"))
+}
+
+fn opt_int(
+    args: &[Value],
+    n: usize,
+    name: &str,
+    default: IntType,
+) -> Result<IntType, RuntimeError> {
+    match args.get(n - 1) {
+        None | Some(Value::Nil) => Ok(default),
+        _ => check_int(args, n, name),
+    }
+}
+
+// a start position in a string of `len` bytes, from 1 to past the end
+fn start_pos(pos: IntType, len: usize) -> usize {
+    if pos > 0 {
+        pos as usize
+    } else if pos == 0 || pos.unsigned_abs() > len as u64 {
+        1
+    } else {
+        len - pos.unsigned_abs() as usize + 1
+    }
+}
+
+// an end position in a string of `len` bytes, from 0 to the end
+fn end_pos(pos: IntType, len: usize) -> usize {
+    if pos > len as IntType {
+        len
+    } else if pos >= 0 {
+        pos as usize
+    } else if pos.unsigned_abs() > len as u64 {
+        0
+    } else {
+        len - pos.unsigned_abs() as usize + 1
+    }
+}
+
+fn string(bytes: Vec<u8>) -> LibResult {
+    Ok(vec![Value::Str(LuaStr::from(bytes))])
+}
+
+fn len(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let s = check_str(&args, 1, "len")?;
+    Ok(vec![Value::Int(s.len() as IntType)])
+}
+
+// sub(s, i [, j]) is the bytes from i to j, which is -1 for the end by default
+fn sub(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let s = check_str(&args, 1, "sub")?;
+    let bytes = s.as_bytes();
+    let start = start_pos(check_int(&args, 2, "sub")?, bytes.len());
+    let end = end_pos(opt_int(&args, 3, "sub", -1)?, bytes.len());
+    if start > end {
+        return string(Vec::new());
+    }
+    string(bytes[start - 1..end].to_vec())
+}
+
+// letters are those of ascii, like in the c locale
+fn upper(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let s = check_str(&args, 1, "upper")?;
+    string(s.as_bytes().to_ascii_uppercase())
+}
+
+fn lower(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let s = check_str(&args, 1, "lower")?;
+    string(s.as_bytes().to_ascii_lowercase())
+}
+
+// rep(s, n [, sep]) is n copies of s separated by sep, the result counts against the memory
+// limit
+fn rep(vm: &mut Vm, args: Vec<Value>) -> LibResult {
+    let s = check_str(&args, 1, "rep")?;
+    let n = check_int(&args, 2, "rep")?;
+    let sep = match args.get(2) {
+        None | Some(Value::Nil) => LuaStr::from(""),
+        _ => check_str(&args, 3, "rep")?,
+    };
+    if n <= 0 {
+        return string(Vec::new());
+    }
+    let n = n as usize;
+    let size = s
+        .len()
+        .checked_mul(n)
+        .and_then(|size| size.checked_add(sep.len().checked_mul(n - 1)?))
+        .filter(|size| *size <= IntType::MAX as usize)
+        .ok_or_else(|| RuntimeError::new("resulting string too large"))?;
+    // the loop below would run `n` times to build nothing
+    if size == 0 {
+        return string(Vec::new());
+    }
+    vm.allocate(size)?;
+    let mut bytes = Vec::new();
+    if bytes.try_reserve_exact(size).is_err() {
+        return Err(RuntimeError::out_of_memory());
+    }
+    for i in 0..n {
+        if i > 0 {
+            bytes.extend_from_slice(sep.as_bytes());
+        }
+        bytes.extend_from_slice(s.as_bytes());
+    }
+    string(bytes)
+}
+
+// byte(s [, i [, j]]) is the codes of the bytes from i to j, which are 1 and i by default
+fn byte(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let s = check_str(&args, 1, "byte")?;
+    let bytes = s.as_bytes();
+    let i = opt_int(&args, 2, "byte", 1)?;
+    let start = start_pos(i, bytes.len());
+    let end = end_pos(opt_int(&args, 3, "byte", i)?, bytes.len());
+    if start > end {
+        return Ok(Vec::new());
+    }
+    Ok(bytes[start - 1..end]
+        .iter()
+        .map(|b| Value::Int(*b as IntType))
+        .collect())
+}
+
+// char(...) is the string of the bytes with the codes of the args
+fn char(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let mut bytes = Vec::with_capacity(args.len());
+    for n in 1..=args.len() {
+        let code = check_int(&args, n, "char")?;
+        if !(0..=255).contains(&code) {
+            return Err(bad_argument(n, "char", "value out of range"));
+        }
+        bytes.push(code as u8);
+    }
+    string(bytes)
+}
+
+fn reverse(_: &mut Vm, args: Vec<Value>) -> LibResult {
+    let s = check_str(&args, 1, "reverse")?;
+    string(s.as_bytes().iter().rev().cloned().collect())
+}
+
+// add the library to the globals, and set the metatable of strings to look up its functions
+pub fn open(vm: &mut Vm) {
+    let functions: [(&str, LibFn); 8] = [
+        ("byte", byte),
+        ("char", char),
+        ("len", len),
+        ("lower", lower),
+        ("rep", rep),
+        ("reverse", reverse),
+        ("sub", sub),
+        ("upper", upper),
+    ];
+    let mut lib = Table::new();
+    for (name, func) in functions.iter() {
+        let native = NativeFunction::new(name, *func);
+        lib.set_str(name, Value::Native(Rc::new(native)));
+    }
+    let lib = Rc::new(RefCell::new(lib));
+    let mut meta = Table::new();
+    meta.set_str("__index", Value::Table(lib.clone()));
+    vm.set_string_metatable(Some(Rc::new(RefCell::new(meta))));
+    vm.set_global("string", Value::Table(lib));
+}
@@ -0,0 +1,128 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, OnceLock};
+
+// interned identifier, names in the ast are compared and hashed by address.
+//
+// a symbol holds its text, so reading it needs no lookup, only interning takes the lock of the
+// table. equal names share their text through the table, so asts and protos can be sent to
+// other threads and still compare with their symbols. names only referred to by the table are
+// dropped when it has doubled since the last sweep
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+struct SymbolTable {
+    names: HashSet<Arc<str>>,
+    threshold: usize,
+}
+
+const MIN_SYMBOLS: usize = 256;
+
+static SYMBOLS: OnceLock<Mutex<SymbolTable>> = OnceLock::new();
+
+impl SymbolTable {
+    fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(s) = self.names.get(name) {
+            return s.clone();
+        }
+        if self.names.len() >= self.threshold {
+            // only the table can clone a name referred to by nothing else, which it's locked for
+            self.names.retain(|s| Arc::strong_count(s) > 1);
+            self.threshold = MIN_SYMBOLS.max(self.names.len() * 2);
+        }
+        let s: Arc<str> = name.into();
+        self.names.insert(s.clone());
+        s
+    }
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let symbols = SYMBOLS.get_or_init(|| {
+            Mutex::new(SymbolTable {
+                names: HashSet::new(),
+                threshold: MIN_SYMBOLS,
+            })
+        });
+        // the table is still usable if another thread panicked while interning
+        let mut symbols = symbols.lock().unwrap_or_else(|e| e.into_inner());
+        Symbol(symbols.intern(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(Arc::as_ptr(&self.0) as *const u8, state)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::intern(&name)
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+// same as the text, so the ast prints like it did with string names
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
@@ -0,0 +1,279 @@
+use crate::types::IntType;
+use crate::value::{float_to_int, TableRef, Value};
+use std::collections::HashMap;
+
+// tables of the vm, keys are compared by raw equality, so `t[1]` and `t[1.0]` are the same field.
+//
+// like in lua, fields with keys 1..n are kept in an array part and all others in a hash part.
+// appending to the array part grows it, and when the hash part is full, integer keys are
+// redistributed so that the array part is the largest size with more than half of it in use.
+
+#[derive(Default)]
+pub struct Table {
+    // t[i + 1], may have nil holes
+    array: Vec<Value>,
+    hash: HashMap<Value, Value>,
+    metatable: Option<TableRef>,
+    // changed by every write, the collector rescans tables written during a collection
+    version: u32,
+}
+
+// index in the array part of an integer key, if it can be there
+fn array_index(key: &Value) -> Option<usize> {
+    let i = match key {
+        Value::Int(i) => *i,
+        Value::Float(f) => float_to_int(*f)?,
+        _ => return None,
+    };
+    if i >= 1 {
+        Some(i as usize - 1)
+    } else {
+        None
+    }
+}
+
+// floats with integral values are stored as integers
+fn normalize(key: Value) -> Value {
+    match key {
+        Value::Float(f) => float_to_int(f).map_or(key, Value::Int),
+        key => key,
+    }
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Table::default()
+    }
+
+    // preallocated for `array` fields in the array part and `hash` fields in the hash part
+    pub fn with_capacity(array: usize, hash: usize) -> Self {
+        Table {
+            array: Vec::with_capacity(array),
+            hash: HashMap::with_capacity(hash),
+            metatable: None,
+            version: 0,
+        }
+    }
+
+    pub fn metatable(&self) -> Option<&TableRef> {
+        self.metatable.as_ref()
+    }
+
+    pub fn set_metatable(&mut self, metatable: Option<TableRef>) {
+        self.version = self.version.wrapping_add(1);
+        self.metatable = metatable;
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(value) = array_index(key).and_then(|i| self.array.get(i)) {
+            return value.clone();
+        }
+        self.hash.get(key).cloned().unwrap_or(Value::Nil)
+    }
+
+    pub fn get_int(&self, key: IntType) -> Value {
+        self.get(&Value::Int(key))
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::str(key))
+    }
+
+    // caller should make sure the key is neither nil nor NaN, assigning nil removes the field
+    pub fn set(&mut self, key: Value, value: Value) {
+        self.version = self.version.wrapping_add(1);
+        let index = array_index(&key);
+        if let Some(slot) = index.and_then(|i| self.array.get_mut(i)) {
+            *slot = value;
+            return;
+        }
+        if value.is_nil() {
+            self.hash.remove(&key);
+            return;
+        }
+        // appending, move following fields from the hash part too
+        if index == Some(self.array.len()) {
+            self.array.push(value);
+            self.migrate();
+            return;
+        }
+        let key = normalize(key);
+        if !self.hash.contains_key(&key) && self.hash.len() == self.hash.capacity() {
+            self.rehash(index);
+            if let Some(slot) = index.and_then(|i| self.array.get_mut(i)) {
+                *slot = value;
+                return;
+            }
+        }
+        self.hash.insert(key, value);
+    }
+
+    pub fn set_int(&mut self, key: IntType, value: Value) {
+        self.set(Value::Int(key), value);
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        self.set(Value::str(key), value);
+    }
+
+    // a border, which is `n` where t[n] isn't nil and t[n + 1] is nil, or 0 if t[1] is nil.
+    // with holes, any border may be returned
+    pub fn len(&self) -> usize {
+        let n = self.array.len();
+        if n > 0 && self.array[n - 1].is_nil() {
+            // binary search in the array part, t[lo] isn't nil and t[hi] is
+            let (mut lo, mut hi) = (0, n);
+            while hi - lo > 1 {
+                let m = (lo + hi) / 2;
+                if self.array[m - 1].is_nil() {
+                    hi = m;
+                } else {
+                    lo = m;
+                }
+            }
+            return lo;
+        }
+        if self.hash.is_empty() {
+            return n;
+        }
+        self.hash_border(n)
+    }
+
+    // a border after `n`, where t[n] isn't nil or n is 0
+    fn hash_border(&self, n: usize) -> usize {
+        let present = |i: usize| self.hash.contains_key(&Value::Int(i as IntType));
+        let (mut i, mut j) = (n, n + 1);
+        while present(j) {
+            i = j;
+            if j > IntType::MAX as usize / 2 {
+                // pathological table, linear search
+                let mut i = 1;
+                while present(i) {
+                    i += 1;
+                }
+                return i - 1;
+            }
+            j *= 2;
+        }
+        while j - i > 1 {
+            let m = (i + j) / 2;
+            if present(m) {
+                i = m;
+            } else {
+                j = m;
+            }
+        }
+        i
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty() && self.array.iter().all(Value::is_nil)
+    }
+
+    // fields of the array part and then of the hash part, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (Value, &Value)> {
+        let array = self
+            .array
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !value.is_nil())
+            .map(|(i, value)| (Value::Int(i as IntType + 1), value));
+        array.chain(self.hash.iter().map(|(key, value)| (key.clone(), value)))
+    }
+
+    // sizes of the array and hash parts, nil holes in the array part included
+    pub fn array_size(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn hash_size(&self) -> usize {
+        self.hash.len()
+    }
+
+    // remove all fields and the metatable
+    pub fn clear(&mut self) {
+        self.version = self.version.wrapping_add(1);
+        self.array = Vec::new();
+        self.hash = HashMap::new();
+        self.metatable = None;
+    }
+
+    pub(crate) fn version(&self) -> u32 {
+        self.version
+    }
+
+    // approximate number of bytes used, with the allocated capacity of both parts
+    pub fn memory(&self) -> usize {
+        Table::memory_for(self.array.capacity(), self.hash.capacity())
+    }
+
+    // estimated bytes of a table with room for `array` items and `hash` fields
+    pub fn memory_for(array: usize, hash: usize) -> usize {
+        std::mem::size_of::<Table>()
+            + array * std::mem::size_of::<Value>()
+            + hash * 2 * std::mem::size_of::<Value>()
+    }
+
+    // move fields following the array part from the hash part
+    fn migrate(&mut self) {
+        while let Some(value) = self
+            .hash
+            .remove(&Value::Int(self.array.len() as IntType + 1))
+        {
+            self.array.push(value);
+        }
+    }
+
+    // resize the array part for the integer keys, with `extra` being inserted
+    fn rehash(&mut self, extra: Option<usize>) {
+        // nums[b] is the number of keys k with 2^(b-1) < k <= 2^b, k = index + 1
+        let mut nums = [0usize; 64];
+        let mut count =
+            |index: usize| nums[usize::BITS as usize - index.leading_zeros() as usize] += 1;
+        for (i, value) in self.array.iter().enumerate() {
+            if !value.is_nil() {
+                count(i);
+            }
+        }
+        for key in self.hash.keys() {
+            if let Some(i) = array_index(key) {
+                count(i);
+            }
+        }
+        if let Some(i) = extra {
+            count(i);
+        }
+
+        // largest power of 2 with more than half of the slots in use
+        let total: usize = nums.iter().sum();
+        let (mut size, mut used) = (0, 0);
+        for (b, n) in nums.iter().enumerate() {
+            let two_to_b = 1usize << b;
+            if total <= two_to_b / 2 {
+                break;
+            }
+            used += n;
+            if used > two_to_b / 2 {
+                size = two_to_b;
+            }
+        }
+
+        if size < self.array.len() {
+            for (i, value) in self.array.drain(size..).enumerate() {
+                if !value.is_nil() {
+                    self.hash
+                        .insert(Value::Int((size + i) as IntType + 1), value);
+                }
+            }
+        } else if size > self.array.len() {
+            let start = self.array.len();
+            self.array.resize(size, Value::Nil);
+            for i in start..size {
+                if let Some(value) = self.hash.remove(&Value::Int(i as IntType + 1)) {
+                    self.array[i] = value;
+                }
+            }
+        }
+        self.hash.reserve(self.hash.len().max(4));
+    }
+}
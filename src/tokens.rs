@@ -83,6 +83,15 @@ pub enum TokenType {
     Semi,
     // .
     Attr,
+    // += -= *= /= //= %= ^= ..= of the extended dialect
+    AddAssign,
+    SubAssign,
+    MulAssign,
+    DivAssign,
+    IDivAssign,
+    ModAssign,
+    PowAssign,
+    ConcatAssign,
     // single line coment
     SComment,
     // multi-line comment
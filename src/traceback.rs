@@ -0,0 +1,106 @@
+use crate::value::Value;
+use std::fmt;
+use std::rc::Rc;
+
+// the lua functions on the stack when an error was raised, innermost first. native functions
+// have no frames, so they aren't listed
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    // named like in listings of the disassembler, e.g. `main.0`
+    pub function: String,
+    // none without line info
+    pub line: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Traceback(pub Vec<TraceFrame>);
+
+impl Traceback {
+    pub fn frames(&self) -> &[TraceFrame] {
+        &self.0
+    }
+
+    // the position of the error, `name:line:` like the prefix of messages of `error`
+    pub fn position(&self) -> Option<String> {
+        self.0.first().map(|frame| frame.to_string())
+    }
+}
+
+// what `debug.getinfo` tells about a function, and about its call if it's on the stack
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+    pub func: Value,
+    // named like in listings of the disassembler, or the name of a native function
+    pub function: String,
+    pub is_native: bool,
+    // of the running instruction, for calls on the stack with line info
+    pub current_line: Option<u32>,
+    // first and last lines of the code, none without line info
+    pub line_defined: Option<u32>,
+    pub last_line_defined: Option<u32>,
+    pub params: u32,
+    pub is_vararg: bool,
+    pub up_values: usize,
+    // the call replaced the frame of its caller, which isn't on the stack anymore
+    pub is_tail_call: bool,
+}
+
+impl FunctionInfo {
+    // none for values which aren't functions
+    pub fn of(func: &Value) -> Option<FunctionInfo> {
+        let info = match func {
+            Value::Function(closure) => {
+                let proto = &closure.proto.proto;
+                FunctionInfo {
+                    func: Value::Function(Rc::clone(closure)),
+                    function: closure.proto.name.clone(),
+                    is_native: false,
+                    current_line: None,
+                    line_defined: proto.line_info.iter().min(),
+                    last_line_defined: proto.line_info.iter().max(),
+                    params: proto.param_count,
+                    is_vararg: proto.is_vararg,
+                    up_values: closure.up_values.len(),
+                    is_tail_call: false,
+                }
+            }
+            Value::Native(native) => FunctionInfo {
+                func: func.clone(),
+                function: native.name().to_string(),
+                is_native: true,
+                current_line: None,
+                line_defined: None,
+                last_line_defined: None,
+                params: 0,
+                is_vararg: true,
+                up_values: 0,
+                is_tail_call: false,
+            },
+            _ => return None,
+        };
+        Some(info)
+    }
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}:", self.function, line),
+            None => write!(f, "{}:", self.function),
+        }
+    }
+}
+
+// like the tracebacks of lua, e.g.
+// stack traceback:
+//     main.0:3: in function 'main.0'
+impl fmt::Display for Traceback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "stack traceback:")?;
+        for frame in self.0.iter() {
+            write!(f, "\n\t{} in function '{}'", frame, frame.function)?;
+        }
+        Ok(())
+    }
+}
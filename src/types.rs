@@ -1,5 +1,14 @@
 pub type IntType = i64;
 pub type FloatType = f64;
+// language extensions on top of lua 5.3, used by some embedders
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Dialect {
+    #[default]
+    Standard,
+    // `continue` in loops and `!=` as an alias of `~=`
+    Extended,
+}
+
 pub enum Number {
     Int(IntType),
     Float(FloatType),
@@ -0,0 +1,441 @@
+use crate::lexer::Lexer;
+use crate::table::Table;
+use crate::types::{FloatType, IntType, Number};
+use crate::vm::{Closure, NativeFunction, Thread};
+use std::any::{Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// values of the vm, tables, functions, userdata and threads are references
+
+pub type TableRef = Rc<RefCell<Table>>;
+pub type ThreadRef = Rc<RefCell<Thread>>;
+
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Int(IntType),
+    Float(FloatType),
+    Str(LuaStr),
+    Table(TableRef),
+    Function(Rc<Closure>),
+    Native(Rc<NativeFunction>),
+    UserData(Rc<UserData>),
+    Thread(ThreadRef),
+}
+
+// data of the host, scripts can only pass it around and use its metatable
+pub struct UserData {
+    data: RefCell<Box<dyn Any>>,
+    metatable: RefCell<Option<TableRef>>,
+}
+
+impl UserData {
+    pub fn new<T: Any>(data: T) -> Self {
+        UserData {
+            data: RefCell::new(Box::new(data)),
+            metatable: RefCell::new(None),
+        }
+    }
+
+    pub fn metatable(&self) -> Option<TableRef> {
+        self.metatable.borrow().clone()
+    }
+
+    pub fn set_metatable(&self, metatable: Option<TableRef>) {
+        *self.metatable.borrow_mut() = metatable;
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.data.borrow().is::<T>()
+    }
+
+    // type of the data, which selects the metatable of `Vm::create_user_data`
+    pub fn data_type(&self) -> TypeId {
+        (**self.data.borrow()).type_id()
+    }
+
+    // approximate number of bytes, the data may own more
+    pub fn memory(&self) -> usize {
+        std::mem::size_of::<UserData>() + std::mem::size_of_val(&**self.data.borrow())
+    }
+
+    // none if the data isn't a `T`, panics if it's mutably borrowed
+    pub fn borrow<T: Any>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.data.borrow(), |data| data.downcast_ref()).ok()
+    }
+
+    pub fn borrow_mut<T: Any>(&self) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.data.borrow_mut(), |data| data.downcast_mut()).ok()
+    }
+}
+
+// strings of lua are immutable bytes, not necessarily utf-8. short ones are interned, so equal
+// short strings share their bytes and compare and hash by address, e.g. names of fields
+#[derive(Clone, PartialOrd, Ord)]
+pub struct LuaStr(Rc<[u8]>);
+
+// the longest string that's interned, as in lua
+pub const MAX_SHORT_LEN: usize = 40;
+
+// short strings of the thread, values are `Rc`s, so they can't be shared between threads.
+// strings only referred to by the table are dropped when it has doubled since the last sweep
+struct StringTable {
+    strings: HashSet<Rc<[u8]>>,
+    threshold: usize,
+}
+
+const MIN_STRINGS: usize = 64;
+
+thread_local! {
+    static STRINGS: RefCell<StringTable> = RefCell::new(StringTable {
+        strings: HashSet::new(),
+        threshold: MIN_STRINGS,
+    });
+}
+
+impl StringTable {
+    fn intern(&mut self, bytes: &[u8]) -> Rc<[u8]> {
+        if let Some(s) = self.strings.get(bytes) {
+            return s.clone();
+        }
+        if self.strings.len() >= self.threshold {
+            self.strings.retain(|s| Rc::strong_count(s) > 1);
+            self.threshold = MIN_STRINGS.max(self.strings.len() * 2);
+        }
+        let s: Rc<[u8]> = bytes.into();
+        self.strings.insert(s.clone());
+        s
+    }
+}
+
+impl LuaStr {
+    pub fn new(bytes: &[u8]) -> Self {
+        if bytes.len() <= MAX_SHORT_LEN {
+            LuaStr(STRINGS.with(|strings| strings.borrow_mut().intern(bytes)))
+        } else {
+            LuaStr(bytes.into())
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_short(&self) -> bool {
+        self.0.len() <= MAX_SHORT_LEN
+    }
+
+    // whether the strings share their bytes, which is always the case for equal short strings
+    pub fn ptr_eq(a: &LuaStr, b: &LuaStr) -> bool {
+        Rc::ptr_eq(&a.0, &b.0)
+    }
+
+    // invalid utf-8 is replaced
+    pub fn to_str_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.0).into_owned()
+    }
+}
+
+impl From<&str> for LuaStr {
+    fn from(s: &str) -> Self {
+        LuaStr::new(s.as_bytes())
+    }
+}
+
+impl From<Vec<u8>> for LuaStr {
+    fn from(bytes: Vec<u8>) -> Self {
+        if bytes.len() <= MAX_SHORT_LEN {
+            LuaStr::new(&bytes)
+        } else {
+            LuaStr(bytes.into())
+        }
+    }
+}
+
+impl PartialEq for LuaStr {
+    fn eq(&self, other: &LuaStr) -> bool {
+        LuaStr::ptr_eq(self, other) || (!self.is_short() && self.0 == other.0)
+    }
+}
+
+impl Eq for LuaStr {}
+
+impl Hash for LuaStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if self.is_short() {
+            (Rc::as_ptr(&self.0) as *const u8 as usize).hash(state);
+        } else {
+            self.0.hash(state);
+        }
+    }
+}
+
+impl fmt::Debug for LuaStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.to_str_lossy())
+    }
+}
+
+impl Value {
+    pub fn str(s: &str) -> Value {
+        Value::Str(LuaStr::from(s))
+    }
+
+    pub fn new_table() -> Value {
+        Value::Table(Rc::new(RefCell::new(Table::new())))
+    }
+
+    pub fn user_data<T: Any>(data: T) -> Value {
+        Value::UserData(Rc::new(UserData::new(data)))
+    }
+
+    // only nil and false are false
+    pub fn is_falsy(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !self.is_falsy()
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    // lua functions and native ones
+    pub fn is_function(&self) -> bool {
+        matches!(self, Value::Function(_) | Value::Native(_))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Int(_) | Value::Float(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::UserData(_) => "userdata",
+            Value::Thread(_) => "thread",
+        }
+    }
+}
+
+// raw equality of lua, numbers are equal by their mathematical values,
+// tables and functions by reference
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Int(i), Value::Float(f)) | (Value::Float(f), Value::Int(i)) => {
+                float_to_int(*f) == Some(*i)
+            }
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::UserData(a), Value::UserData(b)) => Rc::ptr_eq(a, b),
+            (Value::Thread(a), Value::Thread(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+// NaN isn't equal to itself, tables reject it as a key
+impl Eq for Value {}
+
+// floats with integral values hash like the integers they're equal to
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Nil => 0.hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::Int(i) => i.hash(state),
+            Value::Float(f) => match float_to_int(*f) {
+                Some(i) => i.hash(state),
+                None => f.to_bits().hash(state),
+            },
+            Value::Str(s) => s.hash(state),
+            Value::Table(t) => (Rc::as_ptr(t) as usize).hash(state),
+            Value::Function(f) => (Rc::as_ptr(f) as usize).hash(state),
+            Value::Native(f) => (Rc::as_ptr(f) as usize).hash(state),
+            Value::UserData(u) => (Rc::as_ptr(u) as usize).hash(state),
+            Value::Thread(t) => (Rc::as_ptr(t) as usize).hash(state),
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "Nil"),
+            Value::Bool(b) => write!(f, "Bool({})", b),
+            Value::Int(i) => write!(f, "Int({})", i),
+            Value::Float(n) => write!(f, "Float({:?})", n),
+            Value::Str(s) => write!(f, "Str({:?})", s),
+            Value::Table(t) => write!(f, "Table({:p})", Rc::as_ptr(t)),
+            Value::Function(c) => write!(f, "Function({:p})", Rc::as_ptr(c)),
+            Value::Native(n) => write!(f, "Native({})", n.name()),
+            Value::UserData(u) => write!(f, "UserData({:p})", Rc::as_ptr(u)),
+            Value::Thread(t) => write!(f, "Thread({:p})", Rc::as_ptr(t)),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<IntType> for Value {
+    fn from(i: IntType) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<FloatType> for Value {
+    fn from(f: FloatType) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::str(s)
+    }
+}
+
+impl From<TableRef> for Value {
+    fn from(t: TableRef) -> Self {
+        Value::Table(t)
+    }
+}
+
+// the integer a float is equal to, if any
+pub fn float_to_int(f: FloatType) -> Option<IntType> {
+    // 2^63 is out of range, while -2^63 is exact
+    if f.floor() == f && f >= -(2.0 as FloatType).powi(63) && f < (2.0 as FloatType).powi(63) {
+        Some(f as IntType)
+    } else {
+        None
+    }
+}
+
+// the number of a string holding a numeral, as lua coerces strings in arithmetic. the formats
+// of literals are accepted, with a sign and spaces around, e.g. " -0x10 " is -16
+pub fn str_to_number(s: &[u8]) -> Option<Value> {
+    let s = std::str::from_utf8(s).ok()?;
+    match Lexer::str_to_num(s) {
+        Number::Int(i) => Some(Value::Int(i)),
+        Number::Float(f) => Some(Value::Float(f)),
+        Number::None => None,
+    }
+}
+
+// the string of a float like `tostring` of lua, `%.14g` of c with `.0` added to integral
+// values, e.g. "1e+15", "-0.0", "inf" and "-nan"
+pub fn float_to_str(f: FloatType) -> String {
+    let s = format_g(f);
+    if s.chars().all(|c| c == '-' || c.is_ascii_digit()) {
+        s + ".0"
+    } else {
+        s
+    }
+}
+
+// `%.14g`, the number format of lua
+fn format_g(f: FloatType) -> String {
+    if f.is_nan() {
+        return if f.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if f.is_infinite() {
+        return if f < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    let sci = format!("{:.13e}", f);
+    let (mantissa, exp) = sci.split_at(sci.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    if (-4..14).contains(&exp) {
+        let fixed = format!("{:.*}", (13 - exp) as usize, f);
+        trim_zeros(&fixed).to_string()
+    } else {
+        format!(
+            "{}e{}{:02}",
+            trim_zeros(mantissa),
+            if exp < 0 { '-' } else { '+' },
+            exp.abs()
+        )
+    }
+}
+
+fn trim_zeros(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+// arithmetic of lua shared by the vm and constant folding, integers wrap around on overflow
+
+// floor division, `b` isn't 0
+pub fn int_idiv(a: IntType, b: IntType) -> IntType {
+    let q = a.wrapping_div(b);
+    // round towards minus infinity
+    if a.wrapping_rem(b) != 0 && (a < 0) != (b < 0) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+// remainder with the sign of `b`, which isn't 0
+pub fn int_mod(a: IntType, b: IntType) -> IntType {
+    let r = a.wrapping_rem(b);
+    if r != 0 && (r ^ b) < 0 {
+        r + b
+    } else {
+        r
+    }
+}
+
+pub fn float_idiv(a: FloatType, b: FloatType) -> FloatType {
+    (a / b).floor()
+}
+
+// remainder with the sign of `b`
+pub fn float_mod(a: FloatType, b: FloatType) -> FloatType {
+    let r = a % b;
+    if r != 0.0 && (r < 0.0) != (b < 0.0) {
+        r + b
+    } else {
+        r
+    }
+}
+
+// logical shift, shifting by 64 or more bits results in 0 and negative counts shift right
+pub fn shift_left(a: IntType, n: IntType) -> IntType {
+    if n <= -64 || n >= 64 {
+        0
+    } else if n >= 0 {
+        ((a as u64) << n) as IntType
+    } else {
+        ((a as u64) >> -n) as IntType
+    }
+}
@@ -0,0 +1,270 @@
+use crate::opcodes::*;
+use crate::proto::Proto;
+
+// static checks of a proto from an untrusted binary chunk, so that running it can't read or write
+// out of registers, constants, upvalues or code.
+//
+// it checks the operands of every instruction like `luaG_checkcode` of lua 5.1,
+// not that the code makes sense, e.g. registers may be read before they're written.
+// functions are named like in listings of the disassembler, e.g. `main.0`.
+
+#[derive(Debug, PartialEq)]
+pub struct VerifyError(pub String);
+
+type VerifyResult = Result<(), VerifyError>;
+
+impl Proto {
+    // verify the function and all nested functions
+    pub fn verify(&self) -> VerifyResult {
+        Verifier {
+            proto: self,
+            name: "main".to_string(),
+        }
+        .function()
+    }
+}
+
+struct Verifier<'a> {
+    proto: &'a Proto,
+    name: String,
+}
+
+impl<'a> Verifier<'a> {
+    fn error(&self, msg: &str) -> VerifyResult {
+        Err(VerifyError(format!("bad code in {}: {}", self.name, msg)))
+    }
+
+    fn error_at(&self, pc: usize, msg: &str) -> VerifyResult {
+        self.error(&format!("{} at pc {}", msg, pc))
+    }
+
+    fn function(&self) -> VerifyResult {
+        let proto = self.proto;
+        if proto.stack_size > MAX_REGS {
+            return self.error(&format!("stack size {} is too large", proto.stack_size));
+        }
+        if proto.param_count > proto.stack_size {
+            return self.error("params out of stack");
+        }
+        match proto.code.last() {
+            Some(last) if last.get_op() == OpCode::Return => (),
+            _ => return self.error("missing return at the end"),
+        }
+        if !proto.line_info.is_empty() && proto.line_info.len() != proto.code.len() {
+            return self.error("line info size mismatch");
+        }
+        for pc in 0..proto.code.len() {
+            self.instruction(pc)?;
+        }
+        for (i, child) in proto.protos.iter().enumerate() {
+            let verifier = Verifier {
+                proto: child,
+                name: format!("{}.{}", self.name, i),
+            };
+            // upvalues are captured from registers or upvalues of this function
+            for up_val in child.up_vars.iter() {
+                let size = if up_val.in_stack {
+                    proto.stack_size
+                } else {
+                    proto.up_vars.len() as u32
+                };
+                if up_val.index >= size {
+                    return verifier.error(&format!("upvalue index {} out of range", up_val.index));
+                }
+            }
+            verifier.function()?;
+        }
+        Ok(())
+    }
+
+    fn instruction(&self, pc: usize) -> VerifyResult {
+        let code = &self.proto.code;
+        let instruction = &code[pc];
+        let op = instruction.get_op();
+        let a = instruction.get_arg_A();
+        let b = instruction.get_arg_B();
+        let c = instruction.get_arg_C();
+        let next = code.get(pc + 1).map(|next| next.get_op());
+
+        // A is a register except for a few instructions
+        match op {
+            OpCode::SetTabUp => self.up_value(pc, a)?,
+            // close upvalues >= R(A - 1) if A is not 0
+            OpCode::Jmp if a > 0 => self.register(pc, a - 1)?,
+            // return with no values
+            OpCode::Return if b == 1 => (),
+            OpCode::Jmp | OpCode::Eq | OpCode::Lt | OpCode::Le | OpCode::ExtraArg => (),
+            _ => self.register(pc, a)?,
+        }
+
+        match instruction.mode() {
+            OpMode::IA | OpMode::IAB | OpMode::IABC | OpMode::IAC => {
+                let (b_mode, c_mode) = op.arg_modes();
+                self.arg(pc, b_mode, b)?;
+                self.arg(pc, c_mode, c)?;
+            }
+            OpMode::IABx => {
+                let bx = instruction.get_arg_Bx();
+                match op {
+                    OpCode::LoadK => self.constant(pc, bx)?,
+                    OpCode::Closure if bx as usize >= self.proto.protos.len() => {
+                        return self.error_at(pc, &format!("function {} out of range", bx))
+                    }
+                    _ => (),
+                }
+            }
+            OpMode::IAsBx => self.jump(pc, instruction.get_arg_sBx())?,
+            OpMode::IAx => (),
+        }
+
+        match op {
+            OpCode::LoadKx => {
+                if next != Some(OpCode::ExtraArg) {
+                    return self.error_at(pc, "missing extra arg");
+                }
+                self.constant(pc, code[pc + 1].get_arg_Ax())?;
+            }
+            // skips the next instruction
+            OpCode::LoadBool if c != 0 => self.jump(pc, 1)?,
+            OpCode::LoadNil => self.register(pc, a + b)?,
+            OpCode::GetUpVal | OpCode::SetUpVal | OpCode::GetTabUp => self.up_value(pc, b)?,
+            OpCode::Self_ => self.register(pc, a + 1)?,
+            OpCode::Concat if b >= c => return self.error_at(pc, "empty concat"),
+            OpCode::NewTable if b > MAX_TABLE_HINT || c > MAX_TABLE_HINT => {
+                return self.error_at(pc, "table size too large")
+            }
+            OpCode::Eq | OpCode::Lt | OpCode::Le | OpCode::Test | OpCode::TestSet
+                if next != Some(OpCode::Jmp) =>
+            {
+                return self.error_at(pc, "missing jump after test")
+            }
+            OpCode::Call | OpCode::TailCall | OpCode::Return | OpCode::SetList if b == 0 => {
+                self.open_results(pc, op, a)?;
+                if op == OpCode::Call && c > 1 {
+                    self.register(pc, a + c - 2)?;
+                }
+                if op == OpCode::SetList && c == 0 && next != Some(OpCode::ExtraArg) {
+                    return self.error_at(pc, "missing extra arg");
+                }
+            }
+            OpCode::Call | OpCode::TailCall => {
+                if b > 0 {
+                    self.register(pc, a + b - 1)?;
+                }
+                if op == OpCode::Call && c > 1 {
+                    self.register(pc, a + c - 2)?;
+                }
+            }
+            OpCode::Return | OpCode::Vararg if b > 1 => self.register(pc, a + b - 2)?,
+            OpCode::ForLoop | OpCode::ForPrep => self.register(pc, a + 3)?,
+            OpCode::TForCall => {
+                self.register(pc, a + 2 + c.max(1))?;
+                if next != Some(OpCode::TForLoop) {
+                    return self.error_at(pc, "missing loop after generic for call");
+                }
+            }
+            OpCode::TForLoop => self.register(pc, a + 1)?,
+            OpCode::SetList => {
+                self.register(pc, a + b)?;
+                if c == 0 && next != Some(OpCode::ExtraArg) {
+                    return self.error_at(pc, "missing extra arg");
+                }
+            }
+            OpCode::ExtraArg if !self.takes_extra_arg(pc) => {
+                return self.error_at(pc, "unexpected extra arg")
+            }
+            _ => (),
+        }
+        if op == OpCode::Vararg && !self.proto.is_vararg {
+            return self.error_at(pc, "vararg in a function without varargs");
+        }
+        Ok(())
+    }
+
+    fn arg(&self, pc: usize, mode: OpArgMode, arg: u32) -> VerifyResult {
+        match mode {
+            OpArgMode::R => self.register(pc, arg),
+            OpArgMode::K if is_const(arg) => self.constant(pc, arg & !MASK_K),
+            OpArgMode::K => self.register(pc, arg),
+            OpArgMode::N | OpArgMode::U => Ok(()),
+        }
+    }
+
+    fn register(&self, pc: usize, reg: u32) -> VerifyResult {
+        if reg >= self.proto.stack_size {
+            return self.error_at(pc, &format!("register {} out of stack", reg));
+        }
+        Ok(())
+    }
+
+    fn constant(&self, pc: usize, index: u32) -> VerifyResult {
+        if index as usize >= self.proto.consts.len() {
+            return self.error_at(pc, &format!("constant {} out of range", index));
+        }
+        Ok(())
+    }
+
+    fn up_value(&self, pc: usize, index: u32) -> VerifyResult {
+        if index as usize >= self.proto.up_vars.len() {
+            return self.error_at(pc, &format!("upvalue {} out of range", index));
+        }
+        Ok(())
+    }
+
+    // targets must be instructions, not the extra arg of the previous one
+    fn jump(&self, pc: usize, offset: i32) -> VerifyResult {
+        let target = pc as i64 + 1 + offset as i64;
+        if target < 0 || target as usize >= self.proto.code.len() {
+            return self.error_at(pc, &format!("jump to {} out of code", target));
+        }
+        if self.takes_extra_arg(target as usize) {
+            return self.error_at(pc, &format!("jump into extra arg at {}", target));
+        }
+        if self.takes_open_results(target as usize) {
+            return self.error_at(pc, &format!("jump to open results at {}", target));
+        }
+        Ok(())
+    }
+
+    // B = 0 of CALL, TAILCALL, RETURN and SETLIST takes the values up to the top, which must be
+    // set by the previous instruction, a CALL with C = 0, a TAILCALL or a VARARG with B = 0, to
+    // values from the first register taken, after the function or table
+    fn open_results(&self, pc: usize, op: OpCode, a: u32) -> VerifyResult {
+        let first = if op == OpCode::Return { a } else { a + 1 };
+        let set = match pc.checked_sub(1).map(|prev| &self.proto.code[prev]) {
+            Some(prev) => match prev.get_op() {
+                OpCode::Call => prev.get_arg_C() == 0 && prev.get_arg_A() >= first,
+                OpCode::TailCall => prev.get_arg_A() >= first,
+                OpCode::Vararg => prev.get_arg_B() == 0 && prev.get_arg_A() >= first,
+                _ => false,
+            },
+            None => false,
+        };
+        if !set {
+            return self.error_at(pc, "open results not set by the previous instruction");
+        }
+        Ok(())
+    }
+
+    fn takes_open_results(&self, pc: usize) -> bool {
+        let instruction = &self.proto.code[pc];
+        match instruction.get_op() {
+            OpCode::Call | OpCode::TailCall | OpCode::Return | OpCode::SetList => {
+                instruction.get_arg_B() == 0
+            }
+            _ => false,
+        }
+    }
+
+    // the instruction at `pc` is the extra arg of the previous one
+    fn takes_extra_arg(&self, pc: usize) -> bool {
+        match pc.checked_sub(1).map(|prev| &self.proto.code[prev]) {
+            Some(prev) => match prev.get_op() {
+                OpCode::LoadKx => true,
+                OpCode::SetList => prev.get_arg_C() == 0,
+                _ => false,
+            },
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,2479 @@
+use crate::consts::Const;
+use crate::gc::{GcMode, Heap};
+use crate::intercept::CallHooks;
+use crate::metamethod::{self, MetaMethod};
+use crate::opcodes::*;
+use crate::proto::Proto;
+use crate::table::Table;
+use crate::traceback::{FunctionInfo, TraceFrame, Traceback};
+use crate::types::{FloatType, IntType};
+use crate::value::{
+    float_idiv, float_mod, float_to_int, float_to_str, int_idiv, int_mod, shift_left,
+    str_to_number, LuaStr, TableRef, ThreadRef, UserData, Value,
+};
+use crate::verify::VerifyError;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+
+// register based interpreter of compiled functions.
+//
+// registers of all active functions live in one stack, each call frame owns the registers
+// from its base, and calls between lua functions push frames instead of recursing in rust.
+// upvalues refer to the registers of variables while their functions run, so closures share
+// them, and keep the values once the scopes of the variables end.
+
+// an error of a script, with the value it was raised with, which protected calls get back,
+// and the traceback from where it was raised. boxed, results of the interpreter are on the
+// stack of every nested call
+#[derive(Debug, Clone)]
+pub struct RuntimeError(Box<ErrorData>);
+
+#[derive(Debug, Clone)]
+struct ErrorData {
+    message: String,
+    // none for errors raised with their message, e.g. errors of the vm
+    value: Option<Value>,
+    // whether the message needs no position, like values raised by `error`, which adds it
+    positioned: bool,
+    // set when the error leaves the frame raising it
+    traceback: Option<Traceback>,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        RuntimeError(Box::new(ErrorData {
+            message: message.into(),
+            value: None,
+            positioned: false,
+            traceback: None,
+        }))
+    }
+
+    // an error raising any value as it is, with a message for values which aren't strings
+    pub fn from_value(value: Value) -> Self {
+        let (message, value) = match &value {
+            Value::Str(s) => (s.to_str_lossy(), None),
+            Value::Int(_) | Value::Float(_) => {
+                (to_lua_str(&value).unwrap().to_str_lossy(), Some(value))
+            }
+            _ => (
+                format!("(error object is a {} value)", value.type_name()),
+                Some(value),
+            ),
+        };
+        RuntimeError(Box::new(ErrorData {
+            message,
+            value,
+            positioned: true,
+            traceback: None,
+        }))
+    }
+
+    // running out of memory, which has no position like in lua
+    pub(crate) fn out_of_memory() -> Self {
+        let mut e = RuntimeError::new("not enough memory");
+        e.0.positioned = true;
+        e
+    }
+
+    pub fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    // the value the error was raised with, its message for errors of the vm
+    pub fn value(&self) -> Value {
+        match &self.0.value {
+            Some(value) => value.clone(),
+            None => Value::str(&self.0.message),
+        }
+    }
+
+    // the lua functions on the stack where the error was raised, once it left a call of the vm
+    pub fn traceback(&self) -> Option<&Traceback> {
+        self.0.traceback.as_ref()
+    }
+
+    // `name:line:` of the lua function raising the error
+    pub fn position(&self) -> Option<String> {
+        self.traceback().and_then(Traceback::position)
+    }
+}
+
+// errors are equal with the same message and value, wherever they were raised
+impl PartialEq for RuntimeError {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.message == other.0.message && self.0.value == other.0.value
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+type RuntimeResult<T> = Result<T, RuntimeError>;
+
+// called every n instructions, see `Vm::set_count_hook`
+pub type CountHook = dyn Fn(&mut Vm) -> Result<(), RuntimeError>;
+
+// events of debug hooks, see `Vm::set_hook`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookEvent {
+    // a function was called, before its first instruction
+    Call,
+    // a lua function was called in place of the returning one
+    TailCall,
+    // a function is about to return
+    Return,
+    // a new line is about to run, or a jump went back
+    Line(u32),
+    // the period of the hook has passed
+    Count,
+}
+
+// events a debug hook is called for, `count` instructions for count events, 0 for none
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HookMask {
+    pub call: bool,
+    pub ret: bool,
+    pub line: bool,
+    pub count: u32,
+}
+
+pub type DebugHook = dyn Fn(&mut Vm, HookEvent) -> Result<(), RuntimeError>;
+
+struct DebugHookState {
+    mask: HookMask,
+    // instructions left until the next count event
+    left: u32,
+    hook: Rc<DebugHook>,
+}
+
+// stops the script running in its vm before the next instruction, from any thread, e.g. on
+// a timeout. see `Vm::interrupt_handle`
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    pub fn trigger(&self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+
+    // not yet taken by the vm
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed)
+    }
+
+    // polled often, so the cache line is only written when the flag is set
+    fn take(&self) -> bool {
+        self.is_triggered() && self.0.swap(false, atomic::Ordering::Relaxed)
+    }
+}
+
+// proto prepared for running, constants are converted to values once
+pub struct FuncProto {
+    // without nested functions, which are in `children`
+    pub proto: Proto,
+    pub consts: Vec<Value>,
+    pub children: Vec<Rc<FuncProto>>,
+    // named like in listings of the disassembler, e.g. `main.0`
+    pub name: String,
+}
+
+impl FuncProto {
+    // the proto is verified first, so running it can't go out of its registers, constants or code
+    pub fn new(proto: Proto) -> Result<Rc<FuncProto>, VerifyError> {
+        proto.verify()?;
+        Ok(FuncProto::with_name(proto, "main".to_string()))
+    }
+
+    fn with_name(mut proto: Proto, name: String) -> Rc<FuncProto> {
+        let children = std::mem::take(&mut proto.protos)
+            .into_iter()
+            .enumerate()
+            .map(|(i, child)| FuncProto::with_name(child, format!("{}.{}", name, i)))
+            .collect();
+        let consts = proto
+            .consts
+            .iter()
+            .map(|k| match k {
+                Const::Nil => Value::Nil,
+                Const::Bool(b) => Value::Bool(*b),
+                Const::Int(i) => Value::Int(*i),
+                Const::Float(f) => Value::Float(*f),
+                Const::Str(s) => Value::str(s),
+            })
+            .collect();
+        Rc::new(FuncProto {
+            proto,
+            consts,
+            children,
+            name,
+        })
+    }
+}
+
+// a variable captured by closures, open while it's a register of a running function, and
+// closed with its own value when the scope of the variable ends
+#[derive(Clone)]
+pub enum UpValue {
+    // slot of the variable in the stack
+    Open(usize),
+    Closed(Value),
+}
+
+pub type UpValueRef = Rc<RefCell<UpValue>>;
+
+pub struct Closure {
+    pub(crate) proto: Rc<FuncProto>,
+    pub(crate) up_values: Vec<UpValueRef>,
+}
+
+pub type NativeFn = dyn Fn(&mut Vm, Vec<Value>) -> Result<Vec<Value>, RuntimeError>;
+
+// function implemented in rust, e.g. of the libraries
+pub struct NativeFunction {
+    name: String,
+    func: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, func: F) -> Self
+    where
+        F: Fn(&mut Vm, Vec<Value>) -> Result<Vec<Value>, RuntimeError> + 'static,
+    {
+        NativeFunction {
+            name: name.to_string(),
+            func: Box::new(func),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreadStatus {
+    Suspended,
+    Running,
+    // resumed another thread
+    Normal,
+    Dead,
+}
+
+// a coroutine running a function, with its own stack and call frames.
+// they are swapped with those of the vm while the coroutine runs
+pub struct Thread {
+    func: Value,
+    status: ThreadStatus,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    top: usize,
+    // slot and expected results of the call to yield, which get the args of the next resume
+    resume_at: Option<(usize, Option<usize>)>,
+    // upvalues open in the stack, by slot
+    open_up_values: Vec<(usize, UpValueRef)>,
+}
+
+impl Thread {
+    pub fn new(func: Value) -> Self {
+        Thread {
+            func,
+            status: ThreadStatus::Suspended,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            top: 0,
+            resume_at: None,
+            open_up_values: Vec::new(),
+        }
+    }
+
+    pub fn func(&self) -> &Value {
+        &self.func
+    }
+
+    pub fn status(&self) -> ThreadStatus {
+        self.status
+    }
+
+    // values the thread refers to, its function, registers and the closures of its frames
+    pub(crate) fn references(&self) -> Vec<Value> {
+        let frames = self.frames.iter().flat_map(|frame| {
+            std::iter::once(Value::Function(frame.closure.clone())).chain(frame.varargs.clone())
+        });
+        std::iter::once(self.func.clone())
+            .chain(self.stack.iter().cloned())
+            .chain(frames)
+            .collect()
+    }
+
+    // drop all references of a thread which can't be resumed anymore
+    pub(crate) fn clear(&mut self) {
+        self.func = Value::Nil;
+        self.status = ThreadStatus::Dead;
+        self.stack = Vec::new();
+        self.frames = Vec::new();
+        self.top = 0;
+        self.resume_at = None;
+        self.open_up_values = Vec::new();
+    }
+
+    pub(crate) fn memory(&self) -> usize {
+        std::mem::size_of::<Thread>()
+            + self.stack.capacity() * std::mem::size_of::<Value>()
+            + self.frames.capacity() * std::mem::size_of::<Frame>()
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    pc: usize,
+    // register 0 of the function, the function itself is in the slot below
+    base: usize,
+    varargs: Vec<Value>,
+    // number of results the caller expects, none for all of them
+    results: Option<usize>,
+    // the closure is wrapped with hooks
+    hooked: bool,
+    // slots of to-be-closed variables, in the order they were marked
+    tbc: Vec<usize>,
+    // pc of the last instruction seen by line hooks
+    traced: Option<usize>,
+    // the function replaced its caller, whose frame is gone
+    tail_call: bool,
+}
+
+// a protected call, the innermost one handles errors
+struct Protection {
+    // message handler of xpcall
+    handler: Option<Value>,
+    // error value returned by the handler
+    error: Option<Value>,
+}
+
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    globals: TableRef,
+    // values the host keeps alive through collections
+    registry: TableRef,
+    heap: Heap,
+    // end of the values of the last call or vararg with multiple results
+    top: usize,
+    // shared by all strings
+    string_meta: Option<TableRef>,
+    // of userdata created by `create_user_data`, by the type of their data
+    type_metas: HashMap<TypeId, TableRef>,
+    // instructions left to run, if limited
+    budget: Option<u64>,
+    // the hook with its period and the instructions left until it's called
+    count_hook: Option<(u32, u32, Rc<CountHook>)>,
+    debug_hook: Option<DebugHookState>,
+    // a debug hook is running, which isn't hooked itself
+    in_hook: bool,
+    // there's a budget, a count hook or a line or count debug hook, checked before each
+    // instruction
+    metered: bool,
+    // the instruction a count hook yielded at was counted already
+    resumed_hook: bool,
+    // frames of lua functions a thread may have, and nesting of calls from rust into the vm
+    call_depth_limit: usize,
+    native_depth_limit: usize,
+    native_depth: usize,
+    // bytes scripts may use, and the estimate of those in use
+    memory_limit: Option<usize>,
+    allocated: usize,
+    interrupt: Interrupt,
+    // whether protected calls can catch interruptions
+    interrupt_catchable: bool,
+    // an uncatchable interruption is unwinding
+    interrupted: bool,
+    meta_names: Vec<Value>,
+    // hooks of wrapped closures, which are kept alive so their addresses aren't reused
+    hooks: HashMap<*const Closure, (Rc<Closure>, CallHooks)>,
+    // running coroutine, none for the main thread
+    thread: Option<ThreadRef>,
+    resume_at: Option<(usize, Option<usize>)>,
+    // upvalues open in the stack, by slot
+    open_up_values: Vec<(usize, UpValueRef)>,
+    // values passed to yield, until the coroutine is suspended
+    yielding: Option<Vec<Value>>,
+    // nesting of `call`, coroutines can only yield at the level they were resumed at
+    calls: usize,
+    yield_level: usize,
+    protections: Vec<Protection>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+// like closing a lua state, all finalizers are called
+impl Drop for Vm {
+    fn drop(&mut self) {
+        for value in self.heap.take_finalizable() {
+            self.finalize(value);
+        }
+    }
+}
+
+// fields of `Instruction` are u32
+fn reg(i: u32) -> usize {
+    i as usize
+}
+
+// handlers of `__index` and `__newindex` which are tables again
+const MAX_META_CHAIN: usize = 2000;
+// default limits of calls. lua has room for about as many frames in its stack. nested calls
+// from rust take more of the stack than those of c, so there are half as many as in lua, which
+// fit in the 2 MiB of spawned threads even in debug builds
+const CALL_DEPTH_LIMIT: usize = 200_000;
+const NATIVE_DEPTH_LIMIT: usize = 100;
+
+impl Vm {
+    pub fn new() -> Self {
+        let globals = Rc::new(RefCell::new(Table::new()));
+        globals
+            .borrow_mut()
+            .set_str("_G", Value::Table(globals.clone()));
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals,
+            registry: Rc::new(RefCell::new(Table::new())),
+            heap: Heap::default(),
+            top: 0,
+            string_meta: None,
+            meta_names: metamethod::names(),
+            hooks: HashMap::new(),
+            thread: None,
+            resume_at: None,
+            open_up_values: Vec::new(),
+            yielding: None,
+            calls: 0,
+            yield_level: 0,
+            protections: Vec::new(),
+            type_metas: HashMap::new(),
+            budget: None,
+            count_hook: None,
+            debug_hook: None,
+            in_hook: false,
+            metered: false,
+            resumed_hook: false,
+            call_depth_limit: CALL_DEPTH_LIMIT,
+            native_depth_limit: NATIVE_DEPTH_LIMIT,
+            native_depth: 0,
+            memory_limit: None,
+            allocated: 0,
+            interrupt: Interrupt::default(),
+            interrupt_catchable: false,
+            interrupted: false,
+        }
+    }
+
+    pub fn globals(&self) -> &TableRef {
+        &self.globals
+    }
+
+    pub fn registry(&self) -> &TableRef {
+        &self.registry
+    }
+
+    pub fn get_global(&self, name: &str) -> Value {
+        self.globals.borrow().get_str(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    // metatable of tables, and the shared one of strings
+    pub fn get_metatable(&self, value: &Value) -> Option<TableRef> {
+        match value {
+            Value::Table(t) => t.borrow().metatable().cloned(),
+            Value::Str(_) => self.string_meta.clone(),
+            Value::UserData(u) => u.metatable(),
+            _ => None,
+        }
+    }
+
+    // the table is tracked by the collector, which clears it if it's weak and calls `__gc` of
+    // the metatable when it's unreachable
+    pub fn set_metatable(&mut self, table: &TableRef, metatable: Option<TableRef>) {
+        let finalize = has_finalizer(&metatable);
+        table.borrow_mut().set_metatable(metatable);
+        self.heap.track_table(table);
+        if finalize {
+            self.heap.track_finalizer(Value::Table(table.clone()));
+        }
+    }
+
+    // the userdata is tracked by the collector, like tables with metatables
+    pub fn set_user_data_metatable(&mut self, data: &Rc<UserData>, metatable: Option<TableRef>) {
+        let finalize = has_finalizer(&metatable);
+        data.set_metatable(metatable);
+        self.heap.track_user_data(data);
+        if finalize {
+            self.heap.track_finalizer(Value::UserData(data.clone()));
+        }
+    }
+
+    pub fn set_string_metatable(&mut self, metatable: Option<TableRef>) {
+        self.string_meta = metatable;
+    }
+
+    // metatable of the userdata wrapping a `T` created from now on, e.g. with `__index` to
+    // look up its methods. it's kept alive by the vm
+    pub fn set_type_metatable<T: Any>(&mut self, metatable: Option<TableRef>) {
+        match metatable {
+            Some(metatable) => self.type_metas.insert(TypeId::of::<T>(), metatable),
+            None => self.type_metas.remove(&TypeId::of::<T>()),
+        };
+    }
+
+    pub fn type_metatable<T: Any>(&self) -> Option<TableRef> {
+        self.type_metas.get(&TypeId::of::<T>()).cloned()
+    }
+
+    // a userdata of a rust value for scripts, with the metatable of its type. it's tracked by
+    // the collector, so cycles through its metatable are freed and `__gc` is called
+    pub fn create_user_data<T: Any>(&mut self, data: T) -> Value {
+        let data = Rc::new(UserData::new(data));
+        let metatable = self.type_metas.get(&data.data_type()).cloned();
+        self.set_user_data_metatable(&data, metatable);
+        Value::UserData(data)
+    }
+
+    // handler of an event in the metatable of a value, none if nil
+    fn meta_method(&self, value: &Value, event: MetaMethod) -> Option<Value> {
+        let meta = self.get_metatable(value)?;
+        let handler = meta.borrow().get(&self.meta_names[event as usize]);
+        if handler.is_nil() {
+            None
+        } else {
+            Some(handler)
+        }
+    }
+
+    // operators on values which aren't numbers, with the handler of either operand.
+    // `e` is the error of the operator on the values themselves
+    fn arith_meta(
+        &mut self,
+        op: OpCode,
+        a: &Value,
+        b: &Value,
+        e: RuntimeError,
+    ) -> RuntimeResult<Value> {
+        let is_number = |value: &Value| matches!(value, Value::Int(_) | Value::Float(_));
+        if is_number(a) && is_number(b) {
+            return Err(e);
+        }
+        let event = MetaMethod::from_op(op).unwrap();
+        let handler = self
+            .meta_method(a, event)
+            .or_else(|| self.meta_method(b, event));
+        match handler {
+            Some(handler) => {
+                let results = self.call(&handler, &[a.clone(), b.clone()])?;
+                Ok(results.into_iter().next().unwrap_or(Value::Nil))
+            }
+            None => Err(e),
+        }
+    }
+
+    // a == b with `__eq`, which is only called for two different tables or two userdata
+    pub fn equals(&mut self, a: &Value, b: &Value) -> RuntimeResult<bool> {
+        if a == b {
+            return Ok(true);
+        }
+        if !has_eq_meta(a, b) {
+            return Ok(false);
+        }
+        let handler = self
+            .meta_method(a, MetaMethod::Eq)
+            .or_else(|| self.meta_method(b, MetaMethod::Eq));
+        match handler {
+            Some(handler) => self.call_truthy(&handler, a, b),
+            None => Ok(false),
+        }
+    }
+
+    // a < b with `__lt`
+    pub fn less_than(&mut self, a: &Value, b: &Value) -> RuntimeResult<bool> {
+        match compare(MetaMethod::Lt, a, b) {
+            Ok(result) => Ok(result),
+            Err(e) => self.compare_meta(MetaMethod::Lt, a, b, e),
+        }
+    }
+
+    // a <= b with `__le`, which isn't computed as not b < a
+    pub fn less_equal(&mut self, a: &Value, b: &Value) -> RuntimeResult<bool> {
+        match compare(MetaMethod::Le, a, b) {
+            Ok(result) => Ok(result),
+            Err(e) => self.compare_meta(MetaMethod::Le, a, b, e),
+        }
+    }
+
+    // `__lt` or `__le` of either operand, `e` is the error of comparing them without
+    fn compare_meta(
+        &mut self,
+        event: MetaMethod,
+        a: &Value,
+        b: &Value,
+        e: RuntimeError,
+    ) -> RuntimeResult<bool> {
+        let handler = self
+            .meta_method(a, event)
+            .or_else(|| self.meta_method(b, event));
+        match handler {
+            Some(handler) => self.call_truthy(&handler, a, b),
+            None => Err(e),
+        }
+    }
+
+    // first result of a handler as a boolean
+    fn call_truthy(&mut self, handler: &Value, a: &Value, b: &Value) -> RuntimeResult<bool> {
+        let results = self.call(handler, &[a.clone(), b.clone()])?;
+        Ok(results.first().is_some_and(Value::is_truthy))
+    }
+
+    // #v with `__len`, which tables only use instead of their borders if they have it
+    pub fn len(&mut self, value: &Value) -> RuntimeResult<Value> {
+        if let Value::Str(s) = value {
+            return Ok(Value::Int(s.len() as IntType));
+        }
+        match self.meta_method(value, MetaMethod::Len) {
+            Some(handler) => {
+                let results = self.call(&handler, &[value.clone(), value.clone()])?;
+                Ok(results.into_iter().next().unwrap_or(Value::Nil))
+            }
+            None => match value {
+                Value::Table(t) => Ok(Value::Int(t.borrow().len() as IntType)),
+                _ => Err(type_error("get length of", value)),
+            },
+        }
+    }
+
+    // concatenation of values from right to left, runs of strings and numbers are joined
+    // at once and other pairs use `__concat`
+    pub fn concat(&mut self, mut values: Vec<Value>) -> RuntimeResult<Value> {
+        while values.len() > 1 {
+            let n = values.len();
+            let strings = values
+                .iter()
+                .rev()
+                .take_while(|value| to_lua_str(value).is_some())
+                .count();
+            if strings >= 2 {
+                let strings = to_lua_strs(&values[n - strings..]).unwrap();
+                let s = self.join_strings(&strings)?;
+                values.truncate(n - strings.len());
+                values.push(Value::Str(s));
+                continue;
+            }
+            let b = values.pop().unwrap();
+            let a = values.pop().unwrap();
+            let handler = self
+                .meta_method(&a, MetaMethod::Concat)
+                .or_else(|| self.meta_method(&b, MetaMethod::Concat));
+            let value = match handler {
+                Some(handler) => {
+                    let results = self.call(&handler, &[a, b])?;
+                    results.into_iter().next().unwrap_or(Value::Nil)
+                }
+                None => {
+                    let culprit = if to_lua_str(&a).is_some() { b } else { a };
+                    return Err(type_error("concatenate", &culprit));
+                }
+            };
+            values.push(value);
+        }
+        Ok(values.pop().unwrap_or_else(|| Value::str("")))
+    }
+
+    // the strings joined, which count against the memory limit before they're built
+    fn join_strings(&mut self, strings: &[LuaStr]) -> RuntimeResult<LuaStr> {
+        let len = strings.iter().map(LuaStr::len).sum();
+        self.allocate(len)?;
+        let mut bytes = Vec::with_capacity(len);
+        for s in strings {
+            bytes.extend_from_slice(s.as_bytes());
+        }
+        Ok(LuaStr::from(bytes))
+    }
+
+    // string form of a value with `__tostring`, which must return a string
+    pub fn tostring(&mut self, value: &Value) -> RuntimeResult<LuaStr> {
+        if let Some(handler) = self.meta_method(value, MetaMethod::ToString) {
+            let results = self.call(&handler, std::slice::from_ref(value))?;
+            return match results.into_iter().next() {
+                Some(Value::Str(s)) => Ok(s),
+                _ => Err(error("'__tostring' must return a string".to_string())),
+            };
+        }
+        Ok(match value {
+            Value::Nil => LuaStr::from("nil"),
+            Value::Bool(b) => LuaStr::from(if *b { "true" } else { "false" }),
+            Value::Str(s) => s.clone(),
+            Value::Int(_) | Value::Float(_) => to_lua_str(value).unwrap(),
+            Value::Table(t) => LuaStr::from(format!("table: {:p}", Rc::as_ptr(t)).as_str()),
+            Value::Function(f) => LuaStr::from(format!("function: {:p}", Rc::as_ptr(f)).as_str()),
+            Value::Native(f) => {
+                LuaStr::from(format!("function: builtin: {:p}", Rc::as_ptr(f)).as_str())
+            }
+            Value::UserData(u) => LuaStr::from(format!("userdata: {:p}", Rc::as_ptr(u)).as_str()),
+            Value::Thread(t) => LuaStr::from(format!("thread: {:p}", Rc::as_ptr(t)).as_str()),
+        })
+    }
+
+    // t[k] with `__index`
+    pub fn index(&mut self, table: &Value, key: &Value) -> RuntimeResult<Value> {
+        let mut table = table.clone();
+        for _ in 0..MAX_META_CHAIN {
+            if let Value::Table(t) = &table {
+                let value = t.borrow().get(key);
+                if !value.is_nil() {
+                    return Ok(value);
+                }
+            }
+            let handler = match self.meta_method(&table, MetaMethod::Index) {
+                Some(handler) => handler,
+                None if matches!(table, Value::Table(_)) => return Ok(Value::Nil),
+                None => return Err(type_error("index", &table)),
+            };
+            if handler.is_function() {
+                let results = self.call(&handler, &[table, key.clone()])?;
+                return Ok(results.into_iter().next().unwrap_or(Value::Nil));
+            }
+            table = handler;
+        }
+        Err(error(
+            "'__index' chain too long; possibly a loop".to_string(),
+        ))
+    }
+
+    // t[k] = v with `__newindex`
+    pub fn new_index(&mut self, table: &Value, key: Value, value: Value) -> RuntimeResult<()> {
+        let mut table = table.clone();
+        for _ in 0..MAX_META_CHAIN {
+            if let Value::Table(t) = &table {
+                // existing fields are assigned without asking the metatable
+                if !t.borrow().get(&key).is_nil() {
+                    return self.set_field(t, key, value);
+                }
+            }
+            let handler = match self.meta_method(&table, MetaMethod::NewIndex) {
+                Some(handler) => handler,
+                None => match &table {
+                    Value::Table(t) => return self.set_field(t, key, value),
+                    _ => return Err(type_error("index", &table)),
+                },
+            };
+            if handler.is_function() {
+                self.call(&handler, &[table, key, value])?;
+                return Ok(());
+            }
+            table = handler;
+        }
+        Err(error(
+            "'__newindex' chain too long; possibly a loop".to_string(),
+        ))
+    }
+
+    // t[k] without metamethods
+    pub fn raw_get(&self, table: &Value, key: &Value) -> RuntimeResult<Value> {
+        match table {
+            Value::Table(t) => Ok(t.borrow().get(key)),
+            value => Err(error(format!("table expected, got {}", value.type_name()))),
+        }
+    }
+
+    // t[k] = v without metamethods
+    pub fn raw_set(&mut self, table: &Value, key: Value, value: Value) -> RuntimeResult<()> {
+        match table {
+            Value::Table(t) => self.set_field(&t.clone(), key, value),
+            value => Err(error(format!("table expected, got {}", value.type_name()))),
+        }
+    }
+
+    // #v without `__len`, a border of tables and the number of bytes of strings
+    pub fn raw_len(&self, value: &Value) -> RuntimeResult<IntType> {
+        match value {
+            Value::Table(t) => Ok(t.borrow().len() as IntType),
+            Value::Str(s) => Ok(s.len() as IntType),
+            _ => Err(error("table or string expected".to_string())),
+        }
+    }
+
+    // a == b without `__eq`
+    pub fn raw_equal(&self, a: &Value, b: &Value) -> bool {
+        a == b
+    }
+
+    // a copy of a function which calls the hooks around it, sharing its code and upvalues.
+    // only calls through the copy are hooked
+    pub fn wrap(&mut self, func: &Value, hooks: CallHooks) -> RuntimeResult<Value> {
+        let closure = match func {
+            Value::Function(closure) => closure,
+            Value::Native(_) => return Err(error("only lua functions can be wrapped".to_string())),
+            value => {
+                return Err(error(format!(
+                    "function expected, got {}",
+                    value.type_name()
+                )))
+            }
+        };
+        let wrapped = Rc::new(Closure {
+            proto: closure.proto.clone(),
+            up_values: closure.up_values.clone(),
+        });
+        self.hooks
+            .insert(Rc::as_ptr(&wrapped), (wrapped.clone(), hooks));
+        Ok(Value::Function(wrapped))
+    }
+
+    // replace a global function with a wrapped copy
+    pub fn wrap_global(&mut self, name: &str, hooks: CallHooks) -> RuntimeResult<()> {
+        let wrapped = self.wrap(&self.get_global(name), hooks)?;
+        self.set_global(name, wrapped);
+        Ok(())
+    }
+
+    // replace a function in a table, e.g. a method, with a wrapped copy
+    pub fn wrap_field(
+        &mut self,
+        table: &TableRef,
+        key: &Value,
+        hooks: CallHooks,
+    ) -> RuntimeResult<()> {
+        let func = table.borrow().get(key);
+        let wrapped = self.wrap(&func, hooks)?;
+        table.borrow_mut().set(key.clone(), wrapped);
+        Ok(())
+    }
+
+    // remove the hooks of a wrapped copy, calls through it aren't hooked anymore
+    pub fn unwrap(&mut self, func: &Value) -> bool {
+        match func {
+            Value::Function(closure) => self.hooks.remove(&Rc::as_ptr(closure)).is_some(),
+            _ => false,
+        }
+    }
+
+    // t[k] = v without metamethods, the growth of the table counts against the memory limit
+    fn set_field(&mut self, table: &TableRef, key: Value, value: Value) -> RuntimeResult<()> {
+        match key {
+            Value::Nil => return Err(error("index is nil".to_string())),
+            Value::Float(f) if f.is_nan() => return Err(error("index is NaN".to_string())),
+            _ => (),
+        }
+        let before = table.borrow().memory();
+        table.borrow_mut().set(key, value);
+        let grown = table.borrow().memory().saturating_sub(before);
+        self.allocate(grown)
+    }
+
+    pub fn create_thread(&mut self, func: Value) -> ThreadRef {
+        let thread = Rc::new(RefCell::new(Thread::new(func)));
+        self.heap.track_thread(&thread);
+        thread
+    }
+
+    // running coroutine, none for the main thread
+    pub fn running(&self) -> Option<&ThreadRef> {
+        self.thread.as_ref()
+    }
+
+    // whether the running code can yield, which isn't the case in the main thread
+    // or in functions called by the host, e.g. metamethods
+    pub fn is_yieldable(&self) -> bool {
+        self.thread.is_some() && self.calls == self.yield_level
+    }
+
+    // run a coroutine until it yields or returns, args are passed to the function on the first
+    // resume and returned by yield later
+    pub fn resume(&mut self, thread: &ThreadRef, args: Vec<Value>) -> RuntimeResult<Vec<Value>> {
+        match thread.borrow().status {
+            ThreadStatus::Suspended => (),
+            ThreadStatus::Dead => return Err(error("cannot resume dead coroutine".to_string())),
+            _ => return Err(error("cannot resume non-suspended coroutine".to_string())),
+        }
+        self.enter_native()?;
+        if self.calls == 0 {
+            self.interrupted = false;
+        }
+        if let Some(current) = &self.thread {
+            current.borrow_mut().status = ThreadStatus::Normal;
+        }
+        let previous = self.thread.replace(thread.clone());
+        let yield_level = std::mem::replace(&mut self.yield_level, self.calls);
+        // message handlers apply to the thread of their protected call
+        let protections = std::mem::take(&mut self.protections);
+        self.swap_thread(thread);
+        thread.borrow_mut().status = ThreadStatus::Running;
+
+        let result = self
+            .resume_thread(thread, args)
+            .map_err(|e| self.unwind(0, e));
+
+        let result = match (result, self.yielding.take()) {
+            (Ok(_), Some(values)) => {
+                self.swap_thread(thread);
+                thread.borrow_mut().status = ThreadStatus::Suspended;
+                Ok(values)
+            }
+            (result, _) => {
+                self.swap_thread(thread);
+                let mut thread = thread.borrow_mut();
+                thread.status = ThreadStatus::Dead;
+                thread.stack.clear();
+                thread.frames.clear();
+                thread.open_up_values.clear();
+                result
+            }
+        };
+        self.yield_level = yield_level;
+        self.protections = protections;
+        self.thread = previous;
+        if let Some(current) = &self.thread {
+            current.borrow_mut().status = ThreadStatus::Running;
+        }
+        self.native_depth -= 1;
+        result
+    }
+
+    fn resume_thread(&mut self, thread: &ThreadRef, args: Vec<Value>) -> RuntimeResult<Vec<Value>> {
+        match self.resume_at.take() {
+            // results of the call to yield
+            Some((slot, results)) => {
+                self.place_results(slot, args, results);
+                // the body itself was a native function, it returns them
+                if self.frames.is_empty() {
+                    return Ok(self.stack[..self.top].to_vec());
+                }
+            }
+            // yielded by a count hook, the args are ignored
+            None if !self.frames.is_empty() => self.resumed_hook = self.metered,
+            None => {
+                let func = thread.borrow().func.clone();
+                self.ensure_stack(1 + args.len());
+                self.stack[0] = func;
+                let nargs = args.len();
+                for (i, arg) in args.into_iter().enumerate() {
+                    self.stack[1 + i] = arg;
+                }
+                if !self.precall(0, nargs, None, false)? {
+                    return Ok(self.stack[0..self.top].to_vec());
+                }
+            }
+        }
+        if self.yielding.is_some() {
+            return Ok(Vec::new());
+        }
+        self.execute(0)
+    }
+
+    // exchange the stack and frames of the vm with those of a coroutine. upvalues open in the
+    // stack which is put away keep their values until it's back, so other threads see them
+    fn swap_thread(&mut self, thread: &ThreadRef) {
+        for (slot, up_value) in self.open_up_values.iter() {
+            *up_value.borrow_mut() = UpValue::Closed(self.stack[*slot].clone());
+        }
+        let mut thread = thread.borrow_mut();
+        std::mem::swap(&mut self.stack, &mut thread.stack);
+        std::mem::swap(&mut self.frames, &mut thread.frames);
+        std::mem::swap(&mut self.top, &mut thread.top);
+        std::mem::swap(&mut self.resume_at, &mut thread.resume_at);
+        std::mem::swap(&mut self.open_up_values, &mut thread.open_up_values);
+        for (slot, up_value) in self.open_up_values.iter() {
+            let up_value = std::mem::replace(&mut *up_value.borrow_mut(), UpValue::Open(*slot));
+            if let UpValue::Closed(value) = up_value {
+                self.stack[*slot] = value;
+            }
+        }
+    }
+
+    // the value of an upvalue of the running thread
+    fn up_value(&self, up_value: &UpValueRef) -> Value {
+        match &*up_value.borrow() {
+            UpValue::Open(slot) => self.stack[*slot].clone(),
+            UpValue::Closed(value) => value.clone(),
+        }
+    }
+
+    fn set_up_value(&mut self, up_value: &UpValueRef, value: Value) {
+        match &mut *up_value.borrow_mut() {
+            UpValue::Open(slot) => self.stack[*slot] = value,
+            UpValue::Closed(closed) => *closed = value,
+        }
+    }
+
+    // the upvalue of the variable in `slot`, shared by all closures which capture it
+    fn find_up_value(&mut self, slot: usize) -> UpValueRef {
+        let open = &mut self.open_up_values;
+        match open.binary_search_by_key(&slot, |(slot, _)| *slot) {
+            Ok(i) => open[i].1.clone(),
+            Err(i) => {
+                let up_value = Rc::new(RefCell::new(UpValue::Open(slot)));
+                open.insert(i, (slot, up_value.clone()));
+                up_value
+            }
+        }
+    }
+
+    // the scopes of the variables from `slot` end, their upvalues keep the values
+    fn close_up_values(&mut self, slot: usize) {
+        while let Some((var, _)) = self.open_up_values.last() {
+            if *var < slot {
+                break;
+            }
+            let (var, up_value) = self.open_up_values.pop().unwrap();
+            *up_value.borrow_mut() = UpValue::Closed(self.stack[var].clone());
+        }
+    }
+
+    // suspend the running coroutine, to be called by native functions which return
+    // right after it. `resume` returns the values
+    pub fn yield_values(&mut self, values: Vec<Value>) -> RuntimeResult<()> {
+        if self.thread.is_none() {
+            return Err(error(
+                "attempt to yield from outside a coroutine".to_string(),
+            ));
+        }
+        if !self.is_yieldable() {
+            return Err(error(
+                "attempt to yield across a C-call boundary".to_string(),
+            ));
+        }
+        self.yielding = Some(values);
+        Ok(())
+    }
+
+    // call `func` and return its results, or the error value, which is the result of `handler`
+    // if there's one. the handler runs before unwinding, so it sees the frames of the error
+    pub fn pcall(
+        &mut self,
+        func: &Value,
+        args: &[Value],
+        handler: Option<Value>,
+    ) -> Result<Vec<Value>, Value> {
+        self.protections.push(Protection {
+            handler,
+            error: None,
+        });
+        let result = self.call(func, args);
+        let protection = self.protections.pop().unwrap();
+        result.map_err(|e| match protection.error {
+            Some(value) => value,
+            None => e.value(),
+        })
+    }
+
+    // run the message handler of the innermost protected call, once per error
+    fn handle_error(&mut self, e: &RuntimeError) {
+        if self.interrupted {
+            return;
+        }
+        let handler = match self.protections.last() {
+            Some(Protection {
+                handler: Some(handler),
+                error: None,
+            }) => handler.clone(),
+            _ => return,
+        };
+        let value = e.value();
+        // errors of the handler aren't handled again
+        self.protections.push(Protection {
+            handler: None,
+            error: None,
+        });
+        let value = match self.call(&handler, &[value]) {
+            Ok(values) => values.into_iter().next().unwrap_or(Value::Nil),
+            Err(_) => Value::str("error in error handling"),
+        };
+        self.protections.pop();
+        self.protections.last_mut().unwrap().error = Some(value);
+    }
+
+    // `name:line:` of the function `level` calls up the stack, 1 for the running lua function.
+    // empty without line info
+    pub fn position(&self, level: usize) -> String {
+        if level == 0 {
+            return String::new();
+        }
+        match self.traceback(level).0.first() {
+            Some(frame) if frame.line.is_some() => format!("{} ", frame),
+            _ => String::new(),
+        }
+    }
+
+    // the lua functions on the stack from `level` calls up, 1 for the running lua function
+    pub fn traceback(&self, level: usize) -> Traceback {
+        let frames = self
+            .frames
+            .iter()
+            .rev()
+            .skip(level.max(1) - 1)
+            .map(|frame| {
+                let proto = &frame.closure.proto;
+                TraceFrame {
+                    function: proto.name.clone(),
+                    line: proto.proto.line_info.get(frame.pc.saturating_sub(1)),
+                }
+            })
+            .collect();
+        Traceback(frames)
+    }
+
+    // number of calls of lua functions on the stack of the running coroutine, the levels of
+    // `frame_info`
+    pub fn stack_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    // the lua function `level` calls up the stack with its current line, 1 for the running one
+    pub fn frame_info(&self, level: usize) -> Option<FunctionInfo> {
+        let index = self.frames.len().checked_sub(level.max(1))?;
+        let frame = &self.frames[index];
+        let mut info = FunctionInfo::of(&Value::Function(frame.closure.clone()))?;
+        info.current_line = frame
+            .closure
+            .proto
+            .proto
+            .line_info
+            .get(frame.pc.saturating_sub(1));
+        info.is_tail_call = frame.tail_call;
+        Some(info)
+    }
+
+    // free the objects which are only reachable from each other
+    pub fn collect_garbage(&mut self) {
+        let mut heap = std::mem::take(&mut self.heap);
+        heap.collect(|| self.roots());
+        self.heap = heap;
+        self.run_finalizers();
+    }
+
+    // run `budget` units of work of an incremental collection, starting one if none is running.
+    // true when it's finished
+    pub fn step_garbage(&mut self, budget: usize) -> bool {
+        let mut heap = std::mem::take(&mut self.heap);
+        if !heap.is_collecting() {
+            heap.start();
+        }
+        let finished = heap.step(budget, || self.roots());
+        self.heap = heap;
+        self.run_finalizers();
+        finished
+    }
+
+    // switch between full and incremental collections, returning the previous mode
+    pub fn set_gc_mode(&mut self, mode: GcMode) -> GcMode {
+        let previous = self.heap.mode();
+        self.heap.set_mode(mode);
+        previous
+    }
+
+    // `pause` is the growth of the number of objects starting the next collection, in percent
+    // of the objects alive after the last one. `step_size` is the number of objects counted,
+    // marked or swept by each step of an incremental collection
+    pub fn tune_gc(&mut self, pause: Option<usize>, step_size: Option<usize>) {
+        self.heap.tune(pause, step_size);
+    }
+
+    // approximate number of bytes used by tables with their strings, closures, coroutines and
+    // userdata of scripts
+    pub fn memory(&self) -> usize {
+        self.heap.memory()
+    }
+
+    // limit the memory scripts use to about `limit` bytes. allocations beyond it collect
+    // garbage, and fail with `not enough memory` if it's still exceeded. none for no limit
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+        self.allocated = self.memory();
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    // limit the frames of lua functions in the stack of each thread, calls beyond it fail with
+    // `stack overflow`, which protected calls can catch
+    pub fn set_call_depth_limit(&mut self, limit: usize) {
+        self.call_depth_limit = limit;
+    }
+
+    pub fn call_depth_limit(&self) -> usize {
+        self.call_depth_limit
+    }
+
+    // limit the nesting of calls into the vm from rust, e.g. by native functions like `pcall`,
+    // metamethods, hooks and resumes, which recurse in rust. deeper calls fail with
+    // `C stack overflow` instead of overflowing the stack of the host thread
+    pub fn set_native_depth_limit(&mut self, limit: usize) {
+        self.native_depth_limit = limit;
+    }
+
+    pub fn native_depth_limit(&self) -> usize {
+        self.native_depth_limit
+    }
+
+    // count a call from rust into the vm, failing if it would nest too deeply
+    fn enter_native(&mut self) -> RuntimeResult<()> {
+        if self.native_depth >= self.native_depth_limit {
+            return Err(error("C stack overflow".to_string()));
+        }
+        self.native_depth += 1;
+        Ok(())
+    }
+
+    // a handle to stop the running script with an `interrupted` error at the next instruction.
+    // handles share the state, a trigger is taken by one interruption
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    // by default interruptions unwind through protected calls, like `pcall`, up to the host
+    pub fn set_interrupt_catchable(&mut self, catchable: bool) {
+        self.interrupt_catchable = catchable;
+    }
+
+    // whether an uncatchable interruption is unwinding, which protected calls pass on
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted
+    }
+
+    fn interruption(&mut self) -> RuntimeError {
+        self.interrupted = !self.interrupt_catchable;
+        error("interrupted".to_string())
+    }
+
+    // count `bytes` allocated for a script against the limit
+    pub(crate) fn allocate(&mut self, bytes: usize) -> RuntimeResult<()> {
+        let limit = match self.memory_limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        self.allocated += bytes;
+        if self.allocated <= limit {
+            return Ok(());
+        }
+        self.collect_garbage();
+        // strings aren't objects of the heap, so the allocation counts on top of what's live
+        self.allocated = self.memory() + bytes;
+        if self.allocated > limit {
+            return Err(RuntimeError::out_of_memory());
+        }
+        Ok(())
+    }
+
+    // values of the vm, which are roots of collections
+    fn roots(&self) -> Vec<Value> {
+        let mut roots = self.stack.clone();
+        roots.push(Value::Table(self.globals.clone()));
+        roots.push(Value::Table(self.registry.clone()));
+        roots.extend(self.string_meta.clone().map(Value::Table));
+        roots.extend(self.type_metas.values().cloned().map(Value::Table));
+        roots.extend(self.thread.clone().map(Value::Thread));
+        roots.extend(self.frames.iter().flat_map(|frame| {
+            std::iter::once(Value::Function(frame.closure.clone())).chain(frame.varargs.clone())
+        }));
+        roots.extend(
+            self.hooks
+                .values()
+                .map(|(closure, _)| Value::Function(closure.clone())),
+        );
+        for protection in self.protections.iter() {
+            roots.extend(protection.handler.clone());
+            roots.extend(protection.error.clone());
+        }
+        roots
+    }
+
+    // collect when enough objects were created since the last collection, a step of it in
+    // incremental mode
+    fn check_garbage(&mut self) {
+        if self.heap.should_collect() {
+            let mut heap = std::mem::take(&mut self.heap);
+            heap.run(|| self.roots());
+            self.heap = heap;
+            self.run_finalizers();
+        }
+    }
+
+    // call `__gc` of unreachable objects, errors are ignored
+    fn run_finalizers(&mut self) {
+        for value in self.heap.take_finalizing() {
+            self.finalize(value);
+        }
+    }
+
+    fn finalize(&mut self, value: Value) {
+        if let Some(gc) = self.meta_method(&value, MetaMethod::Gc) {
+            let _ = self.call(&gc, &[value]);
+        }
+    }
+
+    // main function of a compiled chunk, with the globals as _ENV. fails if the chunk doesn't
+    // verify, e.g. a corrupted binary chunk
+    pub fn load(&mut self, proto: Proto) -> RuntimeResult<Value> {
+        let proto = FuncProto::new(proto).map_err(|e| error(e.0))?;
+        Ok(self.load_func(proto))
+    }
+
+    // like `load`, for functions prepared once and loaded many times
+    pub fn load_func(&mut self, proto: Rc<FuncProto>) -> Value {
+        let up_values = (0..proto.proto.up_vars.len())
+            .map(|i| {
+                let value = if i == 0 {
+                    Value::Table(self.globals.clone())
+                } else {
+                    Value::Nil
+                };
+                Rc::new(RefCell::new(UpValue::Closed(value)))
+            })
+            .collect();
+        Value::Function(Rc::new(Closure { proto, up_values }))
+    }
+
+    // limit the number of instructions lua functions run from now on, after which each
+    // instruction fails with an error, so scripts can't run forever. none for no limit
+    pub fn set_instruction_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+        self.update_metered();
+    }
+
+    // instructions left to run
+    pub fn instruction_budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    // call `hook` every `n` instructions of lua functions, before the instruction runs. it can
+    // abort the script by returning an error, or yield the running coroutine with
+    // `yield_values` if it's yieldable, to be continued by the next resume
+    pub fn set_count_hook<F>(&mut self, n: u32, hook: F)
+    where
+        F: Fn(&mut Vm) -> RuntimeResult<()> + 'static,
+    {
+        let n = n.max(1);
+        self.count_hook = Some((n, n, Rc::new(hook)));
+        self.update_metered();
+    }
+
+    pub fn remove_count_hook(&mut self) {
+        self.count_hook = None;
+        self.update_metered();
+    }
+
+    // call `hook` on the events of `mask`, like `debug.sethook`. it runs in the vm, with the
+    // function of the event on the top of the stack, and isn't hooked itself. errors of the
+    // hook are raised in the running code. it replaces the previous hook, and applies to all
+    // coroutines
+    pub fn set_hook<F>(&mut self, mask: HookMask, hook: F)
+    where
+        F: Fn(&mut Vm, HookEvent) -> RuntimeResult<()> + 'static,
+    {
+        self.debug_hook = Some(DebugHookState {
+            mask,
+            left: mask.count,
+            hook: Rc::new(hook),
+        });
+        self.update_metered();
+    }
+
+    pub fn remove_hook(&mut self) {
+        self.debug_hook = None;
+        self.update_metered();
+    }
+
+    // events of the debug hook, none without one
+    pub fn hook_mask(&self) -> Option<HookMask> {
+        self.debug_hook.as_ref().map(|hook| hook.mask)
+    }
+
+    fn update_metered(&mut self) {
+        let traced = match &self.debug_hook {
+            Some(hook) => hook.mask.line || hook.mask.count > 0,
+            None => false,
+        };
+        self.metered = self.budget.is_some() || self.count_hook.is_some() || traced;
+    }
+
+    fn call_hook(&mut self, event: HookEvent) -> RuntimeResult<()> {
+        if self.in_hook {
+            return Ok(());
+        }
+        let hook = match &self.debug_hook {
+            Some(hook) => {
+                let mask = &hook.mask;
+                let enabled = match event {
+                    HookEvent::Call | HookEvent::TailCall => mask.call,
+                    HookEvent::Return => mask.ret,
+                    HookEvent::Line(_) => mask.line,
+                    HookEvent::Count => mask.count > 0,
+                };
+                if !enabled {
+                    return Ok(());
+                }
+                hook.hook.clone()
+            }
+            None => return Ok(()),
+        };
+        // hooks can't yield
+        self.in_hook = true;
+        self.calls += 1;
+        let result = hook(self, event);
+        self.calls -= 1;
+        self.in_hook = false;
+        result
+    }
+
+    // count and line events before the instruction at the pc of the running frame
+    fn trace(&mut self) -> RuntimeResult<()> {
+        let (mask, counted) = match &mut self.debug_hook {
+            Some(hook) if !self.in_hook => {
+                let mut counted = false;
+                if hook.mask.count > 0 {
+                    hook.left -= 1;
+                    if hook.left == 0 {
+                        hook.left = hook.mask.count;
+                        counted = true;
+                    }
+                }
+                (hook.mask, counted)
+            }
+            _ => return Ok(()),
+        };
+        if counted {
+            self.trace_hook(HookEvent::Count)?;
+        }
+        if !mask.line {
+            return Ok(());
+        }
+        let frame = self.frames.last_mut().unwrap();
+        let pc = frame.pc;
+        let lines = &frame.closure.proto.proto.line_info;
+        let line = match lines.get(pc) {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        let new_line = match frame.traced {
+            Some(old) => pc <= old || lines.get(old) != Some(line),
+            None => true,
+        };
+        frame.traced = Some(pc);
+        if new_line {
+            self.trace_hook(HookEvent::Line(line))?;
+        }
+        Ok(())
+    }
+
+    // hooks see the instruction about to run as the current one, like after a call
+    fn trace_hook(&mut self, event: HookEvent) -> RuntimeResult<()> {
+        self.frames.last_mut().unwrap().pc += 1;
+        let result = self.call_hook(event);
+        self.frames.last_mut().unwrap().pc -= 1;
+        result
+    }
+
+    // count an instruction against the budget and the period of the hook
+    fn meter(&mut self) -> RuntimeResult<()> {
+        if let Some(budget) = &mut self.budget {
+            if *budget == 0 {
+                return Err(error("instruction budget exceeded".to_string()));
+            }
+            *budget -= 1;
+        }
+        self.trace()?;
+        let hook = match &mut self.count_hook {
+            Some((n, left, hook)) => {
+                *left -= 1;
+                if *left > 0 {
+                    return Ok(());
+                }
+                *left = *n;
+                hook.clone()
+            }
+            None => return Ok(()),
+        };
+        hook(self)
+    }
+
+    // load and call the main function of a chunk
+    pub fn run(&mut self, proto: Proto) -> RuntimeResult<Vec<Value>> {
+        let main = self.load(proto)?;
+        self.call(&main, &[])
+    }
+
+    pub fn call(&mut self, func: &Value, args: &[Value]) -> RuntimeResult<Vec<Value>> {
+        let depth = self.frames.len();
+        let slot = self.frames.last().map_or(0, |frame| {
+            frame.base + frame.closure.proto.proto.stack_size as usize
+        });
+        let slot = slot.max(self.top);
+        self.ensure_stack(slot + 1 + args.len());
+        self.stack[slot] = func.clone();
+        self.stack[slot + 1..slot + 1 + args.len()].clone_from_slice(args);
+        // the caller may be between a call with multiple results and their use
+        let top = self.top;
+        if self.calls == 0 {
+            self.interrupted = false;
+        }
+        if let Err(e) = self.enter_native() {
+            self.top = top;
+            return Err(e);
+        }
+        self.calls += 1;
+        let result = match self.precall(slot, args.len(), None, false) {
+            Ok(true) => self.execute(depth),
+            Ok(false) => Ok(self.stack[slot..self.top].to_vec()),
+            Err(e) => Err(e),
+        };
+        self.calls -= 1;
+        self.native_depth -= 1;
+        let result = result.map_err(|e| self.unwind(depth, e));
+        self.top = top;
+        result
+    }
+
+    // results of a native function in place of it and its args
+    fn place_results(&mut self, slot: usize, values: Vec<Value>, results: Option<usize>) {
+        let n = values.len();
+        self.ensure_stack(slot + n.max(results.unwrap_or(0)));
+        for (i, value) in values.into_iter().enumerate() {
+            self.stack[slot + i] = value;
+        }
+        match results {
+            Some(results) => {
+                for i in n..results {
+                    self.stack[slot + i] = Value::Nil;
+                }
+            }
+            None => self.top = slot + n,
+        }
+    }
+
+    fn ensure_stack(&mut self, size: usize) {
+        if self.stack.len() < size {
+            self.stack.resize(size, Value::Nil);
+        }
+    }
+
+    // push a frame for the function at `slot` with `nargs` args above it, false if it's a native
+    // function which has already returned or yielded
+    fn precall(
+        &mut self,
+        slot: usize,
+        nargs: usize,
+        results: Option<usize>,
+        tail_call: bool,
+    ) -> RuntimeResult<bool> {
+        let closure = match &self.stack[slot] {
+            Value::Function(closure) => closure.clone(),
+            Value::Native(native) => {
+                let native = native.clone();
+                let args = self.stack[slot + 1..slot + 1 + nargs].to_vec();
+                self.call_hook(HookEvent::Call)?;
+                let values = (native.func)(self, args)?;
+                if self.yielding.is_some() {
+                    self.resume_at = Some((slot, results));
+                } else {
+                    self.call_hook(HookEvent::Return)?;
+                    self.place_results(slot, values, results);
+                }
+                return Ok(false);
+            }
+            value => {
+                return Err(error(format!(
+                    "attempt to call a {} value",
+                    value.type_name()
+                )))
+            }
+        };
+        if self.frames.len() >= self.call_depth_limit {
+            return Err(error("stack overflow".to_string()));
+        }
+        let base = slot + 1;
+        let hooked = match self.hooks.get_mut(&Rc::as_ptr(&closure)) {
+            Some((_, hooks)) => {
+                if let Some(before) = &mut hooks.before {
+                    before(&self.stack[base..base + nargs])?;
+                }
+                true
+            }
+            None => false,
+        };
+        let proto = &closure.proto.proto;
+        let params = proto.param_count as usize;
+        let varargs = if proto.is_vararg && nargs > params {
+            self.stack[base + params..base + nargs].to_vec()
+        } else {
+            Vec::new()
+        };
+        let stack_size = proto.stack_size as usize;
+        self.ensure_stack(base + stack_size.max(nargs));
+        // missing params are nil, and so are registers above them
+        for value in self.stack[base + params.min(nargs)..base + stack_size].iter_mut() {
+            *value = Value::Nil;
+        }
+        self.frames.push(Frame {
+            closure,
+            pc: 0,
+            base,
+            varargs,
+            results,
+            hooked,
+            tbc: Vec::new(),
+            traced: None,
+            tail_call,
+        });
+        self.call_hook(if tail_call {
+            HookEvent::TailCall
+        } else {
+            HookEvent::Call
+        })?;
+        Ok(true)
+    }
+
+    // run until the frame at `depth` returns, returning its results
+    fn execute(&mut self, depth: usize) -> RuntimeResult<Vec<Value>> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let closure = frame.closure.clone();
+            let base = frame.base;
+            let mut pc = frame.pc;
+            let proto = &closure.proto;
+            let code = &proto.proto.code;
+            // returns from the inner loop when the frame changes
+            loop {
+                if self.interrupt.take() {
+                    self.frames.last_mut().unwrap().pc = pc;
+                    return Err(self.interruption());
+                }
+                if self.metered && !std::mem::take(&mut self.resumed_hook) {
+                    self.frames.last_mut().unwrap().pc = pc;
+                    self.meter()?;
+                    // continued at the instruction by the next resume
+                    if self.yielding.is_some() {
+                        return Ok(Vec::new());
+                    }
+                }
+                let instruction = code[pc];
+                pc += 1;
+                // for metamethods and calls, which may need the pc of the frame
+                macro_rules! save_pc {
+                    () => {
+                        self.frames.last_mut().unwrap().pc = pc
+                    };
+                }
+                macro_rules! rk {
+                    ($arg:expr) => {
+                        if is_const($arg) {
+                            &proto.consts[($arg & !MASK_K) as usize]
+                        } else {
+                            &self.stack[base + reg($arg)]
+                        }
+                    };
+                }
+                match instruction {
+                    Instruction::Move { dst, src } => {
+                        self.stack[base + reg(dst)] = self.stack[base + reg(src)].clone();
+                    }
+                    Instruction::LoadK { dst, k } => {
+                        self.stack[base + reg(dst)] = proto.consts[k as usize].clone();
+                    }
+                    Instruction::LoadKx { dst } => {
+                        let k = code[pc].get_arg_Ax();
+                        pc += 1;
+                        self.stack[base + reg(dst)] = proto.consts[k as usize].clone();
+                    }
+                    Instruction::LoadBool { dst, value, skip } => {
+                        self.stack[base + reg(dst)] = Value::Bool(value != 0);
+                        if skip != 0 {
+                            pc += 1;
+                        }
+                    }
+                    Instruction::LoadNil { dst, n } => {
+                        for value in self.stack[base + reg(dst)..=base + reg(dst + n)].iter_mut() {
+                            *value = Value::Nil;
+                        }
+                    }
+                    Instruction::GetUpVal { dst, up } => {
+                        let value = self.up_value(&closure.up_values[up as usize]);
+                        self.stack[base + reg(dst)] = value;
+                    }
+                    Instruction::GetTabUp { dst, up, key } => {
+                        let table = self.up_value(&closure.up_values[up as usize]);
+                        let key = rk!(key).clone();
+                        save_pc!();
+                        let value = self.index(&table, &key)?;
+                        self.stack[base + reg(dst)] = value;
+                    }
+                    Instruction::GetTable { dst, table, key } => {
+                        let table = self.stack[base + reg(table)].clone();
+                        let key = rk!(key).clone();
+                        save_pc!();
+                        let value = self.index(&table, &key)?;
+                        self.stack[base + reg(dst)] = value;
+                    }
+                    Instruction::SetTabUp { up, key, value } => {
+                        let table = self.up_value(&closure.up_values[up as usize]);
+                        let (key, value) = (rk!(key).clone(), rk!(value).clone());
+                        save_pc!();
+                        self.new_index(&table, key, value)?;
+                    }
+                    Instruction::SetUpVal { src, up } => {
+                        let value = self.stack[base + reg(src)].clone();
+                        self.set_up_value(&closure.up_values[up as usize], value);
+                    }
+                    Instruction::SetTable { table, key, value } => {
+                        let table = self.stack[base + reg(table)].clone();
+                        let (key, value) = (rk!(key).clone(), rk!(value).clone());
+                        save_pc!();
+                        self.new_index(&table, key, value)?;
+                    }
+                    Instruction::NewTable { dst, array, hash } => {
+                        // a hint may ask for far more than the memory limit, so it is charged first
+                        let array = fb2int(array.min(MAX_TABLE_HINT));
+                        let hash = fb2int(hash.min(MAX_TABLE_HINT));
+                        save_pc!();
+                        self.allocate(Table::memory_for(array, hash))?;
+                        let table = Rc::new(RefCell::new(Table::with_capacity(array, hash)));
+                        self.heap.track_table(&table);
+                        self.stack[base + reg(dst)] = Value::Table(table);
+                        self.check_garbage();
+                    }
+                    Instruction::Self_ { dst, table, key } => {
+                        let table = self.stack[base + reg(table)].clone();
+                        let key = rk!(key).clone();
+                        save_pc!();
+                        let method = self.index(&table, &key)?;
+                        self.stack[base + reg(dst) + 1] = table;
+                        self.stack[base + reg(dst)] = method;
+                    }
+                    Instruction::Add { dst, left, right }
+                    | Instruction::Sub { dst, left, right }
+                    | Instruction::Mul { dst, left, right }
+                    | Instruction::Mod { dst, left, right }
+                    | Instruction::Pow { dst, left, right }
+                    | Instruction::Div { dst, left, right }
+                    | Instruction::IDiv { dst, left, right }
+                    | Instruction::BAdd { dst, left, right }
+                    | Instruction::BOr { dst, left, right }
+                    | Instruction::BXor { dst, left, right }
+                    | Instruction::Shl { dst, left, right }
+                    | Instruction::Shr { dst, left, right } => {
+                        let op = instruction.get_op();
+                        let value = match arith(op, rk!(left), rk!(right)) {
+                            Ok(value) => value,
+                            Err(e) => {
+                                let (a, b) = (rk!(left).clone(), rk!(right).clone());
+                                save_pc!();
+                                self.arith_meta(op, &a, &b, e)?
+                            }
+                        };
+                        self.stack[base + reg(dst)] = value;
+                    }
+                    // unary operators get the operand twice, like their metamethods
+                    Instruction::Unm { dst, src } | Instruction::BNot { dst, src } => {
+                        let op = instruction.get_op();
+                        let a = &self.stack[base + reg(src)];
+                        let value = match arith(op, a, a) {
+                            Ok(value) => value,
+                            Err(e) => {
+                                let a = a.clone();
+                                save_pc!();
+                                self.arith_meta(op, &a, &a, e)?
+                            }
+                        };
+                        self.stack[base + reg(dst)] = value;
+                    }
+                    Instruction::Not { dst, src } => {
+                        let value = Value::Bool(self.stack[base + reg(src)].is_falsy());
+                        self.stack[base + reg(dst)] = value;
+                    }
+                    Instruction::Len { dst, src } => {
+                        let value = self.stack[base + reg(src)].clone();
+                        save_pc!();
+                        let value = self.len(&value)?;
+                        self.stack[base + reg(dst)] = value;
+                    }
+                    Instruction::Concat { dst, first, last } => {
+                        let values = &self.stack[base + reg(first)..=base + reg(last)];
+                        let value = match to_lua_strs(values) {
+                            Some(strings) => {
+                                save_pc!();
+                                Value::Str(self.join_strings(&strings)?)
+                            }
+                            None => {
+                                let values = values.to_vec();
+                                save_pc!();
+                                self.concat(values)?
+                            }
+                        };
+                        self.stack[base + reg(dst)] = value;
+                    }
+                    Instruction::Jmp { close, offset } => {
+                        // leaving the scope of variables from R(close - 1)
+                        if close > 0 {
+                            self.close_up_values(base + reg(close) - 1);
+                        }
+                        if close > 0 && !self.frames.last().unwrap().tbc.is_empty() {
+                            save_pc!();
+                            self.close_variables(base + reg(close) - 1, None)?;
+                        }
+                        pc = (pc as i64 + offset as i64) as usize;
+                    }
+                    Instruction::Eq {
+                        expect,
+                        left,
+                        right,
+                    } => {
+                        let (a, b) = (rk!(left), rk!(right));
+                        let equal = if a == b {
+                            true
+                        } else if has_eq_meta(a, b) {
+                            let (a, b) = (a.clone(), b.clone());
+                            save_pc!();
+                            self.equals(&a, &b)?
+                        } else {
+                            false
+                        };
+                        if equal != (expect != 0) {
+                            pc += 1;
+                        }
+                    }
+                    Instruction::Lt {
+                        expect,
+                        left,
+                        right,
+                    }
+                    | Instruction::Le {
+                        expect,
+                        left,
+                        right,
+                    } => {
+                        let (a, b) = (rk!(left), rk!(right));
+                        let event = match instruction.get_op() {
+                            OpCode::Lt => MetaMethod::Lt,
+                            _ => MetaMethod::Le,
+                        };
+                        let result = match compare(event, a, b) {
+                            Ok(result) => result,
+                            Err(e) => {
+                                let (a, b) = (a.clone(), b.clone());
+                                save_pc!();
+                                self.compare_meta(event, &a, &b, e)?
+                            }
+                        };
+                        if result != (expect != 0) {
+                            pc += 1;
+                        }
+                    }
+                    Instruction::Test { src, expect } => {
+                        if self.stack[base + reg(src)].is_falsy() == (expect != 0) {
+                            pc += 1;
+                        }
+                    }
+                    Instruction::TestSet { dst, src, expect } => {
+                        let value = &self.stack[base + reg(src)];
+                        if value.is_falsy() == (expect != 0) {
+                            pc += 1;
+                        } else {
+                            self.stack[base + reg(dst)] = value.clone();
+                        }
+                    }
+                    // tail calls are calls followed by returning all their results
+                    Instruction::Call {
+                        func,
+                        args,
+                        results,
+                    } => {
+                        let slot = base + reg(func);
+                        let nargs = if args == 0 {
+                            self.top - slot - 1
+                        } else {
+                            args as usize - 1
+                        };
+                        let results = match results {
+                            0 => None,
+                            results => Some(results as usize - 1),
+                        };
+                        save_pc!();
+                        if let Err(e) = self.precall(slot, nargs, results, false) {
+                            return self.fail(depth, e);
+                        }
+                        if self.yielding.is_some() {
+                            return Ok(Vec::new());
+                        }
+                        break;
+                    }
+                    Instruction::TailCall { func, args, .. } => {
+                        let mut slot = base + reg(func);
+                        let nargs = if args == 0 {
+                            self.top - slot - 1
+                        } else {
+                            args as usize - 1
+                        };
+                        save_pc!();
+                        // a lua function replaces the caller, so the frames don't grow. native
+                        // functions, and callers which have to run code after the call, get
+                        // the usual call followed by the return
+                        let frame = self.frames.last().unwrap();
+                        let tail_call = matches!(self.stack[slot], Value::Function(_))
+                            && frame.tbc.is_empty()
+                            && !frame.hooked;
+                        let mut results = None;
+                        if tail_call {
+                            self.close_up_values(base);
+                            for i in 0..=nargs {
+                                self.stack[base - 1 + i] = self.stack[slot + i].clone();
+                            }
+                            slot = base - 1;
+                            results = self.frames.pop().unwrap().results;
+                        }
+                        if let Err(e) = self.precall(slot, nargs, results, tail_call) {
+                            return self.fail(depth, e);
+                        }
+                        if self.yielding.is_some() {
+                            return Ok(Vec::new());
+                        }
+                        break;
+                    }
+                    Instruction::Return { first, count } => {
+                        let first = base + reg(first);
+                        let end = if count == 0 {
+                            self.top
+                        } else {
+                            first + count as usize - 1
+                        };
+                        if !self.frames.last().unwrap().tbc.is_empty() {
+                            save_pc!();
+                            self.close_variables(base, None)?;
+                        }
+                        if self.debug_hook.is_some() {
+                            save_pc!();
+                            self.call_hook(HookEvent::Return)?;
+                        }
+                        self.close_up_values(base);
+                        let frame = self.frames.pop().unwrap();
+                        if frame.hooked {
+                            let ptr = Rc::as_ptr(&frame.closure);
+                            if let Some((
+                                _,
+                                CallHooks {
+                                    after: Some(after), ..
+                                },
+                            )) = self.hooks.get_mut(&ptr)
+                            {
+                                after(&self.stack[first..end]);
+                            }
+                        }
+                        if self.frames.len() == depth {
+                            self.top = 0;
+                            return Ok(self.stack[first..end].to_vec());
+                        }
+                        // results replace the function and its args
+                        let slot = base - 1;
+                        let n = end - first;
+                        for i in 0..n {
+                            self.stack[slot + i] = self.stack[first + i].clone();
+                        }
+                        match frame.results {
+                            Some(results) => {
+                                for i in n..results {
+                                    self.stack[slot + i] = Value::Nil;
+                                }
+                            }
+                            None => self.top = slot + n,
+                        }
+                        break;
+                    }
+                    Instruction::ForLoop { base: a, offset } => {
+                        let a = base + reg(a);
+                        let next = match (&self.stack[a], &self.stack[a + 1], &self.stack[a + 2]) {
+                            // integer loops count down the iterations left in place of the limit
+                            (Value::Int(i), Value::Int(count), Value::Int(step)) => {
+                                let count = *count as u64;
+                                if count > 0 {
+                                    let i = i.wrapping_add(*step);
+                                    self.stack[a + 1] = Value::Int((count - 1) as IntType);
+                                    Some(Value::Int(i))
+                                } else {
+                                    None
+                                }
+                            }
+                            (Value::Float(i), Value::Float(limit), Value::Float(step)) => {
+                                let i = i + step;
+                                if (*step > 0.0 && i <= *limit) || (*step <= 0.0 && i >= *limit) {
+                                    Some(Value::Float(i))
+                                } else {
+                                    None
+                                }
+                            }
+                            _ => None,
+                        };
+                        if let Some(i) = next {
+                            self.stack[a] = i.clone();
+                            self.stack[a + 3] = i;
+                            pc = (pc as i64 + offset as i64) as usize;
+                        }
+                    }
+                    Instruction::ForPrep { base: a, offset } => {
+                        let a = base + reg(a);
+                        // a loop which runs goes on with its first iteration, as ForLoop would,
+                        // else it skips over ForLoop
+                        match for_prep(&self.stack[a], &self.stack[a + 1], &self.stack[a + 2]) {
+                            Ok(Some((init, limit, step))) => {
+                                self.stack[a] = init.clone();
+                                self.stack[a + 1] = limit;
+                                self.stack[a + 2] = step;
+                                self.stack[a + 3] = init;
+                            }
+                            Ok(None) => pc = (pc as i64 + offset as i64) as usize + 1,
+                            Err(e) => return self.fail(depth, e),
+                        }
+                    }
+                    Instruction::TForCall { base: a, results } => {
+                        let a = base + reg(a);
+                        for i in 0..3 {
+                            self.stack[a + 3 + i] = self.stack[a + i].clone();
+                        }
+                        save_pc!();
+                        if let Err(e) = self.precall(a + 3, 2, Some(results as usize), false) {
+                            return self.fail(depth, e);
+                        }
+                        if self.yielding.is_some() {
+                            return Ok(Vec::new());
+                        }
+                        break;
+                    }
+                    Instruction::TForLoop { base: a, offset } => {
+                        let a = base + reg(a);
+                        if !self.stack[a + 1].is_nil() {
+                            self.stack[a] = self.stack[a + 1].clone();
+                            pc = (pc as i64 + offset as i64) as usize;
+                        }
+                    }
+                    Instruction::SetList {
+                        table,
+                        count,
+                        block,
+                    } => {
+                        let a = base + reg(table);
+                        let count = if count == 0 {
+                            self.top - a - 1
+                        } else {
+                            count as usize
+                        };
+                        let block = if block == 0 {
+                            pc += 1;
+                            code[pc - 1].get_arg_Ax()
+                        } else {
+                            block
+                        } as usize;
+                        if let Value::Table(t) = &self.stack[a] {
+                            let before = t.borrow().memory();
+                            let mut t = t.borrow_mut();
+                            for i in 1..=count {
+                                let key = ((block - 1) * FIELDS_PER_FLUSH + i) as IntType;
+                                t.set(Value::Int(key), self.stack[a + i].clone());
+                            }
+                            let grown = t.memory().saturating_sub(before);
+                            drop(t);
+                            save_pc!();
+                            self.allocate(grown)?;
+                        }
+                    }
+                    Instruction::Closure { dst, proto: index } => {
+                        let child = proto.children[index as usize].clone();
+                        let up_values = child
+                            .proto
+                            .up_vars
+                            .iter()
+                            .map(|up_val| {
+                                if up_val.in_stack {
+                                    self.find_up_value(base + reg(up_val.index))
+                                } else {
+                                    closure.up_values[up_val.index as usize].clone()
+                                }
+                            })
+                            .collect();
+                        let closure = Rc::new(Closure {
+                            proto: child,
+                            up_values,
+                        });
+                        let size = closure_memory(&closure);
+                        self.heap.track_closure(&closure);
+                        self.stack[base + reg(dst)] = Value::Function(closure);
+                        self.check_garbage();
+                        save_pc!();
+                        self.allocate(size)?;
+                    }
+                    Instruction::Vararg { dst, count } => {
+                        let varargs = std::mem::take(&mut self.frames.last_mut().unwrap().varargs);
+                        let dst = base + reg(dst);
+                        let n = if count == 0 {
+                            self.ensure_stack(dst + varargs.len());
+                            self.top = dst + varargs.len();
+                            varargs.len()
+                        } else {
+                            count as usize - 1
+                        };
+                        for i in 0..n {
+                            self.stack[dst + i] = varargs.get(i).cloned().unwrap_or(Value::Nil);
+                        }
+                        self.frames.last_mut().unwrap().varargs = varargs;
+                    }
+                    Instruction::ExtraArg { .. } => unreachable!(),
+                    Instruction::Tbc { src } => {
+                        let slot = base + reg(src);
+                        // nil and false are ignored
+                        if self.stack[slot].is_truthy() {
+                            if self
+                                .meta_method(&self.stack[slot], MetaMethod::Close)
+                                .is_none()
+                            {
+                                save_pc!();
+                                let name = proto.proto.local_var_name(src, pc - 1).unwrap_or("?");
+                                return Err(error(format!(
+                                    "variable '{}' got a non-closable value",
+                                    name
+                                )));
+                            }
+                            self.frames.last_mut().unwrap().tbc.push(slot);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // unwind frames of the call and return the error
+    fn fail<T>(&mut self, depth: usize, e: RuntimeError) -> RuntimeResult<T> {
+        Err(self.unwind(depth, e))
+    }
+
+    // trace the error where it's raised, run the message handler and drop the frames of the call,
+    // closing their variables. an error of `__close` replaces the error
+    fn unwind(&mut self, depth: usize, mut e: RuntimeError) -> RuntimeError {
+        if e.0.traceback.is_none() {
+            // errors of the vm and of natives called by lua functions get the current line
+            if !e.0.positioned && self.frames.len() > depth {
+                e.0.message.insert_str(0, &self.position(1));
+            }
+            e.0.positioned = true;
+            e.0.traceback = Some(self.traceback(1));
+        }
+        self.handle_error(&e);
+        while self.frames.len() > depth {
+            while !self.frames.last().unwrap().tbc.is_empty() {
+                if let Err(close_error) = self.close_variables(0, Some(e.value())) {
+                    e = close_error;
+                }
+            }
+            let frame = self.frames.pop().unwrap();
+            self.close_up_values(frame.base);
+        }
+        e
+    }
+
+    // call `__close` of the variables of the running function from `slot`, the last marked
+    // first, with the error if the scope is left by one
+    fn close_variables(&mut self, slot: usize, error: Option<Value>) -> RuntimeResult<()> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let var = match frame.tbc.last() {
+                Some(var) if *var >= slot => *var,
+                _ => return Ok(()),
+            };
+            frame.tbc.pop();
+            let value = self.stack[var].clone();
+            let close = self
+                .meta_method(&value, MetaMethod::Close)
+                .unwrap_or(Value::Nil);
+            self.call(&close, &[value, error.clone().unwrap_or(Value::Nil)])?;
+        }
+    }
+}
+
+fn has_finalizer(metatable: &Option<TableRef>) -> bool {
+    metatable
+        .as_ref()
+        .is_some_and(|meta| !meta.borrow().get_str("__gc").is_nil())
+}
+
+// sizes of tables are encoded as "floating point bytes", eeeeexxx is (1xxx) * 2^(eeeee - 1)
+fn error(msg: String) -> RuntimeError {
+    RuntimeError::new(msg)
+}
+
+fn closure_memory(closure: &Closure) -> usize {
+    std::mem::size_of::<Closure>()
+        + closure.up_values.len() * std::mem::size_of::<RefCell<UpValue>>()
+}
+
+fn type_error(op: &str, value: &Value) -> RuntimeError {
+    error(format!("attempt to {} a {} value", op, value.type_name()))
+}
+
+// strings and numbers, which can be concatenated
+pub(crate) fn to_lua_str(value: &Value) -> Option<LuaStr> {
+    match value {
+        Value::Str(s) => Some(s.clone()),
+        Value::Int(i) => Some(LuaStr::from(i.to_string().as_str())),
+        Value::Float(f) => Some(LuaStr::from(float_to_str(*f).as_str())),
+        _ => None,
+    }
+}
+
+// none if some of the values aren't strings or numbers
+fn to_lua_strs(values: &[Value]) -> Option<Vec<LuaStr>> {
+    values.iter().map(to_lua_str).collect()
+}
+
+fn arith_error(a: &Value, b: &Value) -> RuntimeError {
+    let value = match a {
+        Value::Int(_) | Value::Float(_) => b,
+        _ => a,
+    };
+    error(format!(
+        "attempt to perform arithmetic on a {} value",
+        value.type_name()
+    ))
+}
+
+fn to_float(value: &Value) -> Option<FloatType> {
+    match value {
+        Value::Int(i) => Some(*i as FloatType),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+// integer operand of bitwise operators, floats must have an exact integer value
+fn to_int(value: &Value) -> RuntimeResult<IntType> {
+    match value {
+        Value::Int(i) => Ok(*i),
+        Value::Float(f) => float_to_int(*f)
+            .ok_or_else(|| error("number has no integer representation".to_string())),
+        value => Err(error(format!(
+            "attempt to perform bitwise operation on a {} value",
+            value.type_name()
+        ))),
+    }
+}
+
+// numbers, and strings converted to numbers as lua coerces them
+fn to_number(value: &Value) -> Option<Value> {
+    match value {
+        Value::Int(_) | Value::Float(_) => Some(value.clone()),
+        Value::Str(s) => str_to_number(s.as_bytes()),
+        _ => None,
+    }
+}
+
+// arithmetic and bitwise operators on numbers, and on strings converted to numbers. unary
+// ones ignore `b`
+fn arith(op: OpCode, a: &Value, b: &Value) -> RuntimeResult<Value> {
+    if let (Value::Str(_), _) | (_, Value::Str(_)) = (a, b) {
+        if let (Some(a), Some(b)) = (to_number(a), to_number(b)) {
+            return arith(op, &a, &b);
+        }
+    }
+    match op {
+        OpCode::BAdd | OpCode::BOr | OpCode::BXor | OpCode::Shl | OpCode::Shr | OpCode::BNot => {
+            let (a, b) = (to_int(a)?, to_int(b)?);
+            return Ok(Value::Int(match op {
+                OpCode::BAdd => a & b,
+                OpCode::BOr => a | b,
+                OpCode::BXor => a ^ b,
+                OpCode::Shl => shift_left(a, b),
+                OpCode::Shr => shift_left(a, b.wrapping_neg()),
+                _ => !a,
+            }));
+        }
+        OpCode::Unm => {
+            return match a {
+                Value::Int(i) => Ok(Value::Int(i.wrapping_neg())),
+                Value::Float(f) => Ok(Value::Float(-f)),
+                _ => Err(arith_error(a, a)),
+            }
+        }
+        _ => (),
+    }
+    if let (Value::Int(a), Value::Int(b)) = (a, b) {
+        let (a, b) = (*a, *b);
+        match op {
+            OpCode::Add => return Ok(Value::Int(a.wrapping_add(b))),
+            OpCode::Sub => return Ok(Value::Int(a.wrapping_sub(b))),
+            OpCode::Mul => return Ok(Value::Int(a.wrapping_mul(b))),
+            OpCode::IDiv | OpCode::Mod if b == 0 => {
+                let op = if op == OpCode::IDiv { "//" } else { "%" };
+                return Err(error(format!("attempt to perform 'n{}0'", op)));
+            }
+            OpCode::IDiv => return Ok(Value::Int(int_idiv(a, b))),
+            OpCode::Mod => return Ok(Value::Int(int_mod(a, b))),
+            _ => (),
+        }
+    }
+    let (x, y) = match (to_float(a), to_float(b)) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return Err(arith_error(a, b)),
+    };
+    Ok(Value::Float(match op {
+        OpCode::Add => x + y,
+        OpCode::Sub => x - y,
+        OpCode::Mul => x * y,
+        OpCode::Div => x / y,
+        OpCode::Pow => x.powf(y),
+        OpCode::IDiv => float_idiv(x, y),
+        OpCode::Mod => float_mod(x, y),
+        _ => unreachable!(),
+    }))
+}
+
+// how an integer compares to a float, exactly even where the float can't represent it.
+// none for NaN
+fn compare_int_float(i: IntType, f: FloatType) -> Option<Ordering> {
+    // 2^63 is out of range, while -2^63 is exact
+    let bound = (2.0 as FloatType).powi(63);
+    if f.is_nan() {
+        None
+    } else if f >= bound {
+        Some(Ordering::Less)
+    } else if f < -bound {
+        Some(Ordering::Greater)
+    } else {
+        let floor = f.floor();
+        match i.cmp(&(floor as IntType)) {
+            Ordering::Equal if f > floor => Some(Ordering::Less),
+            ordering => Some(ordering),
+        }
+    }
+}
+
+fn compare_error(a: &Value, b: &Value) -> RuntimeError {
+    if a.type_name() == b.type_name() {
+        error(format!("attempt to compare two {} values", a.type_name()))
+    } else {
+        error(format!(
+            "attempt to compare {} with {}",
+            a.type_name(),
+            b.type_name()
+        ))
+    }
+}
+
+// comparison of numbers or strings, `event` is `Lt` or `Le`
+fn compare(event: MetaMethod, a: &Value, b: &Value) -> RuntimeResult<bool> {
+    let lt = event == MetaMethod::Lt;
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(if lt { a < b } else { a <= b }),
+        (Value::Str(a), Value::Str(b)) => {
+            let (a, b) = (a.as_bytes(), b.as_bytes());
+            Ok(if lt { a < b } else { a <= b })
+        }
+        (Value::Int(i), Value::Float(f)) => Ok(ordered(lt, compare_int_float(*i, *f))),
+        (Value::Float(f), Value::Int(i)) => Ok(ordered(
+            lt,
+            compare_int_float(*i, *f).map(Ordering::reverse),
+        )),
+        _ => match (to_float(a), to_float(b)) {
+            (Some(x), Some(y)) => Ok(if lt { x < y } else { x <= y }),
+            _ => Err(compare_error(a, b)),
+        },
+    }
+}
+
+// whether an ordering is less than, or less than or equal if not `lt`
+fn ordered(lt: bool, ordering: Option<Ordering>) -> bool {
+    match ordering {
+        Some(Ordering::Less) => true,
+        Some(Ordering::Equal) => !lt,
+        _ => false,
+    }
+}
+
+// values of the same type which may have different identities but be equal by `__eq`
+fn has_eq_meta(a: &Value, b: &Value) -> bool {
+    matches!(
+        (a, b),
+        (Value::Table(_), Value::Table(_)) | (Value::UserData(_), Value::UserData(_))
+    )
+}
+
+// init, limit and step of a numeric for loop, none if it doesn't run. integer loops have the
+// number of iterations after the first in place of the limit, like lua 5.4, so they can run up
+// to the ends of the integer range without overflowing the index
+fn for_prep(
+    init: &Value,
+    limit: &Value,
+    step: &Value,
+) -> RuntimeResult<Option<(Value, Value, Value)>> {
+    let check = |value: &Value, what: &str| match value {
+        Value::Int(_) | Value::Float(_) => Ok(()),
+        _ => Err(error(format!("'for' {} must be a number", what))),
+    };
+    check(init, "initial value")?;
+    check(limit, "limit")?;
+    check(step, "step")?;
+    if let (Value::Int(i), Value::Int(s)) = (init, step) {
+        if *s == 0 {
+            return Err(error("'for' step is zero".to_string()));
+        }
+        // float limits are clipped to integers the loop can reach
+        let limit = match limit {
+            Value::Int(l) => *l,
+            Value::Float(l) if l.is_nan() => return Ok(None),
+            Value::Float(l) => {
+                let l = if *s > 0 { l.floor() } else { l.ceil() };
+                match float_to_int(l) {
+                    Some(l) => l,
+                    None if l > 0.0 && *s > 0 => IntType::MAX,
+                    None if l < 0.0 && *s < 0 => IntType::MIN,
+                    // beyond the integers on the other side of the start
+                    None => return Ok(None),
+                }
+            }
+            _ => unreachable!(),
+        };
+        if (*s > 0 && *i > limit) || (*s < 0 && *i < limit) {
+            return Ok(None);
+        }
+        let count = if *s > 0 {
+            (limit as u64).wrapping_sub(*i as u64) / *s as u64
+        } else {
+            // `s + 1` avoids negating the min integer
+            (*i as u64).wrapping_sub(limit as u64) / ((-(*s + 1)) as u64 + 1)
+        };
+        return Ok(Some((
+            Value::Int(*i),
+            Value::Int(count as IntType),
+            Value::Int(*s),
+        )));
+    }
+    let (i, l, s) = (
+        to_float(init).unwrap(),
+        to_float(limit).unwrap(),
+        to_float(step).unwrap(),
+    );
+    if s == 0.0 {
+        return Err(error("'for' step is zero".to_string()));
+    }
+    if !((s > 0.0 && i <= l) || (s < 0.0 && i >= l)) {
+        return Ok(None);
+    }
+    Ok(Some((Value::Float(i), Value::Float(l), Value::Float(s))))
+}
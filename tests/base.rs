@@ -0,0 +1,481 @@
+mod common;
+
+mod base_tests {
+    use crate::common::closure;
+    use rslua::base;
+    use rslua::consts::Const;
+    use rslua::opcodes::*;
+    use rslua::proto::ProtoBuilder;
+    use rslua::table::Table;
+    use rslua::types::{FloatType, IntType};
+    use rslua::value::Value;
+    use rslua::vm::{NativeFunction, RuntimeError, Vm};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // function(a) error(a) end, with the call on line 3
+    fn raise(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(3).line(3);
+        let error = builder.constant(Const::Str("error".to_string()));
+        builder.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(error),
+        });
+        builder.emit(Instruction::Move { dst: 2, src: 0 });
+        builder.emit(Instruction::Call {
+            func: 1,
+            args: 2,
+            results: 1,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        closure(vm, builder)
+    }
+
+    #[test]
+    fn pcall() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let pcall = vm.get_global("pcall");
+        let error = vm.get_global("error");
+        assert_eq!(
+            vm.call(&pcall, &[pcall.clone(), error.clone(), Value::str("boom")]),
+            Ok(vec![
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::str("boom")
+            ])
+        );
+        assert_eq!(
+            vm.call(&pcall, &[error.clone(), Value::str("boom")]),
+            Ok(vec![Value::Bool(false), Value::str("boom")])
+        );
+        // errors of the vm
+        assert_eq!(
+            vm.call(&pcall, &[Value::Int(1)]),
+            Ok(vec![
+                Value::Bool(false),
+                Value::str("attempt to call a number value")
+            ])
+        );
+        assert_eq!(
+            vm.call(&pcall, &[]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'pcall' (value expected)"
+            ))
+        );
+    }
+
+    #[test]
+    fn error_positions() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let pcall = vm.get_global("pcall");
+        let raise = raise(&mut vm);
+        assert_eq!(
+            vm.call(&pcall, &[raise.clone(), Value::str("boom")]),
+            Ok(vec![Value::Bool(false), Value::str("main.0:3: boom")])
+        );
+        assert_eq!(
+            vm.call(&raise, &[Value::str("boom")]),
+            Err(RuntimeError::new("main.0:3: boom"))
+        );
+        // level 0 adds no position
+        let error = vm.get_global("error");
+        assert_eq!(
+            vm.call(&pcall, &[error, Value::str("boom"), Value::Int(0)]),
+            Ok(vec![Value::Bool(false), Value::str("boom")])
+        );
+        // the vm can still run after unwinding
+        assert_eq!(
+            vm.call(&pcall, &[raise, Value::Int(1)]),
+            Ok(vec![Value::Bool(false), Value::Int(1)])
+        );
+    }
+
+    #[test]
+    fn error_values() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let pcall = vm.get_global("pcall");
+        let raise = raise(&mut vm);
+        let t = Value::new_table();
+        assert_eq!(
+            vm.call(&pcall, &[raise.clone(), t.clone()]),
+            Ok(vec![Value::Bool(false), t.clone()])
+        );
+        let e = vm.call(&raise, std::slice::from_ref(&t)).unwrap_err();
+        assert_eq!(e.message(), "(error object is a table value)");
+        assert_eq!(e.value(), t);
+        let e = vm.call(&raise, &[Value::Float(1.5)]).unwrap_err();
+        assert_eq!(e.message(), "1.5");
+        assert_eq!(e.value(), Value::Float(1.5));
+        assert_eq!(
+            vm.call(&pcall, &[raise, Value::Nil]),
+            Ok(vec![Value::Bool(false), Value::Nil])
+        );
+        // a message like the one of an earlier value is only a string
+        let error = vm.get_global("error");
+        let message = Value::str("(error object is a table value)");
+        assert_eq!(
+            vm.call(&pcall, &[error, message.clone()]),
+            Ok(vec![Value::Bool(false), message])
+        );
+    }
+
+    // function(m) local ok, e = pcall(error, "h", 2) return e end, the message of `error` has
+    // the position of the function below the handler
+    fn handler(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(5).line(7);
+        let pcall = builder.constant(Const::Str("pcall".to_string()));
+        let error = builder.constant(Const::Str("error".to_string()));
+        let h = builder.constant(Const::Str("h".to_string()));
+        let two = builder.constant(Const::Int(2));
+        builder.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(pcall),
+        });
+        builder.emit(Instruction::GetTabUp {
+            dst: 2,
+            up: 0,
+            key: rk_as_k(error),
+        });
+        builder.emit(Instruction::LoadK { dst: 3, k: h });
+        builder.emit(Instruction::LoadK { dst: 4, k: two });
+        builder.emit(Instruction::Call {
+            func: 1,
+            args: 4,
+            results: 3,
+        });
+        builder.emit(Instruction::Return { first: 2, count: 2 });
+        closure(vm, builder)
+    }
+
+    #[test]
+    fn xpcall() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let xpcall = vm.get_global("xpcall");
+        let raise = raise(&mut vm);
+        let handler = handler(&mut vm);
+        // the handler runs before unwinding, on top of the frame of the error
+        assert_eq!(
+            vm.call(
+                &xpcall,
+                &[raise.clone(), handler.clone(), Value::str("boom")]
+            ),
+            Ok(vec![Value::Bool(false), Value::str("main.0:3: h")])
+        );
+        // called by xpcall, there's no lua function below
+        assert_eq!(
+            vm.call(&xpcall, &[handler.clone(), raise.clone()]),
+            Ok(vec![Value::Bool(true), Value::str("h")])
+        );
+        // errors of the handler
+        assert_eq!(
+            vm.call(&xpcall, &[raise.clone(), raise.clone(), Value::str("boom")]),
+            Ok(vec![
+                Value::Bool(false),
+                Value::str("error in error handling")
+            ])
+        );
+        assert_eq!(
+            vm.call(&xpcall, &[raise, Value::Nil]),
+            Err(RuntimeError::new(
+                "bad argument #2 to 'xpcall' (function expected)"
+            ))
+        );
+        // errors caught inside the protected call don't reach the handler
+        let pcall = vm.get_global("pcall");
+        let error = vm.get_global("error");
+        assert_eq!(
+            vm.pcall(&pcall, &[error, Value::Int(1)], Some(handler)),
+            Ok(vec![Value::Bool(false), Value::Int(1)])
+        );
+    }
+
+    #[test]
+    fn tonumber() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let tonumber = vm.get_global("tonumber");
+        let mut call = |args: &[Value]| vm.call(&tonumber, args);
+        let cases = [
+            (" 10 ", Value::Int(10)),
+            ("-0x10", Value::Int(-16)),
+            ("0x1p4", Value::Float(16.0)),
+            ("1e2", Value::Float(100.0)),
+            (".5", Value::Float(0.5)),
+            ("9223372036854775808", Value::Float(9223372036854775808.0)),
+            ("-9223372036854775808", Value::Int(i64::MIN)),
+            ("0xffffffffffffffff", Value::Int(-1)),
+            ("inf", Value::Nil),
+            ("nan", Value::Nil),
+            ("1e", Value::Nil),
+            ("0x", Value::Nil),
+            ("1 2", Value::Nil),
+            ("", Value::Nil),
+        ];
+        for (s, n) in cases.iter() {
+            assert_eq!((*s, call(&[Value::str(s)])), (*s, Ok(vec![n.clone()])));
+        }
+        assert_eq!(call(&[Value::Float(1.5)]), Ok(vec![Value::Float(1.5)]));
+        assert_eq!(call(&[Value::Bool(true)]), Ok(vec![Value::Nil]));
+        assert_eq!(
+            call(&[]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'tonumber' (value expected)"
+            ))
+        );
+
+        // with a base
+        let cases = [
+            ("ff", 16, Value::Int(255)),
+            (" -zz ", 36, Value::Int(-1295)),
+            ("777", 8, Value::Int(511)),
+            ("8", 8, Value::Nil),
+            ("1.0", 10, Value::Nil),
+        ];
+        for (s, base, n) in cases.iter() {
+            assert_eq!(
+                call(&[Value::str(s), Value::Int(*base)]),
+                Ok(vec![n.clone()])
+            );
+        }
+        assert_eq!(
+            call(&[Value::str("1"), Value::Int(37)]),
+            Err(RuntimeError::new(
+                "bad argument #2 to 'tonumber' (base out of range)"
+            ))
+        );
+        assert_eq!(
+            call(&[Value::Int(1), Value::Int(10)]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'tonumber' (string expected, got number)"
+            ))
+        );
+    }
+
+    fn native(name: &str, result: Value) -> Value {
+        let func = NativeFunction::new(name, move |_, _| Ok(vec![result.clone()]));
+        Value::Native(Rc::new(func))
+    }
+
+    #[test]
+    fn raw_access() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let (rawget, rawset) = (vm.get_global("rawget"), vm.get_global("rawset"));
+        let (rawequal, rawlen) = (vm.get_global("rawequal"), vm.get_global("rawlen"));
+        let error = |msg: &str| Err(RuntimeError::new(msg));
+
+        // all metamethods are ignored
+        let index = Rc::new(RefCell::new(Table::new()));
+        index.borrow_mut().set_str("x", Value::Int(1));
+        let mut meta = Table::new();
+        meta.set_str("__index", Value::Table(index.clone()));
+        meta.set_str("__newindex", Value::Table(index));
+        meta.set_str("__len", native("len", Value::Int(99)));
+        meta.set_str("__eq", native("eq", Value::Bool(true)));
+        let meta = Rc::new(RefCell::new(meta));
+        let object = |vm: &mut Vm| {
+            let mut t = Table::new();
+            for i in 1..=3 {
+                t.set_int(i, Value::Int(i));
+            }
+            let t = Rc::new(RefCell::new(t));
+            vm.set_metatable(&t, Some(meta.clone()));
+            Value::Table(t)
+        };
+        let (a, b) = (object(&mut vm), object(&mut vm));
+        let x = Value::str("x");
+        assert_eq!(vm.index(&a, &x), Ok(Value::Int(1)));
+        assert_eq!(
+            vm.call(&rawget, &[a.clone(), x.clone()]),
+            Ok(vec![Value::Nil])
+        );
+        assert_eq!(
+            vm.call(&rawset, &[a.clone(), x.clone(), Value::Int(2)]),
+            Ok(vec![a.clone()])
+        );
+        assert_eq!(vm.index(&a, &x), Ok(Value::Int(2)));
+        assert_eq!(vm.index(&b, &x), Ok(Value::Int(1)));
+        assert_eq!(vm.len(&a), Ok(Value::Int(99)));
+        assert_eq!(
+            vm.call(&rawlen, std::slice::from_ref(&a)),
+            Ok(vec![Value::Int(3)])
+        );
+        assert_eq!(
+            vm.call(&rawlen, &[Value::str("abcd")]),
+            Ok(vec![Value::Int(4)])
+        );
+        assert_eq!(vm.equals(&a, &b), Ok(true));
+        assert_eq!(
+            vm.call(&rawequal, &[a.clone(), b.clone()]),
+            Ok(vec![Value::Bool(false)])
+        );
+        assert_eq!(
+            vm.call(&rawequal, &[a.clone(), a.clone()]),
+            Ok(vec![Value::Bool(true)])
+        );
+
+        assert_eq!(
+            vm.call(&rawget, &[Value::Int(1), x.clone()]),
+            error("bad argument #1 to 'rawget' (table expected, got number)")
+        );
+        assert_eq!(
+            vm.call(&rawset, &[a.clone(), x]),
+            error("bad argument #3 to 'rawset' (value expected)")
+        );
+        assert_eq!(
+            vm.call(&rawset, &[a.clone(), Value::Nil, Value::Int(1)]),
+            error("index is nil")
+        );
+        assert_eq!(
+            vm.call(&rawequal, &[a]),
+            error("bad argument #2 to 'rawequal' (value expected)")
+        );
+        assert_eq!(
+            vm.call(&rawlen, &[Value::Int(1)]),
+            error("bad argument #1 to 'rawlen' (table or string expected)")
+        );
+    }
+
+    #[test]
+    fn tostring() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let tostring = vm.get_global("tostring");
+        let cases = [
+            (Value::Nil, "nil"),
+            (Value::Bool(false), "false"),
+            (Value::Int(IntType::MIN), "-9223372036854775808"),
+            (Value::Float(-0.0), "-0.0"),
+            (Value::Float(1e15), "1e+15"),
+            (Value::Float(FloatType::INFINITY), "inf"),
+            (Value::str("s"), "s"),
+        ];
+        for (value, s) in cases.iter() {
+            assert_eq!(
+                vm.call(&tostring, std::slice::from_ref(value)),
+                Ok(vec![Value::str(s)])
+            );
+        }
+
+        let t = Rc::new(RefCell::new(Table::new()));
+        let mut meta = Table::new();
+        meta.set_str("__tostring", native("tostring", Value::str("object")));
+        vm.set_metatable(&t, Some(Rc::new(RefCell::new(meta))));
+        assert_eq!(
+            vm.call(&tostring, &[Value::Table(t)]),
+            Ok(vec![Value::str("object")])
+        );
+        assert_eq!(
+            vm.call(&tostring, &[]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'tostring' (value expected)"
+            ))
+        );
+    }
+
+    #[test]
+    fn select() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let select = vm.get_global("select");
+        let mut call = |args: &[Value]| vm.call(&select, args);
+        let (a, b, c) = (Value::str("a"), Value::Nil, Value::Int(3));
+        let abc = |n: Value| vec![n, a.clone(), b.clone(), c.clone()];
+        assert_eq!(call(&abc(Value::str("#"))), Ok(vec![Value::Int(3)]));
+        assert_eq!(call(&[Value::str("#")]), Ok(vec![Value::Int(0)]));
+        assert_eq!(call(&abc(Value::Int(1))), Ok(abc(Value::Nil)[1..].to_vec()));
+        assert_eq!(
+            call(&abc(Value::Float(2.0))),
+            Ok(vec![b.clone(), c.clone()])
+        );
+        assert_eq!(call(&abc(Value::str("3"))), Ok(vec![c.clone()]));
+        assert_eq!(call(&abc(Value::Int(4))), Ok(vec![]));
+        assert_eq!(call(&abc(Value::Int(-1))), Ok(vec![c.clone()]));
+        assert_eq!(
+            call(&abc(Value::Int(-3))),
+            Ok(abc(Value::Nil)[1..].to_vec())
+        );
+        let error = |msg: &str| Err(RuntimeError::new(msg));
+        assert_eq!(
+            call(&abc(Value::Int(-4))),
+            error("bad argument #1 to 'select' (index out of range)")
+        );
+        assert_eq!(
+            call(&abc(Value::Int(0))),
+            error("bad argument #1 to 'select' (index out of range)")
+        );
+        assert_eq!(
+            call(&abc(Value::Float(1.5))),
+            error("bad argument #1 to 'select' (number has no integer representation)")
+        );
+        assert_eq!(
+            call(&abc(Value::Bool(true))),
+            error("bad argument #1 to 'select' (number expected, got boolean)")
+        );
+    }
+
+    // function(...) return select('#', ...) end, as g
+    // function(a, ...) return g(...) end, both with tail calls
+    fn count_varargs(vm: &mut Vm) -> Value {
+        let mut g = ProtoBuilder::new();
+        g.params(0, true).stack_size(3);
+        let select = g.constant(Const::Str("select".to_string()));
+        let hash = g.constant(Const::Str("#".to_string()));
+        g.emit(Instruction::GetTabUp {
+            dst: 0,
+            up: 0,
+            key: rk_as_k(select),
+        });
+        g.emit(Instruction::LoadK { dst: 1, k: hash });
+        g.emit(Instruction::Vararg { dst: 2, count: 0 });
+        g.emit(Instruction::TailCall {
+            func: 0,
+            args: 0,
+            results: 0,
+        });
+        g.emit(Instruction::Return { first: 0, count: 0 });
+        let g = closure(vm, g);
+        vm.set_global("g", g);
+
+        let mut f = ProtoBuilder::new();
+        f.params(1, true).stack_size(3);
+        let g = f.constant(Const::Str("g".to_string()));
+        f.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(g),
+        });
+        f.emit(Instruction::Vararg { dst: 2, count: 0 });
+        f.emit(Instruction::TailCall {
+            func: 1,
+            args: 0,
+            results: 0,
+        });
+        f.emit(Instruction::Return { first: 1, count: 0 });
+        closure(vm, f)
+    }
+
+    #[test]
+    fn varargs() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let f = count_varargs(&mut vm);
+        let args = |n: usize| vec![Value::Nil; n];
+        assert_eq!(vm.call(&f, &[]), Ok(vec![Value::Int(0)]));
+        assert_eq!(vm.call(&f, &args(1)), Ok(vec![Value::Int(0)]));
+        // trailing nils are args too
+        assert_eq!(vm.call(&f, &args(3)), Ok(vec![Value::Int(2)]));
+        assert_eq!(vm.call(&f, &args(100)), Ok(vec![Value::Int(99)]));
+        // args of the first resume
+        let co = vm.create_thread(f);
+        assert_eq!(vm.resume(&co, args(5)), Ok(vec![Value::Int(4)]));
+    }
+}
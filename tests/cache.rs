@@ -0,0 +1,102 @@
+mod cache_tests {
+    use rslua::cache::*;
+    use rslua::compiler::CompilerOptions;
+    use rslua::opcodes::Instruction;
+    use rslua::proto::Proto;
+    use std::io::{Read, Write};
+
+    fn stored(cache: &mut Cache<MemoryStore>, source: &str) -> Vec<u8> {
+        let key = cache.key(source);
+        let mut chunk = Vec::new();
+        let mut reader = cache.store_mut().reader(&key).unwrap();
+        reader.read_to_end(&mut chunk).unwrap();
+        chunk
+    }
+
+    #[test]
+    fn memory_store() {
+        let mut cache = Cache::new(MemoryStore::new(), CompilerOptions::default());
+        let source = "local a, b = 1, 'x'\nc = a + 2";
+        let proto = cache.load(source).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+        let cached = cache.load(source).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(cached.disasm(), proto.disasm());
+
+        cache.load("local a = 1").unwrap();
+        assert_eq!(cache.store().len(), 2);
+        assert_eq!(cache.misses(), 2);
+
+        // chunks that can't be loaded are replaced
+        let key = cache.key(source);
+        cache
+            .store_mut()
+            .writer(&key)
+            .unwrap()
+            .write_all(b"not a chunk")
+            .unwrap();
+        assert_eq!(cache.load(source).unwrap().disasm(), proto.disasm());
+        assert_eq!(cache.misses(), 3);
+        cache.load(source).unwrap();
+        assert_eq!(cache.hits(), 2);
+
+        assert!(cache.load("local = 1").is_err());
+    }
+
+    #[test]
+    fn invalid_chunks() {
+        let mut cache = Cache::new(MemoryStore::new(), CompilerOptions::default());
+        let source = "x = 1";
+        let proto = cache.load(source).unwrap();
+        let key = cache.key(source);
+
+        // chunks which don't verify are misses
+        let mut bad = Proto::new();
+        bad.stack_size = 2;
+        bad.code = vec![Instruction::Move { dst: 200, src: 0 }];
+        bad.code_return(0, 1);
+        let mut chunk = stored(&mut cache, source)[..16].to_vec();
+        chunk.extend(bad.dump(false));
+        let mut writer = cache.store_mut().writer(&key).unwrap();
+        writer.write_all(&chunk).unwrap();
+        drop(writer);
+        assert_eq!(cache.load(source).unwrap().disasm(), proto.disasm());
+        assert_eq!((cache.hits(), cache.misses()), (0, 2));
+
+        // and so are chunks of another source under the key, as if the keys collided
+        cache.load("y = 2").unwrap();
+        let other = stored(&mut cache, "y = 2");
+        let mut writer = cache.store_mut().writer(&key).unwrap();
+        writer.write_all(&other).unwrap();
+        drop(writer);
+        assert_eq!(cache.load(source).unwrap().disasm(), proto.disasm());
+        assert_eq!((cache.hits(), cache.misses()), (0, 4));
+        cache.load(source).unwrap();
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn keys() {
+        let cache = Cache::new(MemoryStore::new(), CompilerOptions::default());
+        let key = cache.key("local a = 1");
+        assert_eq!(key.len(), 16);
+        assert_eq!(key, cache.key("local a = 1"));
+        assert_ne!(key, cache.key("local a = 2"));
+        let unoptimized = Cache::new(MemoryStore::new(), CompilerOptions::unoptimized());
+        assert_ne!(key, unoptimized.key("local a = 1"));
+    }
+
+    #[test]
+    fn dir_store() {
+        let dir = std::env::temp_dir().join(format!("rslua_cache_{}", std::process::id()));
+        let source = "x = 1";
+        let mut cache = Cache::new(DirStore::new(&dir), CompilerOptions::default());
+        let proto = cache.load(source).unwrap();
+        assert!(dir.join(format!("{}.luac", cache.key(source))).exists());
+
+        let mut cache = Cache::new(DirStore::new(&dir), CompilerOptions::default());
+        assert_eq!(cache.load(source).unwrap().disasm(), proto.disasm());
+        assert_eq!(cache.hits(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+mod checker_tests {
+    use rslua::checker::Checker;
+    use rslua::lexer::{Lexer, LexerConfig};
+    use rslua::parser::Parser;
+    use rslua::types::Dialect;
+    use std::fs::File;
+    use std::io::prelude::*;
+
+    fn try_check(input: &str) -> Result<(), String> {
+        try_check_dialect(input, Dialect::Standard)
+    }
+
+    fn try_check_dialect(input: &str, dialect: Dialect) -> Result<(), String> {
+        let mut lexer = Lexer::new();
+        lexer.set_debug(true);
+        lexer.set_config(LexerConfig {
+            dialect,
+            ..LexerConfig::default()
+        });
+        if let Ok(tokens) = lexer.run(input) {
+            let mut parser = Parser::new();
+            parser.set_debug(true);
+            parser.set_dialect(dialect);
+            if let Ok(block) = parser.run(tokens) {
+                let mut checker = Checker::new();
+                return checker.run(&block).map(|_| ()).map_err(|e| e.0);
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn checker_practical() -> std::io::Result<()> {
+        let mut file = File::open(r"lua/json.lua")?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        assert!(try_check(&content).is_ok());
+        Ok(())
+    }
+
+    fn error(msg: &str) -> Result<(), String> {
+        Err(format!("[compile error] {}.", msg))
+    }
+
+    #[test]
+    fn goto() {
+        assert!(try_check("::top:: do goto top end").is_ok());
+        assert!(try_check("do goto l end\n::l::").is_ok());
+        assert!(try_check("::a:: do ::a:: end").is_ok());
+        assert!(try_check(
+            "for i = 1, 3 do\n if i == 2 then goto continue end\n local x = i\n ::continue::\nend"
+        )
+        .is_ok());
+        assert_eq!(
+            try_check("::a::\n::a::"),
+            error("label 'a' already defined on line 1 at line [2]")
+        );
+        assert_eq!(
+            try_check("local a\ngoto nowhere"),
+            error("no visible label 'nowhere' for goto at line [2]")
+        );
+        assert_eq!(
+            try_check("goto l\ndo ::l:: end"),
+            error("no visible label 'l' for goto at line [1]")
+        );
+        assert_eq!(
+            try_check("::l::\nlocal function f()\n goto l\nend"),
+            error("no visible label 'l' for goto at line [3]")
+        );
+        assert_eq!(
+            try_check("goto l\nlocal x = 1\n::l::\nx = 2"),
+            error("<goto l> jumps into the scope of local 'x' at line [1]")
+        );
+        assert_eq!(
+            try_check("do\n goto l\nend\nlocal x\n::l::\nx = 1"),
+            error("<goto l> jumps into the scope of local 'x' at line [2]")
+        );
+        // the until condition is in the scope of the block
+        assert_eq!(
+            try_check("repeat
+ goto l
+ local x
+ ::l::
+until x"),
+            error("<goto l> jumps into the scope of local 'x' at line [2]")
+        );
+    }
+
+    #[test]
+    fn continue_stat() {
+        let check = |input| try_check_dialect(input, Dialect::Extended);
+        assert!(check("while a do
+ if b then continue end
+ local x = 1
+end").is_ok());
+        assert!(check("for i = 1, 3 do
+ local x = i
+ continue
+end").is_ok());
+        assert!(check("repeat
+ local x = f()
+ if x then continue end
+until x").is_ok());
+        assert_eq!(
+            check("repeat
+ if a then continue end
+ local x = 1
+until x"),
+            error("<goto continue> jumps into the scope of local 'x' at line [2]")
+        );
+    }
+
+    #[test]
+    fn vararg() {
+        assert!(try_check("local a, b = ...").is_ok());
+        assert!(try_check("local function f(a, ...) return ... end").is_ok());
+        assert!(try_check("local t = { f = function(...) g(...) end }").is_ok());
+        assert_eq!(
+            try_check("local function f(a)\n return ...\nend"),
+            Err(
+                "[compile error] cannot use '...' outside a vararg function at line [2]."
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            try_check("function f(...)\n local g = function()\n  if true then\n   x = { ... }\n  end\n end\nend"),
+            Err(
+                "[compile error] cannot use '...' outside a vararg function at line [4]."
+                    .to_string()
+            )
+        );
+    }
+    fn locals(prefix: &str, n: usize) -> String {
+        let names: Vec<String> = (0..n).map(|i| format!("{}{}", prefix, i)).collect();
+        format!("local {}\n", names.join(", "))
+    }
+
+    #[test]
+    fn limits() {
+        assert!(try_check(&locals("a", 200)).is_ok());
+        assert_eq!(
+            try_check(&format!("{}{}", locals("a", 100), locals("b", 101))),
+            error("too many local variables (limit is 200) in main function at line [2]")
+        );
+        // locals of sibling blocks don't accumulate
+        let siblings = format!(
+            "do\n{}end\ndo\n{}end",
+            locals("a", 150),
+            locals("b", 150)
+        );
+        assert!(try_check(&siblings).is_ok());
+        assert_eq!(
+            try_check(&format!(
+                "local function f()\n{}{}end",
+                locals("a", 150),
+                locals("b", 51)
+            )),
+            error("too many local variables (limit is 200) in function at line 1 at line [3]")
+        );
+
+        // a function capturing 300 locals of enclosing functions
+        let names: Vec<String> = (0..150)
+            .map(|i| format!("a{}", i))
+            .chain((0..150).map(|i| format!("b{}", i)))
+            .collect();
+        let upvalues = format!(
+            "{}local function f()\n{}local function g()\nreturn {}\nend\nend",
+            locals("a", 150),
+            locals("b", 150),
+            names.join(" + ")
+        );
+        assert_eq!(
+            try_check(&upvalues),
+            error("too many upvalues (limit is 255) in function at line 4 at line [5]")
+        );
+        // f only captures them for the functions in it, which use 150 each
+        let sum = |prefix: &str| {
+            let names: Vec<String> = (0..150).map(|i| format!("{}{}", prefix, i)).collect();
+            names.join(" + ")
+        };
+        let nested = format!(
+            "{}local function h()\n{}local function f()\nlocal function g()\nreturn {}\nend\nlocal function k()\nreturn {}\nend\nend\nend",
+            locals("a", 150),
+            locals("b", 150),
+            sum("a"),
+            sum("b")
+        );
+        assert_eq!(
+            try_check(&nested),
+            error("too many upvalues (limit is 255) in function at line 4 at line [4]")
+        );
+    }
+}
@@ -0,0 +1,158 @@
+mod common;
+
+mod close_tests {
+    use crate::common::{closure, compile};
+    use rslua::consts::Const;
+    use rslua::opcodes::*;
+    use rslua::proto::ProtoBuilder;
+    use rslua::table::Table;
+    use rslua::value::{TableRef, Value};
+    use rslua::vm::{RuntimeError, Vm};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // function(v, e) log[#log + 1] = v v.err = e end
+    fn close(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(2, false).stack_size(4);
+        let log = builder.constant(Const::Str("log".to_string()));
+        let one = builder.constant(Const::Int(1));
+        let err = builder.constant(Const::Str("err".to_string()));
+        builder.emit(Instruction::GetTabUp {
+            dst: 2,
+            up: 0,
+            key: rk_as_k(log),
+        });
+        builder.emit(Instruction::Len { dst: 3, src: 2 });
+        builder.emit(Instruction::Add {
+            dst: 3,
+            left: 3,
+            right: rk_as_k(one),
+        });
+        builder.emit(Instruction::SetTable {
+            table: 2,
+            key: 3,
+            value: 0,
+        });
+        builder.emit(Instruction::SetTable {
+            table: 0,
+            key: rk_as_k(err),
+            value: 1,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        closure(vm, builder)
+    }
+
+    // a vm with `log` and closable tables `x`, `y` and `z`
+    fn setup() -> (Vm, Vec<TableRef>) {
+        let mut vm = Vm::new();
+        let meta = Rc::new(RefCell::new(Table::new()));
+        meta.borrow_mut().set_str("__close", close(&mut vm));
+        vm.set_global("log", Value::Table(Rc::new(RefCell::new(Table::new()))));
+        let values = ["x", "y", "z"]
+            .iter()
+            .map(|name| {
+                let t = Rc::new(RefCell::new(Table::new()));
+                t.borrow_mut().set_metatable(Some(meta.clone()));
+                vm.set_global(name, Value::Table(t.clone()));
+                t
+            })
+            .collect();
+        (vm, values)
+    }
+
+    fn log(vm: &Vm) -> Vec<Value> {
+        match vm.get_global("log") {
+            Value::Table(t) => (1..=t.borrow().len())
+                .map(|i| t.borrow().get(&Value::Int(i as i64)))
+                .collect(),
+            value => panic!("{:?}", value),
+        }
+    }
+
+    #[test]
+    fn closed_on_return() {
+        let (mut vm, values) = setup();
+        vm.run(compile(
+            "local a <close> = x local b = y local c <close>, d = z local e <close> = nil",
+        ))
+        .unwrap();
+        // in the reverse order, without errors
+        assert_eq!(
+            log(&vm),
+            vec![
+                Value::Table(values[2].clone()),
+                Value::Table(values[0].clone())
+            ]
+        );
+        assert_eq!(values[0].borrow().get_str("err"), Value::Nil);
+    }
+
+    #[test]
+    fn closed_on_error() {
+        let (mut vm, values) = setup();
+        let msg = "main:1: attempt to perform arithmetic on a table value";
+        assert_eq!(
+            vm.run(compile(
+                "local a <close> = x local b <close> = y local c = a + 1"
+            )),
+            Err(RuntimeError::new(msg))
+        );
+        assert_eq!(
+            log(&vm),
+            vec![
+                Value::Table(values[1].clone()),
+                Value::Table(values[0].clone())
+            ]
+        );
+        assert_eq!(values[0].borrow().get_str("err"), Value::str(msg));
+        assert_eq!(values[1].borrow().get_str("err"), Value::str(msg));
+    }
+
+    #[test]
+    fn closed_on_leaving_blocks() {
+        let (mut vm, values) = setup();
+        vm.run(compile(
+            "do local a <close> = x end
+            local i = 0
+            while true do
+                local b <close> = y i = i + 1
+                if i == 2 then break end
+            end
+            repeat local c <close> = z until i > 1
+            ::again:: do
+                local d <close> = x i = i + 1
+                if i < 4 then goto again end
+            end
+            for j = 1, 2 do local e <close> = z end",
+        ))
+        .unwrap();
+        let [x, y, z] = [0, 1, 2].map(|i| Value::Table(values[i].clone()));
+        assert_eq!(
+            log(&vm),
+            vec![
+                x.clone(),
+                y.clone(),
+                y,
+                z.clone(),
+                x.clone(),
+                x,
+                z.clone(),
+                z
+            ]
+        );
+    }
+
+    #[test]
+    fn non_closable() {
+        let (mut vm, values) = setup();
+        assert_eq!(
+            vm.run(compile("local a <close> = x local b <close> = 1")),
+            Err(RuntimeError::new(
+                "main:1: variable 'b' got a non-closable value"
+            ))
+        );
+        // variables marked before are still closed
+        assert_eq!(log(&vm), vec![Value::Table(values[0].clone())]);
+    }
+}
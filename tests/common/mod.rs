@@ -0,0 +1,38 @@
+// each test crate uses some of the helpers
+#![allow(dead_code)]
+
+use rslua::ast::Block;
+use rslua::compiler::Compiler;
+use rslua::lexer::Lexer;
+use rslua::opcodes::Instruction;
+use rslua::parser::Parser;
+use rslua::proto::{Proto, ProtoBuilder};
+use rslua::value::Value;
+use rslua::vm::Vm;
+
+// helpers shared by the tests of the compiler, the vm and the libraries
+
+pub fn parse(input: &str) -> Block {
+    let tokens = Lexer::new().run(input).ok().unwrap();
+    Parser::new().run(tokens).ok().unwrap()
+}
+
+// compile with the default options
+pub fn compile(input: &str) -> Proto {
+    Compiler::new().run(&parse(input)).ok().unwrap()
+}
+
+// a closure of `child`, which gets _ENV as its first upvalue
+pub fn closure(vm: &mut Vm, mut child: ProtoBuilder) -> Value {
+    child.up_value("_ENV", false, 0);
+    let mut main = ProtoBuilder::new();
+    main.stack_size(1);
+    main.up_value("_ENV", true, 0);
+    let child = main.child(child.build());
+    main.emit(Instruction::Closure {
+        dst: 0,
+        proto: child,
+    });
+    main.emit(Instruction::Return { first: 0, count: 2 });
+    vm.run(main.build()).unwrap().remove(0)
+}
@@ -1,16 +1,19 @@
 use rslua::compiler::*;
+use rslua::consts::Const;
+use rslua::opcodes::{rk_as_k, Instruction, OpArgs, OpCode, MAXARG_BX};
 use rslua::lexer::*;
 use rslua::parser::*;
-use rslua::proto::Proto;
+use rslua::proto::{ConstStats, LineInfo, Proto, ProtoBuilder, ProtoStats};
+use rslua::types::Dialect;
 
-fn try_compile(input: &str) -> Result<Proto, CompileError> {
+fn try_compile(input: &str, options: CompilerOptions) -> Result<Proto, CompileError> {
     let mut lexer = Lexer::new();
     lexer.set_debug(true);
     if let Ok(tokens) = lexer.run(input) {
         let mut parser = Parser::new();
         parser.set_debug(true);
         if let Ok(block) = parser.run(tokens) {
-            let mut compiler = Compiler::new();
+            let mut compiler = Compiler::with_options(options);
             match compiler.run(&block) {
                 Ok(proto) => {
                     println!("{:?}", proto);
@@ -25,8 +28,32 @@ fn try_compile(input: &str) -> Result<Proto, CompileError> {
     unreachable!()
 }
 
+fn try_compile_extended_and_print(input: &str) -> String {
+    let mut lexer = Lexer::new();
+    lexer.set_config(LexerConfig {
+        dialect: Dialect::Extended,
+        ..LexerConfig::default()
+    });
+    let tokens = lexer.run(input).ok().unwrap();
+    let mut parser = Parser::new();
+    parser.set_dialect(Dialect::Extended);
+    let block = parser.run(tokens).ok().unwrap();
+    let options = CompilerOptions {
+        dialect: Dialect::Extended,
+        ..CompilerOptions::default()
+    };
+    match Compiler::with_options(options).run(&block) {
+        Ok(proto) => format!("{:?}", proto),
+        Err(e) => e.0,
+    }
+}
+
 fn try_compile_and_print(input: &str) -> String {
-    match try_compile(input) {
+    try_compile_and_print_with_options(input, CompilerOptions::default())
+}
+
+fn try_compile_and_print_with_options(input: &str, options: CompilerOptions) -> String {
+    match try_compile(input, options) {
         Ok(proto) => format!("{:?}", proto),
         Err(e) => format!("{}", e.0),
     }
@@ -329,17 +356,285 @@ instructions :
     }
 
     #[test]
-    fn divide_by_zero() {
-        let result = try_compile_and_print(
+    fn const_folding_int_overflow() {
+        assert_eq!(
+            try_compile_and_print(
+                r#"
+local a, b = 9223372036854775807 + 1, -9223372036854775807 - 2
+local c, d = 4611686018427387904 * 2, -(-9223372036854775807 - 1) // -1
+"#
+            ),
             r#"
+stack size : 4
+consts :
+| 0     | -9223372036854775808 |
+| 1     | 9223372036854775807 |
+locals :
+| 0     | a          |
+| 1     | b          |
+| 2     | c          |
+| 3     | d          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadK      | 0     | 0     |       |
+| 2     | LoadK      | 1     | 1     |       |
+| 3     | LoadK      | 2     | 0     |       |
+| 4     | LoadK      | 3     | 0     |       |
+| 5     | Return     | 0     | 1     |       |
+"#
+        );
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        let input = r#"
 --
 -- test devide by zero
 --
-local a = 1 // 0"#,
+local a = 1 // 0"#;
+        // division by zero is left to runtime by default
+        assert_eq!(
+            try_compile_and_print("local a = 1 // 0; local b = 1 % 0"),
+            r#"
+stack size : 2
+consts :
+| 0     | 1          |
+| 1     | 0          |
+locals :
+| 0     | a          |
+| 1     | b          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | IDiv       | 0     | 256   | 257   |
+| 2     | Mod        | 1     | 256   | 257   |
+| 3     | Return     | 0     | 1     |       |
+"#
+        );
+        let result = try_compile_and_print_with_options(
+            input,
+            CompilerOptions {
+                strict_div_by_zero: true,
+                ..CompilerOptions::default()
+            },
         );
         assert_eq!(result, r#"[compile error] divide by zero at line [5]."#)
     }
 
+    #[test]
+    fn assign_to_const() {
+        let result = try_compile_and_print("local a <const> = 1; a = 2");
+        assert_eq!(
+            result,
+            r#"[compile error] attempt to assign to const variable 'a' at line [1]."#
+        );
+        let result = try_compile_and_print("local a, b <close> = 1, 2; a, b = 3, 4");
+        assert_eq!(
+            result,
+            r#"[compile error] attempt to assign to const variable 'b' at line [1]."#
+        );
+        // also as up values of nested functions, or by function statements
+        let result = try_compile_and_print(
+            "local a <const> = 1\nlocal f = function() return function() a = 2 end end",
+        );
+        assert_eq!(
+            result,
+            r#"[compile error] attempt to assign to const variable 'a' at line [2]."#
+        );
+        let result = try_compile_and_print("local a <close> = nil; function a() end");
+        assert_eq!(
+            result,
+            r#"[compile error] attempt to assign to const variable 'a' at line [1]."#
+        );
+    }
+
+    #[test]
+    fn invalid_code() {
+        // code which parses but has no meaning
+        let cases = [
+            ("f() = 1", "cannot assign to a function call"),
+            ("(a) = 1", "cannot assign to a parenthesized expression"),
+            ("a.b", "syntax error"),
+            ("x = a:b", "function arguments expected"),
+            ("do break end", "break outside a loop"),
+        ];
+        for (input, error) in cases.iter() {
+            assert_eq!(
+                try_compile_and_print(input),
+                format!("[compile error] {} at line [1].", error)
+            );
+        }
+        // at the line of the statement in the nested block
+        assert_eq!(
+            try_compile_and_print("if a then\n\nbreak end"),
+            "[compile error] break outside a loop at line [3]."
+        );
+    }
+
+    #[test]
+    fn calls() {
+        let output =
+            try_compile_and_print("local a, b = f(1, ...) t.x = a:m('s') return g(a, f())");
+        let expected = r#"
+stack size : 6
+consts :
+| 0     | "f"        |
+| 1     | 1          |
+| 2     | "t"        |
+| 3     | "x"        |
+| 4     | "m"        |
+| 5     | "s"        |
+| 6     | "g"        |
+locals :
+| 0     | a          |
+| 1     | b          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | GetTabUp   | 0     | 0     | 256   |
+| 2     | LoadK      | 1     | 1     |       |
+| 3     | Vararg     | 2     | 0     |       |
+| 4     | Call       | 0     | 0     | 3     |
+| 5     | GetTabUp   | 2     | 0     | 258   |
+| 6     | Self_      | 3     | 0     | 260   |
+| 7     | LoadK      | 5     | 5     |       |
+| 8     | Call       | 3     | 3     | 2     |
+| 9     | SetTable   | 2     | 259   | 3     |
+| 10    | GetTabUp   | 2     | 0     | 262   |
+| 11    | Move       | 3     | 0     |       |
+| 12    | GetTabUp   | 4     | 0     | 256   |
+| 13    | Call       | 4     | 1     | 0     |
+| 14    | TailCall   | 2     | 0     | 0     |
+| 15    | Return     | 2     | 0     |       |
+| 16    | Return     | 0     | 1     |       |
+"#;
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn string_coercion() {
+        let input = "local a = '10' + 1; local b = '0x10' * '2'; local c = 'a' + 1";
+        assert_eq!(
+            try_compile_and_print(input),
+            r#"
+stack size : 3
+consts :
+| 0     | "10"       |
+| 1     | 1          |
+| 2     | "0x10"     |
+| 3     | "2"        |
+| 4     | "a"        |
+locals :
+| 0     | a          |
+| 1     | b          |
+| 2     | c          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | Add        | 0     | 256   | 257   |
+| 2     | Mul        | 1     | 258   | 259   |
+| 3     | Add        | 2     | 260   | 257   |
+| 4     | Return     | 0     | 1     |       |
+"#
+        );
+        assert_eq!(
+            try_compile_and_print_with_options(
+                input,
+                CompilerOptions {
+                    string_coercion: true,
+                    ..CompilerOptions::default()
+                }
+            ),
+            r#"
+stack size : 3
+consts :
+| 0     | 11         |
+| 1     | 32         |
+| 2     | "a"        |
+| 3     | 1          |
+locals :
+| 0     | a          |
+| 1     | b          |
+| 2     | c          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadK      | 0     | 0     |       |
+| 2     | LoadK      | 1     | 1     |       |
+| 3     | Add        | 2     | 258   | 259   |
+| 4     | Return     | 0     | 1     |       |
+"#
+        );
+    }
+
+    #[test]
+    fn compiler_options() {
+        let input = "local a; local b, c; local d, e = 1 + 2, 'x' + 'x'";
+        assert_eq!(
+            try_compile_and_print(input),
+            r#"
+stack size : 5
+consts :
+| 0     | 3          |
+| 1     | "x"        |
+locals :
+| 0     | a          |
+| 1     | b          |
+| 2     | c          |
+| 3     | d          |
+| 4     | e          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 2     |       |
+| 2     | LoadK      | 3     | 0     |       |
+| 3     | Add        | 4     | 257   | 257   |
+| 4     | Return     | 0     | 1     |       |
+"#
+        );
+        assert_eq!(
+            try_compile_and_print_with_options(input, CompilerOptions::unoptimized()),
+            r#"
+stack size : 5
+consts :
+| 0     | 1          |
+| 1     | 2          |
+| 2     | "x"        |
+| 3     | "x"        |
+locals :
+| 0     | a          |
+| 1     | b          |
+| 2     | c          |
+| 3     | d          |
+| 4     | e          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 0     |       |
+| 2     | LoadNil    | 1     | 1     |       |
+| 3     | Add        | 3     | 256   | 257   |
+| 4     | Add        | 4     | 258   | 259   |
+| 5     | Return     | 0     | 1     |       |
+"#
+        );
+        assert_eq!(
+            try_compile_and_print_with_options(
+                input,
+                CompilerOptions {
+                    emit_debug_info: false,
+                    ..CompilerOptions::default()
+                }
+            ),
+            r#"
+stack size : 5
+consts :
+| 0     | 3          |
+| 1     | "x"        |
+locals :
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 2     |       |
+| 2     | LoadK      | 3     | 0     |       |
+| 3     | Add        | 4     | 257   | 257   |
+| 4     | Return     | 0     | 1     |       |
+"#
+        );
+    }
+
     #[test]
     fn code_bin_op() {
         let output =
@@ -728,13 +1023,12 @@ instructions :
         let expected = r#"
 stack size : 2
 consts :
-| 0     | "str"      |
-| 1     | 3          |
+| 0     | 3          |
 locals :
 | 0     | a          |
 instructions :
 | line  | OP         | A     | B     | C     |
-| 1     | LoadK      | 0     | 1     |       |
+| 1     | LoadK      | 0     | 0     |       |
 | 2     | Return     | 0     | 1     |       |
 "#;
         assert_eq!(output, expected);
@@ -746,7 +1040,6 @@ instructions :
         let expected = r#"
 stack size : 2
 consts :
-| 0     | "str"      |
 locals :
 | 0     | a          |
 | 1     | b          |
@@ -822,9 +1115,493 @@ instructions :
         assert_eq!(output, expected);
     }
 
+    #[test]
+    fn code_un_op_const() {
+        let output = try_compile_and_print("local a = #'abc'; local b = -nil");
+        let expected = r#"
+stack size : 2
+consts :
+| 0     | "abc"      |
+locals :
+| 0     | a          |
+| 1     | b          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadK      | 0     | 0     |       |
+| 2     | Len        | 0     | 0     |       |
+| 3     | LoadNil    | 1     | 0     |       |
+| 4     | Unm        | 1     | 1     |       |
+| 5     | Return     | 0     | 1     |       |
+"#;
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn code_comp_nil_bool() {
+        let output = try_compile_and_print("local a; local b = a == nil; local c = a ~= true");
+        let expected = r#"
+stack size : 3
+consts :
+| 0     | nil        |
+| 1     | true       |
+locals :
+| 0     | a          |
+| 1     | b          |
+| 2     | c          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 0     |       |
+| 2     | Eq         | 1     | 0     | 256   |
+| 3     | Jmp        | 0     | 1     |       |
+| 4     | LoadBool   | 1     | 0     | 1     |
+| 5     | LoadBool   | 1     | 1     | 0     |
+| 6     | Eq         | 0     | 0     | 257   |
+| 7     | Jmp        | 0     | 1     |       |
+| 8     | LoadBool   | 2     | 0     | 1     |
+| 9     | LoadBool   | 2     | 1     | 0     |
+| 10    | Return     | 0     | 1     |       |
+"#;
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn code_bin_op_large_const_index() {
+        let mut input = String::from("local a;");
+        for i in 0..256 {
+            input.push_str(&format!("a = {};", i));
+        }
+        input.push_str("local b = a + 256");
+        let output = try_compile_and_print(&input);
+        // constant index 256 can't be encoded as RK, load it into a temp register first
+        let expected = r#"
+| 258   | LoadK      | 2     | 256   |       |
+| 259   | Add        | 1     | 0     | 2     |
+| 260   | Return     | 0     | 1     |       |
+"#;
+        assert!(output.starts_with("\nstack size : 3\n"));
+        assert!(output.ends_with(expected));
+    }
+
+    #[test]
+    fn code_const_extra_arg() {
+        let mut proto = Proto::new();
+        proto.code_const(0, MAXARG_BX);
+        proto.code_const(1, MAXARG_BX + 1);
+        let output = format!("{:?}", proto);
+        let expected = r#"
+| 1     | LoadK      | 0     | 262143 |       |
+| 2     | LoadKx     | 1     |       |       |
+| 3     | ExtraArg   | 262144 |       |       |
+"#;
+        assert!(output.ends_with(expected));
+    }
+
     #[test]
     fn code_and_6() {
         let output = try_compile_and_print("local a, b, c; local d = a and b;");
         // TODO
     }
+    #[test]
+    fn global() {
+        let proto = try_compile("x = 1", CompilerOptions::default()).ok().unwrap();
+        assert_eq!(proto.up_vars.len(), 1);
+        assert_eq!(proto.get_up_var("_ENV"), Some(0));
+        assert_eq!(
+            format!("{:?}", proto),
+            r#"
+stack size : 2
+consts :
+| 0     | 1          |
+| 1     | "x"        |
+locals :
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadK      | 0     | 0     |       |
+| 2     | SetTabUp   | 0     | 257   | 0     |
+| 3     | Return     | 0     | 1     |       |
+"#
+        );
+        assert_eq!(
+            try_compile_and_print("local a; a, y = z, a"),
+            r#"
+stack size : 3
+consts :
+| 0     | "z"        |
+| 1     | "y"        |
+locals :
+| 0     | a          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 0     |       |
+| 2     | GetTabUp   | 1     | 0     | 256   |
+| 3     | Move       | 2     | 0     |       |
+| 4     | SetTabUp   | 0     | 257   | 2     |
+| 5     | Move       | 0     | 1     |       |
+| 6     | Return     | 0     | 1     |       |
+"#
+        );
+
+        let options = CompilerOptions {
+            emit_debug_info: false,
+            ..CompilerOptions::default()
+        };
+        let proto = try_compile("local a = x", options).ok().unwrap();
+        assert_eq!(proto.up_vars.len(), 1);
+        assert_eq!(proto.get_up_var("_ENV"), None);
+    }
+    #[test]
+    fn compound_assign() {
+        assert_eq!(
+            try_compile_extended_and_print("local a, b; a *= b + 1"),
+            r#"
+stack size : 3
+consts :
+| 0     | 1          |
+locals :
+| 0     | a          |
+| 1     | b          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 1     |       |
+| 2     | Add        | 2     | 1     | 256   |
+| 3     | Mul        | 0     | 0     | 2     |
+| 4     | Return     | 0     | 1     |       |
+"#
+        );
+        assert_eq!(
+            try_compile_extended_and_print("x ..= 's'"),
+            r#"
+stack size : 3
+consts :
+| 0     | "x"        |
+| 1     | "s"        |
+locals :
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | GetTabUp   | 1     | 0     | 256   |
+| 2     | LoadK      | 2     | 1     |       |
+| 3     | Concat     | 0     | 1     | 2     |
+| 4     | SetTabUp   | 0     | 256   | 0     |
+| 5     | Return     | 0     | 1     |       |
+"#
+        );
+        assert_eq!(
+            try_compile_extended_and_print("local a <const> = 1\na += 1"),
+            "[compile error] attempt to assign to const variable 'a' at line [2]."
+        );
+    }
+    #[test]
+    fn table_index() {
+        assert_eq!(
+            try_compile_and_print("local t; local a = t.x + t[1]; t.y = a; t[a] = 1"),
+            r#"
+stack size : 3
+consts :
+| 0     | "x"        |
+| 1     | 1          |
+| 2     | "y"        |
+locals :
+| 0     | t          |
+| 1     | a          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 0     |       |
+| 2     | GetTable   | 1     | 0     | 256   |
+| 3     | GetTable   | 2     | 0     | 257   |
+| 4     | Add        | 1     | 1     | 2     |
+| 5     | Move       | 2     | 1     |       |
+| 6     | SetTable   | 0     | 258   | 2     |
+| 7     | LoadK      | 2     | 1     |       |
+| 8     | SetTable   | 0     | 1     | 2     |
+| 9     | Return     | 0     | 1     |       |
+"#
+        );
+        assert_eq!(
+            try_compile_and_print("x.y.z = 1"),
+            r#"
+stack size : 2
+consts :
+| 0     | "x"        |
+| 1     | "y"        |
+| 2     | "z"        |
+| 3     | 1          |
+locals :
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | GetTabUp   | 0     | 0     | 256   |
+| 2     | GetTable   | 0     | 0     | 257   |
+| 3     | LoadK      | 1     | 3     |       |
+| 4     | SetTable   | 0     | 258   | 1     |
+| 5     | Return     | 0     | 1     |       |
+"#
+        );
+        // folded constant key
+        assert_eq!(
+            try_compile_and_print("local t; t[2 * 3] = t.a.b"),
+            r#"
+stack size : 2
+consts :
+| 0     | 6          |
+| 1     | "a"        |
+| 2     | "b"        |
+locals :
+| 0     | t          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 0     |       |
+| 2     | GetTable   | 1     | 0     | 257   |
+| 3     | GetTable   | 1     | 1     | 258   |
+| 4     | SetTable   | 0     | 256   | 1     |
+| 5     | Return     | 0     | 1     |       |
+"#
+        );
+        // key is read before the local is assigned
+        assert_eq!(
+            try_compile_and_print("local t, i; t[i], i = i, 2"),
+            r#"
+stack size : 5
+consts :
+| 0     | 2          |
+locals :
+| 0     | t          |
+| 1     | i          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 1     |       |
+| 2     | Move       | 2     | 1     |       |
+| 3     | Move       | 3     | 1     |       |
+| 4     | LoadK      | 1     | 0     |       |
+| 5     | SetTable   | 0     | 2     | 3     |
+| 6     | Return     | 0     | 1     |       |
+"#
+        );
+        assert_eq!(
+            try_compile_extended_and_print("local t; t.x += 1; t.a.b *= 2"),
+            r#"
+stack size : 3
+consts :
+| 0     | "x"        |
+| 1     | 1          |
+| 2     | "a"        |
+| 3     | "b"        |
+| 4     | 2          |
+locals :
+| 0     | t          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 0     |       |
+| 2     | GetTable   | 1     | 0     | 256   |
+| 3     | Add        | 1     | 1     | 257   |
+| 4     | SetTable   | 0     | 256   | 1     |
+| 5     | GetTable   | 1     | 0     | 258   |
+| 6     | GetTable   | 2     | 1     | 259   |
+| 7     | Mul        | 2     | 2     | 260   |
+| 8     | SetTable   | 1     | 259   | 2     |
+| 9     | Return     | 0     | 1     |       |
+"#
+        );
+    }
+
+    #[test]
+    fn resolve_names() {
+        // the latest local shadows the previous one, and is in scope after its declaration
+        assert_eq!(
+            try_compile_and_print("local a = 1; local a = a + 1; x = a"),
+            r#"
+stack size : 3
+consts :
+| 0     | 1          |
+| 1     | "x"        |
+locals :
+| 0     | a          |
+| 1     | a          |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadK      | 0     | 0     |       |
+| 2     | Add        | 1     | 0     | 256   |
+| 3     | Move       | 2     | 1     |       |
+| 4     | SetTabUp   | 0     | 257   | 2     |
+| 5     | Return     | 0     | 1     |       |
+"#
+        );
+        // globals are fields of a local _ENV
+        assert_eq!(
+            try_compile_and_print("local _ENV; x = y"),
+            r#"
+stack size : 2
+consts :
+| 0     | "y"        |
+| 1     | "x"        |
+locals :
+| 0     | _ENV       |
+instructions :
+| line  | OP         | A     | B     | C     |
+| 1     | LoadNil    | 0     | 0     |       |
+| 2     | GetTable   | 1     | 0     | 256   |
+| 3     | SetTable   | 0     | 257   | 1     |
+| 4     | Return     | 0     | 1     |       |
+"#
+        );
+    }
+
+    #[test]
+    fn proto_api() {
+        let proto = try_compile("local a = 1\n\nx = a + 2", CompilerOptions::default())
+            .ok()
+            .unwrap();
+        let instructions: Vec<(OpCode, OpArgs)> = proto.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                (OpCode::LoadK, OpArgs::ABx(0, 0)),
+                (OpCode::Add, OpArgs::ABC(1, 0, rk_as_k(1))),
+                (OpCode::SetTabUp, OpArgs::ABC(0, rk_as_k(2), 1)),
+                (OpCode::Return, OpArgs::AB(0, 1)),
+            ]
+        );
+        assert!(
+            proto.constants() == [Const::Int(1), Const::Int(2), Const::Str("x".to_string())]
+        );
+        assert!(proto.children().is_empty());
+        // return is at the last line
+        let lines: Vec<Option<u32>> = (0..5).map(|pc| proto.line_of(pc)).collect();
+        assert_eq!(lines, vec![Some(1), Some(3), Some(3), Some(3), None]);
+
+        let options = CompilerOptions {
+            emit_debug_info: false,
+            ..CompilerOptions::default()
+        };
+        let proto = try_compile("x = 1", options).ok().unwrap();
+        assert_eq!(proto.line_of(0), None);
+    }
+
+    #[test]
+    fn propagate_constants() {
+        let options = CompilerOptions {
+            propagate_constants: true,
+            ..CompilerOptions::default()
+        };
+        let proto = try_compile("local a = 1; local b = a; local c = b * 2; x = -c", options)
+            .ok()
+            .unwrap();
+        let instructions: Vec<(OpCode, OpArgs)> = proto.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                (OpCode::LoadK, OpArgs::ABx(0, 0)),
+                (OpCode::LoadK, OpArgs::ABx(1, 0)),
+                (OpCode::LoadK, OpArgs::ABx(2, 1)),
+                (OpCode::LoadK, OpArgs::ABx(3, 3)),
+                (OpCode::SetTabUp, OpArgs::ABC(0, rk_as_k(2), 3)),
+                (OpCode::Return, OpArgs::AB(0, 1)),
+            ]
+        );
+        assert!(proto.constants()[3] == Const::Int(-2));
+
+        // registers are unknown at jump targets
+        let mut builder = ProtoBuilder::new();
+        let k = builder.constant(Const::Int(1));
+        builder.emit(Instruction::LoadK { dst: 0, k });
+        builder.emit(Instruction::Move { dst: 1, src: 0 });
+        builder.emit(Instruction::Jmp {
+            close: 0,
+            offset: 0,
+        });
+        builder.emit(Instruction::Move { dst: 1, src: 0 });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        let mut proto = builder.build();
+        proto.propagate_constants(false);
+        assert!(proto.code[1] == Instruction::LoadK { dst: 1, k });
+        assert!(proto.code[3] == Instruction::Move { dst: 1, src: 0 });
+    }
+
+    #[test]
+    fn line_info() {
+        // small steps, jumps too far for a byte, and runs longer than the absolute line interval
+        let mut lines = vec![1, 1, 2, 5, 300, 301, 2, 2, 1000, 873];
+        lines.extend((0..300).map(|i| 1000 + i / 3));
+        let line_info: LineInfo = lines.iter().copied().collect();
+        assert_eq!(line_info.len(), lines.len());
+        assert_eq!(line_info.iter().collect::<Vec<u32>>(), lines);
+        for (pc, line) in lines.iter().enumerate() {
+            assert_eq!(line_info.get(pc), Some(*line));
+        }
+        assert_eq!(line_info.get(lines.len()), None);
+        assert!(line_info.size() < lines.len() * 2);
+    }
+
+    #[test]
+    fn proto_stats() {
+        let mut proto = try_compile("local a, b = 1, 2.5; x = 'y'", CompilerOptions::default())
+            .ok()
+            .unwrap();
+        let child = try_compile("local a, b, c, d = true, 1, 2, 3", CompilerOptions::default())
+            .ok()
+            .unwrap();
+        let mut nested = try_compile("local a", CompilerOptions::default())
+            .ok()
+            .unwrap();
+        nested.protos.push(child);
+        proto.protos.push(nested);
+        assert_eq!(
+            proto.stats(),
+            ProtoStats {
+                instructions: 12,
+                constants: ConstStats {
+                    nil: 0,
+                    bool: 0,
+                    int: 4,
+                    float: 1,
+                    str: 2,
+                },
+                registers: 4,
+                functions: 2,
+            }
+        );
+        // booleans are loaded by LoadBool instead of constants
+        assert_eq!(proto.stats().constants.total(), 7);
+    }
+
+    #[test]
+    fn compile_stats() {
+        let tokens = Lexer::new().run("local a, b, c = 1, 2, 'x'").ok().unwrap();
+        let block = Parser::new().run(tokens).ok().unwrap();
+        let mut compiler = Compiler::with_options(CompilerOptions {
+            register_warning: Some(2),
+            ..CompilerOptions::default()
+        });
+        compiler.run(&block).ok().unwrap();
+        assert_eq!(
+            compiler.stats(),
+            &[FunctionStats {
+                name: "main".to_string(),
+                registers: 3,
+                constants: 3,
+                instructions: 4,
+            }]
+        );
+        assert_eq!(
+            compiler.warnings(),
+            &["[compile warning] function main uses 3 registers, more than 2.".to_string()]
+        );
+
+        // no warning below the threshold or without one
+        for register_warning in [Some(3), None] {
+            let mut compiler = Compiler::with_options(CompilerOptions {
+                register_warning,
+                ..CompilerOptions::default()
+            });
+            compiler.run(&block).ok().unwrap();
+            assert!(compiler.warnings().is_empty());
+        }
+    }
+
+    #[test]
+    fn send_proto() {
+        let proto = std::thread::spawn(|| try_compile("local abc", CompilerOptions::default()).ok())
+            .join()
+            .unwrap()
+            .unwrap();
+        assert_eq!(proto.local_vars[0].name(), "abc");
+    }
 }
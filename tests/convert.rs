@@ -0,0 +1,80 @@
+mod convert_tests {
+    use rslua::convert::*;
+    use rslua::value::Value;
+    use std::collections::HashMap;
+
+    fn round_trip<T: ToLua + FromLua + Clone + PartialEq + std::fmt::Debug>(value: T) {
+        assert_eq!(T::from_lua(value.clone().to_lua()), Ok(value));
+    }
+
+    #[test]
+    fn scalars() {
+        round_trip(true);
+        round_trip(-3i8);
+        round_trip(70000u32);
+        round_trip(i64::MIN);
+        round_trip(1.5f64);
+        round_trip("text".to_string());
+        round_trip(Value::Int(1));
+        assert_eq!(().to_lua(), Value::Nil);
+        assert_eq!("s".to_lua(), Value::str("s"));
+
+        // as lua's arithmetic converts numbers
+        assert_eq!(i32::from_lua(Value::Float(2.0)), Ok(2));
+        assert_eq!(f32::from_lua(Value::Int(2)), Ok(2.0));
+        assert_eq!(bool::from_lua(Value::Int(0)), Ok(true));
+        assert_eq!(bool::from_lua(Value::Nil), Ok(false));
+
+        fn error<T>(msg: &str) -> ConvertResult<T> {
+            Err(ConvertError(msg.to_string()))
+        }
+        assert_eq!(
+            i32::from_lua(Value::Float(2.5)),
+            error("number has no integer representation")
+        );
+        assert_eq!(
+            u8::from_lua(Value::Int(256)),
+            error("256 out of range for u8")
+        );
+        assert_eq!(
+            i64::from_lua(Value::str("1")),
+            error("integer expected, got string")
+        );
+        assert_eq!(
+            String::from_lua(Value::Int(1)),
+            error("string expected, got number")
+        );
+    }
+
+    #[test]
+    fn containers() {
+        round_trip(Some(1i64));
+        round_trip(None::<i64>);
+        assert_eq!(Option::<i64>::from_lua(Value::Nil), Ok(None));
+        round_trip(vec!["a".to_string(), "b".to_string()]);
+        round_trip(Vec::<i64>::new());
+        round_trip((1i64, "b".to_string(), false));
+
+        let mut map = HashMap::new();
+        map.insert("x".to_string(), 1.5f64);
+        map.insert("y".to_string(), 2.0);
+        round_trip(map);
+
+        // sequences are tables
+        match vec![10i64, 20].to_lua() {
+            Value::Table(t) => {
+                assert_eq!(t.borrow().len(), 2);
+                assert_eq!(t.borrow().get_int(2), Value::Int(20));
+            }
+            value => panic!("{:?}", value),
+        }
+        assert_eq!(
+            Vec::<i64>::from_lua(vec![Value::Int(1), Value::Bool(true)].to_lua()),
+            Err(ConvertError("integer expected, got boolean".to_string()))
+        );
+        assert_eq!(
+            HashMap::<String, i64>::from_lua(Value::Nil),
+            Err(ConvertError("table expected, got nil".to_string()))
+        );
+    }
+}
@@ -0,0 +1,263 @@
+mod common;
+
+mod coroutine_tests {
+    use crate::common::closure;
+    use rslua::consts::Const;
+    use rslua::coroutine;
+    use rslua::opcodes::*;
+    use rslua::proto::ProtoBuilder;
+    use rslua::table::Table;
+    use rslua::value::Value;
+    use rslua::vm::{RuntimeError, ThreadStatus, Vm};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // function(a) local x = coroutine.yield(a + 1) return x * 2 end
+    fn generator(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(3);
+        let lib = builder.constant(Const::Str("coroutine".to_string()));
+        let name = builder.constant(Const::Str("yield".to_string()));
+        let one = builder.constant(Const::Int(1));
+        let two = builder.constant(Const::Int(2));
+        builder.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(lib),
+        });
+        builder.emit(Instruction::GetTable {
+            dst: 1,
+            table: 1,
+            key: rk_as_k(name),
+        });
+        builder.emit(Instruction::Add {
+            dst: 2,
+            left: 0,
+            right: rk_as_k(one),
+        });
+        builder.emit(Instruction::Call {
+            func: 1,
+            args: 2,
+            results: 2,
+        });
+        builder.emit(Instruction::Mul {
+            dst: 1,
+            left: 1,
+            right: rk_as_k(two),
+        });
+        builder.emit(Instruction::Return { first: 1, count: 2 });
+        closure(vm, builder)
+    }
+
+    fn lib(vm: &Vm, name: &str) -> Value {
+        match vm.get_global("coroutine") {
+            Value::Table(t) => t.borrow().get_str(name),
+            value => panic!("{:?}", value),
+        }
+    }
+
+    #[test]
+    fn resume_and_yield() {
+        let mut vm = Vm::new();
+        coroutine::open(&mut vm);
+        let gen = generator(&mut vm);
+        let co = vm.create_thread(gen.clone());
+        assert_eq!(vm.resume(&co, vec![Value::Int(5)]), Ok(vec![Value::Int(6)]));
+        assert_eq!(co.borrow().status(), ThreadStatus::Suspended);
+        assert_eq!(
+            vm.resume(&co, vec![Value::Int(10)]),
+            Ok(vec![Value::Int(20)])
+        );
+        assert_eq!(co.borrow().status(), ThreadStatus::Dead);
+        assert_eq!(
+            vm.resume(&co, vec![]),
+            Err(RuntimeError::new("cannot resume dead coroutine"))
+        );
+
+        // from scripts
+        let create = lib(&vm, "create");
+        let resume = lib(&vm, "resume");
+        let status = lib(&vm, "status");
+        let co = vm.call(&create, &[gen]).unwrap().remove(0);
+        assert_eq!(
+            vm.call(&resume, &[co.clone(), Value::Int(1)]),
+            Ok(vec![Value::Bool(true), Value::Int(2)])
+        );
+        assert_eq!(
+            vm.call(&status, std::slice::from_ref(&co)),
+            Ok(vec![Value::str("suspended")])
+        );
+        // errors are returned
+        assert_eq!(
+            vm.call(&resume, &[co.clone(), Value::Nil]),
+            Ok(vec![
+                Value::Bool(false),
+                Value::str("main.0:1: attempt to perform arithmetic on a nil value")
+            ])
+        );
+        assert_eq!(vm.call(&status, &[co]), Ok(vec![Value::str("dead")]));
+        assert_eq!(
+            vm.call(&create, &[Value::Int(1)]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'create' (function expected)"
+            ))
+        );
+    }
+
+    // function() local n = 0; coroutine.yield(function() n = n + 1 end); coroutine.yield(n)
+    // return n end
+    fn counter(vm: &mut Vm) -> Value {
+        let mut inc = ProtoBuilder::new();
+        inc.stack_size(1);
+        let n = inc.up_value("n", true, 0);
+        let one = inc.constant(Const::Int(1));
+        inc.emit(Instruction::GetUpVal { dst: 0, up: n });
+        inc.emit(Instruction::Add {
+            dst: 0,
+            left: 0,
+            right: rk_as_k(one),
+        });
+        inc.emit(Instruction::SetUpVal { src: 0, up: n });
+        inc.emit(Instruction::Return { first: 0, count: 1 });
+
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(4);
+        let lib = builder.constant(Const::Str("coroutine".to_string()));
+        let name = builder.constant(Const::Str("yield".to_string()));
+        let zero = builder.constant(Const::Int(0));
+        let inc = builder.child(inc.build());
+        builder.emit(Instruction::LoadK { dst: 0, k: zero });
+        builder.emit(Instruction::Closure { dst: 1, proto: inc });
+        for value in [1, 0].iter() {
+            builder.emit(Instruction::GetTabUp {
+                dst: 2,
+                up: 0,
+                key: rk_as_k(lib),
+            });
+            builder.emit(Instruction::GetTable {
+                dst: 2,
+                table: 2,
+                key: rk_as_k(name),
+            });
+            builder.emit(Instruction::Move {
+                dst: 3,
+                src: *value,
+            });
+            builder.emit(Instruction::Call {
+                func: 2,
+                args: 2,
+                results: 1,
+            });
+        }
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        closure(vm, builder)
+    }
+
+    #[test]
+    fn native_body() {
+        // coroutine.create(coroutine.yield) returns what it's resumed with
+        let mut vm = Vm::new();
+        coroutine::open(&mut vm);
+        let resume = lib(&vm, "resume");
+        let co = vm
+            .call(&lib(&vm, "create"), &[lib(&vm, "yield")])
+            .unwrap()
+            .remove(0);
+        assert_eq!(
+            vm.call(&resume, &[co.clone(), Value::Int(1)]),
+            Ok(vec![Value::Bool(true), Value::Int(1)])
+        );
+        assert_eq!(
+            vm.call(&resume, &[co.clone(), Value::Int(2), Value::Int(3)]),
+            Ok(vec![Value::Bool(true), Value::Int(2), Value::Int(3)])
+        );
+        assert_eq!(
+            vm.call(&lib(&vm, "status"), &[co]),
+            Ok(vec![Value::str("dead")])
+        );
+    }
+
+    #[test]
+    fn up_values() {
+        // the variable of the suspended coroutine is shared with the main thread
+        let mut vm = Vm::new();
+        coroutine::open(&mut vm);
+        let counter = counter(&mut vm);
+        let co = vm.create_thread(counter);
+        let inc = vm.resume(&co, vec![]).unwrap().remove(0);
+        assert_eq!(vm.call(&inc, &[]), Ok(vec![]));
+        assert_eq!(vm.call(&inc, &[]), Ok(vec![]));
+        vm.collect_garbage();
+        assert_eq!(vm.resume(&co, vec![]), Ok(vec![Value::Int(2)]));
+        assert_eq!(vm.call(&inc, &[]), Ok(vec![]));
+        assert_eq!(vm.resume(&co, vec![]), Ok(vec![Value::Int(3)]));
+        // and closed when the coroutine returns
+        assert_eq!(vm.call(&inc, &[]), Ok(vec![]));
+        assert_eq!(co.borrow().status(), ThreadStatus::Dead);
+    }
+
+    #[test]
+    fn wrap() {
+        let mut vm = Vm::new();
+        coroutine::open(&mut vm);
+        let gen = generator(&mut vm);
+        let f = vm.call(&lib(&vm, "wrap"), &[gen]).unwrap().remove(0);
+        assert_eq!(vm.call(&f, &[Value::Int(1)]), Ok(vec![Value::Int(2)]));
+        assert_eq!(vm.call(&f, &[Value::Int(4)]), Ok(vec![Value::Int(8)]));
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("cannot resume dead coroutine"))
+        );
+    }
+
+    #[test]
+    fn yield_errors() {
+        let mut vm = Vm::new();
+        coroutine::open(&mut vm);
+        let yield_ = lib(&vm, "yield");
+        assert_eq!(
+            vm.call(&yield_, &[]),
+            Err(RuntimeError::new(
+                "attempt to yield from outside a coroutine"
+            ))
+        );
+        assert_eq!(
+            vm.call(&lib(&vm, "isyieldable"), &[]),
+            Ok(vec![Value::Bool(false)])
+        );
+        assert_eq!(
+            vm.call(&lib(&vm, "running"), &[]),
+            Ok(vec![Value::Nil, Value::Bool(true)])
+        );
+
+        // function() return t.x end, where __index of t is coroutine.yield
+        let meta = Rc::new(RefCell::new(Table::new()));
+        meta.borrow_mut().set_str("__index", yield_);
+        let t = Rc::new(RefCell::new(Table::new()));
+        t.borrow_mut().set_metatable(Some(meta));
+        vm.set_global("t", Value::Table(t));
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(1);
+        let t = builder.constant(Const::Str("t".to_string()));
+        let x = builder.constant(Const::Str("x".to_string()));
+        builder.emit(Instruction::GetTabUp {
+            dst: 0,
+            up: 0,
+            key: rk_as_k(t),
+        });
+        builder.emit(Instruction::GetTable {
+            dst: 0,
+            table: 0,
+            key: rk_as_k(x),
+        });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        let f = closure(&mut vm, builder);
+        let co = vm.create_thread(f);
+        assert_eq!(
+            vm.resume(&co, vec![]),
+            Err(RuntimeError::new(
+                "attempt to yield across a C-call boundary"
+            ))
+        );
+    }
+}
@@ -0,0 +1,84 @@
+mod cst_tests {
+    use rslua::ast::*;
+    use rslua::cst::*;
+    use rslua::lexer::Lexer;
+    use rslua::parser::Parser;
+    use rslua::tokens::TokenType;
+    use std::fs::{read_dir, File};
+    use std::io::prelude::*;
+
+    #[test]
+    fn lossless() -> std::io::Result<()> {
+        for entry in read_dir("lua")? {
+            let path = entry?.path();
+            let mut content = String::new();
+            File::open(&path)?.read_to_string(&mut content)?;
+            let tree = SyntaxTree::parse(&content).unwrap();
+            assert_eq!(tree.to_source(), content);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn trivia() {
+        let tree = SyntaxTree::parse("local a = 1 -- one\r\n--[==[ two\n]==] b = 2\n").unwrap();
+        let tokens = tree.tokens();
+        assert_eq!(tokens.len(), 8);
+        assert_eq!(tokens[3].text, "1");
+        assert_eq!(
+            tokens[4].leading,
+            vec![
+                Trivia {
+                    kind: TriviaKind::Whitespace,
+                    text: " ".to_string()
+                },
+                Trivia {
+                    kind: TriviaKind::Comment,
+                    text: "-- one".to_string()
+                },
+                Trivia {
+                    kind: TriviaKind::LineBreak,
+                    text: "\r\n".to_string()
+                },
+                Trivia {
+                    kind: TriviaKind::Comment,
+                    text: "--[==[ two\n]==]".to_string()
+                },
+                Trivia {
+                    kind: TriviaKind::Whitespace,
+                    text: " ".to_string()
+                },
+            ]
+        );
+        assert_eq!(tokens[7].t, TokenType::Eos);
+        assert_eq!(tokens[7].leading.len(), 1);
+    }
+
+    #[test]
+    fn edit() {
+        let input = "local a = { 1, 2 } -- numbers\n\nprint(  a[1]  )\n";
+        let mut lexer = Lexer::new();
+        let tokens = lexer.run(input).unwrap();
+        let mut tree = SyntaxTree::new(input, &tokens);
+        let block = Parser::new().run(tokens).unwrap();
+
+        let stat = &block.stats[1];
+        assert!(matches!(stat.stat, Stat::CallStat(_)));
+        let range = tree.token_range(stat.source);
+        assert_eq!(tree.text(range.clone()), "print(  a[1]  )");
+        tree.replace(range, "print(a[2])").unwrap();
+        assert_eq!(
+            tree.to_source(),
+            "local a = { 1, 2 } -- numbers\n\nprint(a[2])\n"
+        );
+
+        let range = tree.token_range(block.stats[0].source);
+        assert_eq!(tree.text(range.clone()), "local a = { 1, 2 }");
+        tree.replace(range.start + 3..range.end, "{}").unwrap();
+        assert_eq!(tree.to_source(), "local a = {} -- numbers\n\nprint(a[2])\n");
+        // positions are updated after editing
+        let print = &tree.tokens()[5];
+        assert_eq!(print.text, "print");
+        assert_eq!((print.source.pos, print.source.line, print.source.col), (25, 3, 1));
+    }
+}
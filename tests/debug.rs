@@ -0,0 +1,222 @@
+mod common;
+
+mod debug_tests {
+    use crate::common::compile;
+    use rslua::debug;
+    use rslua::value::Value;
+    use rslua::vm::{HookEvent, HookMask, NativeFunction, RuntimeError, Vm};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // a vm with a hook logging the events of `mask`
+    fn hooked(mask: HookMask) -> (Vm, Rc<RefCell<Vec<HookEvent>>>) {
+        let mut vm = Vm::new();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        vm.set_hook(mask, move |_, event| {
+            log.borrow_mut().push(event);
+            Ok(())
+        });
+        (vm, events)
+    }
+
+    #[test]
+    fn line_events() {
+        let (mut vm, events) = hooked(HookMask {
+            call: true,
+            ret: true,
+            line: true,
+            count: 0,
+        });
+        vm.run(compile("local a = 1\nlocal b = 2\n\nlocal c = a +\n b"))
+            .unwrap();
+        let events = events.borrow();
+        assert_eq!(
+            events[..4],
+            [
+                HookEvent::Call,
+                HookEvent::Line(1),
+                HookEvent::Line(2),
+                HookEvent::Line(4),
+            ]
+        );
+        assert_eq!(events.last(), Some(&HookEvent::Return));
+    }
+
+    #[test]
+    fn count_events() {
+        let (mut vm, events) = hooked(HookMask {
+            count: 2,
+            ..HookMask::default()
+        });
+        vm.run(compile("local a = 1 local b = 2 local c = 3 local d = 4"))
+            .unwrap();
+        assert!(events.borrow().iter().all(|e| *e == HookEvent::Count));
+        assert_eq!(events.borrow().len(), 2);
+
+        // errors of hooks are raised in the running code
+        vm.set_hook(
+            HookMask {
+                count: 1,
+                ..HookMask::default()
+            },
+            |_, _| Err(RuntimeError::new("stop")),
+        );
+        assert_eq!(
+            vm.run(compile("local a = 1")),
+            Err(RuntimeError::new("main:1: stop"))
+        );
+        vm.remove_hook();
+        assert_eq!(vm.hook_mask(), None);
+        assert!(vm.run(compile("local a = 1")).is_ok());
+    }
+
+    #[test]
+    fn sethook() {
+        let mut vm = Vm::new();
+        debug::open(&mut vm);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        let hook = Value::Native(Rc::new(NativeFunction::new("hook", move |_, args| {
+            log.borrow_mut().push(args);
+            Ok(Vec::new())
+        })));
+        let lib = match vm.get_global("debug") {
+            Value::Table(lib) => lib,
+            value => panic!("{:?}", value),
+        };
+        let sethook = lib.borrow().get_str("sethook");
+        let gethook = lib.borrow().get_str("gethook");
+        vm.call(&sethook, &[hook.clone(), Value::str("cl")])
+            .unwrap();
+        assert_eq!(
+            vm.call(&gethook, &[]),
+            Ok(vec![hook, Value::str("cl"), Value::Int(0)])
+        );
+        events.borrow_mut().clear();
+        vm.run(compile("local a = 1")).unwrap();
+        assert_eq!(
+            events.borrow()[..2],
+            [
+                vec![Value::str("call")],
+                vec![Value::str("line"), Value::Int(1)]
+            ]
+        );
+
+        vm.call(&sethook, &[]).unwrap();
+        assert_eq!(vm.call(&gethook, &[]), Ok(vec![]));
+        assert_eq!(vm.hook_mask(), None);
+        assert_eq!(
+            vm.call(&sethook, &[Value::Int(1)]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'sethook' (function expected)"
+            ))
+        );
+    }
+
+    fn lib(vm: &Vm, name: &str) -> Value {
+        match vm.get_global("debug") {
+            Value::Table(lib) => lib.borrow().get_str(name),
+            value => panic!("{:?}", value),
+        }
+    }
+
+    #[test]
+    fn frame_info() {
+        let mut vm = Vm::new();
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let log = lines.clone();
+        vm.set_hook(
+            HookMask {
+                line: true,
+                ..HookMask::default()
+            },
+            move |vm, event| {
+                let info = vm.frame_info(1).unwrap();
+                assert_eq!(event, HookEvent::Line(info.current_line.unwrap()));
+                assert_eq!(vm.frame_info(vm.stack_depth() + 1), None);
+                log.borrow_mut().push((info.function, info.current_line));
+                Ok(())
+            },
+        );
+        vm.run(compile("local a = 1\nlocal b = 2")).unwrap();
+        assert_eq!(
+            lines.borrow()[..2],
+            [("main".to_string(), Some(1)), ("main".to_string(), Some(2))]
+        );
+    }
+
+    #[test]
+    fn getinfo() {
+        let mut vm = Vm::new();
+        debug::open(&mut vm);
+        let main = vm.load(compile("local a = 1\n\nlocal b = 2")).unwrap();
+        let info = match vm.call(&lib(&vm, "getinfo"), std::slice::from_ref(&main)) {
+            Ok(values) => match &values[0] {
+                Value::Table(t) => t.clone(),
+                value => panic!("{:?}", value),
+            },
+            Err(e) => panic!("{:?}", e),
+        };
+        let info = info.borrow();
+        assert_eq!(info.get_str("what"), Value::str("main"));
+        assert_eq!(info.get_str("short_src"), Value::str("main"));
+        assert_eq!(info.get_str("linedefined"), Value::Int(1));
+        assert_eq!(info.get_str("lastlinedefined"), Value::Int(3));
+        assert_eq!(info.get_str("currentline"), Value::Int(-1));
+        assert_eq!(info.get_str("nparams"), Value::Int(0));
+        assert_eq!(info.get_str("func"), main);
+
+        let getinfo = lib(&vm, "getinfo");
+        assert_eq!(
+            vm.call(&getinfo, &[getinfo.clone(), Value::str("S")])
+                .map(|values| match &values[0] {
+                    Value::Table(t) => t.borrow().get_str("what"),
+                    value => value.clone(),
+                }),
+            Ok(Value::str("C"))
+        );
+        // no lua function is running
+        assert_eq!(vm.call(&getinfo, &[Value::Int(1)]), Ok(vec![Value::Nil]));
+        assert_eq!(
+            vm.call(&getinfo, &[Value::Int(1), Value::str("x")]),
+            Err(RuntimeError::new(
+                "bad argument #2 to 'getinfo' (invalid option)"
+            ))
+        );
+    }
+
+    #[test]
+    fn traceback() {
+        let mut vm = Vm::new();
+        debug::open(&mut vm);
+        let traceback = lib(&vm, "traceback");
+        let result = Rc::new(RefCell::new(Value::Nil));
+        let log = result.clone();
+        vm.set_hook(
+            HookMask {
+                line: true,
+                ..HookMask::default()
+            },
+            move |vm, event| {
+                if event == HookEvent::Line(2) {
+                    let values = vm.call(&traceback, &[Value::str("here")])?;
+                    *log.borrow_mut() = values[0].clone();
+                }
+                Ok(())
+            },
+        );
+        vm.run(compile("local a = 1\nlocal b = 2")).unwrap();
+        assert_eq!(
+            *result.borrow(),
+            Value::str("here\nstack traceback:\n\tmain:2: in function 'main'")
+        );
+
+        // other values are returned as they are
+        let traceback = lib(&vm, "traceback");
+        assert_eq!(
+            vm.call(&traceback, &[Value::Bool(true)]),
+            Ok(vec![Value::Bool(true)])
+        );
+    }
+}
@@ -0,0 +1,62 @@
+mod common;
+
+mod disasm_tests {
+    use crate::common::compile;
+
+    #[test]
+    fn disasm() {
+        let proto =
+            compile("local a, b = 1.5, 'x\\n' .. 'y'\nc = a\nlocal t; t[2] = -a\n\nt.x = 1e100");
+        assert_eq!(
+            proto.disasm(),
+            r#"main <main> (12 instructions)
+0+ params, 4 slots, 1 upvalue, 3 locals, 7 constants, 0 functions
+	1	[1]	LOADK    	0 -1	; 1.5
+	2	[1]	LOADK    	2 -2	; "x\n"
+	3	[1]	LOADK    	3 -3	; "y"
+	4	[1]	CONCAT   	1 2 3
+	5	[2]	MOVE     	2 0
+	6	[2]	SETTABUP 	0 -4 2	; _ENV "c"
+	7	[3]	LOADNIL  	2 0
+	8	[3]	UNM      	3 0
+	9	[3]	SETTABLE 	2 -5 3	; 2 -
+	10	[5]	LOADK    	3 -7	; 1e+100
+	11	[5]	SETTABLE 	2 -6 3	; "x" -
+	12	[5]	RETURN   	0 1
+constants (7) for main:
+	1	1.5
+	2	"x\n"
+	3	"y"
+	4	"c"
+	5	2
+	6	"x"
+	7	1e+100
+locals (3) for main:
+	0	a	5	13
+	1	b	5	13
+	2	t	8	13
+upvalues (1) for main:
+	0	_ENV	1	0
+"#
+        );
+    }
+
+    #[test]
+    fn disasm_constants() {
+        let proto = compile("local a, b, c, d, e = 2.0, 0.1, 1e-5, -123456789012345.0, 'a\\0\"'");
+        let listing = proto.disasm();
+        for k in [
+            "2.0",
+            "0.1",
+            "1e-05",
+            "-1.2345678901234e+14",
+            r#""a\000\"""#,
+        ] {
+            assert!(listing.contains(&format!("\t; {}\n", k)), "{}", k);
+        }
+        // stripped names
+        let mut proto = compile("x = 1");
+        proto.strip_debug_info();
+        assert!(proto.disasm().contains("\t; - \"x\"\n"));
+    }
+}
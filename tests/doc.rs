@@ -0,0 +1,130 @@
+mod doc_tests {
+    use rslua::doc::*;
+    use rslua::lexer::{Lexer, LexerConfig};
+    use rslua::parser::Parser;
+
+    fn try_extract(input: &str) -> ModuleDoc {
+        let mut lexer = Lexer::new();
+        lexer.set_debug(true);
+        lexer.set_config(LexerConfig {
+            use_origin_string: false,
+            reserve_comments: true,
+            ..LexerConfig::default()
+        });
+        if let Ok(tokens) = lexer.run(input) {
+            let mut parser = Parser::new();
+            parser.set_debug(true);
+            if let Ok(block) = parser.run(tokens) {
+                return DocExtractor::run("test", &block);
+            }
+        }
+        unreachable!()
+    }
+
+    const INPUT: &str = r#"
+--- Math helpers.
+-- Second line of module description.
+
+local M = {}
+
+--- Add two numbers.
+-- @tparam number a first operand
+-- @param b second operand
+-- @treturn number the sum
+function M.add(a, b)
+    return a + b
+end
+
+---Scale a vector.
+---@param v table the vector
+---@param k number factor
+---@return table scaled vector
+M.scale = function(v, k, ...) end
+
+-- not a doc comment
+local function helper() end
+
+--- Method docs.
+-- @return nothing
+function M:method() end
+"#;
+
+    #[test]
+    fn extract() {
+        let doc = try_extract(INPUT);
+        assert_eq!(doc.desc, "Math helpers.\nSecond line of module description.");
+        let names: Vec<&str> = doc.funcs.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["M.add", "M.scale", "M:method"]);
+        assert_eq!(
+            doc.funcs[0].params[0],
+            ParamDoc {
+                name: "a".to_string(),
+                ty: Some("number".to_string()),
+                desc: "first operand".to_string(),
+            }
+        );
+        assert_eq!(doc.funcs[1].line, 19);
+    }
+
+    #[test]
+    fn plain_comments() {
+        // a license header isn't a doc comment, the module is described by the next doc comment
+        let doc = try_extract("-- Copyright (c) someone\n-- MIT license\n\n--- Module.\n\nlocal M = {}");
+        assert_eq!(doc.desc, "Module.");
+    }
+
+    #[test]
+    fn markdown() {
+        assert_eq!(
+            try_extract(INPUT).to_markdown(),
+            r#"# test
+
+Math helpers.
+Second line of module description.
+
+## `M.add(a, b)`
+
+Add two numbers.
+
+**Parameters**
+
+- `a` (`number`): first operand
+- `b`: second operand
+
+**Returns**
+
+- `number`: the sum
+
+## `M.scale(v, k, ...)`
+
+Scale a vector.
+
+**Parameters**
+
+- `v` (`table`): the vector
+- `k` (`number`): factor
+- `...`
+
+**Returns**
+
+- `table`: scaled vector
+
+## `M:method()`
+
+Method docs.
+
+**Returns**
+
+- nothing
+"#
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            try_extract("---say \"hi\"\n---@param name string\nlocal function hi(name) end").to_json(),
+            r#"{"name":"test","desc":"","funcs":[{"name":"hi","line":3,"desc":"say \"hi\"","params":[{"name":"name","type":"string","desc":""}],"returns":[]}]}"#
+        );
+    }
+}
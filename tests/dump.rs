@@ -0,0 +1,186 @@
+mod common;
+
+mod dump_tests {
+    use crate::common::compile;
+    use rslua::dump::{DebugInfo, UndumpError};
+    use rslua::proto::Proto;
+
+    fn header() -> Vec<u8> {
+        let mut header = b"\x1bLua\x53\x00\x19\x93\r\n\x1a\n\x04\x08\x04\x08\x08".to_vec();
+        header.extend_from_slice(&0x5678i64.to_le_bytes());
+        header.extend_from_slice(&370.5f64.to_le_bytes());
+        header
+    }
+
+    fn int(output: &mut Vec<u8>, i: u32) {
+        output.extend_from_slice(&i.to_le_bytes());
+    }
+
+    #[test]
+    fn dump() {
+        let proto = compile("x = 1");
+        let mut expected = header();
+        // upvalues of main closure, source, line defined, last line defined
+        expected.extend_from_slice(&[1, 0]);
+        int(&mut expected, 0);
+        int(&mut expected, 0);
+        // params, vararg, stack size
+        expected.extend_from_slice(&[0, 1, 2]);
+        // LOADK 0 0, SETTABUP 0 257 0, RETURN 0 1
+        int(&mut expected, 3);
+        int(&mut expected, 0x0000_0001);
+        int(&mut expected, 0x8080_0008);
+        int(&mut expected, 0x0080_0026);
+        // 1, "x"
+        int(&mut expected, 2);
+        expected.push(0x13);
+        expected.extend_from_slice(&1i64.to_le_bytes());
+        expected.extend_from_slice(&[0x04, 2, b'x']);
+        // _ENV
+        int(&mut expected, 1);
+        expected.extend_from_slice(&[1, 0]);
+        // protos
+        int(&mut expected, 0);
+
+        let mut stripped = expected.clone();
+        int(&mut stripped, 0);
+        int(&mut stripped, 0);
+        int(&mut stripped, 0);
+        assert_eq!(proto.dump(true), stripped);
+
+        // every instruction is at line 1, no locals, name of _ENV
+        int(&mut expected, 3);
+        for _ in 0..3 {
+            int(&mut expected, 1);
+        }
+        int(&mut expected, 0);
+        int(&mut expected, 1);
+        expected.extend_from_slice(&[5, b'_', b'E', b'N', b'V']);
+        assert_eq!(proto.dump(false), expected);
+    }
+
+    #[test]
+    fn dump_constants() {
+        let long = "a".repeat(300);
+        let proto = compile(&format!(
+            "local a, b, c, d = 1.5, true, '{}', '{}'",
+            "s".repeat(40),
+            long
+        ));
+        let output = proto.dump(false);
+        let find = |bytes: &[u8]| output.windows(bytes.len()).any(|w| w == bytes);
+        let mut float = vec![0x03];
+        float.extend_from_slice(&1.5f64.to_le_bytes());
+        assert!(find(&float));
+        // short string with size + 1 in one byte
+        let mut short = vec![0x04, 41];
+        short.extend_from_slice("s".repeat(40).as_bytes());
+        assert!(find(&short));
+        // long string with size + 1 after 0xFF
+        let mut long_str = vec![0x14, 0xFF];
+        long_str.extend_from_slice(&301u64.to_le_bytes());
+        long_str.extend_from_slice(long.as_bytes());
+        assert!(find(&long_str));
+        // locals are active from their initialization to the end of the function
+        let mut local = vec![2, b'd'];
+        local.extend_from_slice(&(proto.code.len() as u32 - 1).to_le_bytes());
+        local.extend_from_slice(&(proto.code.len() as u32).to_le_bytes());
+        assert!(find(&local));
+    }
+
+    #[test]
+    fn undump() {
+        let input = "local a, b = 1.5, 'x' .. 'y'; c = a; local t; t[2] = -a";
+        let proto = compile(input);
+        for strip in [false, true] {
+            let chunk = proto.dump(strip);
+            let loaded = Proto::undump(&chunk).unwrap();
+            assert_eq!(loaded.dump(strip), chunk);
+            if !strip {
+                assert_eq!(format!("{:?}", loaded), format!("{:?}", proto));
+                assert_eq!(loaded.get_up_var("_ENV"), Some(0));
+            }
+        }
+    }
+
+    #[test]
+    fn undump_errors() {
+        let error = |msg: &str| Err(UndumpError(format!("bad binary format ({})", msg)));
+        let chunk = compile("x = 1").dump(false);
+        let undump = |chunk: &[u8]| Proto::undump(chunk).map(|_| ());
+        let patched = |i: usize, b: u8| {
+            let mut chunk = chunk.clone();
+            chunk[i] = b;
+            chunk
+        };
+        assert_eq!(undump(b"x = 1"), error("not a binary chunk"));
+        assert_eq!(undump(&patched(4, 0x54)), error("version mismatch"));
+        assert_eq!(undump(&patched(5, 1)), error("format mismatch"));
+        assert_eq!(undump(&patched(8, b'\n')), error("corrupted"));
+        assert_eq!(undump(&patched(13, 4)), error("size_t size mismatch"));
+        assert_eq!(undump(&patched(15, 4)), error("lua_Integer size mismatch"));
+        assert_eq!(undump(&patched(17, 0)), error("endianness mismatch"));
+        assert_eq!(undump(&patched(32, 0)), error("float format mismatch"));
+        for n in [5, 20, 40, chunk.len() - 1] {
+            assert_eq!(undump(&chunk[..n]), error("truncated chunk"));
+        }
+        let mut extra = chunk.clone();
+        extra.push(0);
+        assert_eq!(undump(&extra), error("extra bytes after chunk"));
+        // header, upvalues, source, lines, params, vararg, stack size, code size
+        let code = 33 + 1 + 1 + 8 + 3 + 4;
+        assert_eq!(undump(&patched(code, 0x3F)), error("bad opcode 63 at pc 0"));
+        // after the first constant 1
+        assert_eq!(undump(&patched(code + 12 + 4 + 9, 7)), error("bad constant type 7"));
+        // huge counts don't reserve memory before reading what they count
+        let up_values = code + 12 + 4 + 9 + 3;
+        let mut huge = chunk.clone();
+        huge[up_values..up_values + 4].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert_eq!(undump(&huge), error("truncated chunk"));
+        // a long string of size 0 instead of the size of "x"
+        let size = code + 12 + 4 + 9 + 1;
+        let mut empty = chunk.clone();
+        empty.splice(size..size + 2, vec![0xFF, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(undump(&empty), error("bad string size"));
+    }
+
+    #[test]
+    fn debug_info() {
+        let mut proto = compile("local a = 1\nb = a\nlocal c");
+        let chunk = proto.dump(false);
+        let stripped = proto.dump(true);
+        let info = proto.take_debug_info();
+        assert_eq!(info.line_info, vec![1, 2, 2, 3, 3]);
+        // names and the pcs of their scopes
+        let locals: Vec<_> = info
+            .locals
+            .iter()
+            .map(|l| (l.name.as_str(), l.start_pc, l.end_pc))
+            .collect();
+        assert_eq!(locals, vec![("a", 1, 5), ("c", 4, 5)]);
+        assert_eq!(info.up_values, vec!["_ENV"]);
+        assert_eq!(proto.line_of(0), None);
+
+        // ship the stripped chunk and keep debug info apart
+        let saved = info.dump();
+        let info = DebugInfo::undump(&saved).unwrap();
+        let mut loaded = Proto::undump(&stripped).unwrap();
+        loaded.apply_debug_info(&info).unwrap();
+        assert_eq!(loaded.line_of(1), Some(2));
+        assert_eq!(loaded.dump(false), chunk);
+
+        let other = compile("x = 1; y = 2");
+        assert_eq!(
+            compile("x = 1").apply_debug_info(&other.debug_info()),
+            Err(UndumpError("debug info mismatch".to_string()))
+        );
+        assert_eq!(
+            DebugInfo::undump(&stripped),
+            Err(UndumpError("bad binary format (not debug info)".to_string()))
+        );
+        assert_eq!(
+            DebugInfo::undump(&saved[..saved.len() - 1]),
+            Err(UndumpError("bad binary format (truncated chunk)".to_string()))
+        );
+    }
+}
@@ -0,0 +1,531 @@
+mod common;
+
+mod gc_tests {
+    use crate::common::closure;
+    use rslua::base;
+    use rslua::consts::Const;
+    use rslua::gc::GcMode;
+    use rslua::opcodes::*;
+    use rslua::proto::ProtoBuilder;
+    use rslua::value::{TableRef, UserData, Value};
+    use rslua::vm::{RuntimeError, Vm};
+    use std::rc::{Rc, Weak};
+
+    // local a = {} local b = {} a.x = b b.x = a return a
+    fn cycle() -> ProtoBuilder {
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(2);
+        builder.up_value("_ENV", true, 0);
+        let x = builder.constant(Const::Str("x".to_string()));
+        for dst in 0..2 {
+            builder.emit(Instruction::NewTable {
+                dst,
+                array: 0,
+                hash: 0,
+            });
+        }
+        builder.emit(Instruction::SetTable {
+            table: 0,
+            key: rk_as_k(x),
+            value: 1,
+        });
+        builder.emit(Instruction::SetTable {
+            table: 1,
+            key: rk_as_k(x),
+            value: 0,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        builder
+    }
+
+    // local t = {} t.f = function() return t end return t
+    fn closure_cycle() -> ProtoBuilder {
+        let mut child = ProtoBuilder::new();
+        child.stack_size(1);
+        child.up_value("t", true, 0);
+        child.emit(Instruction::GetUpVal { dst: 0, up: 0 });
+        child.emit(Instruction::Return { first: 0, count: 2 });
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(2);
+        builder.up_value("_ENV", true, 0);
+        let f = builder.constant(Const::Str("f".to_string()));
+        let child = builder.child(child.build());
+        builder.emit(Instruction::NewTable {
+            dst: 0,
+            array: 0,
+            hash: 0,
+        });
+        builder.emit(Instruction::Closure {
+            dst: 1,
+            proto: child,
+        });
+        builder.emit(Instruction::SetTable {
+            table: 0,
+            key: rk_as_k(f),
+            value: 1,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        builder
+    }
+
+    fn table(value: Value) -> TableRef {
+        match value {
+            Value::Table(t) => t,
+            value => panic!("{:?}", value),
+        }
+    }
+
+    fn run(vm: &mut Vm, builder: ProtoBuilder) -> TableRef {
+        table(vm.run(builder.build()).unwrap().remove(0))
+    }
+
+    // clear the registers, which still hold the results
+    fn clear_stack(vm: &mut Vm) {
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(2);
+        builder.emit(Instruction::LoadNil { dst: 0, n: 1 });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        vm.run(builder.build()).unwrap();
+    }
+
+    fn collect(vm: &mut Vm) {
+        clear_stack(vm);
+        vm.collect_garbage();
+    }
+
+    #[test]
+    fn collect_cycles() {
+        let mut vm = Vm::new();
+        let weak: Weak<_> = Rc::downgrade(&run(&mut vm, cycle()));
+        assert!(weak.upgrade().is_some());
+        collect(&mut vm);
+        assert!(weak.upgrade().is_none());
+
+        let weak: Weak<_> = Rc::downgrade(&run(&mut vm, closure_cycle()));
+        collect(&mut vm);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn keep_reachable() {
+        let mut vm = Vm::new();
+        // held by the host
+        let t = run(&mut vm, cycle());
+        collect(&mut vm);
+        let b = table(t.borrow().get_str("x"));
+        assert_eq!(b.borrow().get_str("x"), Value::Table(t.clone()));
+
+        // in the globals and the registry
+        vm.set_global("t", Value::Table(t));
+        let weak = Rc::downgrade(&run(&mut vm, closure_cycle()));
+        vm.registry()
+            .borrow_mut()
+            .set_str("t", Value::Table(weak.upgrade().unwrap()));
+        collect(&mut vm);
+        assert!(table(vm.get_global("t")).borrow().get_str("x").is_truthy());
+        let t = weak.upgrade().unwrap();
+        let f = t.borrow().get_str("f");
+        assert_eq!(vm.call(&f, &[]), Ok(vec![Value::Table(t.clone())]));
+    }
+
+    #[test]
+    fn collect_automatically() {
+        let mut vm = Vm::new();
+        let mut weak = Vec::new();
+        for _ in 0..1000 {
+            weak.push(Rc::downgrade(&run(&mut vm, cycle())));
+        }
+        assert!(weak.iter().filter(|t| t.upgrade().is_none()).count() > 500);
+    }
+
+    #[test]
+    fn collectgarbage() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let collectgarbage = vm.get_global("collectgarbage");
+        let count = |vm: &mut Vm| match vm.call(&collectgarbage, &[Value::str("count")]) {
+            Ok(values) => match values[..] {
+                [Value::Float(kb)] => kb,
+                _ => panic!("{:?}", values),
+            },
+            Err(e) => panic!("{:?}", e),
+        };
+        let t = run(&mut vm, cycle());
+        let before = count(&mut vm);
+        assert!(before > 0.0);
+        drop(t);
+        clear_stack(&mut vm);
+        assert_eq!(vm.call(&collectgarbage, &[]), Ok(vec![Value::Int(0)]));
+        assert!(count(&mut vm) < before);
+        assert_eq!(
+            vm.call(&collectgarbage, &[Value::str("x")]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'collectgarbage' (invalid option 'x')"
+            ))
+        );
+    }
+
+    #[test]
+    fn incremental() {
+        let mut vm = Vm::new();
+        assert_eq!(vm.set_gc_mode(GcMode::Incremental), GcMode::Full);
+        vm.tune_gc(Some(100), Some(10));
+        let mut weak = Vec::new();
+        for _ in 0..1000 {
+            weak.push(Rc::downgrade(&run(&mut vm, cycle())));
+        }
+        assert!(weak.iter().filter(|t| t.upgrade().is_none()).count() > 500);
+
+        // steps until the collection is finished
+        clear_stack(&mut vm);
+        let mut steps = 1;
+        while !vm.step_garbage(10) {
+            steps += 1;
+        }
+        assert!(steps > 1);
+        assert!(weak.iter().all(|t| t.upgrade().is_none()));
+    }
+
+    // a cycle moved from one table to another in any phase of a collection
+    #[test]
+    fn write_barrier() {
+        for before in 0..40 {
+            let mut vm = Vm::new();
+            let a = run(&mut vm, cycle());
+            let b = run(&mut vm, cycle());
+            let c = run(&mut vm, cycle());
+            a.borrow_mut().set_str("c", Value::Table(c));
+            clear_stack(&mut vm);
+            for _ in 0..before {
+                vm.step_garbage(1);
+            }
+            let c = a.borrow().get_str("c");
+            a.borrow_mut().set_str("c", Value::Nil);
+            b.borrow_mut().set_str("c", c);
+            while !vm.step_garbage(1) {}
+            let c = table(b.borrow().get_str("c"));
+            let d = table(c.borrow().get_str("x"));
+            assert_eq!(d.borrow().get_str("x"), Value::Table(c.clone()));
+        }
+    }
+
+    #[test]
+    fn collectgarbage_incremental() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let collectgarbage = vm.get_global("collectgarbage");
+        assert_eq!(
+            vm.call(
+                &collectgarbage,
+                &[Value::str("incremental"), Value::Int(150)]
+            ),
+            Ok(vec![Value::str("full")])
+        );
+        let weak = Rc::downgrade(&run(&mut vm, cycle()));
+        clear_stack(&mut vm);
+        let mut finished = Ok(vec![Value::Bool(false)]);
+        while finished == Ok(vec![Value::Bool(false)]) {
+            finished = vm.call(&collectgarbage, &[Value::str("step")]);
+        }
+        assert_eq!(finished, Ok(vec![Value::Bool(true)]));
+        assert!(weak.upgrade().is_none());
+        assert_eq!(
+            vm.call(&collectgarbage, &[Value::str("incremental")]),
+            Ok(vec![Value::str("incremental")])
+        );
+    }
+
+    // a table with the mode, made weak by setmetatable
+    fn weak_table(vm: &mut Vm, mode: &str) -> TableRef {
+        let meta = Value::new_table();
+        if let Value::Table(meta) = &meta {
+            meta.borrow_mut().set_str("__mode", Value::str(mode));
+        }
+        let t = Value::new_table();
+        let setmetatable = vm.get_global("setmetatable");
+        assert_eq!(
+            vm.call(&setmetatable, &[t.clone(), meta]),
+            Ok(vec![t.clone()])
+        );
+        table(t)
+    }
+
+    #[test]
+    fn weak_values() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let weak = weak_table(&mut vm, "v");
+        let x = run(&mut vm, cycle());
+        let y = run(&mut vm, cycle());
+        let w = Rc::downgrade(&x);
+        weak.borrow_mut().set_str("x", Value::Table(x));
+        weak.borrow_mut().set_str("y", Value::Table(y.clone()));
+        weak.borrow_mut().set_str("s", Value::str("s"));
+        weak.borrow_mut().set_int(1, Value::user_data(1));
+        collect(&mut vm);
+        assert!(w.upgrade().is_none());
+        let weak = weak.borrow();
+        assert_eq!(weak.get_str("x"), Value::Nil);
+        assert_eq!(weak.get_str("y"), Value::Table(y));
+        assert_eq!(weak.get_str("s"), Value::str("s"));
+        assert_eq!(weak.get_int(1), Value::Nil);
+    }
+
+    #[test]
+    fn weak_keys() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let weak = weak_table(&mut vm, "k");
+        let kept = Value::user_data(1);
+        weak.borrow_mut().set(kept.clone(), Value::Int(1));
+        weak.borrow_mut().set(Value::user_data(2), Value::Int(2));
+
+        // ephemerons, values referring to their keys don't keep them
+        let k = run(&mut vm, cycle());
+        let w = Rc::downgrade(&k);
+        let v = Value::new_table();
+        if let Value::Table(v) = &v {
+            v.borrow_mut().set_str("k", Value::Table(k.clone()));
+        }
+        weak.borrow_mut().set(Value::Table(k), v);
+
+        // values are kept while their keys are, even as keys of the same table
+        let a = Value::new_table();
+        let b = Value::new_table();
+        let c = Value::new_table();
+        weak.borrow_mut().set(a.clone(), b.clone());
+        weak.borrow_mut().set(b.clone(), c.clone());
+        let b = Rc::downgrade(&table(b));
+        let c = Rc::downgrade(&table(c));
+
+        collect(&mut vm);
+        assert!(w.upgrade().is_none());
+        assert!(b.upgrade().is_some() && c.upgrade().is_some());
+        assert_eq!(weak.borrow().get(&kept), Value::Int(1));
+        assert_eq!(weak.borrow().iter().count(), 3);
+
+        drop(a);
+        collect(&mut vm);
+        assert!(b.upgrade().is_none() && c.upgrade().is_none());
+        assert_eq!(weak.borrow().iter().count(), 1);
+    }
+
+    #[test]
+    fn metatables() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let getmetatable = vm.get_global("getmetatable");
+        let setmetatable = vm.get_global("setmetatable");
+        let t = Value::new_table();
+        let meta = Value::new_table();
+        assert_eq!(
+            vm.call(&getmetatable, std::slice::from_ref(&t)),
+            Ok(vec![Value::Nil])
+        );
+        vm.call(&setmetatable, &[t.clone(), meta.clone()]).unwrap();
+        assert_eq!(
+            vm.call(&getmetatable, std::slice::from_ref(&t)),
+            Ok(vec![meta.clone()])
+        );
+        if let Value::Table(meta) = &meta {
+            meta.borrow_mut().set_str("__metatable", Value::Bool(false));
+        }
+        assert_eq!(
+            vm.call(&getmetatable, std::slice::from_ref(&t)),
+            Ok(vec![Value::Bool(false)])
+        );
+        assert_eq!(
+            vm.call(&setmetatable, &[t, Value::Nil]),
+            Err(RuntimeError::new("cannot change a protected metatable"))
+        );
+        assert_eq!(
+            vm.call(&setmetatable, &[Value::Int(1), Value::Nil]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'setmetatable' (table expected)"
+            ))
+        );
+    }
+
+    // function(o) log = log .. o.id end
+    fn log_id(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(3);
+        let log = builder.constant(Const::Str("log".to_string()));
+        let id = builder.constant(Const::Str("id".to_string()));
+        builder.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(log),
+        });
+        builder.emit(Instruction::GetTable {
+            dst: 2,
+            table: 0,
+            key: rk_as_k(id),
+        });
+        builder.emit(Instruction::Concat {
+            dst: 1,
+            first: 1,
+            last: 2,
+        });
+        builder.emit(Instruction::SetTabUp {
+            up: 0,
+            key: rk_as_k(log),
+            value: 1,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        closure(vm, builder)
+    }
+
+    // function(o) saved = o end
+    fn save(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(1);
+        let saved = builder.constant(Const::Str("saved".to_string()));
+        builder.emit(Instruction::SetTabUp {
+            up: 0,
+            key: rk_as_k(saved),
+            value: 0,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        closure(vm, builder)
+    }
+
+    fn finalizer(gc: Value) -> TableRef {
+        let meta = table(Value::new_table());
+        meta.borrow_mut().set_str("__gc", gc);
+        meta
+    }
+
+    fn with_id(id: &str) -> TableRef {
+        let t = table(Value::new_table());
+        t.borrow_mut().set_str("id", Value::str(id));
+        t
+    }
+
+    #[test]
+    fn finalizers() {
+        let mut vm = Vm::new();
+        vm.set_global("log", Value::str(""));
+        let meta = finalizer(log_id(&mut vm));
+        for id in ["a", "b", "c"].iter() {
+            vm.set_metatable(&with_id(id), Some(meta.clone()));
+        }
+        // without a finalizer when the metatable was set
+        vm.set_metatable(&with_id("d"), Some(table(Value::new_table())));
+        let e = with_id("e");
+        vm.set_metatable(&e, Some(meta.clone()));
+        collect(&mut vm);
+        // in the reverse order of getting them
+        assert_eq!(vm.get_global("log"), Value::str("cba"));
+        drop(e);
+        collect(&mut vm);
+        collect(&mut vm);
+        assert_eq!(vm.get_global("log"), Value::str("cbae"));
+    }
+
+    #[test]
+    fn resurrection() {
+        let mut vm = Vm::new();
+        let meta = finalizer(save(&mut vm));
+        let t = run(&mut vm, cycle());
+        let weak = Rc::downgrade(&t);
+        vm.set_metatable(&t, Some(meta));
+        drop(t);
+        collect(&mut vm);
+        // the object and what it refers to are alive again
+        let t = table(vm.get_global("saved"));
+        let b = table(t.borrow().get_str("x"));
+        assert_eq!(b.borrow().get_str("x"), Value::Table(t.clone()));
+        drop((t, b));
+
+        // and collected when unreachable again, without running the finalizer
+        vm.set_global("saved", Value::Bool(false));
+        collect(&mut vm);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(vm.get_global("saved"), Value::Bool(false));
+    }
+
+    #[test]
+    fn finalize_user_data() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let meta = finalizer(save(&mut vm));
+        let data = Rc::new(UserData::new(1));
+        vm.set_user_data_metatable(&data, Some(meta));
+        drop(data);
+        collect(&mut vm);
+        match vm.get_global("saved") {
+            Value::UserData(data) => assert_eq!(*data.borrow::<i32>().unwrap(), 1),
+            value => panic!("{:?}", value),
+        }
+        vm.set_global("saved", Value::Nil);
+        collect(&mut vm);
+        assert_eq!(vm.get_global("saved"), Value::Nil);
+
+        // errors of finalizers are ignored
+        let error = vm.get_global("error");
+        vm.set_metatable(&with_id("a"), Some(finalizer(error)));
+        collect(&mut vm);
+    }
+
+    struct Point {
+        x: i64,
+    }
+
+    #[test]
+    fn typed_user_data() {
+        let mut vm = Vm::new();
+        let meta = finalizer(save(&mut vm));
+        meta.borrow_mut().set_str("kind", Value::str("point"));
+        vm.set_type_metatable::<Point>(Some(meta.clone()));
+        assert!(vm.type_metatable::<Point>().is_some());
+        assert!(vm.type_metatable::<i32>().is_none());
+
+        // with the metatable of its type, and downcast back to it
+        let point = vm.create_user_data(Point { x: 1 });
+        assert!(Rc::ptr_eq(&vm.get_metatable(&point).unwrap(), &meta));
+        let other = vm.create_user_data(1);
+        assert!(vm.get_metatable(&other).is_none());
+        let data = match &point {
+            Value::UserData(data) => data.clone(),
+            value => panic!("{:?}", value),
+        };
+        assert!(data.is::<Point>());
+        assert!(data.borrow::<i32>().is_none());
+        data.borrow_mut::<Point>().unwrap().x += 1;
+        assert_eq!(data.borrow::<Point>().unwrap().x, 2);
+
+        // `__gc` of the type is called
+        drop((point, data));
+        collect(&mut vm);
+        match vm.get_global("saved") {
+            Value::UserData(data) => assert_eq!(data.borrow::<Point>().unwrap().x, 2),
+            value => panic!("{:?}", value),
+        }
+
+        // a cycle through its metatable is freed
+        let data = match other {
+            Value::UserData(data) => data,
+            value => panic!("{:?}", value),
+        };
+        let weak = Rc::downgrade(&data);
+        let own = table(Value::new_table());
+        own.borrow_mut()
+            .set_str("self", Value::UserData(data.clone()));
+        vm.set_user_data_metatable(&data, Some(own));
+        drop(data);
+        collect(&mut vm);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn finalize_on_drop() {
+        let mut vm = Vm::new();
+        let meta = finalizer(save(&mut vm));
+        let t = with_id("a");
+        vm.set_metatable(&t, Some(meta));
+        let globals = vm.globals().clone();
+        drop(vm);
+        assert_eq!(globals.borrow().get_str("saved"), Value::Table(t));
+    }
+}
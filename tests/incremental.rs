@@ -0,0 +1,109 @@
+mod incremental_tests {
+    use rslua::incremental::*;
+    use std::fs::File;
+    use std::io::prelude::*;
+
+    fn full_parse(source: &str) -> String {
+        let mut parser = IncrementalParser::new();
+        format!("{:?}", parser.run(source).unwrap())
+    }
+
+    // apply edit and check the result equals a full parse, sources included
+    fn check_edit(parser: &mut IncrementalParser, start: usize, end: usize, text: &str) {
+        let edit = TextEdit {
+            start,
+            end,
+            text: text.to_string(),
+        };
+        let block = format!("{:?}", parser.edit(&edit).unwrap());
+        assert_eq!(block, full_parse(parser.source()));
+    }
+
+    #[test]
+    fn edit() -> std::io::Result<()> {
+        let mut file = File::open(r"lua/json.lua")?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let mut parser = IncrementalParser::new();
+        parser.run(&content).unwrap();
+
+        // rename a param inside a function
+        let pos = content.find("local function encode_nil(val)").unwrap();
+        check_edit(&mut parser, pos + 26, pos + 29, "value");
+        let reparsed = parser.reparsed().unwrap();
+        assert!(reparsed.end - reparsed.start < content.len() / 4);
+
+        // insert statements, which shifts lines of the following ones
+        check_edit(&mut parser, pos, pos, "local x = 1\nlocal y = { x,\n 2 }\n");
+        assert!(parser.reparsed().is_some());
+
+        // delete them again
+        check_edit(&mut parser, pos, pos + 32, "");
+        assert_eq!(parser.source(), content.replace("encode_nil(val)", "encode_nil(value)"));
+
+        // edit at the start and at the end
+        check_edit(&mut parser, 0, 0, "local first = true ");
+        let len = parser.source().len();
+        check_edit(&mut parser, len, len, "\nreturn json");
+        Ok(())
+    }
+
+    #[test]
+    fn same_line() {
+        let mut parser = IncrementalParser::new();
+        parser.run("a = 1 b = 2 c = 3 d = 4 e = 5").unwrap();
+        check_edit(&mut parser, 16, 17, "30000");
+        assert_eq!(parser.reparsed(), Some(6..33));
+        check_edit(&mut parser, 16, 21, "3\n\n");
+    }
+
+    #[test]
+    fn fallback() {
+        let mut parser = IncrementalParser::new();
+        parser.run("a = 1\nb = 2\nc = x\nd = 4\ne = 5 --]]\nf = 6").unwrap();
+        // the long comment changes the rest of the source
+        check_edit(&mut parser, 18, 18, "--[[");
+        assert_eq!(parser.reparsed(), None);
+        check_edit(&mut parser, 18, 22, "");
+        assert!(parser.reparsed().is_some());
+
+        // the edited statement continues into the next one, which is reparsed as well
+        parser.run("a = 1\nb = 2\ndo end\n(f)()\ne = 5").unwrap();
+        check_edit(&mut parser, 12, 18, "x = y");
+        assert_eq!(parser.reparsed(), Some(6..29));
+        check_edit(&mut parser, 16, 16, "(g)");
+        assert!(parser.reparsed().is_some());
+
+        // syntax errors are reported, the next edit parses everything again
+        let edit = TextEdit {
+            start: 0,
+            end: 0,
+            text: "+ ".to_string(),
+        };
+        assert!(parser.edit(&edit).is_err());
+        assert!(parser.block().is_none());
+        check_edit(&mut parser, 0, 2, "");
+        assert_eq!(parser.reparsed(), None);
+    }
+
+    #[test]
+    fn invalid_edit() {
+        let mut parser = IncrementalParser::new();
+        parser.run("s = 'é'").unwrap();
+        // reversed, beyond the source, and inside a char
+        for (start, end) in [(3, 2), (0, 10), (10, 11), (6, 6), (5, 6)].iter() {
+            let edit = TextEdit {
+                start: *start,
+                end: *end,
+                text: "x".to_string(),
+            };
+            match parser.edit(&edit) {
+                Err(ParseError::InvalidEdit(range)) => assert_eq!(range, *start..*end),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(parser.source(), "s = 'é'");
+        assert!(parser.block().is_some());
+        check_edit(&mut parser, 5, 7, "ü");
+    }
+}
@@ -0,0 +1,437 @@
+mod common;
+
+mod limits_tests {
+    use crate::common::closure;
+    use rslua::base;
+    use rslua::consts::Const;
+    use rslua::opcodes::*;
+    use rslua::proto::ProtoBuilder;
+    use rslua::table::Table;
+    use rslua::value::Value;
+    use rslua::vm::{RuntimeError, ThreadStatus, Vm};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    // function() while true do n = n + 1 end end
+    fn counter(vm: &mut Vm) -> Value {
+        vm.set_global("n", Value::Int(0));
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(1);
+        let n = builder.constant(Const::Str("n".to_string()));
+        let one = builder.constant(Const::Int(1));
+        builder.emit(Instruction::GetTabUp {
+            dst: 0,
+            up: 0,
+            key: rk_as_k(n),
+        });
+        builder.emit(Instruction::Add {
+            dst: 0,
+            left: 0,
+            right: rk_as_k(one),
+        });
+        builder.emit(Instruction::SetTabUp {
+            up: 0,
+            key: rk_as_k(n),
+            value: 0,
+        });
+        builder.emit(Instruction::Jmp {
+            close: 0,
+            offset: -4,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        closure(vm, builder)
+    }
+
+    #[test]
+    fn instruction_budget() {
+        let mut vm = Vm::new();
+        let f = counter(&mut vm);
+        vm.set_instruction_budget(Some(400));
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("main.0:1: instruction budget exceeded"))
+        );
+        assert_eq!(vm.instruction_budget(), Some(0));
+        assert_eq!(vm.get_global("n"), Value::Int(100));
+
+        // until it's raised again
+        assert!(vm.call(&f, &[]).is_err());
+        vm.set_instruction_budget(Some(4));
+        assert!(vm.call(&f, &[]).is_err());
+        assert_eq!(vm.get_global("n"), Value::Int(101));
+        vm.set_instruction_budget(None);
+        assert_eq!(vm.instruction_budget(), None);
+    }
+
+    #[test]
+    fn count_hook() {
+        let mut vm = Vm::new();
+        let f = counter(&mut vm);
+        let calls = Rc::new(Cell::new(0));
+        let hook_calls = calls.clone();
+        vm.set_count_hook(8, move |_| {
+            hook_calls.set(hook_calls.get() + 1);
+            if hook_calls.get() == 10 {
+                return Err(RuntimeError::new("too long"));
+            }
+            Ok(())
+        });
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("main.0:1: too long"))
+        );
+        assert_eq!(calls.get(), 10);
+        // 79 instructions ran
+        assert_eq!(vm.get_global("n"), Value::Int(20));
+        vm.remove_count_hook();
+    }
+
+    #[test]
+    fn yield_from_count_hook() {
+        let mut vm = Vm::new();
+        let f = counter(&mut vm);
+        vm.set_count_hook(40, |vm| {
+            if vm.is_yieldable() {
+                vm.yield_values(vec![Value::str("tick")])?;
+            }
+            Ok(())
+        });
+        let co = vm.create_thread(f);
+        for i in 1..=3 {
+            assert_eq!(vm.resume(&co, vec![]), Ok(vec![Value::str("tick")]));
+            assert_eq!(co.borrow().status(), ThreadStatus::Suspended);
+            // the loop continues where it was suspended
+            assert_eq!(vm.get_global("n"), Value::Int(10 * i));
+        }
+    }
+
+    // function() while true do local t = tables t[#t + 1] = {} end end, which keeps the
+    // tables alive unless `tables` is nil
+    fn allocator(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(3);
+        let tables = builder.constant(Const::Str("tables".to_string()));
+        let one = builder.constant(Const::Int(1));
+        builder.emit(Instruction::GetTabUp {
+            dst: 0,
+            up: 0,
+            key: rk_as_k(tables),
+        });
+        builder.emit(Instruction::Len { dst: 1, src: 0 });
+        builder.emit(Instruction::Add {
+            dst: 1,
+            left: 1,
+            right: rk_as_k(one),
+        });
+        builder.emit(Instruction::NewTable {
+            dst: 2,
+            array: 0,
+            hash: 0,
+        });
+        builder.emit(Instruction::SetTable {
+            table: 0,
+            key: 1,
+            value: 2,
+        });
+        builder.emit(Instruction::Jmp {
+            close: 0,
+            offset: -6,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        closure(vm, builder)
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut vm = Vm::new();
+        let f = allocator(&mut vm);
+        vm.set_global("tables", Value::Table(Rc::new(RefCell::new(Table::new()))));
+        vm.set_memory_limit(Some(64 * 1024));
+        assert_eq!(vm.memory_limit(), Some(64 * 1024));
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("not enough memory"))
+        );
+        // the tables made before stay alive
+        match vm.get_global("tables") {
+            Value::Table(t) => assert!(t.borrow().len() > 100),
+            value => panic!("{:?}", value),
+        }
+
+        // garbage is collected instead, until the budget runs out
+        vm.set_global("tables", Value::Nil);
+        vm.collect_garbage();
+        let f = allocator_of_garbage(&mut vm);
+        vm.set_instruction_budget(Some(100_000));
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("main.0:1: instruction budget exceeded"))
+        );
+        vm.set_memory_limit(None);
+        assert_eq!(vm.memory_limit(), None);
+    }
+
+    #[test]
+    fn table_memory_limit() {
+        // function() return {} end, presized far beyond the limit
+        let mut vm = Vm::new();
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(1);
+        builder.emit(Instruction::NewTable {
+            dst: 0,
+            array: MAX_TABLE_HINT,
+            hash: MAX_TABLE_HINT,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        let f = closure(&mut vm, builder);
+        vm.set_memory_limit(Some(vm.memory() + 4096));
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("not enough memory"))
+        );
+
+        // within the limit the table is made
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(1);
+        builder.emit(Instruction::NewTable {
+            dst: 0,
+            array: int2fb(100),
+            hash: 0,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        let f = closure(&mut vm, builder);
+        match vm.call(&f, &[]).unwrap().as_slice() {
+            [Value::Table(t)] => assert!(t.borrow().memory() <= Table::memory_for(104, 0)),
+            values => panic!("{:?}", values),
+        }
+    }
+
+    #[test]
+    fn string_memory_limit() {
+        // function() local s = 'x' while true do s = s .. s end end
+        let mut vm = Vm::new();
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(3);
+        let x = builder.constant(Const::Str("x".to_string()));
+        builder.emit(Instruction::LoadK { dst: 0, k: x });
+        builder.emit(Instruction::Move { dst: 1, src: 0 });
+        builder.emit(Instruction::Move { dst: 2, src: 0 });
+        builder.emit(Instruction::Concat {
+            dst: 0,
+            first: 1,
+            last: 2,
+        });
+        builder.emit(Instruction::Jmp {
+            close: 0,
+            offset: -4,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        let f = closure(&mut vm, builder);
+        vm.set_memory_limit(Some(vm.memory() + 4096));
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("not enough memory"))
+        );
+    }
+
+    // function() while true do local t = {} end end
+    fn allocator_of_garbage(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(1);
+        builder.emit(Instruction::NewTable {
+            dst: 0,
+            array: 0,
+            hash: 0,
+        });
+        builder.emit(Instruction::Jmp {
+            close: 0,
+            offset: -2,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        closure(vm, builder)
+    }
+
+    // function() return pcall(f) end
+    fn protected(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(2);
+        let pcall = builder.constant(Const::Str("pcall".to_string()));
+        let f = builder.constant(Const::Str("f".to_string()));
+        builder.emit(Instruction::GetTabUp {
+            dst: 0,
+            up: 0,
+            key: rk_as_k(pcall),
+        });
+        builder.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(f),
+        });
+        builder.emit(Instruction::Call {
+            func: 0,
+            args: 2,
+            results: 0,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 0 });
+        closure(vm, builder)
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut vm = Vm::new();
+        let f = counter(&mut vm);
+        let interrupt = vm.interrupt_handle();
+        let timer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            interrupt.trigger();
+        });
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("main.0:1: interrupted"))
+        );
+        timer.join().unwrap();
+        assert!(!vm.interrupt_handle().is_triggered());
+    }
+
+    #[test]
+    fn uncatchable_interrupt() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let f = counter(&mut vm);
+        vm.set_global("f", f);
+        let g = protected(&mut vm);
+        let interrupt = vm.interrupt_handle();
+        vm.set_count_hook(100, move |_| {
+            interrupt.trigger();
+            Ok(())
+        });
+        assert_eq!(
+            vm.call(&g, &[]),
+            Err(RuntimeError::new("main.0:1: interrupted"))
+        );
+        assert!(vm.is_interrupted());
+
+        vm.set_interrupt_catchable(true);
+        assert_eq!(
+            vm.call(&g, &[]),
+            Ok(vec![
+                Value::Bool(false),
+                Value::str("main.0:1: interrupted")
+            ])
+        );
+        assert!(!vm.is_interrupted());
+    }
+
+    // function f(n) return 1 + f(n) end, which never ends
+    fn recursion(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(3);
+        let f = builder.constant(Const::Str("f".to_string()));
+        let one = builder.constant(Const::Int(1));
+        builder.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(f),
+        });
+        builder.emit(Instruction::Move { dst: 2, src: 0 });
+        builder.emit(Instruction::Call {
+            func: 1,
+            args: 2,
+            results: 2,
+        });
+        builder.emit(Instruction::Add {
+            dst: 1,
+            left: rk_as_k(one),
+            right: 1,
+        });
+        builder.emit(Instruction::Return { first: 1, count: 2 });
+        let f = closure(vm, builder);
+        vm.set_global("f", f.clone());
+        f
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut vm = Vm::new();
+        let f = recursion(&mut vm);
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("main.0:1: stack overflow"))
+        );
+        assert_eq!(
+            vm.pcall(&f, &[], None),
+            Err(Value::str("main.0:1: stack overflow"))
+        );
+        vm.set_call_depth_limit(10);
+        assert_eq!(vm.call_depth_limit(), 10);
+        assert_eq!(
+            vm.call(&f, &[]),
+            Err(RuntimeError::new("main.0:1: stack overflow"))
+        );
+        // the frames are unwound
+        assert_eq!(vm.stack_depth(), 0);
+    }
+
+    // function f() return pcall(f) end
+    fn nested_pcalls(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(2);
+        let pcall = builder.constant(Const::Str("pcall".to_string()));
+        let f = builder.constant(Const::Str("f".to_string()));
+        builder.emit(Instruction::GetTabUp {
+            dst: 0,
+            up: 0,
+            key: rk_as_k(pcall),
+        });
+        builder.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(f),
+        });
+        builder.emit(Instruction::TailCall {
+            func: 0,
+            args: 2,
+            results: 0,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 0 });
+        let f = closure(vm, builder);
+        vm.set_global("f", f.clone());
+        f
+    }
+
+    #[test]
+    fn native_depth() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let f = nested_pcalls(&mut vm);
+        // each pcall calls into the vm again, the innermost one fails
+        let results = vm.call(&f, &[]).unwrap();
+        assert_eq!(results.len(), vm.native_depth_limit() + 1);
+        assert_eq!(
+            results[results.len() - 2..],
+            [Value::Bool(false), Value::str("C stack overflow")]
+        );
+
+        // function(t, k) return t[k] end as __index of t
+        let mut builder = ProtoBuilder::new();
+        builder.params(2, false).stack_size(3);
+        builder.emit(Instruction::GetTable {
+            dst: 2,
+            table: 0,
+            key: 1,
+        });
+        builder.emit(Instruction::Return { first: 2, count: 2 });
+        let index = closure(&mut vm, builder);
+        let t = Rc::new(RefCell::new(Table::new()));
+        let mut meta = Table::new();
+        meta.set_str("__index", index);
+        vm.set_metatable(&t, Some(Rc::new(RefCell::new(meta))));
+        assert_eq!(
+            vm.index(&Value::Table(t), &Value::str("k")),
+            Err(RuntimeError::new("main.0:1: C stack overflow"))
+        );
+        vm.set_native_depth_limit(3);
+        let results = vm.call(&f, &[]).unwrap();
+        assert_eq!(results.len(), 4);
+    }
+}
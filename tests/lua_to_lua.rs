@@ -215,6 +215,11 @@ impl AstVisitor for LuaWritter {
         self.append_space("local");
         for (n, name) in stat.names.iter().enumerate() {
             self.append(name);
+            match stat.attribs[n] {
+                Some(Attrib::Const) => self.append(" <const>"),
+                Some(Attrib::Close) => self.append(" <close>"),
+                None => (),
+            }
             if n < stat.names.len() - 1 {
                 self.append(", ");
             }
@@ -260,6 +265,18 @@ impl AstVisitor for LuaWritter {
         Ok(())
     }
 
+    // write `a op= b` as `a = a op (b)`
+    fn compound_assign_stat(&mut self, stat: &CompoundAssignStat) -> WriteSuccess {
+        ast_walker::walk_assinable(&stat.left, self)?;
+        self.space_append_space("=");
+        ast_walker::walk_assinable(&stat.left, self)?;
+        self.binop(stat.op);
+        self.append("(");
+        ast_walker::walk_expr(&stat.right, self)?;
+        self.append(")");
+        Ok(())
+    }
+
     fn call_stat(&mut self, stat: &CallStat) -> WriteSuccess {
         ast_walker::walk_assinable(&stat.call, self)?;
         Ok(())
@@ -456,6 +473,7 @@ fn try_convert(input: &str) -> String {
     lexer.set_config(LexerConfig {
         use_origin_string: true,
         reserve_comments: true,
+        ..LexerConfig::default()
     });
     if let Ok(tokens) = lexer.run(&input) {
         let mut parser = Parser::new();
@@ -1,8 +1,10 @@
 #[allow(unused_must_use)]
 mod parser_tests {
     use rslua::ast::*;
-    use rslua::lexer::Lexer;
+    use rslua::lexer::{Lexer, LexerConfig};
     use rslua::parser::Parser;
+    use rslua::symbol::Symbol;
+    use rslua::types::Dialect;
     use std::fs::File;
     use std::io::prelude::*;
 
@@ -86,7 +88,7 @@ mod parser_tests {
             forenum,
             Block {
                 stats: vec![Stat::ForStat(ForStat::ForNum(ForNum {
-                    var: String::from("i"),
+                    var: "i".into(),
                     init: Expr::Int(1),
                     limit: Expr::Int(10),
                     step: Some(Expr::Int(1)),
@@ -99,8 +101,8 @@ mod parser_tests {
             forlist,
             Block {
                 stats: vec![Stat::ForStat(ForStat::ForList(ForList {
-                    vars: vec![String::from("a"), String::from("b"),],
-                    exprs: vec![Expr::Name("c".to_string()), Expr::Name("d".to_string())],
+                    vars: vec!["a".into(), "b".into(),],
+                    exprs: vec![Expr::Name("c".into()), Expr::Name("d".into())],
                     body: Block { stats: vec![] },
                 },),)
                 .to_stat_info()],
@@ -130,7 +132,7 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::RepeatStat(RepeatStat {
                     cond: Expr::BinExpr(BinExpr {
-                        left: Box::new(Expr::Name("a".to_string())),
+                        left: Box::new(Expr::Name("a".into())),
                         op: BinOp::Gt,
                         right: Box::new(Expr::Int(0))
                     }),
@@ -150,14 +152,14 @@ mod parser_tests {
                 stats: vec![Stat::FuncStat(FuncStat {
                     func_type: FuncType::Global,
                     func_name: FuncName {
-                        fields: vec!["foo".to_string()],
+                        fields: vec!["foo".into()],
                         method: None,
                     },
                     body: FuncBody {
                         params: vec![
-                            Param::Name(String::from("a")),
-                            Param::Name(String::from("b")),
-                            Param::Name(String::from("c"))
+                            Param::Name("a".into()),
+                            Param::Name("b".into()),
+                            Param::Name("c".into())
                         ],
                         block: Block { stats: vec![] },
                     },
@@ -176,14 +178,14 @@ mod parser_tests {
                 stats: vec![Stat::FuncStat(FuncStat {
                     func_type: FuncType::Local,
                     func_name: FuncName {
-                        fields: vec!["foo".to_string()],
+                        fields: vec!["foo".into()],
                         method: None,
                     },
                     body: FuncBody {
                         params: vec![
-                            Param::Name(String::from("a")),
-                            Param::Name(String::from("b")),
-                            Param::Name(String::from("c"))
+                            Param::Name("a".into()),
+                            Param::Name("b".into()),
+                            Param::Name("c".into())
                         ],
                         block: Block { stats: vec![] },
                     },
@@ -200,7 +202,8 @@ mod parser_tests {
             ast,
             Block {
                 stats: vec![Stat::LocalStat(LocalStat {
-                    names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+                    names: vec!["a".into(), "b".into(), "c".into()],
+                    attribs: vec![None, None, None],
                     exprs: vec![Expr::Int(1), Expr::Int(2), Expr::Int(3),],
                 })
                 .to_stat_info()],
@@ -208,6 +211,91 @@ mod parser_tests {
         )
     }
 
+    #[test]
+    fn localstat_attrib() {
+        let ast = try_parse("local a <const>, b, c <close> = 1, 2, 3");
+        assert_eq!(
+            ast,
+            Block {
+                stats: vec![Stat::LocalStat(LocalStat {
+                    names: vec!["a".into(), "b".into(), "c".into()],
+                    attribs: vec![Some(Attrib::Const), None, Some(Attrib::Close)],
+                    exprs: vec![Expr::Int(1), Expr::Int(2), Expr::Int(3),],
+                })
+                .to_stat_info()],
+            }
+        )
+    }
+
+    fn try_parse_dialect(input: &str, dialect: Dialect) -> Option<Block> {
+        let mut lexer = Lexer::new();
+        lexer.set_config(LexerConfig {
+            dialect,
+            ..LexerConfig::default()
+        });
+        let tokens = lexer.run(input).ok()?;
+        let mut parser = Parser::new();
+        parser.set_dialect(dialect);
+        parser.run(tokens).ok()
+    }
+
+    #[test]
+    fn extended_dialect() {
+        let extended = |input| try_parse_dialect(input, Dialect::Extended);
+        assert_eq!(
+            extended("while a != b do if x then continue end f() end"),
+            Some(try_parse(
+                "while a ~= b do if x then goto continue end f() ::continue:: end"
+            ))
+        );
+        assert_eq!(
+            extended("repeat local x = f() if x then continue end until x"),
+            Some(try_parse(
+                "repeat local x = f() if x then goto continue end ::continue:: until x"
+            ))
+        );
+        assert_eq!(
+            extended("for i = 1, 2 do continue end"),
+            Some(try_parse("for i = 1, 2 do goto continue ::continue:: end"))
+        );
+        // still a name where it starts an expression statement
+        assert_eq!(
+            extended("continue = 1 continue() continue.x = 2"),
+            Some(try_parse("continue = 1 continue() continue.x = 2"))
+        );
+        assert_eq!(
+            extended("a.b //= 2 c ..= 'd'"),
+            Some(Block {
+                stats: vec![
+                    Stat::CompoundAssignStat(CompoundAssignStat {
+                        left: Assignable::SuffixedExpr(SuffixedExpr {
+                            primary: Box::new(Expr::Name("a".into())),
+                            suffixes: vec![Suffix::Attr("b".into())],
+                        }),
+                        op: BinOp::IDiv,
+                        right: Expr::Int(2),
+                    })
+                    .to_stat_info(),
+                    Stat::CompoundAssignStat(CompoundAssignStat {
+                        left: Assignable::Name("c".into()),
+                        op: BinOp::Concat,
+                        right: Expr::String("d".to_string()),
+                    })
+                    .to_stat_info(),
+                ]
+            })
+        );
+        assert_eq!(extended("continue"), None);
+        assert_eq!(extended("while true do local f = function() continue end end"), None);
+
+        assert_eq!(try_parse_dialect("a = b != c", Dialect::Standard), None);
+        assert_eq!(try_parse_dialect("a += 1", Dialect::Standard), None);
+        assert_ne!(
+            try_parse_dialect("while true do continue end", Dialect::Standard),
+            extended("while true do continue end")
+        );
+    }
+
     #[test]
     fn labelstat() {
         let ast = try_parse("::LABEL::");
@@ -215,7 +303,7 @@ mod parser_tests {
             ast,
             Block {
                 stats: vec![Stat::LabelStat(LabelStat {
-                    label: "LABEL".to_string()
+                    label: "LABEL".into()
                 })
                 .to_stat_info()]
             }
@@ -233,10 +321,10 @@ mod parser_tests {
                         Expr::BinExpr(BinExpr {
                             left: Box::new(Expr::Int(1)),
                             op: BinOp::Add,
-                            right: Box::new(Expr::Name("a".to_string()))
+                            right: Box::new(Expr::Name("a".into()))
                         }),
-                        Expr::Name("b".to_string()),
-                        Expr::Name("c".to_string()),
+                        Expr::Name("b".into()),
+                        Expr::Name("c".into()),
                     ],
                 })
                 .to_stat_info()],
@@ -251,7 +339,7 @@ mod parser_tests {
             ast,
             Block {
                 stats: vec![Stat::GotoStat(GotoStat {
-                    label: "LABEL".to_string()
+                    label: "LABEL".into()
                 })
                 .to_stat_info()],
             }
@@ -266,9 +354,9 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::AssignStat(AssignStat {
                     left: vec![
-                        Assignable::Name("a".to_string()),
-                        Assignable::Name("b".to_string()),
-                        Assignable::Name("c".to_string()),
+                        Assignable::Name("a".into()),
+                        Assignable::Name("b".into()),
+                        Assignable::Name("c".into()),
                     ],
                     right: vec![Expr::Int(1), Expr::Int(2), Expr::Int(3),],
                 })
@@ -285,7 +373,7 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::CallStat(CallStat {
                     call: Assignable::SuffixedExpr(SuffixedExpr {
-                        primary: Box::new(Expr::Name("foo".to_string())),
+                        primary: Box::new(Expr::Name("foo".into())),
                         suffixes: vec![Suffix::FuncArgs(FuncArgs::Exprs(vec![
                             Expr::Int(1),
                             Expr::Int(2),
@@ -306,11 +394,11 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::CallStat(CallStat {
                     call: Assignable::SuffixedExpr(SuffixedExpr {
-                        primary: Box::new(Expr::Name("a".to_string())),
+                        primary: Box::new(Expr::Name("a".into())),
                         suffixes: vec![Suffix::FuncArgs(FuncArgs::Exprs(vec![
-                            Expr::Name("a".to_string()),
-                            Expr::Name("b".to_string()),
-                            Expr::Name("c".to_string()),
+                            Expr::Name("a".into()),
+                            Expr::Name("b".into()),
+                            Expr::Name("c".into()),
                         ]))],
                     }),
                 })
@@ -327,7 +415,8 @@ mod parser_tests {
             ast1,
             Block {
                 stats: vec![Stat::LocalStat(LocalStat {
-                    names: vec!["t".to_string()],
+                    names: vec!["t".into()],
+                    attribs: vec![None],
                     exprs: vec![Expr::Table(Table {
                         fields: vec![
                             Field::ListField(Expr::Int(1)),
@@ -343,11 +432,12 @@ mod parser_tests {
             ast2,
             Block {
                 stats: vec![Stat::LocalStat(LocalStat {
-                    names: vec!["t".to_string()],
+                    names: vec!["t".into()],
+                    attribs: vec![None],
                     exprs: vec![Expr::Table(Table {
                         fields: vec![
                             Field::RecFileld(RecField {
-                                key: FieldKey::Name("a".to_string()),
+                                key: FieldKey::Name("a".into()),
                                 value: Expr::String("1".to_string()),
                             }),
                             Field::RecFileld(RecField {
@@ -357,7 +447,7 @@ mod parser_tests {
                             Field::RecFileld(RecField {
                                 key: FieldKey::Expr(Expr::BinExpr(BinExpr {
                                     op: BinOp::Minus,
-                                    left: Box::new(Expr::Name("a".to_string())),
+                                    left: Box::new(Expr::Name("a".into())),
                                     right: Box::new(Expr::Int(1)),
                                 })),
                                 value: Expr::Int(3),
@@ -378,9 +468,9 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::CallStat(CallStat {
                     call: Assignable::SuffixedExpr(SuffixedExpr {
-                        primary: Box::new(Expr::Name("a".to_string())),
+                        primary: Box::new(Expr::Name("a".into())),
                         suffixes: vec![
-                            Suffix::Method("b".to_string()),
+                            Suffix::Method("b".into()),
                             Suffix::FuncArgs(FuncArgs::Table(Table { fields: vec![] })),
                             Suffix::FuncArgs(FuncArgs::String("literal".to_string())),
                             Suffix::FuncArgs(FuncArgs::Exprs(vec![])),
@@ -440,12 +530,12 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::CallStat(CallStat {
                     call: Assignable::SuffixedExpr(SuffixedExpr {
-                        primary: Box::new(Expr::Name("str".to_string())),
+                        primary: Box::new(Expr::Name("str".into())),
                         suffixes: vec![
-                            Suffix::Method("sub".to_string()),
+                            Suffix::Method("sub".into()),
                             Suffix::FuncArgs(FuncArgs::Exprs(vec![
-                                Expr::Name("i".to_string()),
-                                Expr::Name("i".to_string()),
+                                Expr::Name("i".into()),
+                                Expr::Name("i".into()),
                             ])),
                         ]
                     }),
@@ -466,12 +556,12 @@ mod parser_tests {
                         op: BinOp::And,
                         left: Box::new(Expr::BinExpr(BinExpr {
                             op: BinOp::Eq,
-                            left: Box::new(Expr::Name("a".to_string())),
+                            left: Box::new(Expr::Name("a".into())),
                             right: Box::new(Expr::Int(1)),
                         },)),
                         right: Box::new(Expr::BinExpr(BinExpr {
                             op: BinOp::Eq,
-                            left: Box::new(Expr::Name("b".to_string())),
+                            left: Box::new(Expr::Name("b".into())),
                             right: Box::new(Expr::Int(2)),
                         })),
                     })],
@@ -480,4 +570,42 @@ mod parser_tests {
             }
         );
     }
+
+    #[test]
+    fn interned_names() {
+        let ast = try_parse("local abc = abc.abc");
+        let name = match &ast.stats[0].stat {
+            Stat::LocalStat(stat) => stat.names[0].clone(),
+            _ => unreachable!(),
+        };
+        match &ast.stats[0].stat {
+            Stat::LocalStat(LocalStat { exprs, .. }) => match &exprs[0] {
+                Expr::SuffixedExpr(expr) => {
+                    assert_eq!(*expr.primary, Expr::Name(name.clone()));
+                    assert_eq!(expr.suffixes, vec![Suffix::Attr(name.clone())]);
+                }
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+        assert_eq!(name, Symbol::from("abc"));
+        assert_eq!(name.as_str(), "abc");
+        assert_eq!(format!("{} {:?}", name, name), "abc \"abc\"");
+        // names no symbol refers to are dropped, the ones in use stay shared
+        for i in 0..1000 {
+            Symbol::from(format!("tmp{}", i));
+        }
+        assert_eq!(name, Symbol::from("abc"));
+        assert_ne!(name, Symbol::from("abd"));
+    }
+
+    #[test]
+    fn send_ast() {
+        // names parsed by another thread are the same symbols
+        let ast = std::thread::spawn(|| try_parse("local abc")).join().unwrap();
+        match &ast.stats[0].stat {
+            Stat::LocalStat(stat) => assert_eq!(stat.names[0], Symbol::from("abc")),
+            _ => unreachable!(),
+        }
+    }
 }
@@ -58,3 +58,84 @@ fn print_instruction() {
 "#
     )
 }
+
+#[test]
+fn typed_instructions() {
+    let instructions = [
+        Instruction::Move { dst: 1, src: 2 },
+        Instruction::LoadK { dst: 3, k: 456 },
+        Instruction::GetTabUp {
+            dst: 0,
+            up: 0,
+            key: 256,
+        },
+        Instruction::Jmp {
+            close: 0,
+            offset: -255,
+        },
+        Instruction::ExtraArg { arg: 1234567 },
+    ];
+    assert!(
+        instructions[0] == Instruction::create_ABC(OpCode::Move, 1, 2, 0)
+            && instructions[1] == Instruction::create_ABx(OpCode::LoadK, 3, 456)
+            && instructions[3] == Instruction::create_AsBx(OpCode::Jmp, 0, -255)
+    );
+    for instruction in instructions.iter() {
+        assert!(Instruction::from_raw(instruction.raw()) == *instruction);
+    }
+    let mut jump = instructions[3];
+    jump.set_jump(10);
+    assert_eq!(jump.get_arg_sBx(), 10);
+    assert_eq!(instructions[2].get_op(), OpCode::GetTabUp);
+}
+
+#[test]
+fn packed_code() {
+    let code = vec![
+        Instruction::LoadNil { dst: 0, n: 2 },
+        Instruction::LoadK {
+            dst: 255,
+            k: MAXARG_BX,
+        },
+        Instruction::Add {
+            dst: 1,
+            left: 2,
+            right: MASK_K | 3,
+        },
+        Instruction::Jmp {
+            close: 1,
+            offset: -MAXARG_SBX,
+        },
+        Instruction::ForLoop {
+            base: 0,
+            offset: MAXARG_SBX + 2,
+        },
+        Instruction::SetList {
+            table: 0,
+            count: 3,
+            block: 0,
+        },
+        Instruction::ExtraArg { arg: MAXARG_AX },
+        Instruction::Return { first: 0, count: 1 },
+    ];
+    let packed = PackedCode::pack(&code[..4]).unwrap();
+    assert_eq!(packed.len(), 4);
+    assert!(packed.unpack() == code[..4]);
+    assert!(packed.get(1) == Some(code[1]));
+    assert_eq!(packed.words()[0], code[0].raw());
+
+    let packed = PackedCode::from_words(packed.words().to_vec()).unwrap();
+    assert!(packed.iter().eq(code[..4].iter().copied()));
+
+    assert_eq!(
+        PackedCode::pack(&code),
+        Err(PackError(
+            "operand out of range in FORLOOP at pc 4".to_string()
+        ))
+    );
+    assert!(PackedCode::pack(&code[5..]).unwrap().unpack() == code[5..]);
+    assert_eq!(
+        PackedCode::from_words(vec![code[7].raw(), 63]),
+        Err(PackError("bad opcode 63 at pc 1".to_string()))
+    );
+}
@@ -0,0 +1,85 @@
+mod proto_builder_tests {
+    use rslua::consts::Const;
+    use rslua::opcodes::*;
+    use rslua::proto::{Proto, ProtoBuilder};
+
+    #[test]
+    fn build() {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, true).stack_size(3);
+        let env = builder.up_value("_ENV", true, 0);
+        let n = builder.local("n");
+        let one = builder.constant(Const::Int(1));
+        let x = builder.constant(Const::Str("x".to_string()));
+        assert_eq!(builder.constant(Const::Int(1)), one);
+        builder.emit(Instruction::Add {
+            dst: 1,
+            left: n,
+            right: rk_as_k(one),
+        });
+        let jump = builder.emit(Instruction::Jmp {
+            close: 0,
+            offset: 0,
+        });
+        builder.line(2).emit(Instruction::SetTabUp {
+            up: env,
+            key: rk_as_k(x),
+            value: 1,
+        });
+        let target = builder.emit(Instruction::Return { first: 0, count: 1 });
+        builder.patch(
+            jump,
+            Instruction::Jmp {
+                close: 0,
+                offset: (target - jump - 1) as i32,
+            },
+        );
+        assert_eq!(builder.pc(), 4);
+
+        let proto = builder.build();
+        assert_eq!(proto.verify(), Ok(()));
+        assert_eq!(
+            proto.disasm(),
+            r#"main <main> (4 instructions)
+1+ param, 3 slots, 1 upvalue, 1 local, 2 constants, 0 functions
+	1	[1]	ADD      	1 0 -1	; - 1
+	2	[1]	JMP      	0 1	; to 4
+	3	[2]	SETTABUP 	0 -2 1	; _ENV "x"
+	4	[2]	RETURN   	0 1
+constants (2) for main:
+	1	1
+	2	"x"
+locals (1) for main:
+	0	n	1	5
+upvalues (1) for main:
+	0	_ENV	1	0
+"#
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let mut child = ProtoBuilder::new();
+        child.up_value("a", true, 0);
+        child.emit(Instruction::GetUpVal { dst: 0, up: 0 });
+        child.emit(Instruction::Return { first: 0, count: 2 });
+
+        let mut builder = ProtoBuilder::new();
+        let index = builder.child(child.build());
+        builder.emit(Instruction::LoadNil { dst: 0, n: 0 });
+        builder.emit(Instruction::Closure {
+            dst: 1,
+            proto: index,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        let proto = builder.build();
+        assert_eq!(proto.verify(), Ok(()));
+        assert_eq!(proto.children().len(), 1);
+
+        // nothing is added implicitly
+        let proto = ProtoBuilder::new().build();
+        assert!(proto.verify().is_err());
+        let proto = Proto::undump(&proto.dump(false));
+        assert!(proto.is_ok());
+    }
+}
@@ -0,0 +1,179 @@
+mod resolver_tests {
+    use rslua::ast::*;
+    use rslua::lexer::Lexer;
+    use rslua::parser::Parser;
+    use rslua::resolver::{Env, Resolution, Resolutions, Resolver, UpValueDesc};
+
+    fn parse(input: &str) -> Block {
+        let tokens = Lexer::new().run(input).ok().unwrap();
+        Parser::new().run(tokens).ok().unwrap()
+    }
+
+    fn local(block: &Block, i: usize) -> &LocalStat {
+        match &block.stats[i].stat {
+            Stat::LocalStat(stat) => stat,
+            _ => unreachable!(),
+        }
+    }
+
+    fn func(block: &Block, i: usize) -> &FuncStat {
+        match &block.stats[i].stat {
+            Stat::FuncStat(stat) => stat,
+            _ => unreachable!(),
+        }
+    }
+
+    fn ret(block: &Block, i: usize) -> &Vec<Expr> {
+        match &block.stats[i].stat {
+            Stat::RetStat(stat) => &stat.exprs,
+            _ => unreachable!(),
+        }
+    }
+
+    fn name(resolutions: &Resolutions, expr: &Expr) -> Option<Resolution> {
+        match expr {
+            Expr::Name(name) => resolutions.get(name),
+            _ => unreachable!(),
+        }
+    }
+
+    fn up_value(name: &str, in_stack: bool, index: u32) -> UpValueDesc {
+        UpValueDesc {
+            name: name.into(),
+            in_stack,
+            index,
+        }
+    }
+
+    #[test]
+    fn locals_and_globals() {
+        let block = parse("local a; local b = a; local a = c");
+        let resolutions = Resolver::new().run(&block);
+        assert_eq!(
+            resolutions.get(&local(&block, 0).names[0]),
+            Some(Resolution::Local(0))
+        );
+        assert_eq!(
+            name(&resolutions, &local(&block, 1).exprs[0]),
+            Some(Resolution::Local(0))
+        );
+        // a local is not in scope of its own initializer
+        assert_eq!(
+            name(&resolutions, &local(&block, 2).exprs[0]),
+            Some(Resolution::Global(Env::UpValue(0)))
+        );
+        assert_eq!(
+            resolutions.get(&local(&block, 2).names[0]),
+            Some(Resolution::Local(2))
+        );
+        assert_eq!(resolutions.main_up_values(), &[up_value("_ENV", true, 0)]);
+    }
+
+    #[test]
+    fn scopes() {
+        let block = parse(
+            r#"
+            for i = 1, 2 do local x = i end
+            local y
+            repeat local z until z
+            function t:m(p) return self, p end
+            "#,
+        );
+        let resolutions = Resolver::new().run(&block);
+        let for_num = match &block.stats[0].stat {
+            Stat::ForStat(ForStat::ForNum(stat)) => stat,
+            _ => unreachable!(),
+        };
+        // after the internal index, limit and step
+        assert_eq!(resolutions.get(&for_num.var), Some(Resolution::Local(3)));
+        assert_eq!(
+            name(&resolutions, &local(&for_num.body, 0).exprs[0]),
+            Some(Resolution::Local(3))
+        );
+        assert_eq!(
+            resolutions.get(&local(&block, 1).names[0]),
+            Some(Resolution::Local(0))
+        );
+        // until condition can see locals of the loop body
+        let repeat = match &block.stats[2].stat {
+            Stat::RepeatStat(stat) => stat,
+            _ => unreachable!(),
+        };
+        assert_eq!(name(&resolutions, &repeat.cond), Some(Resolution::Local(1)));
+        // self is the first param of a method
+        let body = &func(&block, 3).body;
+        let exprs = ret(&body.block, 0);
+        assert_eq!(name(&resolutions, &exprs[0]), Some(Resolution::Local(0)));
+        assert_eq!(name(&resolutions, &exprs[1]), Some(Resolution::Local(1)));
+        assert_eq!(
+            resolutions.get(&func(&block, 3).func_name.fields[0]),
+            Some(Resolution::Global(Env::UpValue(0)))
+        );
+    }
+
+    #[test]
+    fn up_values() {
+        let block = parse(
+            r#"
+            local a, b
+            local function f()
+                local function g() return b, a, x end
+                return a
+            end
+            "#,
+        );
+        let resolutions = Resolver::new().run(&block);
+        let f = func(&block, 1);
+        let g = func(&f.body.block, 0);
+        assert_eq!(
+            resolutions.get(&f.func_name.fields[0]),
+            Some(Resolution::Local(2))
+        );
+        // captured variables are up values of every function between the use and the definition
+        assert_eq!(
+            resolutions.up_values(&f.body),
+            &[
+                up_value("b", true, 1),
+                up_value("a", true, 0),
+                up_value("_ENV", false, 0)
+            ]
+        );
+        assert_eq!(
+            resolutions.up_values(&g.body),
+            &[
+                up_value("b", false, 0),
+                up_value("a", false, 1),
+                up_value("_ENV", false, 2)
+            ]
+        );
+        let exprs = ret(&g.body.block, 0);
+        assert_eq!(name(&resolutions, &exprs[0]), Some(Resolution::UpValue(0)));
+        assert_eq!(name(&resolutions, &exprs[1]), Some(Resolution::UpValue(1)));
+        assert_eq!(
+            name(&resolutions, &exprs[2]),
+            Some(Resolution::Global(Env::UpValue(2)))
+        );
+        let exprs = ret(&f.body.block, 1);
+        assert_eq!(name(&resolutions, &exprs[0]), Some(Resolution::UpValue(1)));
+    }
+
+    #[test]
+    fn local_env() {
+        let block = parse("local _ENV; x = 1; local function f() return x end");
+        let resolutions = Resolver::new().run(&block);
+        let x = match &block.stats[1].stat {
+            Stat::AssignStat(stat) => match &stat.left[0] {
+                Assignable::Name(name) => name,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        assert_eq!(resolutions.get(x), Some(Resolution::Global(Env::Local(0))));
+        let f = func(&block, 2);
+        assert_eq!(resolutions.up_values(&f.body), &[up_value("_ENV", true, 0)]);
+        assert_eq!(
+            name(&resolutions, &ret(&f.body.block, 0)[0]),
+            Some(Resolution::Global(Env::UpValue(0)))
+        );
+    }
+}
@@ -0,0 +1,52 @@
+mod common;
+
+mod sourcemap_tests {
+    use crate::common::parse;
+    use rslua::compiler::Compiler;
+    use rslua::sourcemap::*;
+
+    fn compile(input: &str) -> SourceMap {
+        let mut compiler = Compiler::new();
+        compiler.run(&parse(input)).ok().unwrap();
+        compiler.source_map().clone()
+    }
+
+    #[test]
+    fn compiled_statements() {
+        let map = compile("local a = 1\n  x = a + 2 local b\n");
+        let pos = |line, col| Some(SourcePos { line, col });
+        assert_eq!(map.get("main", 0), pos(1, 1));
+        assert_eq!(map.get("main", 1), pos(2, 3));
+        assert_eq!(map.get("main", 2), pos(2, 3));
+        assert_eq!(map.get("main", 3), pos(2, 13));
+        // return at the end belongs to the last statement
+        assert_eq!(map.get("main", 4), pos(2, 13));
+        assert_eq!(map.get("main", 5), None);
+        assert_eq!(map.get("main.0", 0), None);
+        assert_eq!(map.mappings().len(), 4);
+    }
+
+    #[test]
+    fn nested_functions() {
+        let map = compile("local f = function()\n  return 1\nend x = 2");
+        let pos = |line, col| Some(SourcePos { line, col });
+        assert_eq!(map.get("main", 0), pos(1, 1));
+        assert_eq!(map.get("main", 1), pos(3, 5));
+        assert_eq!(map.get("main.0", 0), pos(2, 3));
+        // the return at the end of a function belongs to its last statement too
+        assert_eq!(map.get("main.0", 2), pos(2, 3));
+        assert_eq!(map.get("main.0", 3), None);
+    }
+
+    #[test]
+    fn json() {
+        let mut map = SourceMap::new();
+        map.add("main", 0, 2, SourcePos { line: 1, col: 1 });
+        map.add("main", 2, 2, SourcePos { line: 2, col: 1 });
+        map.add("main.0", 0, 1, SourcePos { line: 3, col: 5 });
+        assert_eq!(
+            map.to_json("dir\\\"a\".lua"),
+            r#"{"file":"dir\\\"a\".lua","mappings":[{"function":"main","start_pc":0,"end_pc":2,"line":1,"col":1},{"function":"main.0","start_pc":0,"end_pc":1,"line":3,"col":5}]}"#
+        );
+    }
+}
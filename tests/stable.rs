@@ -0,0 +1,154 @@
+// only uses the stable api, so it breaks when the api changes incompatibly
+mod stable_tests {
+    use rslua::stable::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn run_chunks() {
+        let mut lua = Lua::new();
+        lua.set_global("n", Value::Int(20));
+        let chunk = compile("local a = n x = a + 1 n = x").unwrap();
+        lua.run(&chunk).unwrap();
+        lua.run(&chunk).unwrap();
+        assert_eq!(lua.get_global("n"), Value::Int(22));
+
+        assert_eq!(lua.exec("y = x * 2"), Ok(vec![]));
+        assert_eq!(lua.get_global("y"), Value::Int(44));
+        match lua.get_global("_G") {
+            Value::Table(t) => assert_eq!(t.borrow().get_str("y"), Value::Int(44)),
+            value => panic!("{:?}", value),
+        }
+    }
+
+    #[test]
+    fn libraries() {
+        let lua = Lua::new();
+        for name in ["pcall", "coroutine", "string"] {
+            assert!(!lua.get_global(name).is_nil(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut lua = Lua::new();
+        let runtime = lua.exec("local a x = a.b").unwrap_err();
+        assert_eq!(
+            runtime,
+            Error::Runtime("main:1: attempt to index a nil value".to_string())
+        );
+        assert_eq!(
+            runtime.to_string(),
+            "runtime error: main:1: attempt to index a nil value"
+        );
+        match compile("x = = 1") {
+            Err(Error::Syntax(msg)) => assert!(!msg.contains("SyntaxError"), "{}", msg),
+            _ => panic!("syntax error expected"),
+        }
+        assert!(matches!(lua.call(&Value::Nil, &[]), Err(Error::Runtime(_))));
+        assert!(matches!(compile("goto nowhere"), Err(Error::Compile(_))));
+        assert!(matches!(
+            compile("local a <const> = 1 a = 2"),
+            Err(Error::Compile(_))
+        ));
+    }
+
+    #[test]
+    fn statements() {
+        let mut lua = Lua::new();
+        assert_eq!(lua.exec("x = tostring(1)"), Ok(vec![]));
+        assert_eq!(lua.get_global("x"), Value::from("1"));
+        let source = "local t = {}
+            for i = 1, 3 do t[#t + 1] = i end
+            local function sum(...)
+                local s = 0
+                for i = 1, select('#', ...) do s = s + select(i, ...) end
+                return s
+            end
+            if #t == 3 then return sum(t[1], t[2], t[3]) end";
+        assert_eq!(lua.exec(source), Ok(vec![Value::Int(6)]));
+    }
+
+    #[test]
+    fn functions() {
+        let mut lua = Lua::new();
+        let div = lua.create_function("div", |_, (a, b): (i64, i64)| {
+            if b == 0 {
+                return Err("division by zero");
+            }
+            Ok(a / b)
+        });
+        assert_eq!(
+            lua.call(&div, &[Value::Int(7), Value::Int(2)]),
+            Ok(vec![Value::Int(3)])
+        );
+        // args are checked by their types
+        assert_eq!(
+            lua.call(&div, &[Value::Int(1)]),
+            Err(Error::Runtime(
+                "bad argument #2 to 'div' (integer expected, got nil)".to_string()
+            ))
+        );
+        assert_eq!(
+            lua.call(&div, &[Value::Int(1), Value::Int(0)]),
+            Err(Error::Runtime("division by zero".to_string()))
+        );
+
+        // called by scripts, here as `__index`, with access to the state
+        let index = lua.create_function("index", |ctx, (_, key): (TableRef, String)| {
+            let n = ctx.get_global("n");
+            ctx.set_global("n", Value::Int(0));
+            Ok::<_, Error>((key + "!", n))
+        });
+        let meta = Rc::new(RefCell::new(Table::new()));
+        meta.borrow_mut().set_str("__index", index);
+        let t = Rc::new(RefCell::new(Table::new()));
+        t.borrow_mut().set_metatable(Some(meta));
+        lua.set_global("t", Value::Table(t));
+        lua.set_global("n", Value::Int(5));
+        lua.exec("x = t.key").unwrap();
+        assert_eq!(lua.get_global("x"), Value::str("key!"));
+        assert_eq!(lua.get_global("n"), Value::Int(0));
+
+        // errors of calls from the context keep their messages
+        let call = lua.create_function("call", |ctx, f: Value| ctx.call(&f, &[]).map(MultiValue));
+        assert_eq!(
+            lua.call(&call, &[Value::Int(1)]),
+            Err(Error::Runtime("attempt to call a number value".to_string()))
+        );
+    }
+
+    #[test]
+    fn multiple_values() {
+        let mut lua = Lua::new();
+        // the rest of the args, and a tuple of results
+        let sum = lua.create_function("sum", |_, (scale, n): (f64, Variadic<f64>)| {
+            let total: f64 = n.0.iter().sum();
+            Ok::<_, Error>((total * scale, n.0.len() as i64))
+        });
+        assert_eq!(
+            lua.call(&sum, &[Value::Int(2), Value::Int(1), Value::Float(0.5)]),
+            Ok(vec![Value::Float(3.0), Value::Int(2)])
+        );
+        assert_eq!(
+            lua.call(&sum, &[Value::Int(1), Value::Int(1), Value::Bool(true)]),
+            Err(Error::Runtime(
+                "bad argument #3 to 'sum' (number expected, got boolean)".to_string()
+            ))
+        );
+
+        // any values, and none
+        let reverse = lua.create_function("reverse", |_, MultiValue(mut values)| {
+            values.reverse();
+            Ok::<_, Error>(MultiValue(values))
+        });
+        assert_eq!(
+            lua.call(&reverse, &[Value::Int(1), Value::Nil, Value::str("a")]),
+            Ok(vec![Value::str("a"), Value::Nil, Value::Int(1)])
+        );
+        let nothing = lua.create_function("nothing", |_, ()| Ok::<_, Error>(()));
+        assert_eq!(lua.call(&nothing, &[Value::Int(1)]), Ok(vec![]));
+        let numbers = (1i64, Variadic(vec![2i64, 3])).to_lua_multi();
+        assert_eq!(numbers, vec![Value::Int(1), Value::Int(2), Value::Int(3)]);
+    }
+}
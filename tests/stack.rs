@@ -0,0 +1,108 @@
+mod stack_tests {
+    use rslua::base;
+    use rslua::stack::{Stack, MULTRET};
+    use rslua::value::Value;
+    use rslua::vm::{NativeFunction, RuntimeError, Vm};
+    use std::rc::Rc;
+
+    #[test]
+    fn indices() {
+        let mut vm = Vm::new();
+        let mut stack = Stack::new(&mut vm);
+        stack.push_integer(1);
+        stack.push_number(2.5);
+        stack.push_string("3");
+        stack.push_boolean(false);
+        assert_eq!(stack.get_top(), 4);
+        assert_eq!(stack.to_integer(1), Some(1));
+        assert_eq!(stack.to_integer(-3), None);
+        assert_eq!(stack.to_number(-3), Some(2.5));
+        assert_eq!(stack.to_integer(3), Some(3));
+        assert_eq!(stack.to_string(1).unwrap().as_bytes(), b"1");
+        assert_eq!(stack.type_name(3), "string");
+        assert!(!stack.to_boolean(-1));
+
+        // 1 2.5 "3" false -> false 1 2.5 -> false 2.5 -> 2.5 false
+        stack.pop(1);
+        stack.push_boolean(false);
+        stack.insert(1);
+        stack.remove(-1);
+        stack.remove(2);
+        stack.push_copy(1);
+        stack.replace(1);
+        stack.push_copy(-2);
+        stack.remove(1);
+        assert_eq!(
+            stack.into_values(),
+            vec![Value::Float(2.5), Value::Bool(false)]
+        );
+
+        let mut stack = Stack::new(&mut vm);
+        stack.set_top(2);
+        assert!(stack.is_nil(-1));
+        stack.set_top(0);
+        assert_eq!(stack.get_top(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid stack index 2")]
+    fn invalid_index() {
+        let mut vm = Vm::new();
+        let mut stack = Stack::new(&mut vm);
+        stack.push_nil();
+        stack.get(2);
+    }
+
+    #[test]
+    fn tables_and_calls() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let mut stack = Stack::new(&mut vm);
+        // t = {x = 1, [2] = "two"}
+        stack.new_table();
+        stack.push_integer(1);
+        stack.set_field(-2, "x").unwrap();
+        stack.push_integer(2);
+        stack.push_string("two");
+        stack.set_table(1).unwrap();
+        stack.push_copy(1);
+        stack.set_global("t");
+        stack.get_field(1, "x").unwrap();
+        assert_eq!(stack.to_integer(-1), Some(1));
+        stack.push_integer(2);
+        stack.get_table(1).unwrap();
+        assert_eq!(stack.get(-1), &Value::str("two"));
+        stack.set_top(0);
+
+        // a native function using the stack for its args and results
+        let swap = NativeFunction::new("swap", |vm, args| {
+            let mut stack = Stack::with_values(vm, args);
+            stack.push_copy(1);
+            stack.remove(1);
+            Ok(stack.into_values())
+        });
+        stack.push_value(Value::Native(Rc::new(swap)));
+        stack.set_global("swap");
+        stack.get_global("swap");
+        stack.push_integer(1);
+        stack.push_integer(2);
+        stack.call(2, MULTRET).unwrap();
+        assert_eq!(stack.get_top(), 2);
+        assert_eq!(stack.to_integer(1), Some(2));
+        stack.get_global("swap");
+        stack.insert(1);
+        stack.call(2, Some(1)).unwrap();
+        assert_eq!(stack.to_integer(-1), Some(1));
+        assert_eq!(stack.get_top(), 1);
+
+        // errors pop the function and args
+        stack.get_global("error");
+        stack.push_string("boom");
+        assert_eq!(stack.call(1, Some(0)), Err(RuntimeError::new("boom")));
+        assert_eq!(stack.get_top(), 1);
+        match vm.get_global("t") {
+            Value::Table(t) => assert_eq!(t.borrow().get_int(2), Value::str("two")),
+            value => panic!("{:?}", value),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+mod string_tests {
+    use rslua::string;
+    use rslua::types::IntType;
+    use rslua::value::{LuaStr, Value};
+    use rslua::vm::{RuntimeError, Vm};
+
+    fn lib(vm: &Vm, name: &str) -> Value {
+        match vm.get_global("string") {
+            Value::Table(t) => t.borrow().get_str(name),
+            value => panic!("{:?}", value),
+        }
+    }
+
+    fn call(vm: &mut Vm, name: &str, args: &[Value]) -> Result<Vec<Value>, RuntimeError> {
+        let func = lib(vm, name);
+        vm.call(&func, args)
+    }
+
+    fn ints(values: &[IntType]) -> Vec<Value> {
+        values.iter().map(|i| Value::Int(*i)).collect()
+    }
+
+    fn error(msg: &str) -> Result<Vec<Value>, RuntimeError> {
+        Err(RuntimeError::new(msg))
+    }
+
+    #[test]
+    fn sub() {
+        let mut vm = Vm::new();
+        string::open(&mut vm);
+        let cases = [
+            (2, None, "ello"),
+            (2, Some(3), "el"),
+            (-3, None, "llo"),
+            (-3, Some(-2), "ll"),
+            (0, None, "hello"),
+            (-100, Some(2), "he"),
+            (2, Some(100), "ello"),
+            (4, Some(2), ""),
+            (6, None, ""),
+            (1, Some(0), ""),
+            (IntType::MIN, Some(IntType::MAX), "hello"),
+        ];
+        for (i, j, s) in cases.iter() {
+            let mut args = vec![Value::str("hello"), Value::Int(*i)];
+            args.extend(j.map(Value::Int));
+            assert_eq!(
+                (i, j, call(&mut vm, "sub", &args)),
+                (i, j, Ok(vec![Value::str(s)]))
+            );
+        }
+        // numbers are strings, and numerals integers
+        assert_eq!(
+            call(
+                &mut vm,
+                "sub",
+                &[Value::Int(12345), Value::str("2"), Value::Float(3.0)]
+            ),
+            Ok(vec![Value::str("23")])
+        );
+        assert_eq!(
+            call(&mut vm, "sub", &[Value::str("hello")]),
+            error("bad argument #2 to 'sub' (number expected, got no value)")
+        );
+        assert_eq!(
+            call(&mut vm, "sub", &[Value::str("hello"), Value::Float(1.5)]),
+            error("bad argument #2 to 'sub' (number has no integer representation)")
+        );
+        assert_eq!(
+            call(&mut vm, "sub", &[Value::Nil, Value::Int(1)]),
+            error("bad argument #1 to 'sub' (string expected, got nil)")
+        );
+    }
+
+    #[test]
+    fn bytes() {
+        let mut vm = Vm::new();
+        string::open(&mut vm);
+        let abc = Value::str("abc");
+        assert_eq!(
+            call(&mut vm, "byte", std::slice::from_ref(&abc)),
+            Ok(ints(&[97]))
+        );
+        assert_eq!(
+            call(&mut vm, "byte", &[abc.clone(), Value::Int(-1)]),
+            Ok(ints(&[99]))
+        );
+        assert_eq!(
+            call(
+                &mut vm,
+                "byte",
+                &[abc.clone(), Value::Int(1), Value::Int(-1)]
+            ),
+            Ok(ints(&[97, 98, 99]))
+        );
+        assert_eq!(
+            call(&mut vm, "byte", &[abc.clone(), Value::Int(10)]),
+            Ok(vec![])
+        );
+        assert_eq!(
+            call(&mut vm, "char", &ints(&[104, 105, 0, 255])),
+            Ok(vec![Value::Str(LuaStr::from(b"hi\0\xff".to_vec()))])
+        );
+        assert_eq!(call(&mut vm, "char", &[]), Ok(vec![Value::str("")]));
+        assert_eq!(
+            call(&mut vm, "char", &ints(&[104, 256])),
+            error("bad argument #2 to 'char' (value out of range)")
+        );
+        assert_eq!(
+            call(&mut vm, "len", std::slice::from_ref(&abc)),
+            Ok(ints(&[3]))
+        );
+        assert_eq!(
+            call(
+                &mut vm,
+                "len",
+                &[Value::Str(LuaStr::from(b"\0\0".to_vec()))]
+            ),
+            Ok(ints(&[2]))
+        );
+        assert_eq!(
+            call(&mut vm, "reverse", &[abc]),
+            Ok(vec![Value::str("cba")])
+        );
+    }
+
+    #[test]
+    fn case_and_rep() {
+        let mut vm = Vm::new();
+        string::open(&mut vm);
+        assert_eq!(
+            call(&mut vm, "upper", &[Value::str("Hello, 1!")]),
+            Ok(vec![Value::str("HELLO, 1!")])
+        );
+        assert_eq!(
+            call(&mut vm, "lower", &[Value::str("Hello, 1!")]),
+            Ok(vec![Value::str("hello, 1!")])
+        );
+        let cases = [
+            (3, None, "ababab"),
+            (3, Some(", "), "ab, ab, ab"),
+            (1, Some(", "), "ab"),
+            (0, Some(", "), ""),
+            (-1, None, ""),
+        ];
+        for (n, sep, s) in cases.iter() {
+            let mut args = vec![Value::str("ab"), Value::Int(*n)];
+            args.extend(sep.map(Value::str));
+            assert_eq!(call(&mut vm, "rep", &args), Ok(vec![Value::str(s)]));
+        }
+        assert_eq!(
+            call(
+                &mut vm,
+                "rep",
+                &[Value::str("ab"), Value::Int(IntType::MAX)]
+            ),
+            error("resulting string too large")
+        );
+        // empty strings are done at once
+        assert_eq!(
+            call(&mut vm, "rep", &[Value::str(""), Value::Int(IntType::MAX)]),
+            Ok(vec![Value::str("")])
+        );
+        vm.set_memory_limit(Some(vm.memory() + 1024));
+        assert_eq!(
+            call(&mut vm, "rep", &[Value::str("ab"), Value::Int(1 << 20)]),
+            error("not enough memory")
+        );
+    }
+
+    #[test]
+    fn methods() {
+        let mut vm = Vm::new();
+        string::open(&mut vm);
+        // the functions are looked up in the metatable of strings, like s:upper()
+        let s = Value::str("abc");
+        let upper = vm.index(&s, &Value::str("upper")).unwrap();
+        assert_eq!(upper, lib(&vm, "upper"));
+        assert_eq!(
+            vm.call(&upper, std::slice::from_ref(&s)),
+            Ok(vec![Value::str("ABC")])
+        );
+        assert_eq!(vm.index(&s, &Value::str("missing")), Ok(Value::Nil));
+        match vm.get_metatable(&s) {
+            Some(meta) => assert_eq!(meta.borrow().get_str("__index"), vm.get_global("string")),
+            None => panic!("strings have no metatable"),
+        }
+    }
+}
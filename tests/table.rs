@@ -0,0 +1,85 @@
+mod table_tests {
+    use rslua::table::Table;
+    use rslua::value::Value;
+
+    #[test]
+    fn array_part() {
+        let mut t = Table::new();
+        for i in 1..=10 {
+            t.set_int(i, Value::Int(i * 10));
+        }
+        assert_eq!(t.array_size(), 10);
+        assert_eq!(t.hash_size(), 0);
+        assert_eq!(t.len(), 10);
+        assert_eq!(t.get(&Value::Float(3.0)), Value::Int(30));
+        assert_eq!(t.get_int(11), Value::Nil);
+        assert_eq!(t.get_int(0), Value::Nil);
+
+        // holes stay in the array part
+        t.set_int(10, Value::Nil);
+        assert_eq!(t.array_size(), 10);
+        assert_eq!(t.len(), 9);
+        t.set_int(5, Value::Nil);
+        let n = t.len();
+        assert!(n == 4 || n == 9, "{} isn't a border", n);
+    }
+
+    #[test]
+    fn migrate() {
+        // filled backwards, then appending moves the rest from the hash part
+        let mut t = Table::new();
+        for i in (2..=5).rev() {
+            t.set_int(i, Value::Bool(true));
+        }
+        assert_eq!(t.len(), 0);
+        t.set(Value::Float(1.0), Value::Bool(true));
+        assert_eq!(t.array_size(), 5);
+        assert_eq!(t.hash_size(), 0);
+        assert_eq!(t.len(), 5);
+    }
+
+    #[test]
+    fn rehash() {
+        let mut t = Table::new();
+        t.set_str("a", Value::Int(1));
+        t.set_int(1000, Value::Int(1));
+        t.set_int(-1, Value::Int(1));
+        assert_eq!(t.array_size(), 0);
+        assert_eq!(t.hash_size(), 3);
+
+        // dense integer keys end up in the array part when the hash part grows
+        for i in (1..=64).rev() {
+            t.set_int(i, Value::Int(i));
+        }
+        assert!(t.array_size() >= 32, "array size {}", t.array_size());
+        assert_eq!(t.len(), 64);
+        for i in 1..=64 {
+            assert_eq!(t.get_int(i), Value::Int(i));
+        }
+        assert_eq!(t.get_int(1000), Value::Int(1));
+        assert_eq!(t.get_int(-1), Value::Int(1));
+        assert_eq!(t.get_str("a"), Value::Int(1));
+        assert_eq!(t.iter().count(), 67);
+    }
+
+    #[test]
+    fn hash_border() {
+        let mut t = Table::new();
+        t.set_str("x", Value::Int(0));
+        for i in [3, 2, 1].iter() {
+            t.set_int(*i, Value::Int(*i));
+        }
+        t.set_int(1, Value::Nil);
+        assert_eq!(t.get_int(1), Value::Nil);
+        let n = t.len();
+        assert!(n == 0 || n == 3, "{} isn't a border", n);
+        assert!(!t.is_empty());
+
+        let mut t = Table::new();
+        assert!(t.is_empty());
+        t.set_int(1, Value::Int(1));
+        t.set_int(1, Value::Nil);
+        assert!(t.is_empty());
+        assert_eq!(t.len(), 0);
+    }
+}
@@ -0,0 +1,127 @@
+mod common;
+
+mod traceback_tests {
+    use crate::common::closure;
+    use rslua::base;
+    use rslua::consts::Const;
+    use rslua::opcodes::*;
+    use rslua::proto::ProtoBuilder;
+    use rslua::traceback::{TraceFrame, Traceback};
+    use rslua::value::Value;
+    use rslua::vm::{RuntimeError, Vm};
+
+    // function(a) return a + 1 end, on line 5
+    fn add(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(2).line(5);
+        let one = builder.constant(Const::Int(1));
+        builder.emit(Instruction::Add {
+            dst: 1,
+            left: 0,
+            right: rk_as_k(one),
+        });
+        builder.emit(Instruction::Return { first: 1, count: 2 });
+        closure(vm, builder)
+    }
+
+    // function(f, a) f(a) end, with the call on line 10
+    fn call(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(2, false).stack_size(4).line(10);
+        builder.emit(Instruction::Move { dst: 2, src: 0 });
+        builder.emit(Instruction::Move { dst: 3, src: 1 });
+        builder.emit(Instruction::Call {
+            func: 2,
+            args: 2,
+            results: 1,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 1 });
+        closure(vm, builder)
+    }
+
+    fn frame(function: &str, line: u32) -> TraceFrame {
+        TraceFrame {
+            function: function.to_string(),
+            line: Some(line),
+        }
+    }
+
+    #[test]
+    fn error_traceback() {
+        let mut vm = Vm::new();
+        let add = add(&mut vm);
+        let call = call(&mut vm);
+        let e = vm.call(&call, &[add.clone(), Value::Nil]).unwrap_err();
+        assert_eq!(
+            e,
+            RuntimeError::new("main.0:5: attempt to perform arithmetic on a nil value")
+        );
+        let traceback = e.traceback().unwrap();
+        assert_eq!(
+            *traceback,
+            Traceback(vec![frame("main.0", 5), frame("main.0", 10)])
+        );
+        assert_eq!(e.position(), Some("main.0:5:".to_string()));
+        assert_eq!(
+            traceback.to_string(),
+            "stack traceback:\n\tmain.0:5: in function 'main.0'\n\tmain.0:10: in function 'main.0'"
+        );
+
+        // errors of natives are traced from the lua function calling them
+        base::open(&mut vm);
+        let error = vm.get_global("error");
+        let e = vm.call(&call, &[error, Value::Int(1)]).unwrap_err();
+        assert_eq!(e.traceback(), Some(&Traceback(vec![frame("main.0", 10)])));
+        // each error has its own traceback
+        let e = vm.call(&call, &[add, Value::Nil]).unwrap_err();
+        let other = vm.call(&Value::Int(1), &[]).unwrap_err();
+        assert_eq!(e.position(), Some("main.0:5:".to_string()));
+        assert_eq!(other.position(), None);
+    }
+
+    #[test]
+    fn error_positions() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let call = call(&mut vm);
+        // natives called by lua functions fail at the line of the call
+        let setmetatable = vm.get_global("setmetatable");
+        assert_eq!(
+            vm.call(&call, &[setmetatable.clone(), Value::Nil]),
+            Err(RuntimeError::new(
+                "main.0:10: bad argument #1 to 'setmetatable' (table expected)"
+            ))
+        );
+        // and without a lua function, where they have no position
+        assert_eq!(
+            vm.call(&setmetatable, &[Value::Nil]),
+            Err(RuntimeError::new(
+                "bad argument #1 to 'setmetatable' (table expected)"
+            ))
+        );
+        // values raised by `error` keep the position of their level
+        let error = vm.get_global("error");
+        assert_eq!(
+            vm.call(&call, &[error, Value::str("boom")]),
+            Err(RuntimeError::new("main.0:10: boom"))
+        );
+    }
+
+    #[test]
+    fn caught_errors() {
+        let mut vm = Vm::new();
+        base::open(&mut vm);
+        let pcall = vm.get_global("pcall");
+        let add = add(&mut vm);
+        assert_eq!(
+            vm.call(&pcall, &[add, Value::Nil]),
+            Ok(vec![
+                Value::Bool(false),
+                Value::str("main.0:5: attempt to perform arithmetic on a nil value")
+            ])
+        );
+        let e = RuntimeError::new("attempt to perform arithmetic on a nil value");
+        assert_eq!(e.traceback(), None);
+        assert_eq!(Traceback::default().to_string(), "stack traceback:");
+    }
+}
@@ -0,0 +1,154 @@
+mod value_tests {
+    use rslua::table::Table;
+    use rslua::types::FloatType;
+    use rslua::value::*;
+    use rslua::vm::{Thread, ThreadStatus};
+    use std::cell::RefCell;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::rc::Rc;
+
+    fn hash(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn equality() {
+        assert_eq!(Value::Int(1), Value::Float(1.0));
+        assert_eq!(hash(&Value::Int(1)), hash(&Value::Float(1.0)));
+        assert_ne!(Value::Int(1), Value::Float(1.5));
+        assert_ne!(Value::Int(i64::MAX), Value::Float(2f64.powi(63)));
+        assert_eq!(Value::Int(i64::MIN), Value::Float(-(2f64.powi(63))));
+        assert_ne!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert_eq!(Value::from("a"), Value::str("a"));
+        assert_ne!(Value::str("1"), Value::Int(1));
+        assert_ne!(Value::Bool(false), Value::Nil);
+
+        // by reference
+        let t = Value::new_table();
+        assert_eq!(t, t.clone());
+        assert_ne!(t, Value::new_table());
+        let u = Value::user_data(1);
+        assert_eq!(u, u.clone());
+        assert_ne!(u, Value::user_data(1));
+        let thread = Value::Thread(Rc::new(RefCell::new(Thread::new(Value::Nil))));
+        assert_eq!(thread, thread.clone());
+    }
+
+    #[test]
+    fn interned_strings() {
+        // short strings share their bytes, however they're made
+        let a = LuaStr::from("name");
+        let b = LuaStr::from(b"name".to_vec());
+        assert!(a.is_short());
+        assert!(LuaStr::ptr_eq(&a, &b));
+        assert_eq!(a, b);
+        assert_eq!(hash(&Value::Str(a)), hash(&Value::Str(b)));
+        assert_ne!(LuaStr::from("name"), LuaStr::from("other"));
+
+        // long ones don't, and compare by their bytes
+        let text = "x".repeat(MAX_SHORT_LEN + 1);
+        let a = LuaStr::from(text.as_str());
+        let b = LuaStr::from(text.as_str());
+        assert!(!a.is_short());
+        assert!(!LuaStr::ptr_eq(&a, &b));
+        assert_eq!(a, b);
+        assert_eq!(hash(&Value::Str(a)), hash(&Value::Str(b)));
+
+        // strings in use survive sweeps of the table as it grows
+        let names: Vec<LuaStr> = (0..1000)
+            .map(|i| LuaStr::from(format!("s{}", i).as_str()))
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            assert!(LuaStr::ptr_eq(
+                name,
+                &LuaStr::from(format!("s{}", i).as_str())
+            ));
+        }
+    }
+
+    #[test]
+    fn float_strings() {
+        let cases = [
+            (0.0, "0.0"),
+            (-0.0, "-0.0"),
+            (100.0, "100.0"),
+            (0.1, "0.1"),
+            (1.0 / 3.0, "0.33333333333333"),
+            (1e14, "1e+14"),
+            (1e15, "1e+15"),
+            (123456789012345.0, "1.2345678901234e+14"),
+            (99999999999999.5, "1e+14"),
+            (9007199254740992.0, "9.007199254741e+15"),
+            (9223372036854775808.0, "9.2233720368548e+18"),
+            (1e100, "1e+100"),
+            (1e-5, "1e-05"),
+            (0.0001, "0.0001"),
+            (5e-324, "4.9406564584125e-324"),
+            (FloatType::INFINITY, "inf"),
+            (FloatType::NEG_INFINITY, "-inf"),
+            (FloatType::NAN, "nan"),
+            (-FloatType::NAN, "-nan"),
+        ];
+        for (f, s) in cases.iter() {
+            assert_eq!(float_to_str(*f), *s);
+        }
+    }
+
+    #[test]
+    fn truthiness() {
+        assert!(Value::Nil.is_falsy());
+        assert!(Value::Bool(false).is_falsy());
+        assert!(Value::Bool(true).is_truthy());
+        assert!(Value::Int(0).is_truthy());
+        assert!(Value::str("").is_truthy());
+        assert!(Value::new_table().is_truthy());
+    }
+
+    #[test]
+    fn type_names() {
+        let names: Vec<_> = [
+            Value::Nil,
+            Value::from(true),
+            Value::from(1),
+            Value::from(1.5),
+            Value::from("s"),
+            Value::new_table(),
+            Value::user_data(()),
+            Value::Thread(Rc::new(RefCell::new(Thread::new(Value::Nil)))),
+        ]
+        .iter()
+        .map(Value::type_name)
+        .collect();
+        assert_eq!(
+            names,
+            ["nil", "boolean", "number", "number", "string", "table", "userdata", "thread"]
+        );
+    }
+
+    #[test]
+    fn table_keys() {
+        let mut t = Table::new();
+        t.set(Value::Float(2.0), Value::str("two"));
+        assert_eq!(t.get(&Value::Int(2)), Value::str("two"));
+        assert_eq!(t.get(&Value::Float(f64::NAN)), Value::Nil);
+        let key = Value::user_data("key");
+        t.set(key.clone(), Value::Int(3));
+        assert_eq!(t.get(&key), Value::Int(3));
+        assert_eq!(t.get(&Value::user_data("key")), Value::Nil);
+    }
+
+    #[test]
+    fn user_data() {
+        let u = UserData::new(vec![1, 2]);
+        assert!(u.is::<Vec<i32>>());
+        assert!(u.borrow::<String>().is_none());
+        u.borrow_mut::<Vec<i32>>().unwrap().push(3);
+        assert_eq!(*u.borrow::<Vec<i32>>().unwrap(), [1, 2, 3]);
+
+        let thread = Thread::new(Value::Nil);
+        assert_eq!(thread.status(), ThreadStatus::Suspended);
+    }
+}
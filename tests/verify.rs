@@ -0,0 +1,166 @@
+mod common;
+
+mod verify_tests {
+    use crate::common::compile;
+    use rslua::consts::Const;
+    use rslua::opcodes::*;
+    use rslua::proto::Proto;
+    use rslua::verify::VerifyError;
+
+    // function with 2 registers, constant 1, _ENV and the code followed by a return
+    fn proto(code: Vec<Instruction>) -> Proto {
+        let mut proto = Proto::new();
+        proto.stack_size = 2;
+        proto.add_const(Const::Int(1));
+        proto.add_up_var("_ENV", true, 0);
+        proto.code = code;
+        proto.code_return(0, 0);
+        proto
+    }
+
+    fn error(msg: &str) -> Result<(), VerifyError> {
+        Err(VerifyError(format!("bad code in {}", msg)))
+    }
+
+    #[test]
+    fn verify() {
+        for input in [
+            "local a = 1",
+            "local a, b = 1, 'x'; c = a; local t; t[2] = -a; t.x = b",
+            "local a; local b = not a; local n = -#a",
+            "local t; t.x = 1; local n = #t",
+            "local a, b = 'x', 'y'; c = b .. a .. 'z' .. 1",
+            "local a, b; c = a and b or a < 1; d = (a or 1) and 'x'",
+        ] {
+            let proto = compile(input);
+            assert_eq!(proto.verify(), Ok(()), "{}", input);
+            assert_eq!(Proto::undump(&proto.dump(true)).unwrap().verify(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn verify_errors() {
+        use OpCode::*;
+        let abc = Instruction::create_ABC;
+        let cases = vec![
+            (
+                vec![abc(Move, 2, 0, 0)],
+                "main: register 2 out of stack at pc 0",
+            ),
+            (
+                vec![abc(Add, 0, 1, rk_as_k(1))],
+                "main: constant 1 out of range at pc 0",
+            ),
+            (
+                vec![Instruction::create_ABx(LoadK, 0, 3)],
+                "main: constant 3 out of range at pc 0",
+            ),
+            (
+                vec![abc(GetTabUp, 0, 1, rk_as_k(0))],
+                "main: upvalue 1 out of range at pc 0",
+            ),
+            (
+                vec![abc(LoadNil, 1, 1, 0)],
+                "main: register 2 out of stack at pc 0",
+            ),
+            (
+                vec![Instruction::create_AsBx(Jmp, 0, 1)],
+                "main: jump to 2 out of code at pc 0",
+            ),
+            (
+                vec![Instruction::create_AsBx(Jmp, 0, -2)],
+                "main: jump to -1 out of code at pc 0",
+            ),
+            (
+                vec![abc(Eq, 1, 0, 1)],
+                "main: missing jump after test at pc 0",
+            ),
+            (
+                vec![abc(LoadKx, 0, 0, 0)],
+                "main: missing extra arg at pc 0",
+            ),
+            (
+                vec![Instruction::create_Ax(ExtraArg, 0)],
+                "main: unexpected extra arg at pc 0",
+            ),
+            (
+                vec![
+                    Instruction::create_AsBx(Jmp, 0, 1),
+                    abc(LoadKx, 0, 0, 0),
+                    Instruction::create_Ax(ExtraArg, 0),
+                ],
+                "main: jump into extra arg at 2 at pc 0",
+            ),
+            (
+                vec![abc(Vararg, 0, 2, 0)],
+                "main: vararg in a function without varargs at pc 0",
+            ),
+            (
+                vec![Instruction::create_ABx(Closure, 0, 0)],
+                "main: function 0 out of range at pc 0",
+            ),
+            (
+                vec![abc(NewTable, 0, 0, MAX_TABLE_HINT + 1)],
+                "main: table size too large at pc 0",
+            ),
+        ];
+        for (code, msg) in cases {
+            assert_eq!(proto(code).verify(), error(msg));
+        }
+
+        // open results are taken right after a call or vararg which sets them
+        let cases = vec![
+            (vec![abc(Return, 0, 0, 0)], Some(0)),
+            (vec![abc(Call, 0, 1, 0), abc(Return, 0, 0, 0)], None),
+            (vec![abc(Call, 1, 1, 0), abc(Return, 0, 0, 0)], None),
+            (vec![abc(Call, 0, 1, 0), abc(Call, 0, 0, 1)], Some(1)),
+            (vec![abc(Call, 1, 1, 0), abc(Call, 0, 0, 1)], None),
+            (
+                vec![
+                    abc(Call, 1, 1, 0),
+                    abc(Move, 0, 1, 0),
+                    abc(SetList, 0, 0, 1),
+                ],
+                Some(2),
+            ),
+            (vec![abc(Call, 0, 1, 0), abc(TailCall, 0, 0, 0)], Some(1)),
+        ];
+        for (code, pc) in cases {
+            let result = match pc {
+                Some(pc) => error(&format!(
+                    "main: open results not set by the previous instruction at pc {}",
+                    pc
+                )),
+                None => Ok(()),
+            };
+            assert_eq!(proto(code).verify(), result);
+        }
+        let code = vec![
+            Instruction::create_AsBx(Jmp, 0, 1),
+            abc(Call, 1, 1, 0),
+            abc(Return, 0, 0, 0),
+        ];
+        assert_eq!(
+            proto(code).verify(),
+            error("main: jump to open results at 2 at pc 0")
+        );
+
+        let mut no_return = proto(vec![]);
+        no_return.code.clear();
+        assert_eq!(no_return.verify(), error("main: missing return at the end"));
+
+        // upvalues of nested functions
+        let mut parent = proto(vec![]);
+        let mut child = proto(vec![]);
+        child.up_vars.clear();
+        child.add_up_var("a", true, 2);
+        parent.protos.push(child);
+        assert_eq!(
+            parent.verify(),
+            error("main.0: upvalue index 2 out of range")
+        );
+        parent.protos[0].up_vars.clear();
+        parent.protos[0].add_up_var("_ENV", false, 0);
+        assert_eq!(parent.verify(), Ok(()));
+    }
+}
@@ -0,0 +1,1150 @@
+mod common;
+
+mod vm_tests {
+    use crate::common::{compile, parse};
+    use rslua::compiler::{Compiler, CompilerOptions};
+    use rslua::consts::Const;
+    use rslua::intercept::CallHooks;
+    use rslua::opcodes::*;
+    use rslua::proto::{Proto, ProtoBuilder};
+    use rslua::table::Table;
+    use rslua::value::{TableRef, Value};
+    use rslua::vm::{HookEvent, HookMask, NativeFunction, RuntimeError, Vm};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn run(input: &str) -> Vm {
+        let mut vm = Vm::new();
+        vm.run(compile(input)).unwrap();
+        vm
+    }
+
+    #[test]
+    fn globals() {
+        let vm =
+            run("local a, b = 7, 'b' x = a // 2 y = a / 2 z = -a % 3 w = a << 2 | 1 s = a .. b");
+        assert_eq!(vm.get_global("x"), Value::Int(3));
+        assert_eq!(vm.get_global("y"), Value::Float(3.5));
+        assert_eq!(vm.get_global("z"), Value::Int(2));
+        assert_eq!(vm.get_global("w"), Value::Int(29));
+        assert_eq!(vm.get_global("s"), Value::str("7b"));
+        assert_eq!(vm.get_global("missing"), Value::Nil);
+    }
+
+    #[test]
+    fn numbers() {
+        let input = "local min, i, f = -9223372036854775807 - 1, 9007199254740993, 2^53
+            a = 7 // 2 b = -7 // 2 c = -7 % 3 d = 7 % -3.0 e = 7 / 7 g = 2 ^ 2
+            h = min // -1 j = min % -1 k = min * -1 l = -min
+            m = 1 << 64 n = -1 >> 1 o = 1 << -1 p = 3.0 | 4
+            q = f < i r = i <= f s = min < -2^63 t = min <= -2^63";
+        // folded or not, the results are the same
+        for options in [CompilerOptions::default(), CompilerOptions::unoptimized()].iter() {
+            let proto = Compiler::with_options(*options)
+                .run(&parse(input))
+                .ok()
+                .unwrap();
+            let mut vm = Vm::new();
+            vm.run(proto).unwrap();
+            let min = Value::Int(i64::MIN);
+            let expected = [
+                ("a", Value::Int(3)),
+                ("b", Value::Int(-4)),
+                ("c", Value::Int(2)),
+                ("d", Value::Float(-2.0)),
+                ("e", Value::Float(1.0)),
+                ("g", Value::Float(4.0)),
+                ("h", min.clone()),
+                ("j", Value::Int(0)),
+                ("k", min.clone()),
+                ("l", min),
+                ("m", Value::Int(0)),
+                ("n", Value::Int(i64::MAX)),
+                ("o", Value::Int(0)),
+                ("p", Value::Int(7)),
+                ("q", Value::Bool(true)),
+                ("r", Value::Bool(false)),
+                ("s", Value::Bool(false)),
+                ("t", Value::Bool(true)),
+            ];
+            for (name, value) in expected.iter() {
+                let global = vm.get_global(name);
+                assert_eq!((*name, &global), (*name, value));
+                // integers and floats are kept apart
+                assert_eq!(
+                    matches!(global, Value::Float(_)),
+                    matches!(value, Value::Float(_))
+                );
+            }
+        }
+        let mut vm = Vm::new();
+        assert_eq!(
+            vm.run(compile("local a = 2^63 b = a | 0")),
+            Err(RuntimeError::new(
+                "main:1: number has no integer representation"
+            ))
+        );
+    }
+
+    #[test]
+    fn string_coercion() {
+        let vm = run(
+            "local a, b = '10', ' 0x10 ' x = a + 1 y = a * '2' z = b - 0.5 w = '1e1' // 1
+            v = '3' | 0 u = -a local i, j = 1, 2 t = i .. j local f, e = 1.5, '' s = f .. e",
+        );
+        assert_eq!(vm.get_global("x"), Value::Int(11));
+        assert_eq!(vm.get_global("y"), Value::Int(20));
+        assert_eq!(vm.get_global("z"), Value::Float(15.5));
+        assert_eq!(vm.get_global("w"), Value::Float(10.0));
+        assert_eq!(vm.get_global("v"), Value::Int(3));
+        assert_eq!(vm.get_global("u"), Value::Int(-10));
+        assert_eq!(vm.get_global("t"), Value::str("12"));
+        assert_eq!(vm.get_global("s"), Value::str("1.5"));
+
+        let mut vm = Vm::new();
+        assert_eq!(
+            vm.run(compile("local a = 'abc' x = a + 1")),
+            Err(RuntimeError::new(
+                "main:1: attempt to perform arithmetic on a string value"
+            ))
+        );
+        assert_eq!(
+            vm.run(compile("local a = '1.5' x = a | 1")),
+            Err(RuntimeError::new(
+                "main:1: number has no integer representation"
+            ))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut vm = Vm::new();
+        let error = |msg: &str| Err(RuntimeError::new(msg));
+        assert_eq!(
+            vm.run(compile("local a x = a + 1")),
+            error("main:1: attempt to perform arithmetic on a nil value")
+        );
+        assert_eq!(
+            vm.run(compile("local a = 1 x = a // 0")),
+            error("main:1: attempt to perform 'n//0'")
+        );
+        assert_eq!(
+            vm.run(compile("local a = 1.5 x = a | 1")),
+            error("main:1: number has no integer representation")
+        );
+        assert_eq!(
+            vm.run(compile("local a = true x = a.b")),
+            error("main:1: attempt to index a boolean value")
+        );
+        assert_eq!(
+            vm.run(compile("local a, b = true, 'b' x = a .. b")),
+            error("main:1: attempt to concatenate a boolean value")
+        );
+        // the vm can still run after errors
+        assert_eq!(vm.run(compile("x = 1")), Ok(vec![]));
+        assert_eq!(vm.get_global("x"), Value::Int(1));
+    }
+
+    #[test]
+    fn concat() {
+        // operands are in consecutive registers, whether they're locals in any order or constants
+        let vm = run(
+            "local a, b, c, d = 'a', 'b', 'c', 'd' w = b .. a x = a .. b .. c .. d
+            y = a .. 'x' .. 1 z = 'a' .. 'b' .. 'c' .. 'd' local e = 1.5 v = d .. e .. a",
+        );
+        assert_eq!(vm.get_global("w"), Value::str("ba"));
+        assert_eq!(vm.get_global("x"), Value::str("abcd"));
+        assert_eq!(vm.get_global("y"), Value::str("ax1"));
+        assert_eq!(vm.get_global("z"), Value::str("abcd"));
+        assert_eq!(vm.get_global("v"), Value::str("d1.5a"));
+    }
+
+    #[test]
+    fn and_or() {
+        // the result is the operand which decides it, not a boolean
+        let vm = run("local a, b, f, n = 1, 'b', false
+            w = a and 2 x = n and 2 y = f or b z = a or b
+            v = a < 2 and b or 'c' u = a > 2 and b or 'c' t = (n or f) == false
+            s = a and n == nil r = f or a < 0 q = n and 1 or nil
+            local l = 5 l = n or l + 1 p = l");
+        let expected = [
+            ("w", Value::Int(2)),
+            ("x", Value::Nil),
+            ("y", Value::str("b")),
+            ("z", Value::Int(1)),
+            ("v", Value::str("b")),
+            ("u", Value::str("c")),
+            ("t", Value::Bool(true)),
+            ("s", Value::Bool(true)),
+            ("r", Value::Bool(false)),
+            ("q", Value::Nil),
+            ("p", Value::Int(6)),
+        ];
+        for (name, value) in expected.iter() {
+            assert_eq!((*name, vm.get_global(name)), (*name, value.clone()));
+        }
+    }
+
+    #[test]
+    fn blocks() {
+        // locals of a block free their registers when it ends
+        let vm = run("local a = 1 do local b = 2 x = b end local c = 3 y = c z = a");
+        assert_eq!(vm.get_global("x"), Value::Int(2));
+        assert_eq!(vm.get_global("y"), Value::Int(3));
+        assert_eq!(vm.get_global("z"), Value::Int(1));
+        let vm = run("local a, b = 1, 2 x = not (a > b and a < b) y = not (a < b or a > b)");
+        assert_eq!(vm.get_global("x"), Value::Bool(true));
+        assert_eq!(vm.get_global("y"), Value::Bool(false));
+    }
+
+    #[test]
+    fn control_flow() {
+        let vm = run("local n, s = 0, ''
+            while n < 10 do
+                n = n + 1
+                if n % 2 == 0 then s = s .. 'e'
+                elseif n == 5 then s = s .. 'F'
+                elseif n > 8 and n < 10 then break
+                else s = s .. 'o' end
+            end
+            x, y = n, s
+            local i = 0
+            repeat local j = i * 2 i = i + 1 until j >= 6
+            z = i
+            ::top:: i = i + 1
+            if not (i >= 6) then goto top end
+            do
+                while true do i = i + 1 if i > 7 then goto done end end
+                i = 0
+            end
+            ::done:: w = i");
+        assert_eq!(vm.get_global("x"), Value::Int(9));
+        assert_eq!(vm.get_global("y"), Value::str("oeoeFeoe"));
+        assert_eq!(vm.get_global("z"), Value::Int(4));
+        assert_eq!(vm.get_global("w"), Value::Int(8));
+    }
+
+    #[test]
+    fn for_loops() {
+        let mut vm = Vm::new();
+        // squares of the numbers up to the state
+        let squares = NativeFunction::new("squares", |_, args| match args.as_slice() {
+            [Value::Int(n), Value::Int(i)] if i < n => {
+                Ok(vec![Value::Int(i + 1), Value::Int((i + 1) * (i + 1))])
+            }
+            _ => Ok(vec![Value::Nil]),
+        });
+        vm.set_global("squares", Value::Native(Rc::new(squares)));
+        vm.run(compile(
+            "local s = 0
+            for i = 1, 10 do s = s + i end
+            for i = 10, 1, -3 do s = s * 100 + i end
+            for i = 1, 0 do s = -1 end
+            x = s
+            s = 0
+            for f = 1.0, 2, 0.5 do s = s + f end
+            y = s
+            local t = ''
+            for i, sq in squares, 3, 0 do t = t .. i .. ':' .. sq .. ' ' end
+            for i = 1, 5 do if i == 3 then break end t = t .. i end
+            z = t
+            local n = 0
+            for i = 1, 3 do
+                for j = 1, 3 do if j > i then goto next end n = n + 1 ::next:: end
+            end
+            w = n",
+        ))
+        .unwrap();
+        assert_eq!(vm.get_global("x"), Value::Int(5510070401));
+        assert_eq!(vm.get_global("y"), Value::Float(4.5));
+        assert_eq!(vm.get_global("z"), Value::str("1:1 2:4 3:9 12"));
+        assert_eq!(vm.get_global("w"), Value::Int(6));
+    }
+
+    #[test]
+    fn table_constructors() {
+        let mut vm = Vm::new();
+        rslua::base::open(&mut vm);
+        let mut run = |input: &str| vm.run(compile(input)).unwrap();
+        assert_eq!(
+            run("local a = 7
+                local t = {1, a, x = 'x', [a] = a * 2, ['k' .. a] = a < 8, select(2, 'p', 'q', 'r')}
+                return t[1], t[2], t.x, t[7], t.k7, t[3], t[4], rawlen(t), rawlen{f = {}}"),
+            [
+                Value::Int(1),
+                Value::Int(7),
+                Value::str("x"),
+                Value::Int(14),
+                Value::Bool(true),
+                Value::str("q"),
+                Value::str("r"),
+                Value::Int(4),
+                Value::Int(0)
+            ]
+        );
+        // list items are stored every 50 of them, the blocks of long lists are in EXTRAARG
+        let items = (1..=26000).map(|i| i.to_string()).collect::<Vec<_>>();
+        let input = format!(
+            "local t = {{{}}} return rawlen(t), t[50], t[51], t[26000]",
+            items.join(", ")
+        );
+        assert_eq!(
+            run(&input),
+            [
+                Value::Int(26000),
+                Value::Int(50),
+                Value::Int(51),
+                Value::Int(26000)
+            ]
+        );
+    }
+
+    #[test]
+    fn compiled_functions() {
+        let mut vm = Vm::new();
+        rslua::base::open(&mut vm);
+        let mut run = |input: &str| vm.run(compile(input)).unwrap();
+        // captured locals are shared by the closures and closed when leaving their blocks
+        assert_eq!(
+            run("local function counter()
+                    local n = 0
+                    return function(step) n = n + (step or 1) return n end, function() return n end
+                end
+                local inc, get = counter()
+                inc() inc(5)
+                local fs = {}
+                for i = 1, 3 do fs[i] = function() return i end end
+                return get(), fs[1]() + fs[2]() + fs[3]()"),
+            [Value::Int(6), Value::Int(6)]
+        );
+        // recursive local functions, methods, fields and varargs
+        assert_eq!(
+            run("local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end
+                local obj = {v = 3}
+                function obj:get(k) return self.v * k end
+                m = {sub = {}}
+                function m.sub.count(...) return select('#', ...) end
+                function swap(a, b) return b, a end
+                return fib(10), obj:get(2), m.sub.count(1, nil, 3), swap(1, 2)"),
+            [
+                Value::Int(55),
+                Value::Int(6),
+                Value::Int(3),
+                Value::Int(2),
+                Value::Int(1)
+            ]
+        );
+        // up values of up values
+        assert_eq!(
+            run("local x = 10
+                local function outer() return function() x = x + 1 return x end end
+                local inc = outer()
+                inc()
+                return x, inc()"),
+            [Value::Int(11), Value::Int(12)]
+        );
+    }
+
+    #[test]
+    fn compiled_calls() {
+        let mut vm = Vm::new();
+        rslua::base::open(&mut vm);
+        rslua::string::open(&mut vm);
+        let mut run = |input: &str| vm.run(compile(input)).unwrap();
+        // the last call or vararg of a list gives all its results, others give one
+        assert_eq!(
+            run("local a, b, c = select(2, 'x', 'y', 'z') return a, b, c, ..., select(1, 1, 2)"),
+            [
+                Value::str("y"),
+                Value::str("z"),
+                Value::Nil,
+                Value::Nil,
+                Value::Int(1),
+                Value::Int(2)
+            ]
+        );
+        assert_eq!(
+            run("local a, b = select(1, 1, 2), 3 x, y, z = tostring(a), select(1, b) return x, y, z"),
+            [Value::str("1"), Value::Int(3), Value::Nil]
+        );
+        // methods, and calls in expressions
+        assert_eq!(
+            run("local s = ('ab'):rep(2) return s:upper() .. #s, (select(2, 1, 2, 3))"),
+            [Value::str("ABAB4"), Value::Int(2)]
+        );
+        assert_eq!(
+            run("return pcall(error, 'boom')"),
+            [Value::Bool(false), Value::str("main:1: boom")]
+        );
+        // operands of both sides are in temporary registers
+        assert_eq!(
+            run("local a, b, c, d = 1, 2, 3, 4 return (a + b) + (c + d) * (a + c)"),
+            [Value::Int(31)]
+        );
+    }
+
+    #[test]
+    fn invalid_code() {
+        // chunks are verified before they run
+        let mut proto = Proto::new();
+        proto.stack_size = 2;
+        proto.code = vec![Instruction::Move { dst: 200, src: 0 }];
+        proto.code_return(0, 1);
+        let proto = Proto::undump(&proto.dump(true)).unwrap();
+        let mut vm = Vm::new();
+        assert_eq!(
+            vm.run(proto),
+            Err(RuntimeError::new(
+                "bad code in main: register 200 out of stack at pc 0"
+            ))
+        );
+        // returning values up to a top which no call or vararg set
+        let mut proto = Proto::new();
+        proto.stack_size = 2;
+        proto.code = vec![Instruction::Return { first: 1, count: 0 }];
+        assert_eq!(
+            vm.run(proto),
+            Err(RuntimeError::new(
+                "bad code in main: open results not set by the previous instruction at pc 0"
+            ))
+        );
+    }
+
+    // function(a, b) return a + b, a * b end
+    fn add_mul() -> Proto {
+        let mut builder = ProtoBuilder::new();
+        builder.params(2, false).stack_size(4);
+        builder.emit(Instruction::Add {
+            dst: 2,
+            left: 0,
+            right: 1,
+        });
+        builder.emit(Instruction::Mul {
+            dst: 3,
+            left: 0,
+            right: 1,
+        });
+        builder.emit(Instruction::Return { first: 2, count: 3 });
+        builder.build()
+    }
+
+    #[test]
+    fn calls() {
+        // local f = function ... end; r = f(3, 4) + 1; return f(5, 6)
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(4);
+        let env = builder.up_value("_ENV", true, 0);
+        let r = builder.constant(Const::Str("r".to_string()));
+        let three = builder.constant(Const::Int(3));
+        let four = builder.constant(Const::Int(4));
+        let five = builder.constant(Const::Int(5));
+        let six = builder.constant(Const::Int(6));
+        let one = builder.constant(Const::Int(1));
+        let f = builder.child(add_mul());
+        builder.emit(Instruction::Closure { dst: 0, proto: f });
+        builder.emit(Instruction::Move { dst: 1, src: 0 });
+        builder.emit(Instruction::LoadK { dst: 2, k: three });
+        builder.emit(Instruction::LoadK { dst: 3, k: four });
+        builder.emit(Instruction::Call {
+            func: 1,
+            args: 3,
+            results: 2,
+        });
+        builder.emit(Instruction::Add {
+            dst: 1,
+            left: 1,
+            right: rk_as_k(one),
+        });
+        builder.emit(Instruction::SetTabUp {
+            up: env,
+            key: rk_as_k(r),
+            value: 1,
+        });
+        builder.emit(Instruction::Move { dst: 1, src: 0 });
+        builder.emit(Instruction::LoadK { dst: 2, k: five });
+        builder.emit(Instruction::LoadK { dst: 3, k: six });
+        builder.emit(Instruction::TailCall {
+            func: 1,
+            args: 3,
+            results: 0,
+        });
+        builder.emit(Instruction::Return { first: 1, count: 0 });
+        let proto = builder.build();
+        assert_eq!(proto.verify(), Ok(()));
+
+        let mut vm = Vm::new();
+        assert_eq!(vm.run(proto), Ok(vec![Value::Int(11), Value::Int(30)]));
+        assert_eq!(vm.get_global("r"), Value::Int(8));
+
+        // host calls
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(1);
+        let f = builder.child(add_mul());
+        builder.emit(Instruction::Closure { dst: 0, proto: f });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        let f = vm.run(builder.build()).unwrap().remove(0);
+        assert_eq!(
+            vm.call(&f, &[Value::Float(1.5), Value::Int(2)]),
+            Ok(vec![Value::Float(3.5), Value::Float(3.0)])
+        );
+        assert_eq!(
+            vm.call(&f, &[Value::Int(1)]),
+            Err(RuntimeError::new(
+                "main.0:1: attempt to perform arithmetic on a nil value"
+            ))
+        );
+        assert_eq!(
+            vm.call(&Value::Int(1), &[]),
+            Err(RuntimeError::new("attempt to call a number value"))
+        );
+    }
+
+    #[test]
+    fn numeric_for() {
+        // local s = 0; for i = 1, 10, 3 do s = s + i end; return s
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(5);
+        let zero = builder.constant(Const::Int(0));
+        let one = builder.constant(Const::Int(1));
+        let ten = builder.constant(Const::Int(10));
+        let three = builder.constant(Const::Int(3));
+        builder.emit(Instruction::LoadK { dst: 0, k: zero });
+        builder.emit(Instruction::LoadK { dst: 1, k: one });
+        builder.emit(Instruction::LoadK { dst: 2, k: ten });
+        builder.emit(Instruction::LoadK { dst: 3, k: three });
+        builder.emit(Instruction::ForPrep { base: 1, offset: 1 });
+        builder.emit(Instruction::Add {
+            dst: 0,
+            left: 0,
+            right: 4,
+        });
+        builder.emit(Instruction::ForLoop {
+            base: 1,
+            offset: -2,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        let proto = builder.build();
+        assert_eq!(proto.verify(), Ok(()));
+        assert_eq!(Vm::new().run(proto), Ok(vec![Value::Int(22)]));
+    }
+
+    #[test]
+    fn numeric_for_bounds() {
+        let vm = run(
+            "local min, max = -9223372036854775807 - 1, 9223372036854775807
+            local function count(i, limit, step)
+                local n, last = 0
+                for v = i, limit, step do n = n + 1 last = v end
+                return n, last
+            end
+            a, b = count(min, min + 2, 1)
+            c, d = count(max - 2, max, 1)
+            e, f = count(max, max - 2, -1)
+            g, h = count(min + 2, min, -1)
+            i = count(min, max, max)
+            j = count(max, min, min)
+            k = count(1, -1e100, 1)
+            l = count(1, 1e100, -1)",
+        );
+        let [min, max] = [i64::MIN, i64::MAX];
+        let expected = [
+            ("a", 3),
+            ("b", min + 2),
+            ("c", 3),
+            ("d", max),
+            ("e", 3),
+            ("f", max - 2),
+            ("g", 3),
+            ("h", min),
+            ("i", 3),
+            ("j", 2),
+            ("k", 0),
+            ("l", 0),
+        ];
+        for (name, value) in expected.iter() {
+            assert_eq!(vm.get_global(name), Value::Int(*value), "{}", name);
+        }
+    }
+
+    #[test]
+    fn closures() {
+        // local n = 10; local function get() return n end; n = 20; return get()
+        let mut get = ProtoBuilder::new();
+        get.stack_size(1);
+        let n = get.up_value("n", true, 0);
+        get.emit(Instruction::GetUpVal { dst: 0, up: n });
+        get.emit(Instruction::Return { first: 0, count: 2 });
+
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(3);
+        let ten = builder.constant(Const::Int(10));
+        let twenty = builder.constant(Const::Int(20));
+        let get = builder.child(get.build());
+        builder.emit(Instruction::LoadK { dst: 0, k: ten });
+        builder.emit(Instruction::Closure { dst: 1, proto: get });
+        builder.emit(Instruction::LoadK { dst: 0, k: twenty });
+        builder.emit(Instruction::Move { dst: 2, src: 1 });
+        builder.emit(Instruction::Call {
+            func: 2,
+            args: 1,
+            results: 0,
+        });
+        builder.emit(Instruction::Return { first: 2, count: 0 });
+        let proto = builder.build();
+        assert_eq!(proto.verify(), Ok(()));
+        // the closure refers to the variable, not its value
+        assert_eq!(Vm::new().run(proto), Ok(vec![Value::Int(20)]));
+    }
+
+    // function() n = n + 1 return n end
+    fn increment() -> Proto {
+        let mut inc = ProtoBuilder::new();
+        inc.stack_size(1);
+        let n = inc.up_value("n", true, 0);
+        let one = inc.constant(Const::Int(1));
+        inc.emit(Instruction::GetUpVal { dst: 0, up: n });
+        inc.emit(Instruction::Add {
+            dst: 0,
+            left: 0,
+            right: rk_as_k(one),
+        });
+        inc.emit(Instruction::SetUpVal { src: 0, up: n });
+        inc.emit(Instruction::Return { first: 0, count: 2 });
+        inc.build()
+    }
+
+    // local n = 0; return function() n = n + 1 return n end, function() return n end
+    fn counter() -> Proto {
+        let mut get = ProtoBuilder::new();
+        get.stack_size(1);
+        let n = get.up_value("n", true, 0);
+        get.emit(Instruction::GetUpVal { dst: 0, up: n });
+        get.emit(Instruction::Return { first: 0, count: 2 });
+
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(3);
+        let zero = builder.constant(Const::Int(0));
+        let inc = builder.child(increment());
+        let get = builder.child(get.build());
+        builder.emit(Instruction::LoadK { dst: 0, k: zero });
+        builder.emit(Instruction::Closure { dst: 1, proto: inc });
+        builder.emit(Instruction::Closure { dst: 2, proto: get });
+        builder.emit(Instruction::Return { first: 1, count: 3 });
+        builder.build()
+    }
+
+    #[test]
+    fn shared_up_values() {
+        // the variable is closed when the function returns, and still shared
+        let mut vm = Vm::new();
+        let mut functions = vm.run(counter()).unwrap();
+        let (inc, get) = (functions.remove(0), functions.remove(0));
+        assert_eq!(vm.call(&inc, &[]), Ok(vec![Value::Int(1)]));
+        assert_eq!(vm.call(&inc, &[]), Ok(vec![Value::Int(2)]));
+        assert_eq!(vm.call(&get, &[]), Ok(vec![Value::Int(2)]));
+        // each run has its own variable
+        let other = vm.run(counter()).unwrap().remove(1);
+        assert_eq!(vm.call(&other, &[]), Ok(vec![Value::Int(0)]));
+        assert_eq!(vm.call(&get, &[]), Ok(vec![Value::Int(2)]));
+    }
+
+    #[test]
+    fn close_up_values() {
+        // do local n = 1; f = function() n = n + 1 return n end end; local m = 10; f()
+        // return f(). the jump leaving the block closes n, so m can take its register
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(3);
+        let one = builder.constant(Const::Int(1));
+        let ten = builder.constant(Const::Int(10));
+        let inc = builder.child(increment());
+        builder.emit(Instruction::LoadK { dst: 0, k: one });
+        builder.emit(Instruction::Closure { dst: 1, proto: inc });
+        builder.emit(Instruction::Jmp {
+            close: 1,
+            offset: 0,
+        });
+        builder.emit(Instruction::LoadK { dst: 0, k: ten });
+        builder.emit(Instruction::Move { dst: 2, src: 1 });
+        builder.emit(Instruction::Call {
+            func: 2,
+            args: 1,
+            results: 2,
+        });
+        builder.emit(Instruction::Move { dst: 0, src: 1 });
+        builder.emit(Instruction::Call {
+            func: 0,
+            args: 1,
+            results: 2,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        let proto = builder.build();
+        assert_eq!(proto.verify(), Ok(()));
+        assert_eq!(Vm::new().run(proto), Ok(vec![Value::Int(3)]));
+    }
+
+    fn table(fields: &[(&str, Value)]) -> TableRef {
+        let t = Rc::new(RefCell::new(Table::new()));
+        for (key, value) in fields {
+            t.borrow_mut().set_str(key, value.clone());
+        }
+        t
+    }
+
+    #[test]
+    fn metatables() {
+        let mut vm = Vm::new();
+        let t = table(&[("own", Value::Int(0))]);
+        let store = table(&[("a", Value::Int(1))]);
+        let meta = table(&[
+            ("__index", Value::Table(store.clone())),
+            ("__newindex", Value::Table(store.clone())),
+        ]);
+        vm.set_metatable(&t, Some(meta.clone()));
+        vm.set_global("t", Value::Table(t.clone()));
+        vm.run(compile("x = t.a y = t.z t.b = 2 t.own = 3"))
+            .unwrap();
+        assert_eq!(vm.get_global("x"), Value::Int(1));
+        assert_eq!(vm.get_global("y"), Value::Nil);
+        assert_eq!(t.borrow().get_str("b"), Value::Nil);
+        assert_eq!(store.borrow().get_str("b"), Value::Int(2));
+        // existing fields don't go through __newindex
+        assert_eq!(t.borrow().get_str("own"), Value::Int(3));
+
+        // raw access skips the metatable
+        let tv = Value::Table(t.clone());
+        assert_eq!(vm.raw_get(&tv, &Value::str("a")), Ok(Value::Nil));
+        vm.raw_set(&tv, Value::str("c"), Value::Int(4)).unwrap();
+        assert_eq!(t.borrow().get_str("c"), Value::Int(4));
+        assert_eq!(
+            vm.raw_set(&tv, Value::Nil, Value::Int(4)),
+            Err(RuntimeError::new("index is nil"))
+        );
+        assert_eq!(
+            vm.raw_get(&Value::Int(1), &Value::Nil),
+            Err(RuntimeError::new("table expected, got number"))
+        );
+
+        // handlers which are functions, function(t, k) return k end
+        let mut builder = ProtoBuilder::new();
+        builder.params(2, false).stack_size(2);
+        builder.emit(Instruction::Return { first: 1, count: 2 });
+        let mut main = ProtoBuilder::new();
+        main.stack_size(1);
+        let child = main.child(builder.build());
+        main.emit(Instruction::Closure {
+            dst: 0,
+            proto: child,
+        });
+        main.emit(Instruction::Return { first: 0, count: 2 });
+        let handler = vm.run(main.build()).unwrap().remove(0);
+        meta.borrow_mut().set_str("__index", handler);
+        assert_eq!(vm.index(&tv, &Value::str("k")), Ok(Value::str("k")));
+        assert_eq!(vm.index(&tv, &Value::str("own")), Ok(Value::Int(3)));
+
+        // loops
+        let other = table(&[]);
+        vm.set_metatable(&other, Some(table(&[("__index", tv.clone())])));
+        meta.borrow_mut()
+            .set_str("__index", Value::Table(other.clone()));
+        assert_eq!(
+            vm.index(&tv, &Value::str("k")),
+            Err(RuntimeError::new(
+                "'__index' chain too long; possibly a loop"
+            ))
+        );
+    }
+
+    #[test]
+    fn string_metatable() {
+        let mut vm = Vm::new();
+        assert_eq!(
+            vm.run(compile("local s = 'abc' x = s.len")),
+            Err(RuntimeError::new("main:1: attempt to index a string value"))
+        );
+        let methods = table(&[("len", Value::Int(3))]);
+        vm.set_string_metatable(Some(table(&[("__index", Value::Table(methods))])));
+        vm.run(compile("local s = 'abc' x = s.len")).unwrap();
+        assert_eq!(vm.get_global("x"), Value::Int(3));
+        assert!(vm.get_metatable(&Value::str("")).is_some());
+        assert!(vm.get_metatable(&Value::Int(1)).is_none());
+    }
+
+    // return f(2, 3)
+    fn call_global_f() -> Proto {
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(3);
+        let env = builder.up_value("_ENV", true, 0);
+        let f = builder.constant(Const::Str("f".to_string()));
+        let two = builder.constant(Const::Int(2));
+        let three = builder.constant(Const::Int(3));
+        builder.emit(Instruction::GetTabUp {
+            dst: 0,
+            up: env,
+            key: rk_as_k(f),
+        });
+        builder.emit(Instruction::LoadK { dst: 1, k: two });
+        builder.emit(Instruction::LoadK { dst: 2, k: three });
+        builder.emit(Instruction::Call {
+            func: 0,
+            args: 3,
+            results: 0,
+        });
+        builder.emit(Instruction::Return { first: 0, count: 0 });
+        builder.build()
+    }
+
+    #[test]
+    fn wrapped_calls() {
+        let mut vm = Vm::new();
+        let mut builder = ProtoBuilder::new();
+        builder.stack_size(1);
+        let f = builder.child(add_mul());
+        builder.emit(Instruction::Closure { dst: 0, proto: f });
+        builder.emit(Instruction::Return { first: 0, count: 2 });
+        let f = vm.run(builder.build()).unwrap().remove(0);
+        vm.set_global("f", f.clone());
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let (before, after) = (log.clone(), log.clone());
+        let hooks = CallHooks::new()
+            .before(move |args| {
+                before.borrow_mut().push(args.to_vec());
+                Ok(())
+            })
+            .after(move |results| after.borrow_mut().push(results.to_vec()));
+        vm.wrap_global("f", hooks).unwrap();
+        assert_eq!(
+            vm.run(call_global_f()),
+            Ok(vec![Value::Int(5), Value::Int(6)])
+        );
+        assert_eq!(
+            *log.borrow(),
+            [
+                vec![Value::Int(2), Value::Int(3)],
+                vec![Value::Int(5), Value::Int(6)]
+            ]
+        );
+
+        // the original function isn't hooked
+        let wrapped = vm.get_global("f");
+        assert_ne!(wrapped, f);
+        vm.call(&f, &[Value::Int(1), Value::Int(1)]).unwrap();
+        assert_eq!(log.borrow().len(), 2);
+
+        // veto
+        let hooks = CallHooks::new().before(|args| match args.first() {
+            Some(Value::Int(2)) => Err(RuntimeError::new("f(2) is denied")),
+            _ => Ok(()),
+        });
+        let t = table(&[("f", f.clone())]);
+        vm.wrap_field(&t, &Value::str("f"), hooks).unwrap();
+        vm.set_global("f", t.borrow().get_str("f"));
+        assert_eq!(
+            vm.run(call_global_f()),
+            Err(RuntimeError::new("main:1: f(2) is denied"))
+        );
+        assert!(vm.unwrap(&vm.get_global("f")));
+        assert_eq!(
+            vm.run(call_global_f()),
+            Ok(vec![Value::Int(5), Value::Int(6)])
+        );
+        assert_eq!(
+            vm.wrap_global("missing", CallHooks::new()),
+            Err(RuntimeError::new("function expected, got nil"))
+        );
+    }
+
+    // function(a, b) return b end
+    fn second_arg(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(2, false).stack_size(2);
+        builder.emit(Instruction::Return { first: 1, count: 2 });
+        let mut main = ProtoBuilder::new();
+        main.stack_size(1);
+        let child = main.child(builder.build());
+        main.emit(Instruction::Closure {
+            dst: 0,
+            proto: child,
+        });
+        main.emit(Instruction::Return { first: 0, count: 2 });
+        vm.run(main.build()).unwrap().remove(0)
+    }
+
+    #[test]
+    fn arith_metamethods() {
+        let mut vm = Vm::new();
+        let handler = second_arg(&mut vm);
+        let meta = table(&[
+            ("__add", handler.clone()),
+            ("__mul", handler.clone()),
+            ("__unm", handler.clone()),
+            ("__band", handler),
+        ]);
+        let t = table(&[]);
+        vm.set_metatable(&t, Some(meta));
+        let t = Value::Table(t);
+        vm.set_global("t", t.clone());
+        vm.set_global("u", Value::new_table());
+        vm.run(compile("x = t + 1 y = 2 * t z = -t w = t & 1.0"))
+            .unwrap();
+        assert_eq!(vm.get_global("x"), Value::Int(1));
+        assert_eq!(vm.get_global("y"), t);
+        assert_eq!(vm.get_global("z"), t);
+        assert_eq!(vm.get_global("w"), Value::Float(1.0));
+
+        let error = |msg: &str| Err(RuntimeError::new(msg));
+        assert_eq!(
+            vm.run(compile("x = t - 1")),
+            error("main:1: attempt to perform arithmetic on a table value")
+        );
+        assert_eq!(
+            vm.run(compile("x = 1 // u")),
+            error("main:1: attempt to perform arithmetic on a table value")
+        );
+        assert_eq!(
+            vm.run(compile("x = ~u")),
+            error("main:1: attempt to perform bitwise operation on a table value")
+        );
+        // numbers don't have metamethods
+        assert_eq!(
+            vm.run(compile("local a = 1 x = a % 0")),
+            error("main:1: attempt to perform 'n%0'")
+        );
+    }
+
+    // a closure of a function with two params and one register
+    fn function(vm: &mut Vm, code: &[Instruction]) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(2, false).stack_size(3);
+        for instruction in code {
+            builder.emit(*instruction);
+        }
+        let mut main = ProtoBuilder::new();
+        main.stack_size(1);
+        let child = main.child(builder.build());
+        main.emit(Instruction::Closure {
+            dst: 0,
+            proto: child,
+        });
+        main.emit(Instruction::Return { first: 0, count: 2 });
+        vm.run(main.build()).unwrap().remove(0)
+    }
+
+    #[test]
+    fn comparison_metamethods() {
+        let mut vm = Vm::new();
+        // function() return true end
+        let yes = function(
+            &mut vm,
+            &[
+                Instruction::LoadBool {
+                    dst: 2,
+                    value: 1,
+                    skip: 0,
+                },
+                Instruction::Return { first: 2, count: 2 },
+            ],
+        );
+        let (a, b) = (table(&[]), table(&[]));
+        let meta = table(&[("__eq", yes.clone()), ("__lt", yes)]);
+        vm.set_metatable(&a, Some(meta));
+        let (a, b) = (Value::Table(a), Value::Table(b));
+
+        // either operand may have the handler
+        assert_eq!(vm.equals(&a, &b), Ok(true));
+        assert_eq!(vm.equals(&b, &a), Ok(true));
+        assert_eq!(vm.equals(&b, &Value::new_table()), Ok(false));
+        // not called for different types
+        assert_eq!(vm.equals(&a, &Value::Int(1)), Ok(false));
+        assert_eq!(vm.less_than(&b, &a), Ok(true));
+        // no fallback to `not (b < a)`
+        assert_eq!(
+            vm.less_equal(&a, &b),
+            Err(RuntimeError::new("attempt to compare two table values"))
+        );
+        assert_eq!(
+            vm.less_than(&Value::Int(1), &Value::str("2")),
+            Err(RuntimeError::new("attempt to compare number with string"))
+        );
+        assert_eq!(vm.less_equal(&Value::str("a"), &Value::str("ab")), Ok(true));
+        assert_eq!(vm.less_than(&Value::Int(1), &Value::Float(1.5)), Ok(true));
+
+        // function(a, b) return a == b end
+        let eq = function(
+            &mut vm,
+            &[
+                Instruction::Eq {
+                    expect: 1,
+                    left: 0,
+                    right: 1,
+                },
+                Instruction::Jmp {
+                    close: 0,
+                    offset: 1,
+                },
+                Instruction::LoadBool {
+                    dst: 2,
+                    value: 0,
+                    skip: 1,
+                },
+                Instruction::LoadBool {
+                    dst: 2,
+                    value: 1,
+                    skip: 0,
+                },
+                Instruction::Return { first: 2, count: 2 },
+            ],
+        );
+        assert_eq!(vm.call(&eq, &[a.clone(), b]), Ok(vec![Value::Bool(true)]));
+        assert_eq!(vm.call(&eq, &[a, Value::Nil]), Ok(vec![Value::Bool(false)]));
+    }
+
+    #[test]
+    fn string_and_length_metamethods() {
+        let mut vm = Vm::new();
+        let handler = second_arg(&mut vm);
+        let t = table(&[]);
+        let meta = table(&[("__concat", handler.clone()), ("__len", handler)]);
+        vm.set_metatable(&t, Some(meta.clone()));
+        let t = Value::Table(t);
+        vm.set_global("t", t.clone());
+        vm.run(compile("local a, b = 1, 'y' x = a .. b .. t n = #t"))
+            .unwrap();
+        assert_eq!(vm.get_global("x"), t);
+        assert_eq!(vm.get_global("n"), t);
+        // runs of strings and numbers are joined first
+        assert_eq!(
+            vm.concat(vec![t.clone(), Value::Int(1), Value::Float(2.0)]),
+            Ok(Value::str("12.0"))
+        );
+        assert_eq!(
+            vm.concat(vec![Value::str("a"), Value::Nil]),
+            Err(RuntimeError::new("attempt to concatenate a nil value"))
+        );
+        assert_eq!(
+            vm.len(&Value::Bool(true)),
+            Err(RuntimeError::new(
+                "attempt to get length of a boolean value"
+            ))
+        );
+        assert_eq!(vm.len(&Value::str("abc")), Ok(Value::Int(3)));
+
+        // function(v) return "object" end
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(2);
+        let k = builder.constant(Const::Str("object".to_string()));
+        builder.emit(Instruction::LoadK { dst: 1, k });
+        builder.emit(Instruction::Return { first: 1, count: 2 });
+        let mut main = ProtoBuilder::new();
+        main.stack_size(1);
+        let child = main.child(builder.build());
+        main.emit(Instruction::Closure {
+            dst: 0,
+            proto: child,
+        });
+        main.emit(Instruction::Return { first: 0, count: 2 });
+        let to_string = vm.run(main.build()).unwrap().remove(0);
+        assert!(vm
+            .tostring(&t)
+            .unwrap()
+            .to_str_lossy()
+            .starts_with("table: 0x"));
+        meta.borrow_mut().set_str("__tostring", to_string);
+        assert_eq!(vm.tostring(&t), Ok("object".into()));
+        meta.borrow_mut().set_str("__tostring", second_arg(&mut vm));
+        assert_eq!(
+            vm.tostring(&t),
+            Err(RuntimeError::new("'__tostring' must return a string"))
+        );
+        assert_eq!(vm.tostring(&Value::Float(1e100)), Ok("1e+100".into()));
+        assert_eq!(vm.tostring(&Value::Bool(false)), Ok("false".into()));
+    }
+
+    // function f(n) if n == 0 then return "done" end return f(n - 1) end
+    fn countdown(vm: &mut Vm) -> Value {
+        let mut builder = ProtoBuilder::new();
+        builder.params(1, false).stack_size(3);
+        builder.up_value("_ENV", false, 0);
+        let zero = builder.constant(Const::Int(0));
+        let one = builder.constant(Const::Int(1));
+        let done = builder.constant(Const::Str("done".to_string()));
+        let f = builder.constant(Const::Str("f".to_string()));
+        builder.emit(Instruction::Eq {
+            expect: 0,
+            left: 0,
+            right: rk_as_k(zero),
+        });
+        builder.emit(Instruction::Jmp {
+            close: 0,
+            offset: 2,
+        });
+        builder.emit(Instruction::LoadK { dst: 1, k: done });
+        builder.emit(Instruction::Return { first: 1, count: 2 });
+        builder.emit(Instruction::GetTabUp {
+            dst: 1,
+            up: 0,
+            key: rk_as_k(f),
+        });
+        builder.emit(Instruction::Sub {
+            dst: 2,
+            left: 0,
+            right: rk_as_k(one),
+        });
+        builder.emit(Instruction::TailCall {
+            func: 1,
+            args: 2,
+            results: 0,
+        });
+        builder.emit(Instruction::Return { first: 1, count: 0 });
+        let mut main = ProtoBuilder::new();
+        main.stack_size(1);
+        main.up_value("_ENV", true, 0);
+        let child = main.child(builder.build());
+        main.emit(Instruction::Closure {
+            dst: 0,
+            proto: child,
+        });
+        main.emit(Instruction::Return { first: 0, count: 2 });
+        let f = vm.run(main.build()).unwrap().remove(0);
+        vm.set_global("f", f.clone());
+        f
+    }
+
+    #[test]
+    fn tail_calls() {
+        let mut vm = Vm::new();
+        let f = countdown(&mut vm);
+        let depth = Rc::new(RefCell::new(0));
+        let max_depth = depth.clone();
+        vm.set_count_hook(1, move |vm| {
+            let mut max_depth = max_depth.borrow_mut();
+            *max_depth = vm.stack_depth().max(*max_depth);
+            Ok(())
+        });
+        assert_eq!(
+            vm.call(&f, &[Value::Int(100_000)]),
+            Ok(vec![Value::str("done")])
+        );
+        // the frame of the first call is reused
+        assert_eq!(*depth.borrow(), 1);
+        vm.remove_count_hook();
+
+        let tail_calls = Rc::new(RefCell::new(0));
+        let count = tail_calls.clone();
+        vm.set_hook(
+            HookMask {
+                call: true,
+                ..HookMask::default()
+            },
+            move |vm, event| {
+                if event == HookEvent::TailCall {
+                    assert!(vm.frame_info(1).unwrap().is_tail_call);
+                    *count.borrow_mut() += 1;
+                }
+                Ok(())
+            },
+        );
+        vm.call(&f, &[Value::Int(10)]).unwrap();
+        assert_eq!(*tail_calls.borrow(), 10);
+    }
+}